@@ -0,0 +1,56 @@
+//! Functions shared by the binary formats CBOR and MessagePack.
+//!
+//! Because jaq has no type for binary data, binary data is represented by
+//! strings whose characters are the bytes of the data, that is,
+//! every character has a codepoint smaller than 256.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use jaq_interpret::{Error, Val, ValR};
+
+/// Error that occurred while decoding the format with the given name.
+pub(crate) fn err(name: &str, e: impl core::fmt::Display) -> Error {
+    Error::str(format_args!("cannot decode {name}: {e}"))
+}
+
+/// Convert bytes to a string with one character per byte.
+pub(crate) fn to_str(bytes: &[u8]) -> String {
+    bytes.iter().copied().map(char::from).collect()
+}
+
+/// Convert bytes to a string value with one character per byte.
+pub(crate) fn str_val(bytes: &[u8]) -> Val {
+    Val::str(to_str(bytes))
+}
+
+/// Decode a string containing the bytes of a single value.
+///
+/// Fail if the string contains characters that are not bytes or
+/// if bytes remain after decoding the value.
+pub(crate) fn decode(name: &str, s: &str, decode_from: fn(&mut &[u8]) -> ValR) -> ValR {
+    let bytes = s
+        .chars()
+        .map(|c| u8::try_from(c).map_err(|_| err(name, format_args!("invalid byte {c:?}"))))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut bytes = bytes.as_slice();
+    let v = decode_from(&mut bytes)?;
+    if !bytes.is_empty() {
+        return Err(err(name, "trailing bytes"));
+    }
+    Ok(v)
+}
+
+/// Take the first `n` bytes, advancing the bytes past them.
+pub(crate) fn take<'a>(name: &str, bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], Error> {
+    if bytes.len() < n {
+        return Err(err(name, "unexpected end of input"));
+    }
+    let (l, r) = bytes.split_at(n);
+    *bytes = r;
+    Ok(l)
+}
+
+/// Interpret bytes as big-endian unsigned integer.
+pub(crate) fn be(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |acc, b| (acc << 8) | u64::from(*b))
+}
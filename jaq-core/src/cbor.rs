@@ -0,0 +1,242 @@
+//! Encoding and decoding of CBOR (RFC 8949).
+//!
+//! Because jaq has no type for binary data, CBOR data is represented by
+//! strings whose characters are the bytes of the data, that is,
+//! every character has a codepoint smaller than 256.
+
+use crate::binary::{self, be, take};
+use alloc::{string::String, vec::Vec};
+use jaq_interpret::{Error, Val, ValR};
+use num_bigint::{BigInt, Sign};
+
+const NAME: &str = "CBOR";
+
+/// Encode a value to a string containing the bytes of its CBOR representation.
+pub fn encode(v: &Val) -> String {
+    let mut out = Vec::new();
+    encode_into(v, &mut out);
+    binary::to_str(&out)
+}
+
+/// Decode a string containing the bytes of a CBOR representation to a value.
+///
+/// Text strings are decoded to strings, and byte strings are decoded to
+/// strings with one character per byte, like the input of this function.
+/// Because such strings are encoded as text strings,
+/// decoding and encoding a byte string does not yield the original data.
+pub fn decode(s: &str) -> ValR {
+    binary::decode(NAME, s, decode_from)
+}
+
+fn err(e: impl core::fmt::Display) -> Error {
+    binary::err(NAME, e)
+}
+
+/// Write the header of a data item with given major type and argument.
+fn head(major: u8, arg: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    match arg {
+        0..=23 => out.push(major | arg as u8),
+        24..=0xff => out.extend([major | 24, arg as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend((arg as u16).to_be_bytes())
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend((arg as u32).to_be_bytes())
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend(arg.to_be_bytes())
+        }
+    }
+}
+
+fn encode_int(i: i64, out: &mut Vec<u8>) {
+    if i >= 0 {
+        head(0, i as u64, out)
+    } else {
+        head(1, !i as u64, out)
+    }
+}
+
+/// Encode an integer, using a bignum (tag 2 or 3) if it does not fit into 64 bits.
+fn encode_big(i: &BigInt, out: &mut Vec<u8>) {
+    // negative integers `i` are encoded as `-1 - i`
+    let (major, tag, n) = match i.sign() {
+        Sign::Minus => (1, 3, -1 - i),
+        Sign::NoSign | Sign::Plus => (0, 2, i.clone()),
+    };
+    match u64::try_from(&n) {
+        Ok(u) => head(major, u, out),
+        Err(_) => {
+            let (_, bytes) = n.to_bytes_be();
+            head(6, tag, out);
+            head(2, bytes.len() as u64, out);
+            out.extend(bytes)
+        }
+    }
+}
+
+fn encode_float(f: f64, out: &mut Vec<u8>) {
+    out.push(0xfb);
+    out.extend(f.to_be_bytes())
+}
+
+/// Append the CBOR representation of a value to the given bytes.
+pub fn encode_into(v: &Val, out: &mut Vec<u8>) {
+    match v {
+        Val::Null => out.push(0xf6),
+        Val::Bool(b) => out.push(if *b { 0xf5 } else { 0xf4 }),
+        Val::Int(i) => encode_int(*i as i64, out),
+        Val::Float(f) => encode_float(*f, out),
+        Val::BigInt(i) => encode_big(i, out),
+        Val::Num(n) => encode_into(&Val::from_dec_str(n), out),
+        Val::Str(s) => {
+            head(3, s.len() as u64, out);
+            out.extend(s.bytes())
+        }
+        Val::Arr(a) => {
+            head(4, a.len() as u64, out);
+            a.iter().for_each(|x| encode_into(x, out))
+        }
+        Val::Obj(o) => {
+            head(5, o.len() as u64, out);
+            for (k, v) in o.iter() {
+                head(3, k.len() as u64, out);
+                out.extend(k.bytes());
+                encode_into(v, out)
+            }
+        }
+    }
+}
+
+/// Read the argument of a data item, yielding `None` for indefinite length.
+fn arg(info: u8, bytes: &mut &[u8]) -> Result<Option<u64>, Error> {
+    Ok(Some(match info {
+        0..=23 => info.into(),
+        24 => be(take(NAME, bytes, 1)?),
+        25 => be(take(NAME, bytes, 2)?),
+        26 => be(take(NAME, bytes, 4)?),
+        27 => be(take(NAME, bytes, 8)?),
+        31 => return Ok(None),
+        _ => return Err(err(format_args!("invalid additional information {info}"))),
+    }))
+}
+
+/// Convert a half-precision float to a double-precision float.
+fn f16_to_f64(h: u16) -> f64 {
+    let exp = (h >> 10) & 0x1f;
+    let mant = f64::from(h & 0x3ff);
+    let abs = match exp {
+        0 => mant * f64::from_bits(0x3e70_0000_0000_0000), // 2^-24
+        31 if mant == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (1.0 + mant / 1024.0) * f64::from_bits((u64::from(exp) + 1008) << 52),
+    };
+    if h & 0x8000 != 0 {
+        -abs
+    } else {
+        abs
+    }
+}
+
+fn is_break(bytes: &mut &[u8]) -> bool {
+    let brk = bytes.first() == Some(&0xff);
+    if brk {
+        *bytes = &bytes[1..];
+    }
+    brk
+}
+
+/// Decode the contents of a (possibly indefinite-length) byte or text string.
+fn chunks(major: u8, len: Option<u64>, bytes: &mut &[u8]) -> Result<Vec<u8>, Error> {
+    match len {
+        Some(n) => Ok(take(NAME, bytes, usize::try_from(n).map_err(err)?)?.to_vec()),
+        None => {
+            let mut out = Vec::new();
+            while !is_break(bytes) {
+                let initial = take(NAME, bytes, 1)?[0];
+                if initial >> 5 != major {
+                    return Err(err("invalid chunk in indefinite-length string"));
+                }
+                match arg(initial & 0x1f, bytes)? {
+                    None => return Err(err("nested indefinite-length string")),
+                    len => out.extend(chunks(major, len, bytes)?),
+                }
+            }
+            Ok(out)
+        }
+    }
+}
+
+/// Decode items until `len` items were read or, for indefinite length, a break occurs.
+fn items<T>(
+    len: Option<u64>,
+    bytes: &mut &[u8],
+    mut f: impl FnMut(&mut &[u8]) -> Result<T, Error>,
+) -> Result<Vec<T>, Error> {
+    let mut out = Vec::new();
+    match len {
+        Some(n) => {
+            for _ in 0..n {
+                out.push(f(bytes)?)
+            }
+        }
+        None => {
+            while !is_break(bytes) {
+                out.push(f(bytes)?)
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Decode the CBOR data item at the start of the given bytes,
+/// advancing them past the data item.
+pub fn decode_from(bytes: &mut &[u8]) -> ValR {
+    let initial = take(NAME, bytes, 1)?[0];
+    let (major, info) = (initial >> 5, initial & 0x1f);
+    let len = arg(info, bytes)?;
+    let int = |i: i128| Val::big(i.into());
+    let definite = || len.ok_or_else(|| err("unexpected indefinite length"));
+    Ok(match major {
+        0 => int(definite()?.into()),
+        1 => int(-1 - i128::from(definite()?)),
+        2 => binary::str_val(&chunks(major, len, bytes)?),
+        3 => Val::str(String::from_utf8(chunks(major, len, bytes)?).map_err(err)?),
+        4 => Val::arr(items(len, bytes, decode_from)?),
+        5 => {
+            let kv = |bytes: &mut &[u8]| match decode_from(bytes)? {
+                Val::Str(k) => Ok((k, decode_from(bytes)?)),
+                k => Err(err(format_args!("map key is not a string: {k}"))),
+            };
+            Val::obj(items(len, bytes, kv)?.into_iter().collect())
+        }
+        6 => match definite()? {
+            // bignums
+            tag @ (2 | 3) => {
+                let initial = take(NAME, bytes, 1)?[0];
+                if initial >> 5 != 2 {
+                    return Err(err("bignum is not a byte string"));
+                }
+                let len = arg(initial & 0x1f, bytes)?;
+                let n = BigInt::from_bytes_be(Sign::Plus, &chunks(2, len, bytes)?);
+                Val::big(if tag == 2 { n } else { -1 - n })
+            }
+            // other tags are ignored
+            _ => decode_from(bytes)?,
+        },
+        _ => match (info, definite()?) {
+            (20, _) => Val::Bool(false),
+            (21, _) => Val::Bool(true),
+            // null and undefined
+            (22 | 23, _) => Val::Null,
+            (25, h) => Val::Float(f16_to_f64(h as u16)),
+            (26, f) => Val::Float(f32::from_bits(f as u32).into()),
+            (27, f) => Val::Float(f64::from_bits(f)),
+            (_, s) => return Err(err(format_args!("unsupported simple value {s}"))),
+        },
+    })
+}
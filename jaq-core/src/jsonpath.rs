@@ -0,0 +1,445 @@
+//! Evaluation of a practical subset of JSONPath (RFC 9535).
+//!
+//! Supported are the root `$`, child segments (`.a`, `.*`, `['a', 'b']`, `[0, -1]`),
+//! descendant segments (`..a`, `..*`, `..[0]`), array slices (`[1:5:2]`), and
+//! filters (`[?@.price < 10 && !@.sold]`, where `?(...)` is also accepted).
+//! Filters support comparisons (`==`, `!=`, `<`, `<=`, `>`, `>=`) between
+//! literals and singular queries (relative to `@` or to `$`),
+//! existence tests, `&&`, `||`, `!`, and parentheses.
+//! Function extensions, such as `length()`, are not supported.
+
+use alloc::string::{String, ToString};
+use alloc::{boxed::Box, vec::Vec};
+use core::cmp::Ordering;
+use jaq_interpret::{Error, Val};
+
+/// Yield all values that a JSONPath query selects from a value, in document order.
+pub fn query(path: &str, root: &Val) -> Result<Vec<Val>, Error> {
+    let mut p = Parser { s: path, pos: 0 };
+    p.ws();
+    p.expect("$")?;
+    let segs = p.segments()?;
+    p.ws();
+    if p.pos < path.len() {
+        return Err(p.err("expected end of query"));
+    }
+    Ok(eval(&segs, root, root))
+}
+
+enum Selector {
+    Name(String),
+    Index(isize),
+    Slice(Option<isize>, Option<isize>, isize),
+    Wildcard,
+    Filter(Expr),
+}
+
+enum Segment {
+    Child(Vec<Selector>),
+    Descendant(Vec<Selector>),
+}
+
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Exists(Query),
+    Cmp(Operand, Op, Operand),
+}
+
+/// A query relative to the current node (`@`) or to the root (`$`).
+struct Query {
+    root: bool,
+    segs: Vec<Segment>,
+}
+
+enum Operand {
+    Lit(Val),
+    Query(Query),
+}
+
+#[derive(Clone, Copy)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    fn err(&self, msg: &str) -> Error {
+        let pos = self.pos;
+        Error::str(format_args!(
+            "invalid JSONPath {:?} at byte {pos}: {msg}",
+            self.s
+        ))
+    }
+
+    fn eat(&mut self, prefix: &str) -> bool {
+        let found = self.rest().starts_with(prefix);
+        if found {
+            self.pos += prefix.len();
+        }
+        found
+    }
+
+    fn expect(&mut self, prefix: &str) -> Result<(), Error> {
+        if self.eat(prefix) {
+            Ok(())
+        } else {
+            Err(self.err(&alloc::format!("expected {prefix:?}")))
+        }
+    }
+
+    fn ws(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Consume the longest prefix whose characters satisfy `f`.
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let rest = self.rest();
+        let len = rest.find(|c| !f(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    fn segments(&mut self) -> Result<Vec<Segment>, Error> {
+        let mut segs = Vec::new();
+        loop {
+            // whitespace before a segment is only consumed if a segment follows
+            let pos = self.pos;
+            self.ws();
+            if self.eat("..") {
+                segs.push(Segment::Descendant(self.dotted()?));
+            } else if self.eat(".") {
+                segs.push(Segment::Child(self.dotted()?));
+            } else if self.rest().starts_with('[') {
+                segs.push(Segment::Child(self.bracketed()?));
+            } else {
+                self.pos = pos;
+                return Ok(segs);
+            }
+        }
+    }
+
+    /// Parse what may follow `.` or `..`.
+    fn dotted(&mut self) -> Result<Vec<Selector>, Error> {
+        if self.eat("*") {
+            return Ok(Vec::from([Selector::Wildcard]));
+        } else if self.rest().starts_with('[') {
+            return self.bracketed();
+        }
+        let name = self.take_while(|c| c.is_alphanumeric() || c == '_' || !c.is_ascii());
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(self.err("expected member name"));
+        }
+        Ok(Vec::from([Selector::Name(name.to_string())]))
+    }
+
+    fn bracketed(&mut self) -> Result<Vec<Selector>, Error> {
+        self.expect("[")?;
+        let mut sels = Vec::new();
+        loop {
+            self.ws();
+            sels.push(self.selector()?);
+            self.ws();
+            if self.eat("]") {
+                return Ok(sels);
+            }
+            self.expect(",")?;
+        }
+    }
+
+    fn selector(&mut self) -> Result<Selector, Error> {
+        if self.eat("*") {
+            return Ok(Selector::Wildcard);
+        } else if self.eat("?") {
+            self.ws();
+            return Ok(Selector::Filter(self.or()?));
+        } else if let Some(s) = self.string()? {
+            return Ok(Selector::Name(s));
+        }
+        let start = self.int()?;
+        self.ws();
+        if !self.eat(":") {
+            return start
+                .map(Selector::Index)
+                .ok_or_else(|| self.err("expected selector"));
+        }
+        self.ws();
+        let end = self.int()?;
+        self.ws();
+        let step = if self.eat(":") {
+            self.ws();
+            self.int()?.unwrap_or(1)
+        } else {
+            1
+        };
+        Ok(Selector::Slice(start, end, step))
+    }
+
+    fn int(&mut self) -> Result<Option<isize>, Error> {
+        let start = self.pos;
+        self.eat("-");
+        self.take_while(|c| c.is_ascii_digit());
+        let s = &self.s[start..self.pos];
+        if s.is_empty() {
+            return Ok(None);
+        }
+        s.parse().map(Some).map_err(|_| self.err("invalid integer"))
+    }
+
+    /// Parse a single- or double-quoted string.
+    fn string(&mut self) -> Result<Option<String>, Error> {
+        let quote = match self.rest().chars().next() {
+            Some(q @ ('\'' | '"')) => q,
+            _ => return Ok(None),
+        };
+        self.pos += 1;
+        let mut out = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => out.push('\n'),
+                    Some((_, 't')) => out.push('\t'),
+                    Some((_, c @ ('\\' | '/' | '\'' | '"'))) => out.push(c),
+                    _ => return Err(self.err("invalid escape sequence")),
+                },
+                c if c == quote => {
+                    self.pos += i + 1;
+                    return Ok(Some(out));
+                }
+                c => out.push(c),
+            }
+        }
+        Err(self.err("unterminated string"))
+    }
+
+    fn or(&mut self) -> Result<Expr, Error> {
+        let mut l = self.and()?;
+        while self.eat("||") {
+            self.ws();
+            l = Expr::Or(Box::new(l), Box::new(self.and()?));
+        }
+        Ok(l)
+    }
+
+    fn and(&mut self) -> Result<Expr, Error> {
+        let mut l = self.unary()?;
+        while self.eat("&&") {
+            self.ws();
+            l = Expr::And(Box::new(l), Box::new(self.unary()?));
+        }
+        Ok(l)
+    }
+
+    /// Parse a negation, a parenthesised expression, an existence test, or a comparison.
+    ///
+    /// Consumes trailing whitespace.
+    fn unary(&mut self) -> Result<Expr, Error> {
+        let e = if self.eat("!") {
+            self.ws();
+            Expr::Not(Box::new(self.unary()?))
+        } else if self.eat("(") {
+            self.ws();
+            let e = self.or()?;
+            self.expect(")")?;
+            e
+        } else {
+            let l = self.operand()?;
+            self.ws();
+            match (self.op(), l) {
+                (Some(op), l) => {
+                    self.ws();
+                    Expr::Cmp(l, op, self.operand()?)
+                }
+                (None, Operand::Query(q)) => Expr::Exists(q),
+                (None, Operand::Lit(_)) => return Err(self.err("expected comparison")),
+            }
+        };
+        self.ws();
+        Ok(e)
+    }
+
+    fn op(&mut self) -> Option<Op> {
+        let ops = [
+            ("==", Op::Eq),
+            ("!=", Op::Ne),
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+        ];
+        ops.into_iter().find(|(s, _)| self.eat(s)).map(|(_, op)| op)
+    }
+
+    fn operand(&mut self) -> Result<Operand, Error> {
+        let root = if self.eat("@") {
+            false
+        } else if self.eat("$") {
+            true
+        } else {
+            return self.literal().map(Operand::Lit);
+        };
+        let segs = self.segments()?;
+        Ok(Operand::Query(Query { root, segs }))
+    }
+
+    fn literal(&mut self) -> Result<Val, Error> {
+        if let Some(s) = self.string()? {
+            return Ok(Val::str(s));
+        }
+        for (s, v) in [("true", Val::Bool(true)), ("false", Val::Bool(false))] {
+            if self.eat(s) {
+                return Ok(v);
+            }
+        }
+        if self.eat("null") {
+            return Ok(Val::Null);
+        }
+        let num = self.take_while(|c| c.is_ascii_digit() || "+-.eE".contains(c));
+        if let Ok(i) = num.parse() {
+            Ok(Val::Int(i))
+        } else if let Ok(f) = num.parse() {
+            Ok(Val::Float(f))
+        } else {
+            Err(self.err("expected literal"))
+        }
+    }
+}
+
+fn children(v: &Val) -> Box<dyn Iterator<Item = &Val> + '_> {
+    match v {
+        Val::Arr(a) => Box::new(a.iter()),
+        Val::Obj(o) => Box::new(o.values()),
+        _ => Box::new(core::iter::empty()),
+    }
+}
+
+/// Push `v` and all its descendants to `out`, parents before children.
+fn descendants<'v>(v: &'v Val, out: &mut Vec<&'v Val>) {
+    out.push(v);
+    children(v).for_each(|c| descendants(c, out))
+}
+
+fn eval(segs: &[Segment], v: &Val, root: &Val) -> Vec<Val> {
+    let mut nodes = Vec::from([v.clone()]);
+    for seg in segs {
+        let mut next = Vec::new();
+        for node in &nodes {
+            match seg {
+                Segment::Child(sels) => sels.iter().for_each(|s| select(s, node, root, &mut next)),
+                Segment::Descendant(sels) => {
+                    let mut ds = Vec::new();
+                    descendants(node, &mut ds);
+                    for d in ds {
+                        sels.iter().for_each(|s| select(s, d, root, &mut next))
+                    }
+                }
+            }
+        }
+        nodes = next;
+    }
+    nodes
+}
+
+fn select(sel: &Selector, v: &Val, root: &Val, out: &mut Vec<Val>) {
+    match (sel, v) {
+        (Selector::Name(name), Val::Obj(o)) => out.extend(o.get(name).cloned()),
+        (Selector::Index(i), Val::Arr(a)) => {
+            let i = if *i < 0 { a.len() as isize + i } else { *i };
+            out.extend(usize::try_from(i).ok().and_then(|i| a.get(i)).cloned())
+        }
+        (Selector::Slice(start, end, step), Val::Arr(a)) => slice(a, *start, *end, *step, out),
+        (Selector::Wildcard, _) => out.extend(children(v).cloned()),
+        (Selector::Filter(e), _) => out.extend(children(v).filter(|c| test(e, c, root)).cloned()),
+        _ => (),
+    }
+}
+
+/// Push the elements of an array slice to `out`, following Python's semantics.
+fn slice(a: &[Val], start: Option<isize>, end: Option<isize>, step: isize, out: &mut Vec<Val>) {
+    let len = a.len() as isize;
+    let norm = |i: isize| if i < 0 { len + i } else { i };
+    if step > 0 {
+        let lo = start.map_or(0, norm).clamp(0, len);
+        let hi = end.map_or(len, norm).clamp(0, len);
+        let mut i = lo;
+        while i < hi {
+            out.push(a[i as usize].clone());
+            i = i.saturating_add(step);
+        }
+    } else if step < 0 {
+        let hi = start.map_or(len - 1, norm).clamp(-1, len - 1);
+        let lo = end.map_or(-1, norm).clamp(-1, len - 1);
+        let mut i = hi;
+        while i > lo {
+            out.push(a[i as usize].clone());
+            i = i.saturating_add(step);
+        }
+    }
+}
+
+fn test(e: &Expr, v: &Val, root: &Val) -> bool {
+    match e {
+        Expr::Or(l, r) => test(l, v, root) || test(r, v, root),
+        Expr::And(l, r) => test(l, v, root) && test(r, v, root),
+        Expr::Not(e) => !test(e, v, root),
+        Expr::Exists(q) => !eval_query(q, v, root).is_empty(),
+        Expr::Cmp(l, op, r) => {
+            let (l, r) = (operand(l, v, root), operand(r, v, root));
+            compare(l.as_ref(), *op, r.as_ref())
+        }
+    }
+}
+
+fn eval_query(q: &Query, v: &Val, root: &Val) -> Vec<Val> {
+    eval(&q.segs, if q.root { root } else { v }, root)
+}
+
+/// Evaluate an operand to a value, yielding `None` if a query selects no single value.
+fn operand(o: &Operand, v: &Val, root: &Val) -> Option<Val> {
+    match o {
+        Operand::Lit(l) => Some(l.clone()),
+        Operand::Query(q) => {
+            let mut vs = eval_query(q, v, root);
+            (vs.len() == 1).then(|| vs.remove(0))
+        }
+    }
+}
+
+/// Compare two values, where only numbers and strings are ordered among each other.
+fn compare(l: Option<&Val>, op: Op, r: Option<&Val>) -> bool {
+    let is_num = |v: &Val| {
+        matches!(
+            v,
+            Val::Int(_) | Val::Float(_) | Val::BigInt(_) | Val::Num(_)
+        )
+    };
+    let ord = match (l, r) {
+        (Some(l @ Val::Str(_)), Some(r @ Val::Str(_))) => Some(l.cmp(r)),
+        (Some(l), Some(r)) if is_num(l) && is_num(r) => Some(l.cmp(r)),
+        _ => None,
+    };
+    match op {
+        Op::Eq => l == r,
+        Op::Ne => l != r,
+        Op::Lt => ord == Some(Ordering::Less),
+        Op::Le => matches!(ord, Some(Ordering::Less | Ordering::Equal)),
+        Op::Gt => ord == Some(Ordering::Greater),
+        Op::Ge => matches!(ord, Some(Ordering::Greater | Ordering::Equal)),
+    }
+}
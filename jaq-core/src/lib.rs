@@ -7,17 +7,31 @@ extern crate alloc;
 #[cfg(feature = "std")]
 extern crate std;
 
+#[cfg(any(feature = "cbor", feature = "msgpack"))]
+mod binary;
+#[cfg(feature = "cbor")]
+pub mod cbor;
+#[cfg(feature = "jsonpath")]
+mod jsonpath;
 #[cfg(feature = "math")]
 mod math;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
 #[cfg(feature = "regex")]
 mod regex;
 #[cfg(feature = "time")]
 mod time;
+#[cfg(feature = "xml")]
+mod xml;
 
 use alloc::string::{String, ToString};
 use alloc::{borrow::ToOwned, boxed::Box, format, rc::Rc, vec::Vec};
 use jaq_interpret::results::{box_once, run_if_ok, then};
-use jaq_interpret::{Error, FilterT, Native, RunPtr, UpdatePtr, Val, ValR, ValRs};
+#[cfg(feature = "log")]
+use jaq_interpret::Diagnostic;
+use jaq_interpret::{Args, Error, FilterT, Native, RunPtr, UpdatePtr, Val, ValR, ValRs};
+#[cfg(feature = "unicode")]
+use unicode_normalization::UnicodeNormalization;
 
 /// Return the minimal set of named filters available in jaq
 /// which are implemented as native filters, such as `length`, `keys`, ...,
@@ -35,22 +49,32 @@ pub fn minimal() -> impl Iterator<Item = (String, usize, Native)> {
 /// Does not return filters from the standard library, such as `map`.
 #[cfg(all(
     feature = "std",
+    feature = "cbor",
     feature = "format",
+    feature = "jsonpath",
     feature = "log",
     feature = "math",
+    feature = "msgpack",
     feature = "parse_json",
     feature = "regex",
     feature = "time",
+    feature = "unicode",
+    feature = "xml",
 ))]
 pub fn core() -> impl Iterator<Item = (String, usize, Native)> {
     minimal()
         .chain(run(STD))
+        .chain(run(CBOR))
         .chain(run(FORMAT))
+        .chain(run(JSONPATH))
         .chain(upd(LOG))
         .chain(run(MATH))
+        .chain(run(MSGPACK))
         .chain(run(PARSE_JSON))
         .chain(run(REGEX))
         .chain(run(TIME))
+        .chain(run(UNICODE))
+        .chain(run(XML))
 }
 
 fn run<'a>(fs: &'a [(&str, usize, RunPtr)]) -> impl Iterator<Item = (String, usize, Native)> + 'a {
@@ -80,9 +104,14 @@ fn length(v: &Val) -> ValR {
     match v {
         Val::Null => Ok(Val::Int(0)),
         Val::Bool(_) => Err(Error::str(format_args!("{v} has no length"))),
-        Val::Int(i) => Ok(Val::Int(i.abs())),
+        // the absolute value of the smallest integer is a big integer
+        Val::Int(i) => i
+            .checked_abs()
+            .map_or_else(|| -v.clone(), |i| Ok(Val::Int(i))),
         Val::Num(n) => length(&Val::from_dec_str(n)),
         Val::Float(f) => Ok(Val::Float(f.abs())),
+        Val::BigInt(_) if *v < Val::Int(0) => -v.clone(),
+        Val::BigInt(_) => Ok(v.clone()),
         Val::Str(s) => Ok(Val::Int(s.chars().count() as isize)),
         Val::Arr(a) => Ok(Val::Int(a.len() as isize)),
         Val::Obj(o) => Ok(Val::Int(o.len() as isize)),
@@ -125,6 +154,74 @@ fn group_by<'a>(xs: Vec<Val>, f: impl Fn(Val) -> ValRs<'a>) -> ValR {
     Ok(Val::arr(grouped))
 }
 
+/// Remove all but the first occurrence of every element, preserving order.
+fn distinct(xs: &mut Vec<Val>) {
+    let mut seen = alloc::collections::BTreeSet::new();
+    xs.retain(|x| seen.insert(x.clone()));
+}
+
+/// Count the elements of an array that yield the same outputs for the given function.
+///
+/// This is equivalent to `group_by(f) | map({key: .[0] | f, count: length})`
+/// if `f` yields a single output, but it does not store the groups.
+/// If `f` yields zero or multiple outputs, then the key is the array of outputs.
+fn group_count_by<'a>(xs: Vec<Val>, f: impl Fn(Val) -> ValRs<'a>) -> ValR {
+    let mut counts = alloc::collections::BTreeMap::<Vec<Val>, usize>::new();
+    for x in xs {
+        *counts.entry(f(x).collect::<Result<_, _>>()?).or_default() += 1;
+    }
+    let obj = |(mut y, n): (Vec<Val>, usize)| {
+        let key = if y.len() == 1 {
+            y.remove(0)
+        } else {
+            Val::arr(y)
+        };
+        let count = Val::Int(n as isize);
+        let kvs = [("key", key), ("count", count)].into_iter();
+        Val::obj(kvs.map(|(k, v)| (Rc::new(k.to_string()), v)).collect())
+    };
+    Ok(Val::arr(counts.into_iter().map(obj).collect()))
+}
+
+/// Construct an object that maps every output of `f` for a row to the row.
+///
+/// This is equivalent to `reduce rows as $row ({}; .[$row | f | tostring] |= $row)`,
+/// but it does not copy the object for every row.
+fn index<'a>(rows: ValRs<'a>, f: impl Fn(Val) -> ValRs<'a>) -> ValR {
+    let mut kvs = Vec::new();
+    for row in rows {
+        let row = row?;
+        for k in f(row.clone()) {
+            kvs.push((Rc::new(k?.to_string_or_clone()), row.clone()));
+        }
+    }
+    // later rows replace the values of earlier rows with the same key
+    Ok(Val::obj(kvs.into_iter().collect()))
+}
+
+/// Return whether any output of `xs` equals any output of `ys`.
+///
+/// This stores the outputs of `ys` in a set, instead of
+/// running `ys` once for every output of `xs`.
+/// Like `any(xs == ys; .)`, this stops as soon as an equal output is found.
+fn any_in<'a>(xs: ValRs<'a>, mut ys: ValRs<'a>) -> Result<bool, Error> {
+    let mut seen = alloc::collections::BTreeSet::new();
+    for x in xs {
+        let x = x?;
+        if seen.contains(&x) {
+            return Ok(true);
+        }
+        for y in ys.by_ref() {
+            let y = y?;
+            if y == x {
+                return Ok(true);
+            }
+            seen.insert(y);
+        }
+    }
+    Ok(false)
+}
+
 /// Get the minimum or maximum element from an array according to the given function.
 fn cmp_by<'a, R>(xs: Vec<Val>, f: impl Fn(Val) -> ValRs<'a>, replace: R) -> ValR
 where
@@ -146,6 +243,132 @@ where
     Ok(mx)
 }
 
+/// Compute the minimal number of codepoint insertions, deletions, and substitutions
+/// to transform one string into another.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // distances between the processed prefix of `a` and all prefixes of `b`
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let sub = diag + usize::from(ca != *cb);
+            diag = row[j + 1];
+            row[j + 1] = sub.min(row[j] + 1).min(diag + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Break lines of a string on whitespace such that they are at most `width` codepoints long.
+///
+/// Existing line breaks are preserved, and words longer than `width` are not broken.
+fn wordwrap(s: &str, width: usize) -> String {
+    let mut out = String::new();
+    for (i, line) in s.split('\n').enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let mut len = 0;
+        for word in line.split_whitespace() {
+            let wlen = word.chars().count();
+            if len > 0 && len + 1 + wlen > width {
+                out.push('\n');
+                len = 0;
+            } else if len > 0 {
+                out.push(' ');
+                len += 1;
+            }
+            out.push_str(word);
+            len += wlen;
+        }
+    }
+    out
+}
+
+/// Pad a string with `fill` to `width` codepoints, like Python's `str.ljust` & co.
+///
+/// The function `left` takes the total number of padding characters and `width`, and
+/// returns how many padding characters to put left of the string.
+fn justify<'a>(args: Args<'a>, cv: Cv<'a>, left: fn(usize, usize) -> usize) -> ValRs<'a> {
+    let width_fill = args.get(0).cartesian(args.get(1), cv.clone());
+    Box::new(width_fill.map(move |(width, fill)| {
+        let s = cv.1.as_str()?;
+        let width = usize::try_from(width?.as_int()?).unwrap_or(0);
+        let fill = fill?;
+        let mut chars = fill.as_str()?.chars();
+        let fill = match (chars.next(), chars.next()) {
+            (Some(c), None) => c,
+            _ => {
+                return Err(Error::str(format_args!(
+                    "fill must be a single character: {fill}"
+                )))
+            }
+        };
+        let pad = width.saturating_sub(s.chars().count());
+        if pad == 0 {
+            return Ok(cv.1.clone());
+        }
+        let left = left(pad, width);
+        let mut out: String = core::iter::repeat(fill).take(left).collect();
+        out.push_str(s);
+        out.extend(core::iter::repeat(fill).take(pad - left));
+        Ok(Val::str(out))
+    }))
+}
+
+const BYTE_UNITS: [&str; 7] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+/// Format a number of bytes with binary units, such as `1.5 KiB`.
+///
+/// The number is rounded to `prec` decimals, omitting trailing zeros.
+fn humanize_bytes(mut x: f64, prec: usize) -> String {
+    let mut unit = 0;
+    while x.abs() >= 1024.0 && unit + 1 < BYTE_UNITS.len() {
+        x /= 1024.0;
+        unit += 1;
+    }
+    let x = format!("{x:.prec$}");
+    let x = if x.contains('.') {
+        x.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        &x
+    };
+    format!("{x} {}", BYTE_UNITS[unit])
+}
+
+/// Parse a number of bytes with an optional binary or decimal unit, such as `1.5 KiB` or `2 MB`.
+fn parse_bytes(s: &str) -> ValR {
+    let err = || Error::str(format_args!("cannot parse as bytes: {s}"));
+    let s = s.trim();
+    let split = s.find(|c: char| !(c.is_ascii_digit() || "+-.".contains(c)));
+    let (num, unit) = s.split_at(split.unwrap_or(s.len()));
+    let num: f64 = num.parse().map_err(|_| err())?;
+    let unit = unit.trim_start();
+    let mult = if let Some(i) = BYTE_UNITS.iter().position(|u| *u == unit) {
+        1u64 << (10 * i)
+    } else {
+        let si = ["B", "kB", "MB", "GB", "TB", "PB", "EB"];
+        let i = si
+            .iter()
+            .position(|u| *u == unit || (unit == "KB" && *u == "kB"));
+        let i = match (unit, i) {
+            ("", _) => 0,
+            (_, Some(i)) => i,
+            (_, None) => return Err(err()),
+        };
+        1000u64.pow(i as u32)
+    };
+    let y = num * mult as f64;
+    // return integers if possible
+    if y == (y as isize) as f64 {
+        Ok(Val::Int(y as isize))
+    } else {
+        Ok(Val::Float(y))
+    }
+}
+
 /// Convert a string into an array of its Unicode codepoints.
 fn explode(s: &str) -> Result<Vec<Val>, Error> {
     // conversion from u32 to isize may fail on 32-bit systems for high values of c
@@ -158,6 +381,16 @@ fn implode(xs: &[Val]) -> Result<String, Error> {
     xs.iter().map(as_codepoint).collect()
 }
 
+/// Convert an array of UTF-8 bytes into a string.
+fn frombytes(xs: &[Val]) -> Result<String, Error> {
+    let byte = |v: &Val| {
+        let i = v.as_int()?;
+        u8::try_from(i).map_err(|_| Error::str(format_args!("cannot use {i} as byte")))
+    };
+    let bytes = xs.iter().map(byte).collect::<Result<Vec<_>, _>>()?;
+    String::from_utf8(bytes).map_err(Error::str)
+}
+
 /// If the value is an integer representing a valid Unicode codepoint, return it, else fail.
 fn as_codepoint(v: &Val) -> Result<char, Error> {
     let i = v.as_int()?;
@@ -193,11 +426,15 @@ fn range(mut from: ValR, to: Val, by: Val) -> impl Iterator<Item = ValR> {
     })
 }
 
-fn strip<F>(s: &Rc<String>, other: &str, f: F) -> Rc<String>
+/// If both values are strings, strip `other` from `v`, else return `v` unchanged.
+fn strip<F>(v: &Val, other: &Val, f: F) -> Val
 where
     F: for<'a> Fn(&'a str, &str) -> Option<&'a str>,
 {
-    f(s, other).map_or_else(|| s.clone(), |stripped| Rc::new(stripped.into()))
+    match (v, other) {
+        (Val::Str(s), Val::Str(o)) => f(s, o).map_or_else(|| v.clone(), |s| Val::str(s.into())),
+        _ => v.clone(),
+    }
 }
 
 fn to_sh(v: &Val) -> Result<String, Error> {
@@ -224,6 +461,65 @@ fn to_csv(vs: &[Val]) -> Result<String, Error> {
     Ok(vs.iter().map(fr).collect::<Result<Vec<_>, _>>()?.join(","))
 }
 
+/// Split CSV into rows of fields, omitting empty lines.
+///
+/// Fields may be quoted with `"`, in which case `""` stands for `"`.
+fn csv_rows(s: &str) -> Result<Vec<Vec<String>>, Error> {
+    use core::mem::take;
+    let (mut rows, mut row, mut field) = (Vec::new(), Vec::new(), String::new());
+    let mut chars = s.chars().peekable();
+    let mut end_row = |row: &mut Vec<String>, field: &mut String| {
+        row.push(take(field));
+        if !(row.len() == 1 && row[0].is_empty()) {
+            rows.push(take(row));
+        }
+        row.clear();
+    };
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if field.is_empty() => loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => field.push(chars.next().unwrap()),
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                    None => return Err(Error::str("unterminated quoted CSV field")),
+                }
+            },
+            ',' => row.push(take(&mut field)),
+            '\n' => end_row(&mut row, &mut field),
+            '\r' if chars.peek() == Some(&'\n') => (),
+            c => field.push(c),
+        }
+    }
+    end_row(&mut row, &mut field);
+    Ok(rows)
+}
+
+/// Parse CSV with a header row to an array of objects.
+///
+/// Missing fields of a row yield empty strings, and
+/// fields of a row beyond the number of headers are dropped.
+fn from_csv(s: &str) -> ValR {
+    let mut rows = csv_rows(s)?.into_iter();
+    let header: Vec<Rc<String>> = rows
+        .next()
+        .unwrap_or_default()
+        .into_iter()
+        .map(Rc::new)
+        .collect();
+    let obj = |row: Vec<String>| {
+        let mut fields = row.into_iter().map(Val::str);
+        let kvs = header.iter().map(|k| {
+            (
+                k.clone(),
+                fields.next().unwrap_or_else(|| Val::str(String::new())),
+            )
+        });
+        Val::obj(kvs.collect())
+    };
+    Ok(Val::arr(rows.map(obj).collect()))
+}
+
 /// Return the string windows having `n` characters, where `n` > 0.
 ///
 /// Taken from <https://users.rust-lang.org/t/iterator-over-windows-of-chars/17841/3>.
@@ -262,6 +558,14 @@ const CORE_RUN: &[(&str, usize, RunPtr)] = &[
     ("inputs", 0, |_, cv| {
         Box::new(cv.0.inputs().map(|r| r.map_err(Error::str)))
     }),
+    ("input_filename", 0, |_, cv| {
+        let file = cv.0.input_filename();
+        once_with(move || Ok(file.map_or(Val::Null, |f| Val::str(f.to_string()))))
+    }),
+    ("input_line_number", 0, |_, cv| {
+        let line = cv.0.input_line_number().unwrap_or(0);
+        box_once(Ok(Val::Int(line.try_into().unwrap_or(isize::MAX))))
+    }),
     ("length", 0, |_, cv| once_with(move || length(&cv.1))),
     ("keys_unsorted", 0, |_, cv| {
         once_with(move || cv.1.keys_unsorted().map(Val::arr))
@@ -287,6 +591,15 @@ const CORE_RUN: &[(&str, usize, RunPtr)] = &[
     ("implode", 0, |_, cv| {
         once_with(move || cv.1.as_arr().and_then(|a| Ok(Val::str(implode(a)?))))
     }),
+    ("bytes", 0, |_, cv| {
+        once_with(move || {
+            let bytes = cv.1.as_str()?.bytes();
+            Ok(Val::arr(bytes.map(|b| Val::Int(b.into())).collect()))
+        })
+    }),
+    ("frombytes", 0, |_, cv| {
+        once_with(move || cv.1.as_arr().and_then(|a| Ok(Val::str(frombytes(a)?))))
+    }),
     ("ascii_downcase", 0, |_, cv| {
         once_with(move || cv.1.mutate_str(|s| s.make_ascii_lowercase()))
     }),
@@ -296,6 +609,9 @@ const CORE_RUN: &[(&str, usize, RunPtr)] = &[
     ("reverse", 0, |_, cv| {
         once_with(move || cv.1.mutate_arr(|a| a.reverse()))
     }),
+    ("distinct", 0, |_, cv| {
+        once_with(move || cv.1.mutate_arr(distinct))
+    }),
     ("sort", 0, |_, cv| {
         once_with(move || cv.1.mutate_arr(|a| a.sort()))
     }),
@@ -308,6 +624,19 @@ const CORE_RUN: &[(&str, usize, RunPtr)] = &[
         let group = move |arr| group_by(arr, |v| args.get(0).run((cv.0.clone(), v)));
         once_with(move || cv.1.into_arr().map(rc_unwrap_or_clone).and_then(group))
     }),
+    ("group_count_by", 1, |args, cv| {
+        let count = move |arr| group_count_by(arr, |v| args.get(0).run((cv.0.clone(), v)));
+        once_with(move || cv.1.into_arr().map(rc_unwrap_or_clone).and_then(count))
+    }),
+    ("INDEX", 2, |args, cv| {
+        let rows = args.get(0).run(cv.clone());
+        let f = move |v| args.get(1).run((cv.0.clone(), v));
+        once_with(move || index(rows, f))
+    }),
+    ("IN", 2, |args, cv| {
+        let (xs, ys) = (args.get(0).run(cv.clone()), args.get(1).run(cv));
+        once_with(move || any_in(xs, ys).map(Val::Bool))
+    }),
     ("min_by", 1, |args, cv| {
         let f = move |v| args.get(0).run((cv.0.clone(), v));
         let cmp = move |arr| cmp_by(arr, f, |my, y| y < my);
@@ -354,20 +683,49 @@ const CORE_RUN: &[(&str, usize, RunPtr)] = &[
         let keys = args.get(0).run(cv.clone());
         Box::new(keys.map(move |k| Ok(Val::Bool(cv.1.as_str()?.ends_with(&**k?.as_str()?)))))
     }),
-    ("ltrimstr", 1, |args, cv| {
-        Box::new(args.get(0).run(cv.clone()).map(move |pre| {
-            Ok(Val::Str(strip(cv.1.as_str()?, &pre?.to_str()?, |s, o| {
-                s.strip_prefix(o)
-            })))
+    ("levenshtein", 2, |args, cv| {
+        let ab = args.get(0).cartesian(args.get(1), cv);
+        Box::new(ab.map(|(a, b)| {
+            let d = levenshtein(a?.as_str()?, b?.as_str()?);
+            Ok(Val::Int(d as isize))
         }))
     }),
-    ("rtrimstr", 1, |args, cv| {
-        Box::new(args.get(0).run(cv.clone()).map(move |suf| {
-            Ok(Val::Str(strip(cv.1.as_str()?, &suf?.to_str()?, |s, o| {
-                s.strip_suffix(o)
-            })))
+    ("wordwrap", 1, |args, cv| {
+        let widths = args.get(0).run(cv.clone());
+        Box::new(widths.map(move |w| {
+            let w = usize::try_from(w?.as_int()?).map_err(Error::str)?;
+            Ok(Val::str(wordwrap(cv.1.as_str()?, w)))
         }))
     }),
+    ("ljust", 2, |args, cv| justify(args, cv, |_, _| 0)),
+    ("rjust", 2, |args, cv| justify(args, cv, |pad, _| pad)),
+    // this rounds like Python's `str.center`
+    ("center", 2, |args, cv| {
+        justify(args, cv, |pad, width| pad / 2 + (pad & width & 1))
+    }),
+    ("humanize_bytes", 1, |args, cv| {
+        let precs = args.get(0).run(cv.clone());
+        Box::new(precs.map(move |p| {
+            let p = usize::try_from(p?.as_int()?).map_err(Error::str)?;
+            Ok(Val::str(humanize_bytes(cv.1.as_float()?, p)))
+        }))
+    }),
+    ("parse_bytes", 0, |_, cv| {
+        once_with(move || parse_bytes(cv.1.as_str()?))
+    }),
+    ("ltrimstr", 1, |args, cv| {
+        let pres = args.get(0).run(cv.clone());
+        Box::new(pres.map(move |pre| Ok(strip(&cv.1, &pre?, |s, o| s.strip_prefix(o)))))
+    }),
+    ("rtrimstr", 1, |args, cv| {
+        let sufs = args.get(0).run(cv.clone());
+        Box::new(sufs.map(move |suf| Ok(strip(&cv.1, &suf?, |s, o| s.strip_suffix(o)))))
+    }),
+    ("halt", 0, |_, _| box_once(Err(Error::Halt(None, 0)))),
+    ("halt_error", 1, |args, cv| {
+        let codes = args.get(0).run(cv.clone());
+        Box::new(codes.map(move |code| Err(Error::Halt(Some(cv.1.clone()), code?.as_int()?))))
+    }),
     ("@text", 0, |_, cv| {
         once_with(move || Ok(Val::str(cv.1.to_string_or_clone())))
     }),
@@ -388,6 +746,9 @@ const CORE_RUN: &[(&str, usize, RunPtr)] = &[
     ("@csv", 0, |_, cv| {
         once_with(move || cv.1.as_arr().and_then(|a| to_csv(a)).map(Val::str))
     }),
+    ("from_csv", 0, |_, cv| {
+        once_with(move || cv.1.as_str().and_then(|s| from_csv(s)))
+    }),
 ];
 
 #[cfg(feature = "std")]
@@ -399,11 +760,41 @@ fn now() -> Result<f64, Error> {
         .map_err(Error::str)
 }
 
+/// Convert environment variables to an object, such as yielded by `env`.
+///
+/// Names and values that are not valid UTF-8 are converted lossily.
+///
+/// The native `env` filter yields the environment given by `Ctx::with_env`, or
+/// the environment of the current process if no environment was given.
+/// To provide `$ENV` with the same environment, such as an empty one in a sandbox,
+/// bind the output of this function to a global variable `$ENV`.
+#[cfg(feature = "std")]
+pub fn env_obj<I>(vars: I) -> Val
+where
+    I: IntoIterator<Item = (std::ffi::OsString, std::ffi::OsString)>,
+{
+    let lossy = |s: std::ffi::OsString| s.to_string_lossy().into_owned();
+    let vars = vars.into_iter();
+    Val::obj(
+        vars.map(|(k, v)| (Rc::new(lossy(k)), Val::str(lossy(v))))
+            .collect(),
+    )
+}
+
 #[cfg(feature = "std")]
 const STD: &[(&str, usize, RunPtr)] = &[
-    ("env", 0, |_, _| {
-        let vars = std::env::vars().map(|(k, v)| (Rc::new(k), Val::str(v)));
-        once_with(|| Ok(Val::obj(vars.collect())))
+    ("env", 0, |_, cv| {
+        let env = cv.0.env();
+        once_with(move || {
+            Ok(match env {
+                Some(env) => Val::obj(
+                    env.iter()
+                        .map(|(k, v)| (Rc::new(k.clone()), Val::str(v.clone())))
+                        .collect(),
+                ),
+                None => env_obj(std::env::vars_os()),
+            })
+        })
     }),
     ("now", 0, |_, _| once_with(|| now().map(Val::Float))),
 ];
@@ -418,10 +809,35 @@ fn from_json(s: &str) -> ValR {
         .map_err(|e| Error::str(format_args!("cannot parse {s} as JSON: {e}")))
 }
 
+/// Parse newline-delimited JSON, yielding one value per non-blank line.
 #[cfg(feature = "parse_json")]
-const PARSE_JSON: &[(&str, usize, RunPtr)] = &[("fromjson", 0, |_, cv| {
-    once_with(move || cv.1.as_str().and_then(|s| from_json(s)))
-})];
+fn from_ndjson(s: Rc<String>) -> impl Iterator<Item = ValR> {
+    use hifijson::token::Lex;
+    let (mut pos, mut line) = (0, 0);
+    core::iter::from_fn(move || loop {
+        let rest = s.get(pos..).filter(|rest| !rest.is_empty())?;
+        let len = rest.find('\n').map_or(rest.len(), |i| i + 1);
+        pos += len;
+        line += 1;
+        let l = &rest[..len];
+        if l.trim().is_empty() {
+            continue;
+        }
+        let mut lexer = hifijson::SliceLexer::new(l.as_bytes());
+        let fail = |e| Error::str(format_args!("cannot parse line {line} as JSON: {e}"));
+        return Some(lexer.exactly_one(Val::parse).map_err(fail));
+    })
+}
+
+#[cfg(feature = "parse_json")]
+const PARSE_JSON: &[(&str, usize, RunPtr)] = &[
+    ("fromjson", 0, |_, cv| {
+        once_with(move || cv.1.as_str().and_then(|s| from_json(s)))
+    }),
+    ("from_ndjson", 0, |_, cv| {
+        then(cv.1.as_str(), |s| Box::new(from_ndjson(s.clone())))
+    }),
+];
 
 #[cfg(feature = "format")]
 fn replace(s: &str, patterns: &[&str], replacements: &[&str]) -> String {
@@ -455,20 +871,29 @@ const FORMAT: &[(&str, usize, RunPtr)] = &[
         once_with(move || Ok(Val::str(STANDARD.encode(cv.1.to_string_or_clone()))))
     }),
     ("@base64d", 0, |_, cv| {
-        use base64::{engine::general_purpose::STANDARD, Engine};
+        use base64::engine::{general_purpose, DecodePaddingMode, GeneralPurpose};
+        use base64::{alphabet, Engine};
+        // like jq, accept input with or without padding
+        let config = general_purpose::PAD.with_decode_padding_mode(DecodePaddingMode::Indifferent);
+        let engine = GeneralPurpose::new(&alphabet::STANDARD, config);
         once_with(move || {
-            STANDARD
-                .decode(cv.1.to_string_or_clone())
+            let d = engine.decode(cv.1.to_string_or_clone());
+            // like jq, replace invalid UTF-8 sequences
+            d.map(|d| Val::str(String::from_utf8_lossy(&d).into_owned()))
                 .map_err(Error::str)
-                .and_then(|d| {
-                    std::str::from_utf8(&d)
-                        .map_err(Error::str)
-                        .map(|s| Val::str(s.to_owned()))
-                })
         })
     }),
 ];
 
+#[cfg(feature = "jsonpath")]
+const JSONPATH: &[(&str, usize, RunPtr)] = &[("jsonpath", 1, |args, cv| {
+    let paths = args.get(0).run(cv.clone());
+    Box::new(paths.flat_map(move |path| {
+        let vs = path.and_then(|p| jsonpath::query(p.as_str()?, &cv.1));
+        then(vs, |vs| Box::new(vs.into_iter().map(Ok)))
+    }))
+})];
+
 #[cfg(feature = "math")]
 const MATH: &[(&str, usize, RunPtr)] = &[
     math::f_f!(acos),
@@ -531,7 +956,6 @@ const MATH: &[(&str, usize, RunPtr)] = &[
     math::fff_f!(fma),
 ];
 
-#[cfg(feature = "regex")]
 type Cv<'a> = (jaq_interpret::Ctx<'a>, Val);
 
 #[cfg(feature = "regex")]
@@ -539,10 +963,15 @@ fn re<'a, F: FilterT<'a>>(re: F, flags: F, s: bool, m: bool, cv: Cv<'a>) -> ValR
     let re_flags = re.cartesian(flags, (cv.0, cv.1.clone()));
 
     Box::new(re_flags.map(move |(re, flags)| {
+        // like in jq, `null` flags are the same as no flags
+        let flags = match flags? {
+            Val::Null => Val::str(String::new()),
+            flags => flags,
+        };
         Ok(Val::arr(regex::regex(
             cv.1.as_str()?,
             re?.as_str()?,
-            flags?.as_str()?,
+            flags.as_str()?,
             (s, m),
         )?))
     }))
@@ -569,8 +998,86 @@ const TIME: &[(&str, usize, RunPtr)] = &[
     ("todateiso8601", 0, |_, cv| {
         once_with(move || time::to_iso8601(&cv.1).map(Val::str))
     }),
+    ("day_of_week", 0, |_, cv| {
+        let wday = move |d: ::time::Date| Val::Int(d.weekday().number_days_from_sunday().into());
+        once_with(move || time::to_date(&cv.1).map(wday))
+    }),
+    ("week_of_year", 0, |_, cv| {
+        once_with(move || time::to_date(&cv.1).map(|d| Val::Int(d.iso_week().into())))
+    }),
+    ("is_leap_year", 0, |_, cv| {
+        let leap = |d: ::time::Date| Val::Bool(::time::util::is_leap_year(d.year()));
+        once_with(move || time::to_date(&cv.1).map(leap))
+    }),
+    ("gmtime", 0, |_, cv| once_with(move || time::gmtime(&cv.1))),
+    ("localtime", 0, |_, cv| {
+        once_with(move || time::localtime(&cv.1))
+    }),
+    ("mktime", 0, |_, cv| once_with(move || time::mktime(&cv.1))),
+    ("strftime", 1, |args, cv| {
+        let fmts = args.get(0).run(cv.clone());
+        let f = move |fmt: ValR| time::strftime(&cv.1, &fmt?.to_str()?, false).map(Val::str);
+        Box::new(fmts.map(f))
+    }),
+    ("strflocaltime", 1, |args, cv| {
+        let fmts = args.get(0).run(cv.clone());
+        let f = move |fmt: ValR| time::strftime(&cv.1, &fmt?.to_str()?, true).map(Val::str);
+        Box::new(fmts.map(f))
+    }),
+    ("strptime", 1, |args, cv| {
+        let fmts = args.get(0).run(cv.clone());
+        let f = move |fmt: ValR| time::strptime(cv.1.as_str()?, &fmt?.to_str()?);
+        Box::new(fmts.map(f))
+    }),
 ];
 
+#[cfg(feature = "unicode")]
+const UNICODE: &[(&str, usize, RunPtr)] = &[
+    ("nfc", 0, |_, cv| {
+        once_with(move || normalize(&cv.1, |s| s.nfc().collect()))
+    }),
+    ("nfd", 0, |_, cv| {
+        once_with(move || normalize(&cv.1, |s| s.nfd().collect()))
+    }),
+    ("nfkc", 0, |_, cv| {
+        once_with(move || normalize(&cv.1, |s| s.nfkc().collect()))
+    }),
+    ("nfkd", 0, |_, cv| {
+        once_with(move || normalize(&cv.1, |s| s.nfkd().collect()))
+    }),
+];
+
+/// Apply a Unicode normalization form to a string.
+#[cfg(feature = "unicode")]
+fn normalize(v: &Val, f: impl FnOnce(&str) -> String) -> ValR {
+    Ok(Val::str(f(v.as_str()?)))
+}
+
+#[cfg(feature = "cbor")]
+const CBOR: &[(&str, usize, RunPtr)] = &[
+    ("@cbor", 0, |_, cv| {
+        box_once(Ok(Val::str(cbor::encode(&cv.1))))
+    }),
+    ("fromcbor", 0, |_, cv| {
+        once_with(move || cbor::decode(cv.1.as_str()?))
+    }),
+];
+
+#[cfg(feature = "msgpack")]
+const MSGPACK: &[(&str, usize, RunPtr)] = &[
+    ("@msgpack", 0, |_, cv| {
+        box_once(msgpack::encode(&cv.1).map(Val::str))
+    }),
+    ("frommsgpack", 0, |_, cv| {
+        once_with(move || msgpack::decode(cv.1.as_str()?))
+    }),
+];
+
+#[cfg(feature = "xml")]
+const XML: &[(&str, usize, RunPtr)] = &[("fromxml", 0, |_, cv| {
+    once_with(move || cv.1.as_str().and_then(|s| xml::parse(s)))
+})];
+
 const CORE_UPDATE: &[(&str, usize, RunPtr, UpdatePtr)] = &[
     (
         "empty",
@@ -586,16 +1093,29 @@ const CORE_UPDATE: &[(&str, usize, RunPtr, UpdatePtr)] = &[
     ),
 ];
 
+/// Send a diagnostic about a value to the context, logging it if the context does not handle it.
 #[cfg(feature = "log")]
-fn debug<T: core::fmt::Display>(x: T) -> T {
-    log::debug!("{}", x);
-    x
+fn diagnose(ctx: &jaq_interpret::Ctx, v: Val, d: fn(Val) -> Diagnostic) -> Val {
+    match ctx.diagnose(d(v.clone())) {
+        Some(Diagnostic::Debug(v)) => log::debug!("{}", v),
+        Some(Diagnostic::Stderr(v)) => log::debug!("{}", v.to_string_or_clone()),
+        None => (),
+    }
+    v
 }
 
 #[cfg(feature = "log")]
-const LOG: &[(&str, usize, RunPtr, UpdatePtr)] = &[(
-    "debug",
-    0,
-    |_, cv| once_with(move || Ok(debug(cv.1))),
-    |_, cv, f| f(debug(cv.1)),
-)];
+const LOG: &[(&str, usize, RunPtr, UpdatePtr)] = &[
+    (
+        "debug",
+        0,
+        |_, cv| once_with(move || Ok(diagnose(&cv.0, cv.1, Diagnostic::Debug))),
+        |_, cv, f| f(diagnose(&cv.0, cv.1, Diagnostic::Debug)),
+    ),
+    (
+        "stderr",
+        0,
+        |_, cv| once_with(move || Ok(diagnose(&cv.0, cv.1, Diagnostic::Stderr))),
+        |_, cv, f| f(diagnose(&cv.0, cv.1, Diagnostic::Stderr)),
+    ),
+];
@@ -0,0 +1,216 @@
+//! Encoding and decoding of MessagePack.
+//!
+//! Like for CBOR, binary data is represented by
+//! strings whose characters are the bytes of the data.
+
+use crate::binary::{self, be, take};
+use alloc::{string::String, vec::Vec};
+use jaq_interpret::{Error, Val, ValR};
+
+const NAME: &str = "MessagePack";
+
+/// Encode a value to a string containing the bytes of its MessagePack representation.
+///
+/// Fail on integers that are too large for MessagePack, see [`encode_into`].
+pub fn encode(v: &Val) -> Result<String, Error> {
+    let mut out = Vec::new();
+    encode_into(v, &mut out)?;
+    Ok(binary::to_str(&out))
+}
+
+/// Decode a string containing the bytes of a MessagePack representation to a value.
+///
+/// Strings are decoded to strings, and binary data and the data of extension types
+/// are decoded to strings with one character per byte, like the input of this function.
+/// Because such strings are encoded as strings,
+/// decoding and encoding binary data does not yield the original data.
+pub fn decode(s: &str) -> ValR {
+    binary::decode(NAME, s, decode_from)
+}
+
+fn err(e: impl core::fmt::Display) -> Error {
+    binary::err(NAME, e)
+}
+
+/// Write the header of a string, array, or map.
+///
+/// `fix` is the first byte of the format with the length in its lower bits,
+/// `max` is the largest length that fits into these bits, and
+/// `tags` are the first bytes of the formats with 8-bit, 16-bit, and 32-bit lengths.
+fn head(len: usize, (fix, max): (u8, usize), tags: [Option<u8>; 3], out: &mut Vec<u8>) {
+    match (len, tags) {
+        (0..=0x1f, _) if len <= max => out.push(fix | len as u8),
+        (0..=0xff, [Some(tag), ..]) => out.extend([tag, len as u8]),
+        (0..=0xffff, [_, Some(tag), _]) => {
+            out.push(tag);
+            out.extend((len as u16).to_be_bytes())
+        }
+        (_, [.., Some(tag)]) => {
+            out.push(tag);
+            out.extend((len as u32).to_be_bytes())
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn encode_str(s: &str, out: &mut Vec<u8>) {
+    head(
+        s.len(),
+        (0xa0, 31),
+        [Some(0xd9), Some(0xda), Some(0xdb)],
+        out,
+    );
+    out.extend(s.bytes())
+}
+
+fn encode_int(i: i64, out: &mut Vec<u8>) {
+    match i {
+        -32..=0x7f => out.push(i as u8),
+        0x80..=0xff => out.extend([0xcc, i as u8]),
+        0x100..=0xffff => {
+            out.push(0xcd);
+            out.extend((i as u16).to_be_bytes())
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(0xce);
+            out.extend((i as u32).to_be_bytes())
+        }
+        -0x80..=-33 => out.extend([0xd0, i as u8]),
+        -0x8000..=-0x81 => {
+            out.push(0xd1);
+            out.extend((i as i16).to_be_bytes())
+        }
+        -0x8000_0000..=-0x8001 => {
+            out.push(0xd2);
+            out.extend((i as i32).to_be_bytes())
+        }
+        _ if i > 0 => {
+            out.push(0xcf);
+            out.extend(i.to_be_bytes())
+        }
+        _ => {
+            out.push(0xd3);
+            out.extend(i.to_be_bytes())
+        }
+    }
+}
+
+fn encode_float(f: f64, out: &mut Vec<u8>) {
+    out.push(0xcb);
+    out.extend(f.to_be_bytes())
+}
+
+/// Append the MessagePack representation of a value to the given bytes.
+///
+/// Fail on integers that fit neither into `i64` nor into `u64`,
+/// because MessagePack has no larger integers.
+pub fn encode_into(v: &Val, out: &mut Vec<u8>) -> Result<(), Error> {
+    match v {
+        Val::Null => out.push(0xc0),
+        Val::Bool(b) => out.push(if *b { 0xc3 } else { 0xc2 }),
+        Val::Int(i) => encode_int(*i as i64, out),
+        Val::Float(f) => encode_float(*f, out),
+        Val::BigInt(i) => match (i64::try_from(&**i), u64::try_from(&**i)) {
+            (Ok(i), _) => encode_int(i, out),
+            (_, Ok(u)) => {
+                out.push(0xcf);
+                out.extend(u.to_be_bytes())
+            }
+            _ => {
+                let e = format_args!("cannot encode MessagePack: integer out of range: {i}");
+                return Err(Error::str(e));
+            }
+        },
+        Val::Num(n) => encode_into(&Val::from_dec_str(n), out)?,
+        Val::Str(s) => encode_str(s, out),
+        Val::Arr(a) => {
+            head(a.len(), (0x90, 15), [None, Some(0xdc), Some(0xdd)], out);
+            a.iter().try_for_each(|x| encode_into(x, out))?
+        }
+        Val::Obj(o) => {
+            head(o.len(), (0x80, 15), [None, Some(0xde), Some(0xdf)], out);
+            for (k, v) in o.iter() {
+                encode_str(k, out);
+                encode_into(v, out)?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read a big-endian length with the given number of bytes.
+fn len(n: usize, bytes: &mut &[u8]) -> Result<usize, Error> {
+    usize::try_from(be(take(NAME, bytes, n)?)).map_err(err)
+}
+
+fn int(i: i128) -> Val {
+    Val::big(i.into())
+}
+
+fn str(bytes: &[u8]) -> ValR {
+    Ok(Val::str(String::from_utf8(bytes.to_vec()).map_err(err)?))
+}
+
+fn arr(n: usize, bytes: &mut &[u8]) -> ValR {
+    let items = (0..n)
+        .map(|_| decode_from(bytes))
+        .collect::<Result<_, _>>()?;
+    Ok(Val::arr(items))
+}
+
+fn obj(n: usize, bytes: &mut &[u8]) -> ValR {
+    let kv = |bytes: &mut &[u8]| match decode_from(bytes)? {
+        Val::Str(k) => Ok((k, decode_from(bytes)?)),
+        k => Err(err(format_args!("map key is not a string: {k}"))),
+    };
+    let items = (0..n).map(|_| kv(bytes)).collect::<Result<_, _>>()?;
+    Ok(Val::obj(items))
+}
+
+/// Decode the MessagePack value at the start of the given bytes,
+/// advancing them past the value.
+pub fn decode_from(bytes: &mut &[u8]) -> ValR {
+    let initial = take(NAME, bytes, 1)?[0];
+    let fix = usize::from(initial & 0x1f);
+    Ok(match initial {
+        0x00..=0x7f => Val::Int(initial.into()),
+        0x80..=0x8f => obj(fix & 0xf, bytes)?,
+        0x90..=0x9f => arr(fix & 0xf, bytes)?,
+        0xa0..=0xbf => str(take(NAME, bytes, fix)?)?,
+        0xc0 => Val::Null,
+        0xc2 => Val::Bool(false),
+        0xc3 => Val::Bool(true),
+        0xc4..=0xc6 => {
+            let n = len(1 << (initial - 0xc4), bytes)?;
+            binary::str_val(take(NAME, bytes, n)?)
+        }
+        0xc7..=0xc9 => {
+            let n = len(1 << (initial - 0xc7), bytes)?;
+            // the extension type is ignored
+            let _ty = take(NAME, bytes, 1)?;
+            binary::str_val(take(NAME, bytes, n)?)
+        }
+        0xca => Val::Float(f32::from_bits(be(take(NAME, bytes, 4)?) as u32).into()),
+        0xcb => Val::Float(f64::from_bits(be(take(NAME, bytes, 8)?))),
+        0xcc..=0xcf => int(be(take(NAME, bytes, 1 << (initial - 0xcc))?).into()),
+        0xd0..=0xd3 => {
+            let n = 1 << (initial - 0xd0);
+            let u = be(take(NAME, bytes, n)?);
+            // sign-extend the integer
+            let shift = 64 - 8 * n;
+            int((((u << shift) as i64) >> shift).into())
+        }
+        0xd4..=0xd8 => {
+            let _ty = take(NAME, bytes, 1)?;
+            binary::str_val(take(NAME, bytes, 1 << (initial - 0xd4))?)
+        }
+        0xd9..=0xdb => {
+            let n = len(1 << (initial - 0xd9), bytes)?;
+            str(take(NAME, bytes, n)?)?
+        }
+        0xdc | 0xdd => arr(len(2 << (initial - 0xdc), bytes)?, bytes)?,
+        0xde | 0xdf => obj(len(2 << (initial - 0xde), bytes)?, bytes)?,
+        0xe0..=0xff => Val::Int((initial as i8).into()),
+        0xc1 => return Err(err("invalid byte 0xc1")),
+    })
+}
@@ -52,7 +52,7 @@ impl Flags {
         self.g
     }
 
-    fn impact<'a>(&'a self, builder: &'a mut regex::RegexBuilder) -> &mut regex::RegexBuilder {
+    fn impact<'a>(&'a self, builder: &'a mut regex::RegexBuilder) -> &'a mut regex::RegexBuilder {
         builder
             .case_insensitive(self.i)
             .multi_line(self.m)
@@ -69,6 +69,7 @@ impl Flags {
 
 /// Mapping between byte and character indices.
 pub struct ByteChar<'a> {
+    s: &'a str,
     prev_byte: usize,
     prev_char: usize,
     rest: core::str::CharIndices<'a>,
@@ -80,6 +81,7 @@ impl<'a> ByteChar<'a> {
         // skip the first one, because it is already taken into account
         ci.next();
         Self {
+            s,
             prev_byte: 0,
             prev_char: 0,
             rest: ci,
@@ -88,9 +90,13 @@ impl<'a> ByteChar<'a> {
 
     /// Convert byte offset to UTF-8 character offset.
     ///
-    /// This needs to be called with monotonically increasing values of `byte_offset`.
+    /// This is fast when called with monotonically increasing values of `byte_offset`.
+    /// That is not always the case, because in `(?:(a)|(b))+`,
+    /// the second group may precede the first one.
     fn char_of_byte(&mut self, byte_offset: usize) -> usize {
-        assert!(self.prev_byte <= byte_offset);
+        if byte_offset < self.prev_byte {
+            return self.s[..byte_offset].chars().count();
+        }
         if self.prev_byte != byte_offset {
             self.prev_byte = byte_offset;
             self.prev_char += 1 + self.rest.position(|(p, _)| p == byte_offset).unwrap();
@@ -103,30 +109,52 @@ pub struct Match {
     pub offset: usize,
     pub length: usize,
     pub string: String,
-    pub name: Option<String>,
 }
 
 impl Match {
-    pub fn new<'a>(bc: &mut ByteChar, m: regex::Match<'a>, name: Option<&'a str>) -> Self {
+    pub fn new(bc: &mut ByteChar, m: regex::Match) -> Self {
         Self {
             offset: bc.char_of_byte(m.start()),
             length: m.as_str().chars().count(),
             string: m.as_str().to_string(),
-            name: name.map(|s| s.to_string()),
         }
     }
+
+    /// Convert a capture group to a value, like jq does.
+    ///
+    /// Groups without name have the name `null`, and
+    /// groups that did not participate in the match have
+    /// the offset -1 and the string `null`.
+    fn group(m: Option<Self>, name: Option<&str>) -> Val {
+        let (offset, length, string) = match m {
+            Some(m) => (m.offset as isize, m.length, Val::str(m.string)),
+            None => (-1, 0, Val::Null),
+        };
+        let name = name.map_or(Val::Null, |name| Val::str(name.to_string()));
+        obj([
+            ("offset", Val::Int(offset)),
+            ("length", Val::Int(length as isize)),
+            ("string", string),
+            ("name", name),
+        ])
+    }
+}
+
+fn obj<const N: usize>(kvs: [(&str, Val); N]) -> Val {
+    Val::obj(
+        kvs.into_iter()
+            .map(|(k, v)| (Rc::new(k.to_string()), v))
+            .collect(),
+    )
 }
 
 impl From<Match> for Val {
     fn from(m: crate::regex::Match) -> Self {
-        let obj = [
+        obj([
             ("offset", Self::Int(m.offset as isize)),
             ("length", Self::Int(m.length as isize)),
             ("string", Self::str(m.string)),
-            ("name", m.name.map(Self::str).unwrap_or(Self::Null)),
-        ];
-        let obj = obj.into_iter().filter(|(_, v)| *v != Self::Null);
-        Self::obj(obj.map(|(k, v)| (Rc::new(k.to_string()), v)).collect())
+        ])
     }
 }
 
@@ -151,11 +179,10 @@ pub fn regex(s: &str, re: &str, flags: &str, sm: (bool, bool)) -> Result<Vec<Val
         if whole.start() >= s.len() || (flags.ignore_empty() && whole.as_str().is_empty()) {
             continue;
         }
-        let vs = c
-            .iter()
-            .zip(re.capture_names())
-            .filter_map(|(match_, name)| Some(Match::new(&mut bc, match_?, name)))
-            .map(Val::from);
+        let first = Val::from(Match::new(&mut bc, whole));
+        let groups = c.iter().zip(re.capture_names()).skip(1);
+        let groups = groups.map(|(m, name)| Match::group(m.map(|m| Match::new(&mut bc, m)), name));
+        let vs = core::iter::once(first).chain(groups);
         if split {
             out.push(Val::str(s[last_byte..whole.start()].to_string()));
             last_byte = whole.end();
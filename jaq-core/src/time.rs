@@ -1,5 +1,5 @@
 use alloc::string::{String, ToString};
-use jaq_interpret::{Error, Val, ValR};
+use jaq_interpret::{error::Type, Error, Val, ValR};
 
 /// Parse an ISO-8601 timestamp string to a number holding the equivalent UNIX timestamp
 /// (seconds elapsed since 1970/01/01).
@@ -19,7 +19,7 @@ pub fn from_iso8601(s: &str) -> ValR {
     }
 }
 
-/// Format a number as an ISO-8601 timestamp string.
+/// Format a number or broken-down time as an ISO-8601 timestamp string.
 pub fn to_iso8601(v: &Val) -> Result<String, Error> {
     use time::format_description::well_known::iso8601;
     use time::OffsetDateTime;
@@ -48,6 +48,691 @@ pub fn to_iso8601(v: &Val) -> Result<String, Error> {
                 .format(&iso8601_fmt_s)
                 .map_err(fail2)
         }
-        _ => todo!(),
+        Val::BigInt(_) => to_iso8601(&Val::Float(v.as_float()?)),
+        // broken-down time, such as yielded by `gmtime`
+        Val::Arr(_) => strftime(v, "%Y-%m-%dT%H:%M:%SZ", false),
+        _ => Err(Error::Type(v.clone(), Type::Num)),
+    }
+}
+
+/// Convert seconds since the epoch or broken-down time to a date.
+///
+/// Broken-down time is an array starting with
+/// the year, the month (starting from 0), and the day of the month (starting from 1).
+pub fn to_date(v: &Val) -> Result<time::Date, Error> {
+    use time::{Date, Month, OffsetDateTime};
+    let fail =
+        |e: &dyn core::fmt::Display| Error::str(format_args!("cannot convert {v} to date: {e}"));
+    match v {
+        Val::Arr(a) => {
+            let get = |i: usize| a.get(i).unwrap_or(&Val::Null).as_int();
+            let month = u8::try_from(get(1)? + 1).map_err(|e| fail(&e))?;
+            let month = Month::try_from(month).map_err(|e| fail(&e))?;
+            let (year, day) = (get(0)?, get(2)?);
+            let year = i32::try_from(year).map_err(|e| fail(&e))?;
+            let day = u8::try_from(day).map_err(|e| fail(&e))?;
+            Date::from_calendar_date(year, month, day).map_err(|e| fail(&e))
+        }
+        _ => {
+            let secs = v.as_float()?;
+            // round towards negative infinity, also for negative timestamps
+            let floor = secs as i64 - i64::from(secs < (secs as i64) as f64);
+            let datetime = OffsetDateTime::from_unix_timestamp(floor).map_err(|e| fail(&e))?;
+            Ok(datetime.date())
+        }
+    }
+}
+
+const DAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+fn int(i: i64) -> Val {
+    isize::try_from(i)
+        .map(Val::Int)
+        .unwrap_or_else(|_| Val::Num(i.to_string().into()))
+}
+
+/// Convert seconds since the epoch to a UTC date,
+/// returning also the fractional part of the seconds.
+fn from_secs(v: &Val) -> Result<(time::OffsetDateTime, f64), Error> {
+    let secs = v.as_float()?;
+    let floor = secs.floor();
+    time::OffsetDateTime::from_unix_timestamp(floor as i64)
+        .map(|dt| (dt, secs - floor))
+        .map_err(|e| Error::str(format_args!("cannot convert {v} to date: {e}")))
+}
+
+/// Convert a date to broken-down time.
+///
+/// Like in jq, broken-down time is an array consisting of
+/// the year, the month (starting from 0), the day of the month (starting from 1),
+/// the hours, the minutes, the seconds, the day of the week (starting from Sunday = 0), and
+/// the day of the year (starting from 0).
+fn to_arr(dt: time::OffsetDateTime, frac: f64) -> Val {
+    let secs = if frac == 0.0 {
+        Val::Int(dt.second().into())
+    } else {
+        Val::Float(f64::from(dt.second()) + frac)
+    };
+    Val::arr(alloc::vec![
+        int(dt.year().into()),
+        Val::Int(isize::from(u8::from(dt.month())) - 1),
+        Val::Int(dt.day().into()),
+        Val::Int(dt.hour().into()),
+        Val::Int(dt.minute().into()),
+        secs,
+        Val::Int(dt.weekday().number_days_from_sunday().into()),
+        int(i64::from(dt.ordinal()) - 1),
+    ])
+}
+
+/// Convert broken-down time to a UTC date,
+/// returning also the fractional part of the seconds.
+///
+/// Like `timegm` in C, this ignores the day of the week and the day of the year and
+/// normalises values outside their usual ranges, such as the 32nd of January.
+fn from_arr(v: &Val) -> Result<(time::OffsetDateTime, f64), Error> {
+    use time::{Date, Month, OffsetDateTime};
+    let fail = |e: &dyn core::fmt::Display| {
+        Error::str(format_args!(
+            "cannot convert {v} from broken-down time: {e}"
+        ))
+    };
+    let a = v.as_arr()?;
+    if a.len() < 6 {
+        return Err(fail(&"expected at least 6 elements"));
+    }
+    let get = |i: usize| a[i].as_float().map(f64::trunc);
+    let (year, month) = (get(0)?, get(1)?);
+    let year = year + (month / 12.0).floor();
+    let month = month.rem_euclid(12.0) as u8 + 1;
+    let month = Month::try_from(month).map_err(|e| fail(&e))?;
+    let year = i32::try_from(year as i64).map_err(|e| fail(&e))?;
+    let date = Date::from_calendar_date(year, month, 1).map_err(|e| fail(&e))?;
+
+    let secs = a[5].as_float()?;
+    let offset = (get(2)? - 1.0) * 86400.0 + get(3)? * 3600.0 + get(4)? * 60.0 + secs.floor();
+    let ts = date.midnight().assume_utc().unix_timestamp();
+    let ts = ts
+        .checked_add(offset as i64)
+        .ok_or_else(|| fail(&"overflow"))?;
+    let dt = OffsetDateTime::from_unix_timestamp(ts).map_err(|e| fail(&e))?;
+    Ok((dt, secs - secs.floor()))
+}
+
+/// Convert seconds since the epoch to broken-down UTC time.
+pub fn gmtime(v: &Val) -> ValR {
+    from_secs(v).map(|(dt, frac)| to_arr(dt, frac))
+}
+
+/// Convert seconds since the epoch to broken-down local time.
+pub fn localtime(v: &Val) -> ValR {
+    let (dt, frac) = from_secs(v)?;
+    let (offset, _zone) = local(dt.unix_timestamp())?;
+    Ok(to_arr(dt.to_offset(offset), frac))
+}
+
+/// Convert broken-down UTC time to seconds since the epoch.
+pub fn mktime(v: &Val) -> ValR {
+    from_arr(v).map(|(dt, _)| int(dt.unix_timestamp()))
+}
+
+/// Format seconds since the epoch or broken-down time with a `strftime`-like format string.
+///
+/// If `local` is true, then the time is formatted in the local time zone, else in UTC.
+pub fn strftime(v: &Val, fmt: &str, local: bool) -> Result<String, Error> {
+    let (mut dt, _) = match v {
+        Val::Arr(_) => from_arr(v)?,
+        _ => from_secs(v)?,
+    };
+    let mut zone = "UTC".to_string();
+    if local {
+        let (offset, z) = self::local(dt.unix_timestamp())?;
+        zone = z;
+        dt = match v {
+            // broken-down time is already in local time
+            Val::Arr(_) => dt.replace_offset(offset),
+            _ => dt.to_offset(offset),
+        };
+    }
+    let mut out = String::new();
+    format(dt, &zone, fmt, &mut out)
+        .ok_or_else(|| Error::str(format_args!("invalid strftime format: {fmt}")))?;
+    Ok(out)
+}
+
+/// Return the expansion of a composite conversion specification, such as `%T`.
+fn composite(c: char) -> Option<&'static str> {
+    Some(match c {
+        'c' => "%a %b %e %H:%M:%S %Y",
+        'D' | 'x' => "%m/%d/%y",
+        'F' => "%Y-%m-%d",
+        'r' => "%I:%M:%S %p",
+        'R' => "%H:%M",
+        'T' | 'X' => "%H:%M:%S",
+        _ => return None,
+    })
+}
+
+fn format(dt: time::OffsetDateTime, zone: &str, fmt: &str, out: &mut String) -> Option<()> {
+    use core::fmt::Write;
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let c = chars.next()?;
+        if let Some(fmt) = composite(c) {
+            format(dt, zone, fmt, out)?;
+            continue;
+        }
+        let (wday, yday) = (dt.weekday().number_days_from_sunday(), dt.ordinal() - 1);
+        let day = DAYS[usize::from(wday)];
+        let month = MONTHS[usize::from(u8::from(dt.month())) - 1];
+        let hour12 = (dt.hour() + 11) % 12 + 1;
+        let (iso_year, iso_week, _) = dt.to_iso_week_date();
+        let offset = dt.offset();
+        let r = match c {
+            'a' => write!(out, "{}", &day[..3]),
+            'A' => write!(out, "{day}"),
+            'b' | 'h' => write!(out, "{}", &month[..3]),
+            'B' => write!(out, "{month}"),
+            'C' => write!(out, "{:02}", dt.year().div_euclid(100)),
+            'd' => write!(out, "{:02}", dt.day()),
+            'e' => write!(out, "{:2}", dt.day()),
+            'g' => write!(out, "{:02}", iso_year.rem_euclid(100)),
+            'G' => write!(out, "{iso_year}"),
+            'H' => write!(out, "{:02}", dt.hour()),
+            'I' => write!(out, "{hour12:02}"),
+            'j' => write!(out, "{:03}", dt.ordinal()),
+            'k' => write!(out, "{:2}", dt.hour()),
+            'l' => write!(out, "{hour12:2}"),
+            'm' => write!(out, "{:02}", u8::from(dt.month())),
+            'M' => write!(out, "{:02}", dt.minute()),
+            'n' => writeln!(out),
+            'p' => write!(out, "{}", if dt.hour() < 12 { "AM" } else { "PM" }),
+            'P' => write!(out, "{}", if dt.hour() < 12 { "am" } else { "pm" }),
+            's' => write!(out, "{}", dt.unix_timestamp()),
+            'S' => write!(out, "{:02}", dt.second()),
+            't' => write!(out, "\t"),
+            'u' => write!(out, "{}", dt.weekday().number_from_monday()),
+            'U' => write!(out, "{:02}", (yday + 7 - u16::from(wday)) / 7),
+            'V' => write!(out, "{iso_week:02}"),
+            'w' => write!(out, "{wday}"),
+            'W' => write!(out, "{:02}", (yday + 7 - u16::from((wday + 6) % 7)) / 7),
+            'y' => write!(out, "{:02}", dt.year().rem_euclid(100)),
+            'Y' => write!(out, "{}", dt.year()),
+            'z' => {
+                let sign = if offset.is_negative() { '-' } else { '+' };
+                let (h, m) = (offset.whole_hours(), offset.minutes_past_hour());
+                write!(out, "{sign}{:02}{:02}", h.abs(), m.abs())
+            }
+            'Z' => write!(out, "{zone}"),
+            '%' => write!(out, "%"),
+            _ => return None,
+        };
+        r.ok()?;
+    }
+    Some(())
+}
+
+/// Parse a string with a `strptime`-like format string to broken-down time.
+///
+/// Like in jq, a time zone offset given by `%z` is parsed, but not applied.
+pub fn strptime(s: &str, fmt: &str) -> ValR {
+    let fail = || Error::str(format_args!("date \"{s}\" does not match format \"{fmt}\""));
+    let mut tm = Tm::default();
+    let mut rest = s;
+    tm.parse(&mut rest, fmt).ok_or_else(fail)?;
+    if !rest.is_empty() {
+        return Err(fail());
+    }
+    let dt = match tm.epoch {
+        Some(epoch) => from_secs(&int(epoch))?.0,
+        None => {
+            let mut hour = tm.hour;
+            if let Some(pm) = tm.pm {
+                hour = hour % 12 + if pm { 12 } else { 0 }
+            }
+            let (month, day) = match (tm.day, tm.yday) {
+                (None, Some(yday)) => (0, yday + 1),
+                (day, _) => (tm.month, day.unwrap_or(1)),
+            };
+            let year = tm.year.unwrap_or(1900);
+            let arr = [year, month, day, hour, tm.min, tm.sec].map(int);
+            from_arr(&Val::arr(arr.into()))?.0
+        }
+    };
+    Ok(to_arr(dt, 0.0))
+}
+
+/// Broken-down time during parsing.
+#[derive(Default)]
+struct Tm {
+    year: Option<i64>,
+    month: i64,
+    day: Option<i64>,
+    yday: Option<i64>,
+    hour: i64,
+    min: i64,
+    sec: i64,
+    pm: Option<bool>,
+    epoch: Option<i64>,
+}
+
+/// Parse a number with up to `max` digits, preceded by optional whitespace.
+fn num(s: &mut &str, max: usize) -> Option<i64> {
+    let t = s.trim_start();
+    let (sign, t) = match t.strip_prefix('-') {
+        Some(t) => (-1, t),
+        None => (1, t.strip_prefix('+').unwrap_or(t)),
+    };
+    let len = t.bytes().take(max).take_while(u8::is_ascii_digit).count();
+    let n: i64 = t[..len].parse().ok()?;
+    *s = &t[len..];
+    Some(sign * n)
+}
+
+/// Parse a name or its three-letter abbreviation, ignoring case,
+/// returning the index of the name.
+fn name(s: &mut &str, names: &[&str]) -> Option<usize> {
+    names.iter().enumerate().find_map(|(i, name)| {
+        [name, name.get(..3).unwrap_or(name)]
+            .iter()
+            .find_map(|name| {
+                let prefix = s.get(..name.len())?;
+                prefix.eq_ignore_ascii_case(name).then(|| {
+                    *s = &s[name.len()..];
+                    i
+                })
+            })
+    })
+}
+
+impl Tm {
+    fn parse(&mut self, s: &mut &str, fmt: &str) -> Option<()> {
+        let mut chars = fmt.chars();
+        while let Some(c) = chars.next() {
+            if c.is_whitespace() {
+                *s = s.trim_start();
+                continue;
+            } else if c != '%' {
+                *s = s.strip_prefix(c)?;
+                continue;
+            }
+            let c = chars.next()?;
+            if let Some(fmt) = composite(c) {
+                self.parse(s, fmt)?;
+                continue;
+            }
+            match c {
+                'a' | 'A' => drop(name(s, &DAYS)?),
+                'b' | 'B' | 'h' => self.month = name(s, &MONTHS)? as i64,
+                'd' | 'e' => self.day = Some(num(s, 2)?),
+                'H' | 'k' => self.hour = num(s, 2)?,
+                'I' | 'l' => self.hour = num(s, 2)?,
+                'j' => self.yday = Some(num(s, 3)? - 1),
+                'm' => self.month = num(s, 2)? - 1,
+                'M' => self.min = num(s, 2)?,
+                'n' | 't' => *s = s.trim_start(),
+                'p' | 'P' => self.pm = Some(name(s, &["AM", "PM"])? == 1),
+                's' => self.epoch = Some(num(s, 20)?),
+                'S' => self.sec = num(s, 2)?,
+                'y' => {
+                    let y = num(s, 2)?;
+                    self.year = Some(y + if y < 69 { 2000 } else { 1900 })
+                }
+                'Y' => self.year = Some(num(s, 4)?),
+                'z' => {
+                    if let Some(t) = s.strip_prefix('Z') {
+                        *s = t
+                    } else {
+                        let t = s.strip_prefix(['+', '-'])?;
+                        let t = t.get(2..)?;
+                        let t = t.strip_prefix(':').unwrap_or(t);
+                        let t = t.get(2..)?;
+                        *s = t;
+                    }
+                }
+                'Z' => *s = s.trim_start_matches(|c: char| c.is_ascii_alphabetic()),
+                '%' => *s = s.strip_prefix('%')?,
+                _ => return None,
+            }
+        }
+        Some(())
+    }
+}
+
+/// Return the offset and the abbreviation of the local time zone at the given UNIX timestamp.
+///
+/// The time zone is given by the `TZ` environment variable, which may be
+/// the name of a time zone such as "Europe/Vienna", the path of a TZif file, or
+/// a POSIX TZ string such as "CET-1CEST,M3.5.0,M10.5.0/3".
+/// If `TZ` is not set, the time zone is read from `/etc/localtime`.
+/// UTC is used if `TZ` is empty or if neither `TZ` nor `/etc/localtime` exist.
+/// Fail if the time zone is given, but cannot be read.
+#[cfg(feature = "std")]
+fn local(ts: i64) -> Result<(time::UtcOffset, String), Error> {
+    use std::io::ErrorKind::NotFound;
+    let utc = || (time::UtcOffset::UTC, "UTC".to_string());
+    let fail = |e: &dyn core::fmt::Display| Error::str(format_args!("invalid time zone: {e}"));
+    let file = |path: &str| match std::fs::read(path) {
+        Ok(data) => tzif(&data, ts).map(Some).ok_or_else(|| fail(&path)),
+        Err(e) if e.kind() == NotFound => Ok(None),
+        Err(e) => Err(fail(&format_args!("{path}: {e}"))),
+    };
+    let zone = |tz: &str| match tz.starts_with('/') {
+        true => file(tz),
+        false => file(&alloc::format!("/usr/share/zoneinfo/{tz}")),
+    };
+    let tz = match std::env::var("TZ") {
+        Ok(tz) => tz,
+        Err(std::env::VarError::NotPresent) => {
+            return Ok(file("/etc/localtime")?.unwrap_or_else(utc))
+        }
+        Err(e) => return Err(fail(&e)),
+    };
+    match tz.strip_prefix(':') {
+        _ if tz.is_empty() => Ok(utc()),
+        // a leading colon marks the name of a file
+        Some(name) => zone(name)?.ok_or_else(|| fail(&tz)),
+        None => match zone(&tz)? {
+            Some(local) => Ok(local),
+            None => Posix::parse(&tz)
+                .and_then(|posix| posix.at(ts))
+                .ok_or_else(|| fail(&tz)),
+        },
+    }
+}
+
+/// Return the offset and the abbreviation of the local time zone, which is always UTC
+/// without the standard library.
+#[cfg(not(feature = "std"))]
+fn local(_ts: i64) -> Result<(time::UtcOffset, String), Error> {
+    Ok((time::UtcOffset::UTC, "UTC".to_string()))
+}
+
+/// Look up the local time type at the given UNIX timestamp in TZif data.
+///
+/// Times after the last transition use the POSIX TZ string at the end of
+/// TZif version 2+ files, or the time type of the last transition if there is none.
+fn tzif(data: &[u8], ts: i64) -> Option<(time::UtcOffset, String)> {
+    // return the version and the counts of a TZif header, as well as the data following it
+    fn header(b: &[u8]) -> Option<(u8, [usize; 6], &[u8])> {
+        let version = *b.strip_prefix(b"TZif")?.first()?;
+        let mut counts = [0; 6];
+        for (i, c) in counts.iter_mut().enumerate() {
+            let n = b.get(20 + i * 4..24 + i * 4)?.try_into().ok()?;
+            *c = usize::try_from(u32::from_be_bytes(n)).ok()?;
+        }
+        Some((version, counts, b.get(44..)?))
+    }
+    let (version, counts, body) = header(data)?;
+    let (tsize, counts, body) = if version >= b'2' {
+        // skip version 1 data, which uses 32-bit instead of 64-bit transition times
+        let [isut, isstd, leap, time, typ, chr] = counts;
+        let len = time * 5 + typ * 6 + chr + leap * 8 + isstd + isut;
+        let (_, counts, body) = header(body.get(len..)?)?;
+        (8, counts, body)
+    } else {
+        (4, counts, body)
+    };
+    let [isut, isstd, leap, time, typ, chr] = counts;
+
+    let times = body.get(..time * tsize)?;
+    let idxs = body.get(time * tsize..time * (tsize + 1))?;
+    let types = body.get(time * (tsize + 1)..time * (tsize + 1) + typ * 6)?;
+    let chars = body.get(time * (tsize + 1) + typ * 6..)?.get(..chr)?;
+    // POSIX TZ string between two newlines after the version 2+ data
+    let footer = if version >= b'2' {
+        let len = time * 9 + typ * 6 + chr + leap * 12 + isstd + isut;
+        let footer = body.get(len..)?.strip_prefix(b"\n")?;
+        let end = footer.iter().position(|c| *c == b'\n')?;
+        core::str::from_utf8(&footer[..end]).ok()?
+    } else {
+        ""
+    };
+
+    let at = |i: usize| -> Option<i64> {
+        let b = times.get(i * tsize..(i + 1) * tsize)?;
+        Some(match tsize {
+            4 => i32::from_be_bytes(b.try_into().ok()?).into(),
+            _ => i64::from_be_bytes(b.try_into().ok()?),
+        })
+    };
+    // number of transitions at or before `ts`
+    let n = (0..time)
+        .take_while(|i| at(*i).map_or(false, |t| t <= ts))
+        .count();
+    if n == time && !footer.is_empty() {
+        return Posix::parse(footer)?.at(ts);
+    }
+    let idx = match n {
+        0 => 0,
+        n => usize::from(*idxs.get(n - 1)?),
+    };
+    let ty = types.get(idx * 6..idx * 6 + 6)?;
+    let utoff = i32::from_be_bytes(ty[..4].try_into().ok()?);
+    let abbr = chars.get(usize::from(ty[5])..)?;
+    let abbr = abbr.split(|c| *c == 0).next()?;
+    let offset = time::UtcOffset::from_whole_seconds(utoff).ok()?;
+    Some((offset, String::from_utf8_lossy(abbr).into_owned()))
+}
+
+/// Time zone given by a POSIX TZ string, such as "CET-1CEST,M3.5.0,M10.5.0/3".
+///
+/// Offsets are stored in seconds east of UTC.
+struct Posix {
+    std: (String, i32),
+    /// daylight saving time and the rules when it starts and ends
+    dst: Option<((String, i32), [Rule; 2])>,
+}
+
+/// Day of a year and time of that day in seconds when a change of time occurs.
+struct Rule(Day, i64);
+
+enum Day {
+    /// `Jn`: day `n` of the year, counting from 1 and never counting February 29
+    Julian(u16),
+    /// `n`: day `n` of the year, counting from 0
+    Ordinal(u16),
+    /// `Mm.w.d`: day `d` (0 = Sunday) of week `w` (5 = last) of month `m`
+    Month(u8, u8, u8),
+}
+
+impl Posix {
+    fn parse(s: &str) -> Option<Self> {
+        let mut s = s.as_bytes();
+        let std = (Self::name(&mut s)?, -Self::hms(&mut s, 24)?);
+        if s.is_empty() {
+            return Some(Self { std, dst: None });
+        }
+        let name = Self::name(&mut s)?;
+        let offset = match s.first() {
+            Some(b',') | None => std.1 + 3600,
+            Some(_) => -Self::hms(&mut s, 24)?,
+        };
+        let rules = match s {
+            // default rules of the United States
+            [] => [
+                Rule(Day::Month(3, 2, 0), 7200),
+                Rule(Day::Month(11, 1, 0), 7200),
+            ],
+            [b',', rest @ ..] => {
+                s = rest;
+                let start = Rule::parse(&mut s)?;
+                s = s.strip_prefix(b",")?;
+                [start, Rule::parse(&mut s)?]
+            }
+            _ => return None,
+        };
+        s.is_empty().then_some(Self {
+            std,
+            dst: Some(((name, offset), rules)),
+        })
+    }
+
+    /// Parse an abbreviation that is either quoted by `<>` or consists of letters.
+    fn name(s: &mut &[u8]) -> Option<String> {
+        let name = if let Some(rest) = s.strip_prefix(b"<") {
+            let len = rest.iter().position(|c| *c == b'>')?;
+            *s = &rest[len + 1..];
+            &rest[..len]
+        } else {
+            let len = s.iter().take_while(|c| c.is_ascii_alphabetic()).count();
+            let (name, rest) = s.split_at(len);
+            *s = rest;
+            name
+        };
+        (name.len() >= 3).then(|| String::from_utf8_lossy(name).into_owned())
+    }
+
+    /// Parse a signed time `[+-]hh[:mm[:ss]]` with at most `max` hours into seconds.
+    fn hms(s: &mut &[u8], max: i32) -> Option<i32> {
+        let sign = match s.first() {
+            Some(b'-') => -1,
+            Some(b'+') => 1,
+            _ => 0,
+        };
+        if sign != 0 {
+            *s = &s[1..];
+        }
+        fn num(s: &mut &[u8], max: i32) -> Option<i32> {
+            let len = s.iter().take(3).take_while(|c| c.is_ascii_digit()).count();
+            let (n, rest) = s.split_at(len);
+            *s = rest;
+            let n = core::str::from_utf8(n).ok()?.parse().ok()?;
+            (n <= max).then_some(n)
+        }
+        let mut secs = num(s, max)? * 3600;
+        for factor in [60, 1] {
+            match s.strip_prefix(b":") {
+                Some(rest) => *s = rest,
+                None => break,
+            }
+            secs += num(s, 59)? * factor;
+        }
+        Some(if sign < 0 { -secs } else { secs })
+    }
+
+    /// Return the offset and the abbreviation of the time zone at the given UNIX timestamp.
+    fn at(&self, ts: i64) -> Option<(time::UtcOffset, String)> {
+        let (std, (dst, [start, end])) = match &self.dst {
+            None => {
+                return Some((
+                    time::UtcOffset::from_whole_seconds(self.std.1).ok()?,
+                    self.std.0.clone(),
+                ))
+            }
+            Some(dst) => (&self.std, dst),
+        };
+        let year = time::OffsetDateTime::from_unix_timestamp(ts + i64::from(std.1))
+            .ok()?
+            .year();
+        // DST starts at a local time in standard time and ends at a local time in DST
+        let start = start.at(year)? - i64::from(std.1);
+        let end = end.at(year)? - i64::from(dst.1);
+        let is_dst = if start < end {
+            start <= ts && ts < end
+        } else {
+            // DST spans the turn of the year, as on the southern hemisphere
+            !(end <= ts && ts < start)
+        };
+        let (name, offset) = if is_dst { dst } else { std };
+        let offset = time::UtcOffset::from_whole_seconds(*offset).ok()?;
+        Some((offset, name.clone()))
+    }
+}
+
+impl Rule {
+    /// Parse a day `Jn`, `n` or `Mm.w.d`, optionally followed by a time `/[+-]hh[:mm[:ss]]`.
+    fn parse(s: &mut &[u8]) -> Option<Self> {
+        fn num<T: core::str::FromStr>(s: &mut &[u8]) -> Option<T> {
+            let len = s.iter().take_while(|c| c.is_ascii_digit()).count();
+            let (n, rest) = s.split_at(len);
+            *s = rest;
+            core::str::from_utf8(n).ok()?.parse().ok()
+        }
+        let day = match s.first()? {
+            b'J' => {
+                *s = &s[1..];
+                Some(num(s)?)
+                    .filter(|n| (1..=365).contains(n))
+                    .map(Day::Julian)?
+            }
+            b'M' => {
+                *s = &s[1..];
+                let m = num(s)?;
+                *s = s.strip_prefix(b".")?;
+                let w = num(s)?;
+                *s = s.strip_prefix(b".")?;
+                let d = num(s)?;
+                let valid = (1..=12).contains(&m) && (1..=5).contains(&w) && d <= 6;
+                valid.then_some(Day::Month(m, w, d))?
+            }
+            _ => Some(num(s)?).filter(|n| *n <= 365).map(Day::Ordinal)?,
+        };
+        let time = match s.strip_prefix(b"/") {
+            Some(rest) => {
+                *s = rest;
+                Posix::hms(s, 167)?
+            }
+            None => 7200,
+        };
+        Some(Self(day, time.into()))
+    }
+
+    /// Return the UNIX timestamp of the rule in the given year, as if the time zone were UTC.
+    fn at(&self, year: i32) -> Option<i64> {
+        use time::util::{days_in_year, days_in_year_month, is_leap_year};
+        let date = match self.0 {
+            Day::Julian(n) => {
+                // February 29 is not counted, so skip it
+                let n = if is_leap_year(year) && n >= 60 {
+                    n + 1
+                } else {
+                    n
+                };
+                time::Date::from_ordinal_date(year, n).ok()?
+            }
+            Day::Ordinal(n) => {
+                time::Date::from_ordinal_date(year, (n + 1).min(days_in_year(year))).ok()?
+            }
+            Day::Month(m, w, d) => {
+                let month = time::Month::try_from(m).ok()?;
+                let first = time::Date::from_calendar_date(year, month, 1).ok()?;
+                let first_d = first.weekday().number_days_from_sunday();
+                // day of the month of the first weekday `d`
+                let mut day = 1 + (7 + d - first_d) % 7 + (w - 1) * 7;
+                while day > days_in_year_month(year, month) {
+                    day -= 7;
+                }
+                time::Date::from_calendar_date(year, month, day).ok()?
+            }
+        };
+        Some(date.midnight().assume_utc().unix_timestamp() + self.1)
     }
 }
@@ -0,0 +1,215 @@
+//! Parsing of a simple subset of XML.
+//!
+//! An element is parsed to an object of the shape
+//! `{"name": ..., "attributes": {...}, "children": [...]}`,
+//! where children are either elements or strings for text.
+//! Text that consists only of whitespace is dropped.
+//!
+//! Comments, processing instructions (such as `<?xml ...?>`) and
+//! declarations (such as `<!DOCTYPE ...>`) are skipped.
+//! Namespaces and DTDs are not interpreted.
+
+use alloc::string::{String, ToString};
+use alloc::{format, vec::Vec};
+use jaq_interpret::{Error, Val, ValR};
+
+/// Parse a string containing a single XML root element.
+pub fn parse(s: &str) -> ValR {
+    let mut p = Parser { s, pos: 0 };
+    p.misc()?;
+    let root = p.element()?;
+    p.misc()?;
+    if p.pos < s.len() {
+        return Err(p.err("expected end of input"));
+    }
+    Ok(root)
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    fn err(&self, msg: &str) -> Error {
+        Error::str(format_args!("cannot parse XML at byte {}: {msg}", self.pos))
+    }
+
+    fn eat(&mut self, prefix: &str) -> bool {
+        let found = self.rest().starts_with(prefix);
+        if found {
+            self.pos += prefix.len();
+        }
+        found
+    }
+
+    fn expect(&mut self, prefix: &str) -> Result<(), Error> {
+        if self.eat(prefix) {
+            Ok(())
+        } else {
+            Err(self.err(&format!("expected {prefix:?}")))
+        }
+    }
+
+    /// Consume everything up to and including `end`, returning what came before it.
+    fn until(&mut self, end: &str) -> Result<&'a str, Error> {
+        let i = self
+            .rest()
+            .find(end)
+            .ok_or_else(|| self.err(&format!("expected {end:?}")))?;
+        let out = &self.rest()[..i];
+        self.pos += i + end.len();
+        Ok(out)
+    }
+
+    fn ws(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Skip a comment, processing instruction, or declaration, if present.
+    fn skip(&mut self) -> Result<bool, Error> {
+        if self.eat("<!--") {
+            self.until("-->")?;
+        } else if self.eat("<?") {
+            self.until("?>")?;
+        } else if !self.rest().starts_with("<![CDATA[") && self.eat("<!") {
+            self.until(">")?;
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Skip whitespace and everything that [`Self::skip`] skips.
+    fn misc(&mut self) -> Result<(), Error> {
+        self.ws();
+        while self.skip()? {
+            self.ws()
+        }
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<&'a str, Error> {
+        let rest = self.rest();
+        let delim = |c: char| c.is_whitespace() || "/>=<\"'".contains(c);
+        let len = rest.find(delim).unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.err("expected name"));
+        }
+        self.pos += len;
+        Ok(&rest[..len])
+    }
+
+    fn element(&mut self) -> ValR {
+        self.expect("<")?;
+        let name = self.name()?;
+        let mut attrs = Vec::new();
+        loop {
+            self.ws();
+            if self.eat("/>") {
+                return Ok(elem(name, attrs, Vec::new()));
+            } else if self.eat(">") {
+                break;
+            }
+            let key = self.name()?;
+            self.ws();
+            self.expect("=")?;
+            self.ws();
+            let quote = if self.eat("\"") {
+                "\""
+            } else {
+                self.expect("'")?;
+                "'"
+            };
+            let value = unescape(self.until(quote)?).map_err(|e| self.err(e))?;
+            attrs.push((key, Val::str(value)));
+        }
+
+        let mut children = Vec::new();
+        let mut text = String::new();
+        loop {
+            if self.eat("</") {
+                if self.name()? != name {
+                    return Err(self.err(&format!("expected closing tag for {name:?}")));
+                }
+                self.ws();
+                self.expect(">")?;
+                break;
+            } else if self.eat("<![CDATA[") {
+                text.push_str(self.until("]]>")?);
+            } else if self.skip()? {
+                // comments and processing instructions do not interrupt text
+            } else if self.rest().starts_with('<') {
+                flush(&mut text, &mut children);
+                children.push(self.element()?);
+            } else if self.rest().is_empty() {
+                return Err(self.err(&format!("unclosed element {name:?}")));
+            } else {
+                let rest = self.rest();
+                let len = rest.find('<').unwrap_or(rest.len());
+                text.push_str(&unescape(&rest[..len]).map_err(|e| self.err(e))?);
+                self.pos += len;
+            }
+        }
+        flush(&mut text, &mut children);
+        Ok(elem(name, attrs, children))
+    }
+}
+
+fn elem(name: &str, attrs: Vec<(&str, Val)>, children: Vec<Val>) -> Val {
+    let attrs = attrs.into_iter().map(|(k, v)| (k.to_string().into(), v));
+    Val::obj(
+        [
+            ("name", Val::str(name.to_string())),
+            ("attributes", Val::obj(attrs.collect())),
+            ("children", Val::arr(children)),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string().into(), v))
+        .collect(),
+    )
+}
+
+/// Add accumulated text to the children, unless it consists only of whitespace.
+fn flush(text: &mut String, children: &mut Vec<Val>) {
+    if !text.trim().is_empty() {
+        children.push(Val::str(core::mem::take(text)))
+    }
+    text.clear()
+}
+
+/// Replace predefined entities and character references.
+fn unescape(s: &str) -> Result<String, &'static str> {
+    let mut out = String::new();
+    let mut parts = s.split('&');
+    out.push_str(parts.next().unwrap_or_default());
+    for part in parts {
+        let (entity, rest) = part.split_once(';').ok_or("unterminated entity")?;
+        let c = match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = if let Some(hex) = entity.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok()
+                } else if let Some(dec) = entity.strip_prefix('#') {
+                    dec.parse().ok()
+                } else {
+                    return Err("unknown entity");
+                };
+                code.and_then(char::from_u32)
+                    .ok_or("invalid character reference")?
+            }
+        };
+        out.push(c);
+        out.push_str(rest);
+    }
+    Ok(out)
+}
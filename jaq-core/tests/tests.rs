@@ -55,6 +55,216 @@ fn dateiso8601() {
         "todateiso8601",
         json!("1970-01-02T00:00:00.123456789Z"),
     );
+    // broken-down time
+    give(
+        json!(1425599507),
+        "gmtime | todateiso8601",
+        json!("2015-03-05T23:51:47Z"),
+    );
+    give(json!("1970"), "try todateiso8601 catch 0", json!(0));
+}
+
+#[test]
+fn day_week_leap() {
+    // 2021-01-04 is a Monday in ISO week 1
+    give(
+        json!(1609718400),
+        "[day_of_week, week_of_year]",
+        json!([1, 1]),
+    );
+    // 2021-01-03 is a Sunday in ISO week 53 of 2020
+    give(
+        json!(1609718399.5),
+        "[day_of_week, week_of_year]",
+        json!([0, 53]),
+    );
+    give(
+        json!([2021, 0, 3]),
+        "[day_of_week, week_of_year]",
+        json!([0, 53]),
+    );
+    // 1969-12-31 was a Wednesday
+    give(json!(-0.5), "day_of_week", json!(3));
+
+    give(json!([2020, 1, 29]), "is_leap_year", json!(true));
+    give(json!([1900, 0, 1]), "is_leap_year", json!(false));
+    give(json!([2000, 0, 1]), "is_leap_year", json!(true));
+    give(
+        json!([2021, 1, 29]),
+        "[try is_leap_year catch 0]",
+        json!([0]),
+    );
+}
+
+#[test]
+fn gmtime_mktime() {
+    let bd = json!([2015, 2, 5, 23, 51, 47, 4, 63]);
+    give(json!(1425599507), "gmtime", bd.clone());
+    give(bd, "mktime", json!(1425599507));
+    give(json!(1425599507.5), "gmtime[5]", json!(47.5));
+    // 1969-12-31 was a Wednesday
+    give(
+        json!(-1),
+        "gmtime",
+        json!([1969, 11, 31, 23, 59, 59, 3, 364]),
+    );
+    // values outside their usual ranges are normalised
+    give(json!([2024, 0, 32, 24, 0, 0]), "mktime", json!(1706832000));
+    give(
+        json!([2015, 14, 1, 0, 0, 0]),
+        "mktime | gmtime[:3]",
+        json!([2016, 2, 1]),
+    );
+    give(json!([2015, 2, 5]), "[try mktime catch 0]", json!([0]));
+}
+
+#[test]
+fn strftime_strptime() {
+    let fmt = "%Y-%m-%dT%H:%M:%SZ";
+    let bd = json!([2015, 2, 5, 23, 51, 47, 4, 63]);
+    let s = "2015-03-05T23:51:47Z";
+    give(json!(1425599507), &format!("strftime(\"{fmt}\")"), json!(s));
+    give(bd.clone(), &format!("strftime(\"{fmt}\")"), json!(s));
+    give(json!(s), &format!("strptime(\"{fmt}\")"), bd.clone());
+
+    let fmt = r#""%a %A %b %B %C %e %j %I %p %u %U %V %W %y %z %Z %%""#;
+    let out = "Thu Thursday Mar March 20  5 064 11 PM 4 09 10 09 15 +0000 UTC %";
+    give(json!(1425599507), &format!("strftime({fmt})"), json!(out));
+    give(
+        json!(1425599507),
+        r#"strftime("%c")"#,
+        json!("Thu Mar  5 23:51:47 2015"),
+    );
+
+    let s = "Thu, 05 mar 2015 11:51:47 PM +0100";
+    let f = r#"strptime("%a, %d %b %Y %I:%M:%S %p %z")"#;
+    give(json!(s), f, bd.clone());
+    give(
+        json!("2015 064"),
+        r#"strptime("%Y %j")[:3]"#,
+        json!([2015, 2, 5]),
+    );
+    give(json!("1425599507"), r#"strptime("%s")"#, bd);
+    give(
+        json!("2015-03"),
+        r#"[try strptime("%Y") catch 0]"#,
+        json!([0]),
+    );
+}
+
+#[test]
+fn diagnostics() {
+    use jaq_interpret::{Ctx, Diagnostic, FilterT, ParseCtx, RcIter};
+    use std::cell::RefCell;
+    let mut ctx = ParseCtx::new(Vec::new());
+    ctx.insert_natives(jaq_core::core());
+    let (f, _) = jaq_parse::parse("def f: debug; [1 | f, (2 | stderr)], .", jaq_parse::main());
+    let f = ctx.compile(f.unwrap());
+    assert!(ctx.errs.is_empty());
+
+    let diagnostics = RefCell::new(Vec::new());
+    let push = |d| diagnostics.borrow_mut().push(d);
+    let inputs = RcIter::new(core::iter::empty());
+    let ctx = Ctx::new([], &inputs).with_diagnostics(&push);
+    let out: Vec<_> = f.run((ctx, Val::Null)).collect();
+    assert_eq!(out, [Ok(json!([1, 2]).into()), Ok(Val::Null)]);
+    let expected = [
+        Diagnostic::Debug(Val::Int(1)),
+        Diagnostic::Stderr(Val::Int(2)),
+    ];
+    assert_eq!(diagnostics.into_inner(), expected);
+}
+
+yields!(distinct, "[3, 1, 3, 2, 1] | distinct", [3, 1, 2]);
+yields!(
+    distinct_obj,
+    r#"[{"a": 1, "b": 2}, 1.0, {"b": 2, "a": 1}, 1] | distinct"#,
+    json!([{"a": 1, "b": 2}, 1.0])
+);
+
+yields!(env_unset, "env.JAQ_UNSET_ENV_VAR", json!(null));
+
+#[test]
+fn env_obj() {
+    use std::ffi::OsString;
+    let vars = [("A", "1"), ("B", "")].map(|(k, v)| (k.into(), v.into()));
+    let env = jaq_core::env_obj(vars);
+    assert_eq!(serde_json::Value::from(env), json!({"A": "1", "B": ""}));
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        let invalid = OsString::from_vec(vec![b'a', 0xff]);
+        let env = jaq_core::env_obj([(OsString::from("X"), invalid)]);
+        assert_eq!(serde_json::Value::from(env), json!({"X": "a\u{fffd}"}));
+    }
+}
+
+#[test]
+fn env_injected() {
+    use jaq_interpret::{Ctx, FilterT, ParseCtx, RcIter, Val};
+    let mut ctx = ParseCtx::new(vec!["ENV".to_string()]);
+    ctx.insert_natives(jaq_core::core());
+    let (defs, _) = jaq_parse::parse("def env: $ENV;", jaq_parse::defs());
+    ctx.insert_defs(defs.unwrap());
+
+    let (f, _) = jaq_parse::parse("[env.HOME, $ENV.HOME]", jaq_parse::main());
+    let f = ctx.compile(f.unwrap());
+    assert!(ctx.errs.is_empty());
+
+    let vars = [("HOME".into(), "/sandbox".into())];
+    let inputs = RcIter::new(core::iter::empty());
+    let ctx = Ctx::new([jaq_core::env_obj(vars)], &inputs);
+    let out: Vec<_> = f.run((ctx, Val::Null)).collect();
+    assert_eq!(out, [Ok(json!(["/sandbox", "/sandbox"]).into())]);
+}
+
+#[test]
+fn env_override() {
+    use jaq_interpret::{Ctx, FilterT, ParseCtx, RcIter, Val};
+    use std::collections::BTreeMap;
+    let mut ctx = ParseCtx::new(Vec::new());
+    ctx.insert_natives(jaq_core::core());
+    let (f, _) = jaq_parse::parse("env, env.PATH", jaq_parse::main());
+    let f = ctx.compile(f.unwrap());
+    assert!(ctx.errs.is_empty());
+
+    let env = BTreeMap::from([("A".to_string(), "1".to_string())]);
+    let inputs = RcIter::new(core::iter::empty());
+    let ctx = Ctx::new([], &inputs).with_env(&env);
+    let out: Vec<_> = f.run((ctx, Val::Null)).collect();
+    // the process environment is not consulted, so `PATH` is not set
+    assert_eq!(out, [Ok(json!({"A": "1"}).into()), Ok(Val::Null)]);
+}
+
+#[test]
+fn reduce_inputs_bounded() {
+    use jaq_interpret::{Ctx, FilterT, ParseCtx, RcIter, Val};
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+    let mut ctx = ParseCtx::new(Vec::new());
+    ctx.insert_natives(jaq_core::core());
+    let src = "reduce inputs as $x (0; . + ($x | length))";
+    let (f, _) = jaq_parse::parse(src, jaq_parse::main());
+    let f = ctx.compile(f.unwrap());
+    assert!(ctx.errs.is_empty());
+
+    // weak references to the most recently yielded inputs
+    let recent = RefCell::new(VecDeque::new());
+    let (n, len) = (100_000, 100);
+    let iter = (0..n).map(|i| {
+        let s = Rc::new("x".repeat(len));
+        let mut recent = recent.borrow_mut();
+        recent.push_back(Rc::downgrade(&s));
+        // if inputs were collected, they would still be alive here
+        if recent.len() > 4 {
+            let old = recent.pop_front().unwrap();
+            assert!(old.upgrade().is_none(), "input {} is still alive", i - 4);
+        }
+        Ok(Val::Str(s))
+    });
+    let inputs = RcIter::new(iter);
+    let out: Vec<_> = f.run((Ctx::new([], &inputs), Val::Null)).collect();
+    assert_eq!(out, [Ok(Val::Int((n * len) as isize))]);
 }
 
 #[test]
@@ -65,11 +275,34 @@ fn explode_implode() {
     give(json!("❤ の"), "explode | implode", json!("❤ の"));
     give(json!("y̆"), "explode | implode", json!("y̆"));
 
+    give(json!(""), "explode", json!([]));
+    give(json!([]), "implode", json!(""));
+
     give(json!([1114112]), "try implode catch -1", json!(-1));
+    // negative numbers and surrogates are no Unicode scalar values
+    give(json!([-1]), "try implode catch -1", json!(-1));
+    give(json!([55296]), "try implode catch -1", json!(-1));
+}
+
+#[test]
+fn bytes_frombytes() {
+    give(json!("é"), "bytes", json!([195, 169]));
+    give(json!("❤ é"), "bytes | frombytes", json!("❤ é"));
+    give(json!(""), "bytes", json!([]));
+    give(json!([]), "frombytes", json!(""));
+
+    // invalid UTF-8 and values that are no bytes
+    give(json!([195]), "try frombytes catch -1", json!(-1));
+    give(json!([256]), "try frombytes catch -1", json!(-1));
+    give(json!([-1]), "try frombytes catch -1", json!(-1));
 }
 
 yields!(first_empty, "[first({}[])]", json!([]));
 yields!(first_some, "first(1, 2, 3)", 1);
+yields!(first_empty_some, "first(empty, 5)", 5);
+// outputs after the first are not evaluated
+yields!(first_short_circuit, "first(empty, 5, error)", 5);
+yields!(comma_empty, "[empty, 1, empty, 2]", [1, 2]);
 
 yields!(
     format_text,
@@ -111,6 +344,12 @@ yields!(
     r#""hello cruel world" | @base64 | @base64d"#,
     "hello cruel world"
 );
+// padding is optional, and invalid UTF-8 is replaced
+yields!(
+    format_base64d_lossy,
+    r#"["YWI", "YWI=", "/w" | @base64d]"#,
+    ["ab", "ab", "\u{fffd}"]
+);
 yields!(
     format_sh,
     r#"[0, 0 == 0, {}.a, "O'Hara!", ["Here", "there"] | @sh]"#,
@@ -127,6 +366,128 @@ yields!(
     -1
 );
 
+#[test]
+fn cbor() {
+    // examples from RFC 8949, Appendix A
+    let v = json!({"a": 1, "b": [2, 3]});
+    let bytes = json!([0xa2, 0x61, 0x61, 0x01, 0x61, 0x62, 0x82, 0x02, 0x03]);
+    give(v.clone(), "@cbor | explode", bytes.clone());
+    give(bytes, "implode | fromcbor", v);
+    give(json!(-500), "@cbor | explode", json!([0x39, 0x01, 0xf3]));
+    // floats stay floats, and every float is encoded with double precision
+    let bytes = json!([0x82, 0x01, 0xfb, 0x3f, 0xf0, 0, 0, 0, 0, 0, 0]);
+    give(json!([1, 1.0]), "@cbor | explode", bytes);
+
+    let v = json!([null, true, false, -1, 1.5, "äöü", [], {}, {"a": {"b": []}}]);
+    give(v.clone(), "@cbor | fromcbor", v);
+    give(json!(0.1), "@cbor | fromcbor", json!(0.1));
+
+    // half-precision float
+    give(json!([0xf9, 0x3c, 0x00]), "implode | fromcbor", json!(1.0));
+    // byte strings are decoded to strings with one character per byte
+    give(
+        json!([0x43, 1, 0xff, 3]),
+        "implode | fromcbor | explode",
+        json!([1, 0xff, 3]),
+    );
+    // a byte string containing CBOR data can be decoded further
+    give(
+        json!("\u{42}\u{81}\u{01}"),
+        "fromcbor | fromcbor",
+        json!([1]),
+    );
+    // indefinite-length array and text string, and a tag
+    let bytes = json!([0x9f, 0x7f, 0x61, 0x61, 0x61, 0x62, 0xff, 0xc1, 0x00, 0xff]);
+    give(bytes, "implode | fromcbor", json!(["ab", 0]));
+
+    let fail = |bytes| give(bytes, "try (implode | fromcbor) catch -1", json!(-1));
+    // truncated data, trailing data, and non-string map keys
+    fail(json!([0x82, 0x01]));
+    fail(json!([0x01, 0x01]));
+    fail(json!([0xa1, 0x01, 0x01]));
+    // characters that are not bytes
+    give(json!("ā"), "try fromcbor catch -1", json!(-1));
+
+    // integers that do not fit into 64 bits are encoded as bignums
+    let big = "18446744073709551616";
+    let bytes = json!([0xc2, 0x49, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+    give(json!(null), &format!("{big} | @cbor | explode"), bytes);
+    let bytes = json!([0x3b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    give(json!(null), &format!("-{big} | @cbor | explode"), bytes);
+    let f = format!("[{big}, -{big} - 1, {big} * {big}] | . == (@cbor | fromcbor)");
+    give(json!(null), &f, json!(true));
+}
+
+#[test]
+fn msgpack() {
+    let v = json!({"a": 1, "b": [2, 3]});
+    let bytes = json!([0x82, 0xa1, 0x61, 0x01, 0xa1, 0x62, 0x92, 0x02, 0x03]);
+    give(v.clone(), "@msgpack | explode", bytes.clone());
+    give(bytes, "implode | frommsgpack", v);
+    give(json!(-500), "@msgpack | explode", json!([0xd1, 0xfe, 0x0c]));
+    give(
+        json!([-1, 200]),
+        "@msgpack | explode",
+        json!([0x92, 0xff, 0xcc, 200]),
+    );
+
+    let v = json!([null, true, false, -33, 1.5, "äöü", [], {}, {"a": {"b": []}}]);
+    give(v.clone(), "@msgpack | frommsgpack", v);
+    let v = json!([65536, -70000, 4294967296_i64, "a".repeat(40), vec![0; 20]]);
+    give(v.clone(), "@msgpack | frommsgpack", v);
+
+    // single-precision float
+    let bytes = json!([0xca, 0x3f, 0x80, 0, 0]);
+    give(bytes, "implode | frommsgpack", json!(1.0));
+    // binary data and extension types are decoded to strings with one character per byte
+    give(
+        json!([0xc4, 2, 1, 0xff]),
+        "implode | frommsgpack | explode",
+        json!([1, 0xff]),
+    );
+    give(
+        json!([0xd5, 1, 3, 4]),
+        "implode | frommsgpack | explode",
+        json!([3, 4]),
+    );
+
+    let fail = |bytes| give(bytes, "try (implode | frommsgpack) catch -1", json!(-1));
+    // truncated data, trailing data, non-string map keys, and an unused byte
+    fail(json!([0x92, 0x01]));
+    fail(json!([0x01, 0x01]));
+    fail(json!([0x81, 0x01, 0x01]));
+    fail(json!([0xc1]));
+
+    // integers are encoded with at most 64 bits
+    let f = "[18446744073709551615, -9223372036854775808] | . == (@msgpack | frommsgpack)";
+    give(json!(null), f, json!(true));
+    let f = "try (18446744073709551616 | @msgpack) catch -1";
+    give(json!(null), f, json!(-1));
+}
+
+#[test]
+fn fromxml() {
+    let b = json!({"name": "b", "attributes": {}, "children": ["text"]});
+    let a = json!({"name": "a", "attributes": {"x": "1"}, "children": [b]});
+    give(json!(r#"<a x="1"><b>text</b></a>"#), "fromxml", a);
+
+    let s = r#"<?xml version="1.0"?>
+<!-- comment -->
+<r>
+  <e k='&lt;&#x41;&#66;&gt;'/> a &amp; <![CDATA[<b>]]> <!-- c --> z
+</r>
+"#;
+    let e = json!({"name": "e", "attributes": {"k": "<AB>"}, "children": []});
+    let r = json!({"name": "r", "attributes": {}, "children": [e, " a & <b>  z\n"]});
+    give(json!(s), "fromxml", r);
+
+    let fail = |s| give(json!(s), "try fromxml catch -1", json!(-1));
+    fail("<a>");
+    fail("<a></b>");
+    fail("<a/><b/>");
+    fail("<a>&unknown;</a>");
+}
+
 #[test]
 fn group_by() {
     gives(json!([]), "group_by(.)", [json!([])]);
@@ -137,6 +498,41 @@ fn group_by() {
     );
 }
 
+#[test]
+fn group_count_by() {
+    give(json!([]), "group_count_by(.)", json!([]));
+    let v = json!([{"k": "b"}, {"k": "a"}, {"k": "b"}, {}]);
+    let out = json!([
+        {"key": null, "count": 1},
+        {"key": "a", "count": 1},
+        {"key": "b", "count": 2}
+    ]);
+    give(v, "group_count_by(.k)", out);
+    let multi = json!([{"key": [], "count": 2}, {"key": [1, 2], "count": 1}]);
+    give(json!([[], [1, 2], []]), "group_count_by(.[])", multi);
+}
+
+#[test]
+fn halt() {
+    fail(json!(1), "halt", Error::Halt(None, 0));
+    fail(json!(1), "halt_error(2)", Error::Halt(Some(Val::Int(1)), 2));
+    // halting cannot be caught
+    fail(json!(1), "try halt catch 0", Error::Halt(None, 0));
+    fail(json!(1), "halt // 0", Error::Halt(None, 0));
+    fail(
+        json!(1),
+        "(halt_error(1))?",
+        Error::Halt(Some(Val::Int(1)), 1),
+    );
+
+    let err = Error::Type(Val::str("a".to_string()), Type::Int);
+    give(
+        json!(1),
+        r#"try halt_error("a") catch ."#,
+        json!(err.to_string()),
+    );
+}
+
 #[test]
 fn has() {
     let err = Error::Index(Val::Null, Val::Int(0));
@@ -157,6 +553,27 @@ fn has() {
     give(json!({"a": 1, "b": null}), r#"has("c")"#, json!(false));
 }
 
+yields!(
+    in_short_circuit,
+    "def r: 1, 2, r; [IN(2, 1; r), IN(2, error; 1, 2, error)]",
+    [true, true]
+);
+yields!(in_none, "[IN(empty; 1), IN(1, 2; 3, 4)]", [false, false]);
+#[test]
+fn in_error() {
+    fail(json!(0), "IN(1; 2, error)", Error::Val(Val::Int(0)));
+}
+
+yields!(
+    index_multi,
+    r#"INDEX({"a": 1}, {"a": 2}, {"b": 3}; .a, "x")"#,
+    json!({"1": {"a": 1}, "x": {"b": 3}, "2": {"a": 2}, "null": {"b": 3}})
+);
+#[test]
+fn index_error() {
+    fail(json!(0), "INDEX(1, error; .)", Error::Val(Val::Int(0)));
+}
+
 yields!(indices_str, r#""a,b, cd, efg" | indices(", ")"#, [3, 7]);
 yields!(
     indices_arr_num,
@@ -188,6 +605,85 @@ fn json() {
     give(json!(0), "1.0 / 0.0 | tojson", json!("null"));
 }
 
+#[test]
+fn from_csv() {
+    let csv = json!("name,age\nAlice,30\r\n\"Bob \"\"B\"\", Jr.\",\"4\n2\"\n");
+    let out = json!([{"name": "Alice", "age": "30"}, {"name": "Bob \"B\", Jr.", "age": "4\n2"}]);
+    give(csv, "from_csv", out);
+
+    // missing fields are empty, superfluous fields are dropped
+    let csv = json!("a,b\n1\n\n1,2,3");
+    let out = json!([{"a": "1", "b": ""}, {"a": "1", "b": "2"}]);
+    give(csv, "from_csv", out);
+
+    give(json!(""), "from_csv", json!([]));
+    give(
+        json!("\"a"),
+        "try from_csv catch .",
+        json!("unterminated quoted CSV field"),
+    );
+}
+
+#[test]
+fn from_ndjson() {
+    let s = json!("{\"a\": 1}\n\n[2, 3]\r\n  \n\"four\"");
+    gives(
+        s,
+        "from_ndjson",
+        [json!({"a": 1}), json!([2, 3]), json!("four")],
+    );
+    give(json!("1\n2\n"), "[from_ndjson]", json!([1, 2]));
+    give(json!(""), "[from_ndjson]", json!([]));
+
+    let s = json!("1\n\n[2\n3");
+    let f = r#"[try from_ndjson catch startswith("cannot parse line 3 ")]"#;
+    give(s, f, json!([1, true, 3]));
+}
+
+#[test]
+fn jsonpath() {
+    let store = json!({"store": {
+        "book": [
+            {"title": "A", "price": 8, "isbn": "1"},
+            {"title": "B", "price": 12},
+            {"title": "C", "price": 9, "isbn": "2"}
+        ],
+        "bicycle": {"color": "red", "price": 20}
+    }});
+    let f = |path: &str| format!("[jsonpath({path:?})]");
+    give(
+        store.clone(),
+        &f("$.store.book[*].title"),
+        json!(["A", "B", "C"]),
+    );
+    // the order of `book` and `bicycle` depends on whether `json!` sorts keys
+    let prices = r#"[jsonpath("$..price")] | sort"#;
+    give(store.clone(), prices, json!([8, 9, 12, 20]));
+    give(store.clone(), &f("$.store.*.color"), json!(["red"]));
+    give(store.clone(), &f("$..book[-1:].title"), json!(["C"]));
+    give(store.clone(), &f("$..book[::-2].title"), json!(["C", "A"]));
+    give(
+        store.clone(),
+        &f("$.store.book[0, 2]['title']"),
+        json!(["A", "C"]),
+    );
+    let cheap = "$.store.book[?(@.price < 10 && @.isbn)].title";
+    give(store.clone(), &f(cheap), json!(["A", "C"]));
+    let title = "$..book[?@.title == 'B' || !@.isbn].price";
+    give(store.clone(), &f(title), json!([12]));
+    let max = "$..book[?@.price >= $.store.bicycle.price]";
+    give(store.clone(), &f(max), json!([]));
+    give(store.clone(), &f("$"), json!([store]));
+
+    give(
+        json!([[1, [2]], 3]),
+        "[jsonpath(\"$..[0]\")]",
+        json!([[1, [2]], 1, 2]),
+    );
+    give(json!(null), "try jsonpath(\"$.\") catch -1", json!(-1));
+    give(json!(null), "try jsonpath(\"a\") catch -1", json!(-1));
+}
+
 #[test]
 fn keys_unsorted() {
     give(json!([0, null, "a"]), "keys_unsorted", json!([0, 1, 2]));
@@ -203,6 +699,11 @@ yields!(length_str_namaste, r#""नमस्ते" | length"#, 6);
 yields!(length_obj, r#"{"a": 5, "b": 3} | length"#, 2);
 yields!(length_int_pos, " 2 | length", 2);
 yields!(length_int_neg, "-2 | length", 2);
+yields!(
+    length_int_min,
+    "(-9223372036854775807 - 1) | length",
+    9223372036854775808u64
+);
 yields!(length_float_pos, " 2.5 | length", 2.5);
 yields!(length_float_neg, "-2.5 | length", 2.5);
 
@@ -210,6 +711,23 @@ yields!(utf8bytelength_foo1, r#""foo" | utf8bytelength"#, 3);
 yields!(utf8bytelength_foo2, r#""ƒoo" | utf8bytelength"#, 4);
 yields!(utf8bytelength_namaste, r#""नमस्ते" | utf8bytelength"#, 18);
 
+#[test]
+fn levenshtein() {
+    give(json!(null), r#"levenshtein("kitten"; "sitting")"#, json!(3));
+    give(json!(null), r#"levenshtein(""; "abc")"#, json!(3));
+    give(json!(null), r#"levenshtein("abc"; "")"#, json!(3));
+    give(json!(null), r#"levenshtein("flaw"; "lawn")"#, json!(2));
+    // distances are computed on codepoints, not bytes
+    give(json!(null), r#"levenshtein("äöü"; "aöu")"#, json!(2));
+    let f = r#"[levenshtein("a", "ab"; "b")]"#;
+    give(json!(null), f, json!([1, 1]));
+    fail(
+        json!(null),
+        r#"levenshtein("a"; 1)"#,
+        Error::Type(Val::Int(1), Type::Str),
+    );
+}
+
 #[test]
 fn limit() {
     // a big WTF: jq outputs "1" here! that looks like another bug ...
@@ -258,6 +776,23 @@ yields!(
     [10.0, 11.0, 12.0, 13.0, 7.0, 8.0, 8.0, 9.0]
 );
 
+#[test]
+fn normalization() {
+    // precomposed and decomposed "é"
+    let (pre, de) = ("\u{e9}", "e\u{301}");
+    give(json!([pre, de]), "[.[] | nfc]", json!([pre, pre]));
+    give(json!([pre, de]), "[.[] | nfd]", json!([de, de]));
+    give(json!([pre, de]), "[.[] | nfc] | .[0] == .[1]", json!(true));
+    // compatibility decomposition of the ligature "ﬁ"
+    give(json!("\u{fb01}"), "[nfkc, nfkd]", json!(["fi", "fi"]));
+    give(
+        json!("\u{fb01}"),
+        "[nfc, nfd]",
+        json!(["\u{fb01}", "\u{fb01}"]),
+    );
+    fail(json!(0), "nfc", Error::Type(Val::Int(0), Type::Str));
+}
+
 yields!(range_pp, "[range(0; 6;  2)]", [0, 2, 4]);
 yields!(range_pn, "[range(0; 6; -2)]", json!([]));
 yields!(range_np, "[range(0; -6; 2)]", json!([]));
@@ -286,9 +821,15 @@ fn regex() {
           "string": s
         })
     };
-    let d1 = json!([c(00, "2012-03-14"), c(00, "2012"), c(05, "03"), c(08, "14")]);
-    let d2 = json!([c(12, "2013-01-01"), c(12, "2013"), c(17, "01"), c(20, "01")]);
-    let d3 = json!([c(27, "2014-07-05"), c(27, "2014"), c(32, "07"), c(35, "05")]);
+    // capture groups additionally have a name, which is `null` for unnamed groups
+    let g = |o, s| {
+        let mut c = c(o, s);
+        c["name"] = json!(null);
+        c
+    };
+    let d1 = json!([c(0, "2012-03-14"), g(0, "2012"), g(5, "03"), g(8, "14")]);
+    let d2 = json!([c(12, "2013-01-01"), g(12, "2013"), g(17, "01"), g(20, "01")]);
+    let d3 = json!([c(27, "2014-07-05"), g(27, "2014"), g(32, "07"), g(35, "05")]);
 
     give(json!(s), &f("matches", date, "g"), json!([d1, d2, d3]));
 
@@ -297,6 +838,17 @@ fn regex() {
 
     let out = json!(["", d1, ", 2013-01-01 and 2014-07-05"]);
     give(json!(s), &f("split_matches", date, ""), out);
+
+    // groups that do not participate in the match
+    let unmatched = json!({"offset": -1, "length": 0, "string": null, "name": "x"});
+    give(
+        json!("b"),
+        &f("matches", "(?<x>a)?b", ""),
+        json!([[c(0, "b"), unmatched]]),
+    );
+    // groups may occur in a different order than in the regex
+    let out = json!([[c(0, "ba"), g(1, "a"), g(0, "b")]]);
+    give(json!("ba"), &f("matches", "(?:(a)|(b))+", ""), out);
 }
 
 #[test]
@@ -314,6 +866,13 @@ fn round() {
     give(json!(-1.4), "floor", json!(-2));
     give(json!(-1.4), "ceil", json!(-1));
 
+    // floats that do not fit into machine-sized integers are rounded to big integers
+    give(
+        json!(1e30),
+        "floor | . == 1e30 and . + 1 > 1e30",
+        json!(true),
+    );
+
     let err = |v| Error::Type(Val::from(v), Type::Num);
     fail(json!([]), "round", err(json!([])));
     fail(json!({}), "round", err(json!({})));
@@ -341,6 +900,12 @@ fn ltrimstr() {
     give(json!("foobar"), r#"ltrimstr("foo")"#, json!("bar"));
     give(json!("foobar"), r#"ltrimstr("bar")"#, json!("foobar"));
     give(json!("اَلْعَرَبِيَّةُ"), r#"ltrimstr("ا")"#, json!("َلْعَرَبِيَّةُ"));
+
+    give(json!("abc"), r#"ltrimstr("ab")"#, json!("c"));
+    give(json!("abc"), r#"ltrimstr("x")"#, json!("abc"));
+    // non-string input or argument is passed through, like in jq
+    give(json!(1), r#"ltrimstr("a")"#, json!(1));
+    give(json!("1a"), "ltrimstr(1)", json!("1a"));
 }
 
 #[test]
@@ -349,4 +914,18 @@ fn rtrimstr() {
     give(json!("foobar"), r#"rtrimstr("bar")"#, json!("foo"));
     give(json!("foobar"), r#"rtrimstr("foo")"#, json!("foobar"));
     give(json!("اَلْعَرَبِيَّةُ"), r#"rtrimstr("ا")"#, json!("اَلْعَرَبِيَّةُ"));
+
+    give(json!(["bar"]), r#"rtrimstr("bar")"#, json!(["bar"]));
+}
+
+#[test]
+fn wordwrap() {
+    let s = json!("The quick brown fox jumps over the lazy dog");
+    let out = json!("The quick\nbrown fox\njumps over\nthe lazy\ndog");
+    give(s, "wordwrap(10)", out);
+    let s = json!("a  b\n\nextraordinarily long");
+    give(s, "wordwrap(5)", json!("a b\n\nextraordinarily\nlong"));
+    give(json!("äöü äöü"), "wordwrap(7)", json!("äöü äöü"));
+    give(json!("a b"), "[wordwrap(0, 3)]", json!(["a\nb", "a b"]));
+    give(json!("a"), "[try wordwrap(-1) catch 0]", json!([0]));
 }
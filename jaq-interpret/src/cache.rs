@@ -0,0 +1,586 @@
+//! Serialisation of compiled filters.
+//!
+//! This allows to store compiled filters, such as the standard library, on disk
+//! in order to skip parsing and compilation on subsequent runs.
+//!
+//! A serialised filter starts with a header containing
+//! the version of the serialisation format and a hash of a user-provided key,
+//! such as the source code of the filter.
+//! Deserialisation fails if the version or the key do not match.
+//! Because a serialisation may be corrupt, deserialisation also checks that
+//! all filter IDs, variables, and labels are bound, so that running
+//! a deserialised filter cannot panic.
+//! Native filters are stored by name and arity and
+//! resolved again on deserialisation.
+//! Every source that filters are located in is stored only once.
+
+use crate::filter::{Ast, Call, CallTyp, Def, Id, Native, Owned};
+use crate::path::{Part, Path};
+use crate::{Bind, Loc};
+use alloc::{collections::BTreeSet, rc::Rc, string::String, vec::Vec};
+use jaq_syn::filter::FoldType;
+use jaq_syn::path::Opt;
+use jaq_syn::{MathOp, OrdOp};
+
+const MAGIC: &[u8] = b"jaq\0";
+/// Version of the serialisation format.
+///
+/// This has to be increased whenever the serialisation or
+/// the meaning of the serialised filters changes.
+const FORMAT: u64 = 1;
+
+type Natives = [(String, usize, Native)];
+
+/// 64-bit FNV-1a hash, which is stable across platforms and versions.
+fn hash(bytes: &[u8]) -> u64 {
+    let init = 0xcbf2_9ce4_8422_2325;
+    let prime = 0x0100_0000_01b3;
+    bytes
+        .iter()
+        .fold(init, |h, b| (h ^ u64::from(*b)).wrapping_mul(prime))
+}
+
+pub fn serialize(f: &Owned, natives: &Natives, key: &[u8]) -> Vec<u8> {
+    let mut w = Writer(Vec::new(), Vec::new());
+    w.0.extend(MAGIC);
+    w.u64(FORMAT);
+    w.u64(hash(key));
+    w.usize(natives.len());
+    for (name, arity, _) in natives {
+        w.str(name);
+        w.usize(*arity);
+    }
+    w.usize(f.0 .0);
+    w.usize(f.1.len());
+    f.1.iter().for_each(|ast| w.ast(ast));
+    w.usize(f.2.len());
+    f.2.iter().for_each(|(id, def)| w.def(id, def));
+    w.0
+}
+
+pub fn deserialize(bytes: &[u8], natives: &Natives, vars: usize, key: &[u8]) -> Option<Owned> {
+    let mut r = Reader {
+        bytes,
+        natives,
+        len: 0,
+        map: Vec::new(),
+        sources: Vec::new(),
+    };
+    let header = r.take(MAGIC.len())? == MAGIC && r.u64()? == FORMAT && r.u64()? == hash(key);
+    if !header {
+        return None;
+    }
+    for _ in 0..r.usize()? {
+        let (name, arity) = (r.str()?, r.usize()?);
+        let i = natives
+            .iter()
+            .position(|(n, a, _)| n == name && *a == arity);
+        r.map.push(i);
+    }
+    let main = r.usize()?;
+    r.len = r.usize()?;
+    let asts = (0..r.len).map(|_| r.ast()).collect::<Option<Vec<_>>>()?;
+    let defs = (0..r.usize()?).map(|_| r.def());
+    let defs = defs.collect::<Option<Vec<_>>>()?;
+    let ok = main < asts.len() && r.bytes.is_empty() && bound(&asts, Id(main), vars);
+    ok.then(|| Owned::new(Id(main), asts, defs))
+}
+
+/// Return true if all variables and labels in filters reachable from `main` are bound,
+/// when running `main` with `vars` global variables.
+///
+/// This traverses every filter together with the number of variables bound when running it.
+fn bound(asts: &[Ast], main: Id, vars: usize) -> bool {
+    // every filter of a valid serialisation runs with fewer variables than this
+    let args = asts.iter().map(|ast| match ast {
+        Ast::Call(call) => call.args.len(),
+        _ => 0,
+    });
+    let limit = vars + asts.len() + args.sum::<usize>();
+    let mut seen = BTreeSet::new();
+    let mut todo = Vec::from([(main, vars)]);
+    while let Some((id, vars)) = todo.pop() {
+        if vars > limit {
+            return false;
+        }
+        if !seen.insert((id.0, vars)) {
+            continue;
+        }
+        let mut push = |ids: &[Id], vars| todo.extend(ids.iter().map(|id| (*id, vars)));
+        match &asts[id.0] {
+            Ast::Id | Ast::ToString | Ast::ObjEmpty => (),
+            Ast::Bool(_) | Ast::Int(_) | Ast::Float(_) | Ast::Num(_) | Ast::Str(_) => (),
+            Ast::Array(f) | Ast::Neg(f) | Ast::At(f, _) => push(&[*f], vars),
+            Ast::ObjSingle(l, r) | Ast::Try(l, r) | Ast::Pipe(l, false, r) => push(&[*l, *r], vars),
+            Ast::Comma(l, r) | Ast::Alt(l, r) => push(&[*l, *r], vars),
+            Ast::Update(l, r) | Ast::UpdateMath(l, _, r) | Ast::Assign(l, r) => {
+                push(&[*l, *r], vars)
+            }
+            Ast::Logic(l, _, r) | Ast::Math(l, _, r) | Ast::Ord(l, _, r) => push(&[*l, *r], vars),
+            Ast::Pipe(l, true, r) => {
+                push(&[*l], vars);
+                push(&[*r], vars + 1)
+            }
+            Ast::Ite(if_, then_, else_) => push(&[*if_, *then_, *else_], vars),
+            Ast::Fold(_, xs, init, f) => {
+                push(&[*xs, *init], vars);
+                push(&[*f], vars + 1)
+            }
+            Ast::Path(f, path) => {
+                push(&[*f], vars);
+                for (part, _) in &path.0 {
+                    match part {
+                        Part::Index(i) => push(&[*i], vars),
+                        Part::Range(from, upto) => {
+                            push(&from.iter().chain(upto).copied().collect::<Vec<_>>(), vars)
+                        }
+                    }
+                }
+            }
+            Ast::Var(v) | Ast::Break(v) if *v >= vars => return false,
+            Ast::Var(_) | Ast::Break(_) => (),
+            Ast::Label(f) => push(&[*f], vars + 1),
+            Ast::Call(call) if call.skip > vars => return false,
+            Ast::Call(call) => {
+                for arg in call.args.iter() {
+                    match arg {
+                        Bind::Var(id) | Bind::Fun(id) => push(&[*id], vars),
+                    }
+                }
+                push(&[call.id], vars - call.skip + call.args.len())
+            }
+            Ast::Native(_, _, args) | Ast::Concat(args) => push(args, vars),
+        }
+    }
+    true
+}
+
+/// Serialised bytes, and the sources that were serialised so far.
+struct Writer(Vec<u8>, Vec<Rc<(String, String)>>);
+
+impl Writer {
+    fn u8(&mut self, x: u8) {
+        self.0.push(x)
+    }
+
+    fn u64(&mut self, x: u64) {
+        self.0.extend(x.to_le_bytes())
+    }
+
+    fn usize(&mut self, x: usize) {
+        self.u64(x as u64)
+    }
+
+    fn str(&mut self, s: &str) {
+        self.usize(s.len());
+        self.0.extend(s.as_bytes())
+    }
+
+    fn id(&mut self, id: &Id) {
+        self.usize(id.0)
+    }
+
+    fn opt_id(&mut self, id: &Option<Id>) {
+        match id {
+            None => self.u8(0),
+            Some(id) => {
+                self.u8(1);
+                self.id(id)
+            }
+        }
+    }
+
+    fn ids(&mut self, ids: &[Id]) {
+        self.usize(ids.len());
+        ids.iter().for_each(|id| self.id(id))
+    }
+
+    fn path(&mut self, path: &Path<Id>) {
+        self.usize(path.0.len());
+        for (part, opt) in &path.0 {
+            match part {
+                Part::Index(i) => {
+                    self.u8(0);
+                    self.id(i)
+                }
+                Part::Range(from, upto) => {
+                    self.u8(1);
+                    self.opt_id(from);
+                    self.opt_id(upto)
+                }
+            }
+            self.u8(matches!(opt, Opt::Optional).into())
+        }
+    }
+
+    fn def(&mut self, id: &Id, def: &Def) {
+        self.id(id);
+        self.str(&def.name);
+        self.usize(def.arity);
+        match &def.loc {
+            None => self.u8(0),
+            Some((file, line)) => {
+                self.u8(1);
+                self.str(file);
+                self.usize(*line)
+            }
+        }
+    }
+
+    fn call(&mut self, call: &Call) {
+        self.id(&call.id);
+        self.u8(match call.typ {
+            CallTyp::Normal => 0,
+            CallTyp::Catch => 1,
+            CallTyp::Throw => 2,
+        });
+        self.usize(call.skip);
+        self.usize(call.args.len());
+        for arg in call.args.iter() {
+            match arg {
+                Bind::Var(id) => {
+                    self.u8(0);
+                    self.id(id)
+                }
+                Bind::Fun(id) => {
+                    self.u8(1);
+                    self.id(id)
+                }
+            }
+        }
+    }
+
+    fn loc(&mut self, loc: &Loc) {
+        match self.1.iter().position(|s| Rc::ptr_eq(s, &loc.source)) {
+            Some(i) => {
+                self.u8(0);
+                self.usize(i)
+            }
+            None => {
+                self.u8(1);
+                self.str(&loc.source.0);
+                self.str(&loc.source.1);
+                self.1.push(loc.source.clone())
+            }
+        }
+        self.usize(loc.span.start);
+        self.usize(loc.span.end)
+    }
+
+    fn bin(&mut self, tag: u8, l: &Id, r: &Id) {
+        self.u8(tag);
+        self.id(l);
+        self.id(r)
+    }
+
+    fn ast(&mut self, ast: &Ast) {
+        match ast {
+            Ast::Id => self.u8(0),
+            Ast::ToString => self.u8(1),
+            Ast::Int(i) => {
+                self.u8(2);
+                self.u64(*i as i64 as u64)
+            }
+            Ast::Float(f) => {
+                self.u8(3);
+                self.u64(f.to_bits())
+            }
+            Ast::Str(s) => {
+                self.u8(4);
+                self.str(s)
+            }
+            Ast::Array(f) => {
+                self.u8(5);
+                self.id(f)
+            }
+            Ast::ObjEmpty => self.u8(6),
+            Ast::ObjSingle(k, v) => self.bin(7, k, v),
+            Ast::Try(f, c) => self.bin(8, f, c),
+            Ast::Neg(f) => {
+                self.u8(9);
+                self.id(f)
+            }
+            Ast::Pipe(l, bind, r) => self.bin(10 + u8::from(*bind), l, r),
+            Ast::Comma(l, r) => self.bin(12, l, r),
+            Ast::Alt(l, r) => self.bin(13, l, r),
+            Ast::Ite(if_, then_, else_) => {
+                self.u8(14);
+                self.ids(&[*if_, *then_, *else_])
+            }
+            Ast::Fold(typ, xs, init, f) => {
+                self.u8(15);
+                self.u8(match typ {
+                    FoldType::Reduce => 0,
+                    FoldType::For => 1,
+                    FoldType::Foreach => 2,
+                });
+                self.ids(&[*xs, *init, *f])
+            }
+            Ast::Path(f, path) => {
+                self.u8(16);
+                self.id(f);
+                self.path(path)
+            }
+            Ast::Update(path, f) => self.bin(17, path, f),
+            Ast::UpdateMath(path, op, f) => {
+                self.bin(18, path, f);
+                self.u8(*op as u8)
+            }
+            Ast::Assign(path, f) => self.bin(19, path, f),
+            Ast::Logic(l, stop, r) => self.bin(20 + u8::from(*stop), l, r),
+            Ast::Math(l, op, r) => {
+                self.bin(22, l, r);
+                self.u8(*op as u8)
+            }
+            Ast::Ord(l, op, r) => {
+                self.bin(23, l, r);
+                self.u8(*op as u8)
+            }
+            Ast::Var(v) => {
+                self.u8(24);
+                self.usize(*v)
+            }
+            Ast::Call(call) => {
+                self.u8(25);
+                self.call(call)
+            }
+            Ast::Native(i, _, args) => {
+                self.u8(26);
+                self.usize(*i);
+                self.ids(args)
+            }
+            Ast::Concat(parts) => {
+                self.u8(27);
+                self.ids(parts)
+            }
+            Ast::Num(n) => {
+                self.u8(28);
+                self.str(n)
+            }
+            Ast::Label(f) => {
+                self.u8(29);
+                self.id(f)
+            }
+            Ast::Break(x) => {
+                self.u8(30);
+                self.usize(*x)
+            }
+            Ast::Bool(b) => self.u8(31 + u8::from(*b)),
+            Ast::At(f, loc) => {
+                self.u8(33);
+                self.id(f);
+                self.loc(loc)
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    natives: &'a Natives,
+    /// number of filters, used to validate filter IDs
+    len: usize,
+    /// indices of serialised natives in the current list of natives
+    map: Vec<Option<usize>>,
+    /// sources that were deserialised so far
+    sources: Vec<Rc<(String, String)>>,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < n {
+            return None;
+        }
+        let (l, r) = self.bytes.split_at(n);
+        self.bytes = r;
+        Some(l)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn usize(&mut self) -> Option<usize> {
+        self.u64()?.try_into().ok()
+    }
+
+    fn str(&mut self) -> Option<&'a str> {
+        let len = self.usize()?;
+        core::str::from_utf8(self.take(len)?).ok()
+    }
+
+    fn id(&mut self) -> Option<Id> {
+        let id = self.usize()?;
+        (id < self.len).then_some(Id(id))
+    }
+
+    fn opt_id(&mut self) -> Option<Option<Id>> {
+        match self.u8()? {
+            0 => Some(None),
+            1 => Some(Some(self.id()?)),
+            _ => None,
+        }
+    }
+
+    fn ids(&mut self) -> Option<Vec<Id>> {
+        (0..self.usize()?).map(|_| self.id()).collect()
+    }
+
+    fn id3(&mut self) -> Option<(Id, Id, Id)> {
+        match self.ids()?[..] {
+            [x, y, z] => Some((x, y, z)),
+            _ => None,
+        }
+    }
+
+    fn path(&mut self) -> Option<Path<Id>> {
+        let parts = (0..self.usize()?).map(|_| {
+            let part = match self.u8()? {
+                0 => Part::Index(self.id()?),
+                1 => Part::Range(self.opt_id()?, self.opt_id()?),
+                _ => return None,
+            };
+            let opt = match self.u8()? {
+                0 => Opt::Essential,
+                1 => Opt::Optional,
+                _ => return None,
+            };
+            Some((part, opt))
+        });
+        Some(Path(parts.collect::<Option<_>>()?))
+    }
+
+    fn def(&mut self) -> Option<(Id, Def)> {
+        let (id, name, arity) = (self.id()?, self.str()?.into(), self.usize()?);
+        let loc = match self.u8()? {
+            0 => None,
+            1 => Some((self.str()?.into(), self.usize()?)),
+            _ => return None,
+        };
+        Some((id, Def { name, arity, loc }))
+    }
+
+    fn loc(&mut self) -> Option<Loc> {
+        let source = match self.u8()? {
+            0 => {
+                let i = self.usize()?;
+                self.sources.get(i)?.clone()
+            }
+            1 => {
+                let source = Rc::new((self.str()?.into(), self.str()?.into()));
+                self.sources.push(source.clone());
+                source
+            }
+            _ => return None,
+        };
+        let span = self.usize()?..self.usize()?;
+        Some(Loc { source, span })
+    }
+
+    fn call(&mut self) -> Option<Call> {
+        let id = self.id()?;
+        let typ = match self.u8()? {
+            0 => CallTyp::Normal,
+            1 => CallTyp::Catch,
+            2 => CallTyp::Throw,
+            _ => return None,
+        };
+        let skip = self.usize()?;
+        let args = (0..self.usize()?).map(|_| match self.u8()? {
+            0 => Some(Bind::Var(self.id()?)),
+            1 => Some(Bind::Fun(self.id()?)),
+            _ => None,
+        });
+        let args = args.collect::<Option<_>>()?;
+        Some(Call {
+            id,
+            typ,
+            skip,
+            args,
+        })
+    }
+
+    fn math_op(&mut self) -> Option<MathOp> {
+        use MathOp::*;
+        [Add, Sub, Mul, Div, Rem]
+            .get(usize::from(self.u8()?))
+            .copied()
+    }
+
+    fn ord_op(&mut self) -> Option<OrdOp> {
+        use OrdOp::*;
+        [Lt, Le, Gt, Ge, Eq, Ne]
+            .get(usize::from(self.u8()?))
+            .copied()
+    }
+
+    fn ast(&mut self) -> Option<Ast> {
+        let tag = self.u8()?;
+        Some(match tag {
+            0 => Ast::Id,
+            1 => Ast::ToString,
+            2 => Ast::Int(self.u64()? as i64 as isize),
+            3 => Ast::Float(f64::from_bits(self.u64()?)),
+            4 => Ast::Str(self.str()?.into()),
+            5 => Ast::Array(self.id()?),
+            6 => Ast::ObjEmpty,
+            7 => Ast::ObjSingle(self.id()?, self.id()?),
+            8 => Ast::Try(self.id()?, self.id()?),
+            9 => Ast::Neg(self.id()?),
+            10 | 11 => Ast::Pipe(self.id()?, tag == 11, self.id()?),
+            12 => Ast::Comma(self.id()?, self.id()?),
+            13 => Ast::Alt(self.id()?, self.id()?),
+            14 => {
+                let (if_, then_, else_) = self.id3()?;
+                Ast::Ite(if_, then_, else_)
+            }
+            15 => {
+                let typ = match self.u8()? {
+                    0 => FoldType::Reduce,
+                    1 => FoldType::For,
+                    2 => FoldType::Foreach,
+                    _ => return None,
+                };
+                let (xs, init, f) = self.id3()?;
+                Ast::Fold(typ, xs, init, f)
+            }
+            16 => Ast::Path(self.id()?, self.path()?),
+            17 => Ast::Update(self.id()?, self.id()?),
+            18 => {
+                let (path, f) = (self.id()?, self.id()?);
+                Ast::UpdateMath(path, self.math_op()?, f)
+            }
+            19 => Ast::Assign(self.id()?, self.id()?),
+            20 | 21 => Ast::Logic(self.id()?, tag == 21, self.id()?),
+            22 => {
+                let (l, r) = (self.id()?, self.id()?);
+                Ast::Math(l, self.math_op()?, r)
+            }
+            23 => {
+                let (l, r) = (self.id()?, self.id()?);
+                Ast::Ord(l, self.ord_op()?, r)
+            }
+            24 => Ast::Var(self.usize()?),
+            25 => Ast::Call(self.call()?),
+            26 => {
+                let i = self.usize()?;
+                let i = (*self.map.get(i)?)?;
+                let args: Vec<_> = self.ids()?;
+                let (_, arity, native) = &self.natives[i];
+                (args.len() == *arity).then_some(())?;
+                Ast::Native(i, native.clone(), args.into())
+            }
+            27 => Ast::Concat(self.ids()?.into()),
+            28 => Ast::Num(Rc::new(self.str()?.into())),
+            29 => Ast::Label(self.id()?),
+            30 => Ast::Break(self.usize()?),
+            31 | 32 => Ast::Bool(tag == 32),
+            33 => Ast::At(self.id()?, self.loc()?),
+            _ => return None,
+        })
+    }
+}
@@ -1,6 +1,6 @@
 //! Runtime errors.
 use crate::Val;
-use alloc::string::ToString;
+use alloc::{boxed::Box, rc::Rc, string::String, string::ToString};
 use core::fmt;
 
 /// Errors that can occur during filter execution.
@@ -16,7 +16,9 @@ pub enum Error {
     Type(Val, Type),
     /// `1 - "a"`
     MathOp(Val, jaq_syn::MathOp, Val),
-    /// `{} | .[0]` or `[] | has("a")` or `{} | has(0)`
+    /// `{} | .[0]` or `0 | .a` or `0 | .[1:2]` or `[] | has("a")` or `{} | has(0)`
+    ///
+    /// Slicing is represented by an index `{"start": from, "end": upto}`, like in jq.
     Index(Val, Val),
 
     /// `[] | .[0] = 0`
@@ -24,11 +26,41 @@ pub enum Error {
     /// `0 |= .+1`
     PathExp,
 
-    /// Tail-recursive call.
+    /// `halt` or `"bye" | halt_error(1)`
+    ///
+    /// This stops the execution of the whole filter with an exit code and
+    /// an optional value to be reported, and cannot be caught by `try`.
+    Halt(Option<Val>, isize),
+
+    /// Break out of a label.
     ///
-    /// This is used internally to execute tail-recursive filters.
+    /// This is used internally to execute `label $x | ... break $x ...`.
     /// If this can be observed by users, then this is a bug.
-    TailCall(crate::filter::TailCall),
+    Break(crate::filter::Break),
+
+    /// Error that occurred in the filter at the given location.
+    ///
+    /// Errors are located only if they occur in filters from sources
+    /// given by [`crate::ParseCtx::set_source`] or [`crate::ParseCtx::insert_module`].
+    /// An error that occurs in a definition without source, such as
+    /// from the standard library, is located at the call to the definition.
+    At(Box<Error>, Loc),
+}
+
+/// Location of a filter in its source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Loc {
+    /// name and contents of the source
+    pub source: Rc<(String, String)>,
+    /// range of the filter in the contents of the source, counted in characters
+    pub span: jaq_syn::Span,
+}
+
+impl Loc {
+    /// Return the line (starting from 1) where the filter starts.
+    pub fn line(&self) -> usize {
+        crate::hir::line(&self.source.1, self.span.start)
+    }
 }
 
 /// Types and sets of types.
@@ -45,9 +77,9 @@ pub enum Type {
     Str,
     /// `0 | sort` or `0 | implode` or `[] | .[0:] = 0`
     Arr,
-    /// `0 | .[]` or `0 | .[0]` or `0 | keys` (array or object)
+    /// `0 | .[]` or `0 | keys` (array or object)
     Iter,
-    /// `{}[0:1]` (string or array)
+    /// string or array
     Range,
 }
 
@@ -56,6 +88,7 @@ impl Error {
     pub fn as_val(self) -> Val {
         match self {
             Self::Val(ev) => ev,
+            Self::At(e, _) => e.as_val(),
             _ => Val::str(self.to_string()),
         }
     }
@@ -64,6 +97,32 @@ impl Error {
     pub fn str(s: impl ToString) -> Self {
         Self::Val(Val::str(s.to_string()))
     }
+
+    /// Return the location of the error, if it is known.
+    pub fn loc(&self) -> Option<&Loc> {
+        match self {
+            Self::At(_, loc) => Some(loc),
+            _ => None,
+        }
+    }
+
+    /// Return the error without its location.
+    pub fn unlocated(&self) -> &Self {
+        match self {
+            Self::At(e, _) => e,
+            e => e,
+        }
+    }
+
+    /// Locate the error if a location is given,
+    /// unless the error is located already or it does not stem from a filter.
+    pub(crate) fn at(self, loc: Option<&Loc>) -> Self {
+        match (self, loc) {
+            (e @ (Self::Halt(..) | Self::Break(_) | Self::At(..)), _) => e,
+            (e, None) => e,
+            (e, Some(loc)) => Self::At(Box::new(e), loc.clone()),
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -73,10 +132,16 @@ impl fmt::Display for Error {
             Self::Val(v) => v.fmt(f),
             Self::Type(v, ty) => write!(f, "cannot use {v} as {ty}"),
             Self::MathOp(l, op, r) => write!(f, "cannot calculate {l} {op} {r}"),
-            Self::Index(v, i) => write!(f, "cannot index {v} with {i}"),
+            // like jq, show the key only if it is a string
+            Self::Index(v, i @ Val::Str(_)) => write!(f, "Cannot index {} with {i}", type_name(v)),
+            Self::Index(v, i) => {
+                write!(f, "Cannot index {} with {}", type_name(v), type_name(i))
+            }
             Self::IndexOutOfBounds(i) => write!(f, "index {i} is out of bounds"),
             Self::PathExp => write!(f, "invalid path expression"),
-            Self::TailCall(_) => panic!(),
+            Self::Halt(_, code) => write!(f, "halted with exit code {code}"),
+            Self::At(e, _) => e.fmt(f),
+            Self::Break(_) => panic!(),
         }
     }
 }
@@ -84,6 +149,18 @@ impl fmt::Display for Error {
 #[cfg(feature = "std")]
 impl std::error::Error for Error {}
 
+/// Name of the type of a value, as yielded by `type`.
+fn type_name(v: &Val) -> &'static str {
+    match v {
+        Val::Null => "null",
+        Val::Bool(_) => "boolean",
+        Val::Int(_) | Val::Float(_) | Val::BigInt(_) | Val::Num(_) => "number",
+        Val::Str(_) => "string",
+        Val::Arr(_) => "array",
+        Val::Obj(_) => "object",
+    }
+}
+
 impl fmt::Display for Type {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -1,25 +1,34 @@
 use crate::box_iter::{box_once, flat_map_with, map_with, BoxIter};
 use crate::results::{fold, recurse, then, Fold, Results};
 use crate::val::{Val, ValR, ValRs};
+use crate::vm::{Code, Vm};
 use crate::{rc_lazy_list, Bind, Ctx, Error};
-use alloc::{boxed::Box, string::String, vec::Vec};
-use core::ops::ControlFlow;
+use alloc::{boxed::Box, rc::Rc, string::String, vec::Vec};
 use dyn_clone::DynClone;
 use jaq_syn::filter::FoldType;
 use jaq_syn::{MathOp, OrdOp};
 
 /// Function from a value to a stream of value results.
 #[derive(Debug, Clone)]
-pub struct Owned(Id, Box<[Ast]>);
+pub struct Owned(pub(crate) Id, pub(crate) Code, pub(crate) Box<[(Id, Def)]>);
 
 impl Default for Owned {
     fn default() -> Self {
-        Self(Id(0), Box::new([Ast::Id]))
+        Self::new(Id(0), Vec::from([Ast::Id]), Vec::new())
     }
 }
 
+/// Name and location of a definition, used to report profiling results.
+#[derive(Clone, Debug)]
+pub(crate) struct Def {
+    pub name: String,
+    pub arity: usize,
+    /// name of the source file and line of the definition, if known
+    pub loc: Option<(String, usize)>,
+}
+
 #[derive(Debug, Copy, Clone)]
-pub struct Ref<'a>(Id, &'a [Ast]);
+pub struct Ref<'a>(pub(crate) Id, pub(crate) &'a Code);
 
 #[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Id(pub usize);
@@ -28,14 +37,29 @@ pub struct Id(pub usize);
 pub enum CallTyp {
     /// everything that is not tail-recursive
     Normal,
-    /// set up a tail-recursion handler
+    /// call whose definition contains calls that throw
     Catch,
-    /// throw a tail-recursion exception to be caught by the handler
+    /// tail-recursive call, whose errors are located like those of the catching call
     Throw,
 }
 
+/// Identifier of the label to break out of.
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct TailCall(Id, crate::Vars, Val);
+pub struct Break(pub(crate) usize);
+
+/// Run `f` in a context with a new label, stopping when `f` breaks out of that label.
+fn label<'a, I>(ctx: Ctx<'a>, f: impl FnOnce(Ctx<'a>) -> I) -> ValRs<'a>
+where
+    I: Iterator<Item = ValR> + 'a,
+{
+    let ctx = ctx.cons_var(Val::Null);
+    let id = ctx.vars.id(0);
+    let ys = f(ctx).map_while(move |y| match y {
+        Err(Error::Break(Break(b))) if b == id => None,
+        y => Some(y),
+    });
+    Box::new(ys.fuse())
+}
 
 #[derive(Clone, Debug)]
 pub(crate) struct Call {
@@ -46,8 +70,18 @@ pub(crate) struct Call {
 }
 
 impl Owned {
-    pub(crate) fn new(main: Id, recs: Vec<Ast>) -> Self {
-        Self(main, recs.into())
+    pub(crate) fn new(main: Id, recs: Vec<Ast>, defs: Vec<(Id, Def)>) -> Self {
+        Self(main, Code::new(recs.into()), defs.into())
+    }
+
+    /// Run the filter on a value and return its first output, if any.
+    ///
+    /// The filter is run without inputs, and
+    /// its outputs after the first one are not evaluated.
+    pub fn eval_first(&self, input: Val) -> Result<Option<Val>, Error> {
+        let inputs = crate::RcIter::new(core::iter::empty());
+        let mut outputs = self.run((Ctx::new([], &inputs), input));
+        outputs.next().transpose()
     }
 }
 
@@ -58,8 +92,10 @@ pub(crate) enum Ast {
     Id,
     ToString,
 
+    Bool(bool),
     Int(isize),
     Float(f64),
+    Num(Rc<String>),
     Str(String),
     Array(Id),
     ObjEmpty,
@@ -104,12 +140,24 @@ pub(crate) enum Ast {
 
     Logic(Id, bool, Id),
     Math(Id, MathOp, Id),
+    /// concatenation of the outputs of filters, used for string interpolation
+    ///
+    /// `Concat([x0, x1, ..., xn])` is equivalent to `x0 + (x1 + ... + xn)`.
+    Concat(Box<[Id]>),
     Ord(Id, OrdOp, Id),
 
     Var(usize),
+    /// `label $x | f`, binding the label as variable
+    Label(Id),
+    /// `break $x`, where the label is given by its variable index
+    Break(usize),
     Call(Call),
 
-    Native(Native, Box<[Id]>),
+    /// native filter, its index in the list of natives, and its arguments
+    Native(usize, Native, Box<[Id]>),
+
+    /// filter at the given location, where its errors are located unless they are already
+    At(Id, crate::Loc),
 }
 
 // we can unfortunately not make a `Box<dyn ... + Clone>`
@@ -134,16 +182,55 @@ where
             (ctx, cv, args),
             |y, (ctx, cv, args)| then(y, |y| bind_vars(args, ctx.cons_var(y), cv)),
         ),
-        Some(Bind::Fun(Ref(arg, _defs))) => bind_vars(args, ctx.cons_fun((arg, cv.0.clone())), cv),
+        Some(Bind::Fun(arg)) => {
+            let ctx = bind_fun(arg, ctx, &cv.0);
+            bind_vars(args, ctx, cv)
+        }
         None => box_once(Ok((ctx, cv.1))),
     }
 }
 
-fn run_cvs<'a>(f: Ref<'a>, cvs: Results<'a, Cv<'a>, Error>) -> impl Iterator<Item = ValR> + 'a {
-    cvs.flat_map(move |cv| then(cv, |cv| f.run(cv)))
+/// Enhance the context `ctx` with the filter argument `arg` of a call from `caller`.
+pub(crate) fn bind_fun<'a>(Ref(arg, defs): Ref<'a>, ctx: Ctx<'a>, caller: &Ctx<'a>) -> Ctx<'a> {
+    if is_const(arg, defs, caller) {
+        let mut ys = Ref(arg, defs).run((caller.clone(), Val::Null));
+        match (ys.next(), ys.next()) {
+            (Some(Ok(y)), None) => return ctx.cons_var(y),
+            // errors are yielded only when the argument is used
+            _ => return ctx.cons_fun((arg, caller.clone())),
+        }
+    }
+    let fun = match &defs[arg.0] {
+        // if the argument is a filter argument of the caller, pass it on as is;
+        // wrapping it would create ever longer chains of closures
+        // in recursive filters such as `def f(g): g | f(g)`
+        Ast::Var(v) => match caller.vars.get(*v) {
+            Some(Bind::Fun((f, vars))) => (*f, caller.with_vars(vars.clone())),
+            _ => (arg, caller.clone()),
+        },
+        _ => (arg, caller.clone()),
+    };
+    ctx.cons_fun(fun)
+}
+
+/// Return true if the filter `id` yields at most one value, regardless of its input.
+///
+/// Such filter arguments are evaluated directly when calling a definition.
+/// This is to prevent chains of closures in recursive filters such as
+/// `def f(n): if n > 0 then f(n - 1) else . end`, where
+/// evaluating `n` would otherwise take stack space proportional to the recursion depth.
+fn is_const(id: Id, defs: &[Ast], ctx: &Ctx) -> bool {
+    let is_const = |id: &Id| is_const(*id, defs, ctx);
+    match &defs[id.0] {
+        Ast::Bool(_) | Ast::Int(_) | Ast::Float(_) | Ast::Num(_) | Ast::Str(_) => true,
+        Ast::Var(v) => matches!(ctx.vars.get(*v), Some(Bind::Var(_))),
+        Ast::Neg(f) | Ast::At(f, _) => is_const(f),
+        Ast::Math(l, _, r) | Ast::Ord(l, _, r) | Ast::Logic(l, _, r) => is_const(l) && is_const(r),
+        _ => false,
+    }
 }
 
-fn reduce<'a, T: Clone + 'a, F>(xs: Results<'a, T, Error>, init: Val, f: F) -> ValRs
+fn reduce<'a, T: Clone + 'a, F>(xs: Results<'a, T, Error>, init: Val, f: F) -> ValRs<'a>
 where
     F: Fn(T, Val) -> ValRs<'a> + 'a,
 {
@@ -153,11 +240,11 @@ where
 
 type Cv<'c> = (Ctx<'c>, Val);
 
-/// A filter which is implemented using function pointers.
+/// A filter which is implemented using function pointers or closures.
 #[derive(Clone)]
 pub struct Native {
-    run: RunPtr,
-    update: UpdatePtr,
+    run: Fun<RunPtr, RunFn>,
+    update: Fun<UpdatePtr, UpdateFn>,
 }
 
 /// Run function pointer.
@@ -165,6 +252,27 @@ pub type RunPtr = for<'a> fn(Args<'a>, Cv<'a>) -> ValRs<'a>;
 /// Update function pointer.
 pub type UpdatePtr = for<'a> fn(Args<'a>, Cv<'a>, Box<dyn Update<'a> + 'a>) -> ValRs<'a>;
 
+type RunFn = dyn for<'a> Fn(Args<'a>, Cv<'a>) -> ValRs<'a>;
+type UpdateFn = dyn for<'a> Fn(Args<'a>, Cv<'a>, Box<dyn Update<'a> + 'a>) -> ValRs<'a>;
+
+/// Function pointer or closure.
+///
+/// We keep function pointers separate so that native filters that
+/// do not capture anything can still be constructed in `const` contexts.
+enum Fun<P, F: ?Sized> {
+    Ptr(P),
+    Rc(Rc<F>),
+}
+
+impl<P: Copy, F: ?Sized> Clone for Fun<P, F> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Ptr(p) => Self::Ptr(*p),
+            Self::Rc(f) => Self::Rc(f.clone()),
+        }
+    }
+}
+
 impl Native {
     /// Create a native filter from a run function, without support for updates.
     pub const fn new(run: RunPtr) -> Self {
@@ -173,7 +281,51 @@ impl Native {
 
     /// Create a native filter from a run function and an update function (used for `filter |= ...`).
     pub const fn with_update(run: RunPtr, update: UpdatePtr) -> Self {
-        Self { run, update }
+        Self {
+            run: Fun::Ptr(run),
+            update: Fun::Ptr(update),
+        }
+    }
+
+    /// Create a native filter from a run closure, without support for updates.
+    ///
+    /// Unlike [`Self::new`], this allows the filter to capture state,
+    /// such as a database connection of the host application.
+    /// The arguments of the filter can be obtained via [`Args::get`].
+    pub fn from_fn<R>(run: R) -> Self
+    where
+        R: for<'a> Fn(Args<'a>, Cv<'a>) -> ValRs<'a> + 'static,
+    {
+        Self {
+            run: Fun::Rc(Rc::new(run)),
+            update: Fun::Ptr(|_, _, _| box_once(Err(Error::PathExp))),
+        }
+    }
+
+    /// Create a native filter from a run closure and an update closure.
+    pub fn from_fn_with_update<R, U>(run: R, update: U) -> Self
+    where
+        R: for<'a> Fn(Args<'a>, Cv<'a>) -> ValRs<'a> + 'static,
+        U: for<'a> Fn(Args<'a>, Cv<'a>, Box<dyn Update<'a> + 'a>) -> ValRs<'a> + 'static,
+    {
+        Self {
+            run: Fun::Rc(Rc::new(run)),
+            update: Fun::Rc(Rc::new(update)),
+        }
+    }
+
+    pub(crate) fn run<'a>(&'a self, args: Args<'a>, cv: Cv<'a>) -> ValRs<'a> {
+        match &self.run {
+            Fun::Ptr(f) => f(args, cv),
+            Fun::Rc(f) => f(args, cv),
+        }
+    }
+
+    fn update<'a>(&'a self, args: Args<'a>, cv: Cv<'a>, f: Box<dyn Update<'a> + 'a>) -> ValRs<'a> {
+        match &self.update {
+            Fun::Ptr(u) => u(args, cv, f),
+            Fun::Rc(u) => u(args, cv, f),
+        }
     }
 }
 
@@ -185,7 +337,7 @@ impl core::fmt::Debug for Native {
 
 /// Arguments passed to a native filter.
 #[derive(Copy, Clone)]
-pub struct Args<'a>(&'a [Id], &'a [Ast]);
+pub struct Args<'a>(pub(crate) &'a [Id], pub(crate) &'a Code);
 
 impl<'a> Args<'a> {
     /// Obtain the n-th argument passed to the filter, crash if it is not given.
@@ -210,130 +362,7 @@ impl<'a> FilterT<'a> for &'a Owned {
 
 impl<'a> FilterT<'a> for Ref<'a> {
     fn run(self, cv: Cv<'a>) -> ValRs<'a> {
-        use core::iter::{once, once_with};
-        // wrap a filter AST with the filter definitions
-        let w = move |id: &Id| Ref(*id, self.1);
-        match &self.1[self.0 .0] {
-            Ast::Id => box_once(Ok(cv.1)),
-            Ast::ToString => Box::new(once_with(move || Ok(Val::str(cv.1.to_string_or_clone())))),
-            Ast::Int(n) => box_once(Ok(Val::Int(*n))),
-            Ast::Float(x) => box_once(Ok(Val::Float(*x))),
-            Ast::Str(s) => Box::new(once_with(move || Ok(Val::str(s.clone())))),
-            Ast::Array(f) => Box::new(once_with(move || {
-                w(f).run(cv).collect::<Result<_, _>>().map(Val::arr)
-            })),
-            Ast::ObjEmpty => box_once(Ok(Val::Obj(Default::default()))),
-            Ast::ObjSingle(k, v) => Box::new(
-                Self::cartesian(w(k), w(v), cv)
-                    .map(|(k, v)| Ok(Val::obj([(k?.to_str()?, v?)].into_iter().collect()))),
-            ),
-            Ast::Try(f, c) => Box::new(w(f).run((cv.0.clone(), cv.1)).flat_map(move |y| {
-                y.map_or_else(
-                    |e| w(c).run((cv.0.clone(), e.as_val())),
-                    |v| box_once(Ok(v)),
-                )
-            })),
-            Ast::Neg(f) => Box::new(w(f).run(cv).map(|v| -v?)),
-
-            // `l | r`
-            Ast::Pipe(l, false, r) => {
-                let l = w(l).run((cv.0.clone(), cv.1));
-                flat_map_with(l, cv.0, move |y, ctx| then(y, |y| w(r).run((ctx, y))))
-            }
-            // `l as $x | r`
-            Ast::Pipe(l, true, r) => w(l).pipe(cv, move |cv, y| w(r).run((cv.0.cons_var(y), cv.1))),
-
-            Ast::Comma(l, r) => Box::new(w(l).run(cv.clone()).chain(w(r).run(cv))),
-            Ast::Alt(l, r) => {
-                let mut l = w(l)
-                    .run(cv.clone())
-                    .filter(|v| v.as_ref().map_or(true, |v| v.as_bool()));
-                match l.next() {
-                    Some(head) => Box::new(once(head).chain(l)),
-                    None => w(r).run(cv),
-                }
-            }
-            Ast::Ite(if_, then_, else_) => w(if_).pipe(cv, move |cv, v| {
-                w(if v.as_bool() { then_ } else { else_ }).run(cv)
-            }),
-            Ast::Path(f, path) => {
-                let path = path.map_ref(|i| {
-                    let cv = cv.clone();
-                    crate::into_iter::collect_if_once(move || w(i).run(cv))
-                });
-                flat_map_with(w(f).run(cv), path, |y, path| {
-                    then(y, |y| {
-                        flat_map_with(path.explode(), y, |path, y| then(path, |path| path.run(y)))
-                    })
-                })
-            }
-
-            Ast::Update(path, f) => w(path).update(
-                (cv.0.clone(), cv.1),
-                Box::new(move |v| w(f).run((cv.0.clone(), v))),
-            ),
-            Ast::UpdateMath(path, op, f) => w(f).pipe(cv, move |cv, y| {
-                w(path).update(cv, Box::new(move |x| box_once(op.run(x, y.clone()))))
-            }),
-            Ast::Assign(path, f) => w(f).pipe(cv, move |cv, y| {
-                w(path).update(cv, Box::new(move |_| box_once(Ok(y.clone()))))
-            }),
-            Ast::Logic(l, stop, r) => w(l).pipe(cv, move |cv, l| {
-                if l.as_bool() == *stop {
-                    box_once(Ok(Val::Bool(*stop)))
-                } else {
-                    Box::new(w(r).run(cv).map(|r| Ok(Val::Bool(r?.as_bool()))))
-                }
-            }),
-            Ast::Math(l, op, r) => {
-                Box::new(Self::cartesian(w(l), w(r), cv).map(|(x, y)| op.run(x?, y?)))
-            }
-            Ast::Ord(l, op, r) => Box::new(
-                Self::cartesian(w(l), w(r), cv).map(|(x, y)| Ok(Val::Bool(op.run(&x?, &y?)))),
-            ),
-
-            Ast::Fold(typ, xs, init, f) => {
-                let xs = rc_lazy_list::List::from_iter(w(xs).run(cv.clone()));
-                let init = w(init).run(cv.clone());
-                let f = move |x, v| w(f).run((cv.0.clone().cons_var(x), v));
-                use Fold::{Input, Output};
-                match typ {
-                    FoldType::Reduce => Box::new(fold(false, xs, Output(init), f)),
-                    FoldType::For => Box::new(fold(true, xs, Output(init), f)),
-                    FoldType::Foreach => flat_map_with(init, xs, move |i, xs| {
-                        then(i, |i| Box::new(fold(true, xs, Input(i), f.clone())))
-                    }),
-                }
-            }
-
-            Ast::Var(v) => match cv.0.vars.get(*v).unwrap() {
-                Bind::Var(v) => box_once(Ok(v.clone())),
-                Bind::Fun(f) => w(&f.0).run((cv.0.with_vars(f.1.clone()), cv.1)),
-            },
-            Ast::Call(call) => {
-                let def = w(&call.id);
-                let ctx = cv.0.clone().skip_vars(call.skip);
-                let inputs = cv.0.inputs;
-                let cvs = bind_vars(call.args.iter().map(move |a| a.as_ref().map(w)), ctx, cv);
-                match call.typ {
-                    CallTyp::Normal => Box::new(run_cvs(def, cvs)),
-                    CallTyp::Catch => Box::new(crate::Stack::new(
-                        Vec::from([Box::new(run_cvs(def, cvs)) as Results<_, _>]),
-                        move |r| match r {
-                            Err(Error::TailCall(TailCall(id, vars, v))) if id == call.id => {
-                                ControlFlow::Continue(def.run((Ctx { inputs, vars }, v)))
-                            }
-                            Ok(_) | Err(_) => ControlFlow::Break(r),
-                        },
-                    )),
-                    CallTyp::Throw => Box::new(cvs.map(move |cv| {
-                        cv.and_then(|cv| Err(Error::TailCall(TailCall(call.id, cv.0.vars, cv.1))))
-                    })),
-                }
-            }
-
-            Ast::Native(Native { run, .. }, args) => (run)(Args(args, self.1), cv),
-        }
+        Box::new(Vm::new(self, cv))
     }
 
     fn update(self, cv: Cv<'a>, f: Box<dyn Update<'a> + 'a>) -> ValRs<'a> {
@@ -341,21 +370,21 @@ impl<'a> FilterT<'a> for Ref<'a> {
         let w = move |id: &Id| Ref(*id, self.1);
         match &self.1[self.0 .0] {
             Ast::ToString => err,
-            Ast::Int(_) | Ast::Float(_) | Ast::Str(_) => err,
+            Ast::Bool(_) | Ast::Int(_) | Ast::Float(_) | Ast::Num(_) | Ast::Str(_) => err,
             Ast::Array(_) | Ast::ObjEmpty | Ast::ObjSingle(..) => err,
             Ast::Neg(_) | Ast::Logic(..) | Ast::Math(..) | Ast::Ord(..) => err,
+            Ast::Concat(_) => err,
             Ast::Update(..) | Ast::UpdateMath(..) | Ast::Assign(..) => err,
+            // this includes alternative patterns, such as `.[] as [$x] ?// $x | .`
+            Ast::Try(..) | Ast::Alt(..) => err,
 
             // these are up for grabs to implement :)
-            Ast::Try(..) | Ast::Alt(..) => todo!(),
             Ast::Fold(..) => todo!(),
 
             Ast::Id => f(cv.1),
             Ast::Path(l, path) => {
-                let path = path.map_ref(|i| {
-                    let cv = cv.clone();
-                    crate::into_iter::collect_if_once(move || w(i).run(cv))
-                });
+                let path =
+                    path.map_ref(|i| crate::into_iter::collect_if_once(w(i).run(cv.clone())));
                 let f = move |v| {
                     let mut paths = path.clone().explode();
                     box_once(paths.try_fold(v, |acc, path| path?.update(acc, &f)))
@@ -391,7 +420,14 @@ impl<'a> FilterT<'a> for Ref<'a> {
                 reduce(cvs, init, move |cv, v| def.update((cv.0, v), f.clone()))
             }
 
-            Ast::Native(Native { update, .. }, args) => (update)(Args(args, self.1), cv, f),
+            Ast::Label(l) => label(cv.0, |ctx| w(l).update((ctx, cv.1), f)),
+            Ast::Break(x) => box_once(Err(Error::Break(Break(cv.0.vars.id(*x))))),
+
+            Ast::Native(_, native, args) => native.update(Args(args, self.1), cv, f),
+            Ast::At(l, loc) => Box::new(
+                w(l).update(cv, f)
+                    .map(move |y| y.map_err(|e| e.at(Some(loc)))),
+            ),
         }
     }
 }
@@ -440,7 +476,7 @@ pub trait FilterT<'a>: Clone + 'a {
     /// if `outer` is true, it returns values for which `f` yields no output.
     /// This is useful to implement `while` and `until`.
     #[deprecated(since = "1.2.0")]
-    fn recurse(self, inner: bool, outer: bool, cv: Cv<'a>) -> ValRs {
+    fn recurse(self, inner: bool, outer: bool, cv: Cv<'a>) -> ValRs<'a> {
         let f = move |v| self.clone().run((cv.0.clone(), v));
         Box::new(recurse(inner, outer, box_once(Ok(cv.1)), f))
     }
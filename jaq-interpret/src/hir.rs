@@ -6,63 +6,280 @@
 //! always succeed and do not have to fight with shadowing.
 
 use crate::Bind;
-use alloc::{boxed::Box, string::String, vec::Vec};
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    rc::Rc,
+    string::{String, ToString},
+    vec::Vec,
+};
 use core::fmt;
-use jaq_syn::filter::{BinaryOp, Filter as Expr, Fold};
-use jaq_syn::{Arg, Spanned};
+use jaq_syn::filter::{BinaryOp, Filter as Expr, Fold, KeyPattern, Pattern};
+use jaq_syn::path::{Opt, Part};
+use jaq_syn::{Arg, Import, Span, Spanned, Str};
 
 pub type Filter = jaq_syn::filter::Filter<Call, VarIdx, Num>;
-pub type Main = jaq_syn::Main<Filter>;
-pub type Def = jaq_syn::Def<Main>;
+
+pub struct Main {
+    pub defs: Vec<Def>,
+    pub body: Spanned<Filter>,
+}
+
+pub struct Def {
+    pub lhs: jaq_syn::Call,
+    pub rhs: Main,
+    /// name of the source file and line of the definition, if known
+    pub loc: Option<(String, usize)>,
+    /// name and contents of the source that the spans in the definition refer to, if known
+    pub source: Option<Rc<(String, String)>>,
+}
+
+/// Definitions of a module, and whether their spans refer to the source of the module.
+///
+/// This is not the case for definitions given by [`crate::ParseCtx::insert_defs`].
+pub type ModDefs = (ModId, bool, Vec<jaq_syn::Def>);
 
 #[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq)]
 pub struct RelId(pub usize);
 pub type VarIdx = usize;
 pub type ArgIdx = usize;
+/// index of a module, where 0 is the main module
+pub type ModId = usize;
+
+/// library modules by the path under which they can be imported, with their source
+pub type Modules = BTreeMap<String, (jaq_syn::Module<Vec<jaq_syn::Def>>, Option<(String, String)>)>;
 
 #[derive(Debug, Clone)]
 pub enum Call {
-    Def { id: RelId, skip: usize },
+    Def {
+        id: RelId,
+        skip: usize,
+    },
     Arg(ArgIdx),
-    Native(crate::filter::Native),
+    /// native filter and its index in the list of natives
+    Native(usize, crate::filter::Native),
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub enum Num {
     Float(f64),
     Int(isize),
+    /// literal that would change its representation as `Int` or `Float`,
+    /// such as an integer not fitting into `Int` or a float like `1E+1000`
+    Dec(String),
 }
 
 impl Num {
     fn parse(n: &str) -> Result<Self, Self> {
         if n.contains(['.', 'e', 'E']) {
-            n.parse().map(Num::Float).map_err(|_| Self::Float(0.))
+            let f = n.parse().map_err(|_| Self::Float(0.))?;
+            // keep the literal if printing the float would not reproduce it
+            let lossless = crate::Val::Float(f).to_string() == n;
+            Ok(if lossless {
+                Num::Float(f)
+            } else {
+                Num::Dec(n.into())
+            })
         } else {
-            n.parse().map(Num::Int).map_err(|_| Self::Int(0))
+            Ok(n.parse().map_or_else(|_| Num::Dec(n.into()), Num::Int))
         }
     }
 }
 
 pub enum Error {
-    Undefined(Arg),
+    /// undefined variable or filter, the latter given by its name and arity
+    Undefined(Arg<String, (String, usize)>),
+    /// call to a filter with given name and arity,
+    /// where the filter is defined only with the other given arities
+    Arity(String, usize, Vec<usize>),
+    /// `break $x` outside of `label $x | ...`
+    UndefinedLabel(String),
     Num(Num),
+    /// module with given path was not inserted into the context
+    ModuleNotFound(String),
+    /// module with given path imports itself, possibly via other modules
+    ModuleCycle(String),
+    /// error in the module with given file name, occurring at the given line
+    InModule(String, Option<usize>, Box<Self>),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Self::Undefined(Bind::Var(_)) => "undefined variable",
-            Self::Undefined(Bind::Fun(_)) => "undefined filter",
-            Self::Num(Num::Float(_)) => "cannot interpret as floating-point number",
-            Self::Num(Num::Int(_)) => "cannot interpret as machine-size integer",
+            Self::Undefined(Bind::Var(v)) => write!(f, "undefined variable ${v}"),
+            Self::Undefined(Bind::Fun((name, arity))) => {
+                write!(f, "undefined function {name}/{arity}")
+            }
+            Self::Arity(name, arity, arities) => {
+                write!(f, "undefined function {name}/{arity}, did you mean ")?;
+                for (i, a) in arities.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { " or " };
+                    write!(f, "{sep}{name}/{a}")?;
+                }
+                "?".fmt(f)
+            }
+            Self::UndefinedLabel(x) => write!(f, "undefined label ${x}"),
+            Self::Num(Num::Float(_)) => "cannot interpret as floating-point number".fmt(f),
+            Self::Num(Num::Int(_) | Num::Dec(_)) => {
+                "cannot interpret as machine-size integer".fmt(f)
+            }
+            Self::ModuleNotFound(path) => write!(f, "module not found: {path}"),
+            Self::ModuleCycle(path) => write!(f, "module {path} imports itself"),
+            Self::InModule(file, Some(line), e) => write!(f, "{e} in {file}:{line}"),
+            Self::InModule(file, None, e) => write!(f, "{e} in {file}"),
         }
-        .fmt(f)
     }
 }
 
+/// Name of the variable bound by `label $x | ...`.
+///
+/// Because variable names cannot contain `*`,
+/// labels never clash with ordinary variables.
+fn label(x: &str) -> String {
+    alloc::format!("*label-{x}")
+}
+
+/// Conversion of destructuring to filters that bind only single variables.
+///
+/// For example, `l as [$x, {a: $y}] | r` becomes
+/// `l as $*pat-0 | ($*pat-0 | .[0]) as $x | ($*pat-0 | .[1]) as $*pat-1 |
+/// ($*pat-1 | .["a"]) as $y | r`.
+/// Like labels, the hidden variables `*pat-i` never clash with ordinary variables.
+///
+/// Alternative patterns `l as p1 ?// p2 | r` become
+/// `. as $*input | l as $*pat-0 | label $*alt |
+/// try (p1 | r) catch (($*input | p2 | r), break $*alt)`, where variables not bound by the current pattern are set to `null`.
+struct Destructure(Span);
+
+impl Destructure {
+    fn spanned<T>(&self, f: T) -> Spanned<T> {
+        (f, self.0.clone())
+    }
+
+    fn var(&self, x: &str) -> Spanned<Expr> {
+        self.spanned(Expr::Var(x.to_string()))
+    }
+
+    fn bind(&self, l: Spanned<Expr>, x: &str, r: Spanned<Expr>) -> Spanned<Expr> {
+        let x = self.spanned(x.to_string());
+        Expr::binary(l, BinaryOp::Pipe(Some(x)), r)
+    }
+
+    /// `l | .[k]`
+    fn index(&self, l: Spanned<Expr>, k: Spanned<Expr>) -> Spanned<Expr> {
+        let path = Vec::from([(Part::Index(k), Opt::Essential)]);
+        let r = Expr::path(self.spanned(Expr::Id), path, self.0.clone());
+        Expr::binary(l, BinaryOp::Pipe(None), r)
+    }
+
+    /// `[] | .[0]`
+    fn null(&self) -> Spanned<Expr> {
+        let zero = self.spanned(Expr::Num("0".to_string()));
+        self.index(self.spanned(Expr::Array(None)), zero)
+    }
+
+    /// Bind the output of `f` to `p` and run `r`.
+    fn value(
+        &self,
+        f: Spanned<Expr>,
+        p: Pattern<Spanned<Expr>>,
+        depth: usize,
+        r: Spanned<Expr>,
+    ) -> Spanned<Expr> {
+        match p {
+            Pattern::Var((x, _)) => self.bind(f, &x, r),
+            p => {
+                let v = alloc::format!("*pat-{depth}");
+                let r = self.pattern(&v, p, depth + 1, r);
+                self.bind(f, &v, r)
+            }
+        }
+    }
+
+    /// Bind the value of `$v` to `p` and run `r`.
+    fn pattern(
+        &self,
+        v: &str,
+        p: Pattern<Spanned<Expr>>,
+        depth: usize,
+        r: Spanned<Expr>,
+    ) -> Spanned<Expr> {
+        match p {
+            Pattern::Var((x, _)) => self.bind(self.var(v), &x, r),
+            Pattern::Arr(ps) => ps.into_iter().enumerate().rev().fold(r, |r, (i, p)| {
+                let i = self.spanned(Expr::Num(i.to_string()));
+                self.value(self.index(self.var(v), i), p, depth, r)
+            }),
+            Pattern::Obj(kps) => kps.into_iter().rev().fold(r, |r, kp| match kp {
+                KeyPattern::Var((x, _), p) => {
+                    let r = match p {
+                        Some(p) => self.pattern(&x, p, depth, r),
+                        None => r,
+                    };
+                    let k = self.spanned(Expr::from(Str::from(x.clone())));
+                    self.bind(self.index(self.var(v), k), &x, r)
+                }
+                KeyPattern::Filter(k, p) => self.value(self.index(self.var(v), k), p, depth, r),
+            }),
+        }
+    }
+
+    fn run(
+        &self,
+        l: Spanned<Expr>,
+        mut ps: Vec<Pattern<Spanned<Expr>>>,
+        r: Spanned<Expr>,
+    ) -> Spanned<Expr> {
+        let (v, input, alt) = ("*pat-0", "*input", "*alt");
+        if ps.len() == 1 {
+            return self.value(l, ps.remove(0), 0, r);
+        }
+
+        let vars = ps.iter().flat_map(|p| p.vars());
+        let mut vars: Vec<String> = vars.map(|(x, _)| x.clone()).collect();
+        vars.sort();
+        vars.dedup();
+
+        let mut alts = ps.into_iter().rev().map(|p| {
+            let bound: Vec<String> = p.vars().into_iter().map(|(x, _)| x.clone()).collect();
+            let r = self.pattern(v, p, 1, r.clone());
+            let unbound = vars.iter().filter(|x| !bound.contains(x));
+            unbound.fold(r, |r, x| self.bind(self.null(), x, r))
+        });
+        let last = alts.next().unwrap();
+        let alts = alts.fold(last, |catch, try_| {
+            let catch = Expr::binary(self.var(input), BinaryOp::Pipe(None), catch);
+            let brk = self.spanned(Expr::Break(alt.to_string()));
+            let catch = Expr::binary(catch, BinaryOp::Comma, brk);
+            let try_ = self.spanned(Expr::TryCatch(Box::new(try_), Some(Box::new(catch))));
+            self.spanned(Expr::Label(alt.to_string(), Box::new(try_)))
+        });
+        let alts = self.bind(l, v, alts);
+        self.bind(self.spanned(Expr::Id), input, alts)
+    }
+}
+
+/// Return the line number of the character at position `pos`.
+pub(crate) fn line(src: &str, pos: usize) -> usize {
+    // spans count characters, not bytes
+    let before = src.chars().take(pos);
+    before.filter(|c| *c == '\n').count() + 1
+}
+
+/// Construct `{"file": file, "line": line}`.
+fn loc(file: String, line: usize, span: jaq_syn::Span) -> Filter {
+    use jaq_syn::filter::KeyVal;
+    let line = Num::Int(line as isize);
+    let kv = |k: &str, v| KeyVal::Str(String::from(k).into(), Some((v, span.clone())));
+    let file = kv("file", Expr::Str(Box::new(file.into())));
+    Expr::Object(Vec::from([file, kv("line", Expr::Num(line))]))
+}
+
 struct Callable {
     typ: Relative,
     sig: jaq_syn::Call,
+    /// module that the filter was defined in
+    module: ModId,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -71,6 +288,31 @@ pub enum Relative {
     Sibling,
 }
 
+/// Module that was loaded for compilation.
+pub struct Module {
+    /// path by which the module was imported, such as `lib/foo`
+    path: String,
+    /// name and contents of the source, used for `$__loc__` and errors
+    pub source: Option<Rc<(String, String)>>,
+    /// imported modules, with their names (or `None` if they were included)
+    pub imports: Vec<(Option<String>, ModId)>,
+    /// position of the import in the main module that caused loading this module
+    origin: Span,
+}
+
+impl Module {
+    /// Create the main module, which is never imported.
+    pub fn main(source: Option<(String, String)>) -> Self {
+        let (path, imports) = (String::new(), Vec::new());
+        Self {
+            path,
+            source: source.map(Rc::new),
+            imports,
+            origin: 0..0,
+        }
+    }
+}
+
 /// Convert variables to indices.
 #[derive(Default)]
 pub struct Ctx {
@@ -81,74 +323,235 @@ pub struct Ctx {
     pub native: Vec<(String, usize, crate::filter::Native)>,
     /// locally bound variables (not bound by filter definition)
     vars: Vec<String>,
+    /// loaded modules, where the first one is the main module
+    pub modules: Vec<Module>,
+    /// module that is currently being converted
+    module: ModId,
 }
 
 impl Ctx {
     /// Return all currently bound variables / arguments outside-in.
     fn bound(&self) -> impl DoubleEndedIterator<Item = Bind<&String, &String>> {
-        let by_def = self.callable.iter().filter_map(|Callable { typ, sig }| {
-            (*typ == Relative::Parent).then_some(sig.args.iter().map(|a| a.as_ref()))
-        });
+        let by_def = self
+            .callable
+            .iter()
+            .filter_map(|Callable { typ, sig, .. }| {
+                (*typ == Relative::Parent).then_some(sig.args.iter().map(|a| a.as_ref()))
+            });
         by_def.flatten().chain(self.vars.iter().map(Bind::Var))
     }
 
+    fn imports(&self, module: ModId) -> &[(Option<String>, ModId)] {
+        self.modules.get(module).map_or(&[], |m| &m.imports)
+    }
+
+    /// Return true if `module` includes `target`, possibly via other included modules.
+    fn includes(&self, module: ModId, target: ModId) -> bool {
+        let mut included = self
+            .imports(module)
+            .iter()
+            .filter(|(alias, _)| alias.is_none());
+        included.any(|(_, m)| *m == target || self.includes(*m, target))
+    }
+
     fn resolve_call(&self, name: &str, arity: usize) -> Option<Call> {
+        // if the name is prefixed, such as in `foo::bar`,
+        // then the filter must be defined in the module imported as `foo`
+        let (module, name) = match name.split_once("::") {
+            Some((alias, name)) => {
+                let mut imports = self.imports(self.module).iter();
+                let (_, module) = imports.find(|(a, _)| a.as_deref() == Some(alias))?;
+                (Some(*module), name)
+            }
+            None => (None, name),
+        };
+        let visible = |m: ModId| match module {
+            Some(module) => m == module,
+            None => m == 0 || m == self.module || self.includes(self.module, m),
+        };
+
         let mut bound = self.vars.len();
 
-        for (id, Callable { typ, sig }) in self.callable.iter().enumerate().rev() {
+        for (
+            id,
+            Callable {
+                typ,
+                sig,
+                module: m,
+            },
+        ) in self.callable.iter().enumerate().rev()
+        {
             let id = RelId(id);
             if *typ == Relative::Parent {
                 for arg in sig.args.iter().rev() {
-                    if arity == 0 && arg.as_deref() == Bind::Fun(name) {
+                    if module.is_none() && arity == 0 && arg.as_deref() == Bind::Fun(name) {
                         return Some(Call::Arg(bound));
                     }
                     bound += 1;
                 }
             }
-            if name == sig.name && arity == sig.args.len() {
+            if name == sig.name && arity == sig.args.len() && visible(*m) {
                 return Some(Call::Def { id, skip: bound });
             }
         }
 
+        if module.is_some() {
+            return None;
+        }
         self.native
             .iter()
-            .find(|(name_, arity_, _)| *name_ == name && *arity_ == arity)
-            .map(|(_, _, native)| Call::Native(native.clone()))
+            .position(|(name_, arity_, _)| *name_ == name && *arity_ == arity)
+            .map(|i| Call::Native(i, self.native[i].2.clone()))
+    }
+
+    /// Return the arities other than `arity` with which a filter with the given name can be called.
+    fn arities(&self, name: &str, arity: usize) -> Vec<usize> {
+        let defs = self.callable.iter().map(|c| c.sig.args.len());
+        let natives = self.native.iter().map(|(_, arity, _)| *arity);
+        let mut arities: Vec<_> = defs.chain(natives).chain([0]).collect();
+        arities.sort();
+        arities.dedup();
+        arities.retain(|a| *a != arity && self.resolve_call(name, *a).is_some());
+        arities
+    }
+
+    /// Load the given imports and all modules that they import in turn.
+    ///
+    /// The definitions of newly loaded modules are appended to `defs`,
+    /// such that every module comes after the modules that it imports.
+    pub fn import(
+        &mut self,
+        imports: Vec<Import>,
+        mods: &Modules,
+        loading: &mut Vec<String>,
+        origin: Option<&Span>,
+        defs: &mut Vec<ModDefs>,
+    ) -> Vec<(Option<String>, ModId)> {
+        let mut ids = Vec::new();
+        for Import { path, alias } in imports {
+            let (path, span) = path;
+            let origin = origin.unwrap_or(&span).clone();
+            let loaded = self.modules.iter().skip(1).position(|m| m.path == path);
+            let id = match (loaded, mods.get(&path)) {
+                (Some(id), _) => id + 1,
+                (None, _) if loading.contains(&path) => {
+                    self.errs.push((Error::ModuleCycle(path), origin));
+                    continue;
+                }
+                (None, None) => {
+                    self.errs.push((Error::ModuleNotFound(path), origin));
+                    continue;
+                }
+                (None, Some((module, source))) => {
+                    loading.push(path.clone());
+                    let imports = module.imports.clone();
+                    let imports = self.import(imports, mods, loading, Some(&origin), defs);
+                    loading.pop();
+
+                    let source = source.clone().map(Rc::new);
+                    let id = self.modules.len();
+                    self.modules.push(Module {
+                        path,
+                        source,
+                        imports,
+                        origin,
+                    });
+                    defs.push((id, true, module.body.clone()));
+                    id
+                }
+            };
+            ids.push((alias, id));
+        }
+        ids
     }
 
-    pub fn main(&mut self, main: jaq_syn::Main) -> Main {
-        let defs: Vec<_> = main.defs.into_iter().map(|def| self.def(def)).collect();
+    /// Record an error, reporting errors in library modules at their import.
+    fn error(&mut self, e: Error, span: Span) {
+        let error = match self.modules.get(self.module) {
+            Some(m) if self.module != 0 => {
+                let line = m.source.as_deref().map(|(_, src)| line(src, span.start));
+                let file = m.source.as_deref().map_or(&m.path, |(file, _)| file);
+                let e = Error::InModule(file.clone(), line, Box::new(e));
+                (e, m.origin.clone())
+            }
+            _ => (e, span),
+        };
+        self.errs.push(error)
+    }
+
+    /// Convert definitions that stem from given modules, followed by a body.
+    fn main_modules(&mut self, defs: Vec<ModDefs>, body: Spanned<Expr>) -> Main {
+        let module = self.module;
+        let mut hdefs = Vec::new();
+        for (m, located, defs) in defs {
+            self.module = m;
+            hdefs.extend(defs.into_iter().map(|def| self.def(def, located)));
+        }
+        self.module = module;
+        let defs = hdefs;
+
         assert!(self.vars.is_empty());
-        let body = self.expr(main.body);
+        let body = self.expr(body);
         assert!(self.vars.is_empty());
 
         self.callable
             .drain(self.callable.len() - defs.len()..)
             .for_each(|callable| assert_eq!(callable.typ, Relative::Sibling));
 
-        jaq_syn::Main { defs, body }
+        Main { defs, body }
     }
 
-    pub fn def(&mut self, def: jaq_syn::Def) -> Def {
+    /// Convert a definition, recording its location if `located` is true.
+    pub fn def(&mut self, def: jaq_syn::Def, located: bool) -> Def {
+        let source = self.modules[self.module].source.clone();
+        let source = source.filter(|_| located);
+        let loc = source.as_deref().map(|(file, src)| {
+            let line = line(src, def.rhs.body.1.start);
+            (file.clone(), line)
+        });
+        let defs = Vec::from([(self.module, located, def.rhs.defs)]);
+        let def = self.def_modules(def.lhs, defs, def.rhs.body);
+        Def { loc, source, ..def }
+    }
+
+    /// Convert a definition whose definitions stem from given modules.
+    pub fn def_modules(
+        &mut self,
+        lhs: jaq_syn::Call,
+        defs: Vec<ModDefs>,
+        body: Spanned<Expr>,
+    ) -> Def {
         self.callable.push(Callable {
             typ: Relative::Parent,
-            sig: def.lhs.clone(),
+            sig: lhs.clone(),
+            module: self.module,
         });
-        let rhs = self.main(def.rhs);
+        let rhs = self.main_modules(defs, body);
         self.callable.last_mut().unwrap().typ = Relative::Sibling;
-        jaq_syn::Def { lhs: def.lhs, rhs }
+        let (loc, source) = (None, None);
+        Def {
+            lhs,
+            rhs,
+            loc,
+            source,
+        }
     }
 
     fn expr(&mut self, f: Spanned<Expr>) -> Spanned<Filter> {
         let get = |ctx: &mut Self, f| Box::new(ctx.expr(f));
-        let undefined = |arg| (Error::Undefined(arg), f.1.clone());
+        let undefined = Error::Undefined;
         let result = match f.0 {
             Expr::Call(name, args) => {
                 let args: Vec<_> = args.into_iter().map(|arg| self.expr(arg)).collect();
+                let arity = args.len();
 
-                self.resolve_call(&name, args.len()).map_or_else(
+                self.resolve_call(&name, arity).map_or_else(
                     || {
-                        self.errs.push(undefined(Arg::new_filter(name)));
+                        let e = match self.arities(&name, arity) {
+                            arities if arities.is_empty() => undefined(Arg::Fun((name, arity))),
+                            arities => Error::Arity(name, arity, arities),
+                        };
+                        self.error(e, f.1.clone());
                         Expr::Id
                     },
                     |call| Expr::Call(call, args),
@@ -156,28 +559,85 @@ impl Ctx {
             }
             Expr::Var(v) => {
                 let idx = self.bound().rev().position(|i| i == Bind::Var(&v));
-                Expr::Var(idx.unwrap_or_else(|| {
-                    self.errs.push(undefined(Arg::Var(v)));
-                    0
-                }))
+                let source = self
+                    .modules
+                    .get(self.module)
+                    .and_then(|m| m.source.as_deref());
+                match (idx, source) {
+                    (Some(idx), _) => Expr::Var(idx),
+                    (None, Some((file, src))) if v == "__loc__" => {
+                        loc(file.clone(), line(src, f.1.start), f.1.clone())
+                    }
+                    (None, _) => {
+                        self.error(undefined(Arg::Var(v)), f.1.clone());
+                        Expr::Var(0)
+                    }
+                }
             }
             Expr::Binary(l, BinaryOp::Pipe(Some(x)), r) => {
                 let l = get(self, *l);
-                self.vars.push(x.clone());
+                self.vars.push(x.0.clone());
                 let r = get(self, *r);
-                assert!(self.vars.pop().as_ref() == Some(&x));
+                assert!(self.vars.pop().as_ref() == Some(&x.0));
                 Expr::Binary(l, BinaryOp::Pipe(Some(x)), r)
             }
-            Expr::Fold(typ, Fold { xs, x, init, f }) => {
+            Expr::Destructure(l, ps, r) => {
+                let f = Destructure(f.1.clone()).run(*l, ps, *r);
+                self.expr(f).0
+            }
+            Expr::Label(x, f) => {
+                self.vars.push(label(&x));
+                let f = get(self, *f);
+                assert!(self.vars.pop() == Some(label(&x)));
+                Expr::Label(x, f)
+            }
+            Expr::Break(x) => {
+                let idx = self.bound().rev().position(|i| i == Bind::Var(&label(&x)));
+                Expr::Break(idx.unwrap_or_else(|| {
+                    self.error(Error::UndefinedLabel(x), f.1.clone());
+                    0
+                }))
+            }
+            Expr::Fold(
+                typ,
+                Fold {
+                    xs,
+                    x,
+                    pats,
+                    init,
+                    f,
+                },
+            ) => {
                 let (xs, init) = (get(self, *xs), get(self, *init));
-                self.vars.push(x.clone());
+                // `reduce xs as p (init; f)` becomes
+                // `reduce xs as $*fold (init; $*fold as p | f)`
+                let (x, f) = if pats.is_empty() {
+                    (x, f)
+                } else {
+                    let pats = pats.into_iter().map(|p| p.map(&mut |f| *f)).collect();
+                    let d = Destructure(f.1.clone());
+                    let x = d.spanned("*fold".to_string());
+                    let f = d.run(d.var(&x.0), pats, *f);
+                    (x, Box::new(f))
+                };
+                self.vars.push(x.0.clone());
                 let f = get(self, *f);
-                assert!(self.vars.pop().as_ref() == Some(&x));
-                Expr::Fold(typ, Fold { xs, x, init, f })
+                assert!(self.vars.pop().as_ref() == Some(&x.0));
+                let pats = Vec::new();
+                Expr::Fold(
+                    typ,
+                    Fold {
+                        xs,
+                        x,
+                        pats,
+                        init,
+                        f,
+                    },
+                )
             }
             Expr::Id => Expr::Id,
             Expr::Num(n) => Expr::Num(Num::parse(&n).unwrap_or_else(|n| {
-                self.errs.push((Error::Num(n), f.1.clone()));
+                self.error(Error::Num(n.clone()), f.1.clone());
                 n
             })),
             Expr::Str(s) => Expr::Str(Box::new((*s).map(|f| self.expr(f)))),
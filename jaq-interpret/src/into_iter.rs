@@ -1,15 +1,6 @@
-//! Functions and types for `IntoIterator` and `FnOnce() -> Iterator`.
+//! Functions and types for `IntoIterator`.
 
-#[derive(Clone)]
-pub struct Delay<F>(F);
-
-impl<I: Iterator, F: FnOnce() -> I> IntoIterator for Delay<F> {
-    type Item = I::Item;
-    type IntoIter = I;
-    fn into_iter(self) -> Self::IntoIter {
-        self.0()
-    }
-}
+use crate::rc_lazy_list::List;
 
 #[derive(Clone)]
 pub enum Either<L, R> {
@@ -46,15 +37,30 @@ impl<L: IntoIterator, R: IntoIterator<Item = L::Item>> IntoIterator for Either<L
     }
 }
 
-pub fn collect_if_once<I: Iterator, F: FnOnce() -> I + Clone>(
-    f: F,
-) -> Either<core::iter::Once<I::Item>, Delay<F>> {
-    let mut iter = f.clone()();
-    if iter.size_hint().1 == Some(1) {
-        if let Some(x) = iter.next() {
-            assert!(iter.next().is_none());
+/// If `iter` yields exactly one item, return it, else return a lazy list of its items.
+///
+/// This also checks for a second item when the size hint of the iterator is imprecise,
+/// such as when `iter` calls a filter defined in jq.
+/// This is important because `iter` may capture values, such as the input of a path.
+/// By dropping `iter` as soon as possible, these values become unique again, which
+/// allows updating them in place instead of cloning them.
+///
+/// In any case, the items of `iter` are evaluated only once,
+/// because evaluating them may have side effects, such as reading inputs.
+pub fn collect_if_once<'a, T: Clone + 'a>(
+    mut iter: impl Iterator<Item = T> + 'a,
+) -> Either<core::iter::Once<T>, List<'a, T>> {
+    let exact = iter.size_hint().1 == Some(1);
+    let first = iter.next();
+    if let Some(x) = first {
+        if exact {
             return Either::L(core::iter::once(x));
         }
+        match iter.next() {
+            None => Either::L(core::iter::once(x)),
+            Some(y) => Either::R(List::from_iter([x, y].into_iter().chain(iter))),
+        }
+    } else {
+        Either::R(List::from_iter(core::iter::empty()))
     }
-    Either::R(Delay(f))
 }
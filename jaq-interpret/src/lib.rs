@@ -47,6 +47,7 @@ extern crate alloc;
 extern crate std;
 
 mod box_iter;
+mod cache;
 pub mod error;
 mod filter;
 mod hir;
@@ -54,22 +55,27 @@ mod into_iter;
 mod lir;
 mod mir;
 mod path;
+mod profile;
 mod rc_iter;
 mod rc_lazy_list;
 mod rc_list;
 pub mod results;
-mod stack;
+#[cfg(feature = "serde")]
+mod serde_impl;
+pub mod style;
 mod val;
+mod vm;
 
-pub use error::Error;
+pub use error::{Error, Loc};
 pub use filter::{Args, FilterT, Native, Owned as Filter, RunPtr, UpdatePtr};
+pub use profile::{Profile, Stats};
 pub use rc_iter::RcIter;
 pub use val::{Val, ValR, ValRs};
 
-use alloc::{string::String, vec::Vec};
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::cell::{Cell, RefCell};
 use jaq_syn::Arg as Bind;
 use rc_list::List as RcList;
-use stack::Stack;
 
 /// variable bindings
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -80,6 +86,25 @@ impl Vars {
     fn get(&self, i: usize) -> Option<&Bind<Val, (filter::Id, Self)>> {
         self.0.get(i)
     }
+
+    /// Return a number that identifies the `i`-th binding as long as it is alive.
+    fn id(&self, i: usize) -> usize {
+        self.0.skip(i).id()
+    }
+
+    /// Add a new variable binding.
+    fn cons_var(self, x: Val) -> Self {
+        Self(self.0.cons(Bind::Var(x)))
+    }
+
+    /// Remove the `skip` most recent bindings.
+    fn skip(self, skip: usize) -> Self {
+        if skip > 0 {
+            Self(self.0.skip(skip).clone())
+        } else {
+            self
+        }
+    }
 }
 
 /// Filter execution context.
@@ -87,18 +112,164 @@ impl Vars {
 pub struct Ctx<'a> {
     vars: Vars,
     inputs: &'a Inputs<'a>,
+    progress: Option<&'a Progress<'a>>,
+    profile: Option<&'a Profile<'a>>,
+    env: Option<&'a BTreeMap<String, String>>,
+    input_filename: Option<&'a dyn Fn() -> Option<&'a str>>,
+    input_line_number: Option<usize>,
+    diagnostics: Option<&'a dyn Fn(Diagnostic)>,
+}
+
+/// Message that a filter emits as a side effect while it runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// value passed to `debug`
+    Debug(Val),
+    /// value passed to `stderr`
+    Stderr(Val),
+}
+
+/// Periodic reporting of evaluation progress.
+///
+/// Every evaluation of a filter node counts as one step.
+/// This can be used to update a progress bar while running long filters.
+pub struct Progress<'a> {
+    every: usize,
+    steps: Cell<usize>,
+    f: RefCell<&'a mut dyn FnMut(usize)>,
+}
+
+impl<'a> Progress<'a> {
+    /// Call `f` with the number of steps performed so far every `every` steps.
+    ///
+    /// Panics if `every` is zero.
+    pub fn new(every: usize, f: &'a mut dyn FnMut(usize)) -> Self {
+        assert!(every > 0);
+        let steps = Cell::new(0);
+        let f = RefCell::new(f);
+        Self { every, steps, f }
+    }
+
+    /// Return the number of steps performed so far.
+    pub fn steps(&self) -> usize {
+        self.steps.get()
+    }
+
+    fn step(&self) {
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+        if steps % self.every == 0 {
+            (self.f.borrow_mut())(steps)
+        }
+    }
 }
 
 impl<'a> Ctx<'a> {
     /// Construct a context.
     pub fn new(vars: impl IntoIterator<Item = Val>, inputs: &'a Inputs<'a>) -> Self {
         let vars = Vars(RcList::new().extend(vars.into_iter().map(Bind::Var)));
-        Self { vars, inputs }
+        let (progress, profile, env, input_filename) = (None, None, None, None);
+        let (input_line_number, diagnostics) = (None, None);
+        Self {
+            vars,
+            inputs,
+            progress,
+            profile,
+            env,
+            input_filename,
+            input_line_number,
+            diagnostics,
+        }
+    }
+
+    /// Report evaluation progress to the given handler.
+    pub fn with_progress(self, progress: &'a Progress<'a>) -> Self {
+        let progress = Some(progress);
+        Self { progress, ..self }
+    }
+
+    /// Record the calls to definitions in the given profile.
+    pub fn with_profile(self, profile: &'a Profile<'a>) -> Self {
+        let profile = Some(profile);
+        Self { profile, ..self }
+    }
+
+    /// Use the given environment variables instead of those of the current process.
+    ///
+    /// This affects the native `env` filter; see `jaq_core::env_obj` for `$ENV`.
+    pub fn with_env(self, env: &'a BTreeMap<String, String>) -> Self {
+        let env = Some(env);
+        Self { env, ..self }
+    }
+
+    /// Return the environment variables given by [`Self::with_env`], if any.
+    pub fn env(&self) -> Option<&'a BTreeMap<String, String>> {
+        self.env
+    }
+
+    /// Use the given function to obtain the name of the file
+    /// from which the most recent input value was read.
+    ///
+    /// This is returned by the native `input_filename` filter.
+    /// The function is called whenever the name is requested, so that
+    /// it can also account for values read by filters such as `input`.
+    pub fn with_input_filename(self, file: &'a dyn Fn() -> Option<&'a str>) -> Self {
+        let input_filename = Some(file);
+        Self {
+            input_filename,
+            ..self
+        }
+    }
+
+    /// Return the file name given by [`Self::with_input_filename`], if any.
+    pub fn input_filename(&self) -> Option<&'a str> {
+        self.input_filename.and_then(|file| file())
+    }
+
+    /// Use the given number of the line on which the input ends.
+    ///
+    /// This is returned by the native `input_line_number` filter.
+    pub fn with_input_line_number(self, line: usize) -> Self {
+        let input_line_number = Some(line);
+        Self {
+            input_line_number,
+            ..self
+        }
+    }
+
+    /// Return the line number given by [`Self::with_input_line_number`], if any.
+    pub fn input_line_number(&self) -> Option<usize> {
+        self.input_line_number
+    }
+
+    /// Send diagnostics, such as those emitted by `debug`, to the given handler.
+    ///
+    /// This allows programs that embed jaq to capture diagnostics,
+    /// instead of having them logged.
+    pub fn with_diagnostics(self, f: &'a dyn Fn(Diagnostic)) -> Self {
+        let diagnostics = Some(f);
+        Self {
+            diagnostics,
+            ..self
+        }
+    }
+
+    /// Send a diagnostic to the handler given by [`Self::with_diagnostics`].
+    ///
+    /// If there is no handler, then return the diagnostic.
+    pub fn diagnose(&self, d: Diagnostic) -> Option<Diagnostic> {
+        match self.diagnostics {
+            Some(f) => {
+                f(d);
+                None
+            }
+            None => Some(d),
+        }
     }
 
     /// Add a new variable binding.
     pub(crate) fn cons_var(mut self, x: Val) -> Self {
-        self.vars.0 = self.vars.0.cons(Bind::Var(x));
+        self.vars = self.vars.cons_var(x);
         self
     }
 
@@ -110,15 +281,15 @@ impl<'a> Ctx<'a> {
 
     /// Remove the `skip` most recent variable bindings.
     fn skip_vars(mut self, skip: usize) -> Self {
-        if skip > 0 {
-            self.vars.0 = self.vars.0.skip(skip).clone();
-        }
+        self.vars = self.vars.skip(skip);
         self
     }
 
     fn with_vars(&self, vars: Vars) -> Self {
-        let inputs = self.inputs;
-        Self { vars, inputs }
+        Self {
+            vars,
+            ..self.clone()
+        }
     }
 
     /// Return remaining input values.
@@ -136,6 +307,8 @@ pub struct ParseCtx {
     pub errs: Vec<jaq_syn::Spanned<hir::Error>>,
     native: Vec<(String, usize, filter::Native)>,
     def: jaq_syn::Def,
+    source: Option<(String, String)>,
+    modules: hir::Modules,
 }
 
 impl ParseCtx {
@@ -145,11 +318,16 @@ impl ParseCtx {
     /// values corresponding to the variables have to be supplied in the execution context.
     pub fn new(vars: Vec<String>) -> Self {
         use alloc::string::ToString;
+        let lhs_spans = jaq_syn::Call {
+            name: 0..0,
+            args: vars.iter().map(|_| 0..0).collect(),
+        };
         let def = jaq_syn::Def {
             lhs: jaq_syn::Call {
                 name: "$".to_string(),
                 args: vars.into_iter().map(Bind::Var).collect(),
             },
+            lhs_spans,
             rhs: jaq_syn::Main {
                 defs: Vec::new(),
                 body: (jaq_syn::filter::Filter::Id, 0..0),
@@ -160,10 +338,40 @@ impl ParseCtx {
             errs: Vec::new(),
             native: Vec::new(),
             def,
+            source: None,
+            modules: Default::default(),
         }
     }
 
+    /// Set the name and the contents of the source of the filters to be compiled.
+    ///
+    /// This determines the value of `$__loc__`, which is
+    /// an object with the name of the source and the line where `$__loc__` occurs,
+    /// such as `{"file": "<repl>", "line": 1}`.
+    /// If no source is set, then `$__loc__` is undefined.
+    pub fn set_source(&mut self, file: String, src: String) {
+        self.source = Some((file, src));
+    }
+
+    /// Make a library module available for import under the given path.
+    ///
+    /// A module can then be imported by
+    /// `import "path" as name;`, making its definitions accessible as `name::f`, or by
+    /// `include "path";`, making its definitions accessible directly as `f`.
+    /// Like for [`Self::set_source`], `source` gives the name and the contents of the module.
+    pub fn insert_module(
+        &mut self,
+        path: String,
+        module: jaq_syn::Module<Vec<jaq_syn::Def>>,
+        source: Option<(String, String)>,
+    ) {
+        self.modules.insert(path, (module, source));
+    }
+
     /// Add a native filter with given name and arity.
+    ///
+    /// This allows host applications to provide their own filters,
+    /// for example via closures that capture host state (see [`Native::from_fn`]).
     pub fn insert_native(&mut self, name: String, arity: usize, f: filter::Native) {
         self.native.push((name, arity, f));
     }
@@ -197,23 +405,63 @@ impl ParseCtx {
 
     /// Given a main filter (consisting of definitions and a body), return a finished filter.
     pub fn compile(&mut self, main: jaq_syn::Main) -> Filter {
+        let imports = Vec::new();
+        self.compile_module(jaq_syn::Module {
+            imports,
+            body: main,
+        })
+    }
+
+    /// Compile a main filter preceded by imports of modules given by [`Self::insert_module`].
+    pub fn compile_module(&mut self, main: jaq_syn::Module) -> Filter {
         let mut hctx = hir::Ctx::default();
         hctx.native = self.native.clone();
-        self.def.rhs.defs.extend(main.defs);
-        self.def.rhs.body = main.body;
-        let def = hctx.def(self.def.clone());
+        hctx.modules.push(hir::Module::main(self.source.clone()));
+
+        let mut defs = Vec::from([(0, false, self.def.rhs.defs.clone())]);
+        let imports = hctx.import(
+            main.imports,
+            &self.modules,
+            &mut Vec::new(),
+            None,
+            &mut defs,
+        );
+        hctx.modules[0].imports = imports;
+        defs.push((0, true, main.body.defs.clone()));
+
+        let mut def = hctx.def_modules(self.def.lhs.clone(), defs, main.body.body.clone());
+        // the spans in the main filter refer to the source of the main module
+        def.source = hctx.modules[0].source.clone();
+        self.def.rhs.defs.extend(main.body.defs);
+        self.def.rhs.body = main.body.body;
         self.errs = hctx.errs;
 
         if !self.errs.is_empty() {
             return Default::default();
         }
-        let mut mctx = mir::Ctx::default();
-        //std::dbg!(&def);
-        let def = mctx.def(def, Default::default());
+        let def = mir::def(def);
 
         lir::root_def(def)
     }
 
+    /// Serialise a filter compiled by this context, for example to cache it on disk.
+    ///
+    /// The serialisation contains the version of the serialisation format and
+    /// a hash of `key`, which should identify the source of the filter.
+    pub fn serialize(&self, f: &Filter, key: &[u8]) -> Vec<u8> {
+        cache::serialize(f, &self.native, key)
+    }
+
+    /// Deserialise a filter that was serialised by [`Self::serialize`].
+    ///
+    /// This yields `None` if the serialisation is invalid,
+    /// if it was made with a different format version or with a different `key`,
+    /// if it uses native filters that are not present in this context, or
+    /// if it uses more global variables than this context provides.
+    pub fn deserialize(&self, bytes: &[u8], key: &[u8]) -> Option<Filter> {
+        cache::deserialize(bytes, &self.native, self.def.lhs.args.len(), key)
+    }
+
     /// Compile and run a filter on given input, panic if it does not compile or yield the given output.
     ///
     /// This is for testing purposes.
@@ -2,20 +2,41 @@
 
 use crate::filter::{self, Ast as Filter, CallTyp, Id as AbsId};
 use crate::path::{self, Path};
-use crate::{hir, mir};
-use alloc::vec::Vec;
+use crate::{hir, mir, Bind, Loc, Val};
+use alloc::{rc::Rc, string::String, vec::Vec};
 use jaq_syn::filter::{AssignOp, BinaryOp, Fold, KeyVal};
 use jaq_syn::{MathOp, Spanned, Str};
 
 pub struct Ctx {
     defs: Vec<Filter>,
     callable: Vec<Callable>,
+    /// definition in whose body each filter in `defs` occurs, if any
+    owner: Vec<Option<AbsId>>,
+    /// definitions together with the definitions that they are nested in, if any
+    parents: Vec<(AbsId, Option<AbsId>)>,
+    /// definition that is currently converted, if any
+    current: Option<AbsId>,
+    /// names and locations of all definitions
+    names: Vec<(AbsId, filter::Def)>,
+    /// source of the definition that is currently converted, if known
+    source: Option<Rc<(String, String)>>,
 }
 
 pub struct Callable {
     sig: jaq_syn::Call,
     id: AbsId,
-    tailrec: bool,
+    /// is the source of the definition known?
+    located: bool,
+}
+
+/// Call to a definition.
+struct Site {
+    /// position of the call in `defs`
+    node: usize,
+    caller: AbsId,
+    callee: AbsId,
+    /// is the call in tail position of the caller?
+    tail: bool,
 }
 
 const IDENTITY: AbsId = AbsId(0);
@@ -23,10 +44,16 @@ const TOSTRING: AbsId = AbsId(IDENTITY.0 + 1);
 const EMPTY: AbsId = AbsId(TOSTRING.0 + 2);
 const RECURSE: AbsId = AbsId(EMPTY.0 + 4);
 
+/// Maximal number of nodes in the body of a definition that is inlined.
+const INLINE_SIZE: usize = 16;
+
 pub fn root_def(def: mir::Def) -> filter::Owned {
     let mut ctx = Ctx::default();
     let id = ctx.def(def);
-    filter::Owned::new(id, ctx.defs)
+    ctx.tailrec();
+    ctx.inline();
+    ctx.fold();
+    filter::Owned::new(id, ctx.defs, ctx.names)
 }
 
 impl Default for Ctx {
@@ -34,6 +61,11 @@ impl Default for Ctx {
         let mut ctx = Self {
             defs: Vec::new(),
             callable: Vec::new(),
+            owner: Vec::new(),
+            parents: Vec::new(),
+            current: None,
+            names: Vec::new(),
+            source: None,
         };
 
         for (f, id) in [(Filter::Id, IDENTITY), (Filter::ToString, TOSTRING)] {
@@ -53,6 +85,34 @@ impl Default for Ctx {
     }
 }
 
+/// Return which definitions are transparent with respect to `def`.
+///
+/// This is the largest set of definitions nested in `def` whose
+/// calls that do not throw are in tail position of `def` or of a definition in the set.
+fn transparent(
+    sites: &[Site],
+    throw: &[bool],
+    def: AbsId,
+    len: usize,
+    within: impl Fn(AbsId) -> bool,
+) -> Vec<bool> {
+    let transparent = (0..len).map(|id| AbsId(id) != def && within(AbsId(id)));
+    let mut transparent: Vec<_> = transparent.collect();
+    loop {
+        let mut changed = false;
+        for (site, throw) in sites.iter().zip(throw) {
+            let ok = site.tail && (site.caller == def || transparent[site.caller.0]);
+            if !throw && !ok && transparent[site.callee.0] {
+                transparent[site.callee.0] = false;
+                changed = true;
+            }
+        }
+        if !changed {
+            return transparent;
+        }
+    }
+}
+
 /// Construct a call to `..`.
 fn recurse(typ: CallTyp) -> Filter {
     Filter::Call(filter::Call {
@@ -72,6 +132,9 @@ impl Ctx {
     }
 
     /// `..`, also known as `recurse/0`, is defined as `., (.[]? | ..)`
+    ///
+    /// The recursive call is a tail call, so `..` does not grow
+    /// the frames of the machine even on deeply nested values.
     fn recurse(&mut self) -> Filter {
         // `[]?`
         let path = (path::Part::Range(None, None), path::Opt::Optional);
@@ -86,6 +149,290 @@ impl Ctx {
         Filter::Comma(IDENTITY, self.id_of_ast(pipe))
     }
 
+    /// Decide how to execute calls to definitions.
+    ///
+    /// A call in tail position can "throw", meaning that its errors are not located at it,
+    /// but only at the innermost active call to the called definition, which "catches" them.
+    /// That way, the call runs without a frame, and tail recursion takes constant space.
+    /// This is only done if throwing skips no computation,
+    /// which is the case if the tail call occurs either directly in the body of
+    /// the called definition or in the body of a *transparent* definition nested in it.
+    /// A definition is transparent if all its calls that do not throw are
+    /// in tail position in either the called definition or other transparent definitions.
+    ///
+    /// For example, in `def f: def g: if . > 0 then . - 1 | f else . end; g; f`,
+    /// `g` is transparent, because `g` is called only in tail position of `f`.
+    /// That way, the call to `f` in `g` throws, and calling `f` takes constant space.
+    ///
+    /// Calls to definitions that are thrown to set up a handler for tail calls,
+    /// and all other calls are normal.
+    fn tailrec(&mut self) {
+        // `parent[id]` is `Some(p)` if `id` is a definition with parent `p`
+        let mut parent = alloc::vec![None; self.defs.len()];
+        for (id, p) in &self.parents {
+            parent[id.0] = Some(*p);
+        }
+        // is `id` nested in or equal to `def`?
+        let within = |mut id: AbsId, def: AbsId| loop {
+            if id == def {
+                return true;
+            }
+            match parent[id.0] {
+                Some(Some(p)) => id = p,
+                _ => return false,
+            }
+        };
+
+        let sites = self
+            .defs
+            .iter()
+            .enumerate()
+            .filter_map(|(node, f)| match f {
+                Filter::Call(call) if parent[call.id.0].is_some() => Some(Site {
+                    node,
+                    caller: self.owner[node]?,
+                    callee: call.id,
+                    tail: matches!(call.typ, CallTyp::Throw),
+                }),
+                _ => None,
+            });
+        let sites: Vec<_> = sites.collect();
+
+        // start with all tail calls that might throw and
+        // turn those into normal calls that would skip computation
+        let throw = sites.iter().map(|s| s.tail && within(s.caller, s.callee));
+        let mut throw: Vec<bool> = throw.collect();
+        loop {
+            let mut changed = false;
+            for i in 0..sites.len() {
+                let Site { caller, callee, .. } = sites[i];
+                if throw[i] && caller != callee {
+                    let within = |id| within(id, callee);
+                    let transparent = transparent(&sites, &throw, callee, self.defs.len(), within);
+                    if !transparent[caller.0] {
+                        throw[i] = false;
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let mut catch = alloc::vec![false; self.defs.len()];
+        for (site, throw) in sites.iter().zip(&throw) {
+            catch[site.callee.0] |= *throw;
+        }
+        for (site, throw) in sites.iter().zip(throw) {
+            if let Filter::Call(call) = &mut self.defs[site.node] {
+                call.typ = match (throw, catch[site.callee.0]) {
+                    (true, _) => CallTyp::Throw,
+                    (false, true) => CallTyp::Catch,
+                    (false, false) => CallTyp::Normal,
+                };
+            }
+        }
+    }
+
+    /// Replace calls to small definitions with constant arguments by their bodies.
+    ///
+    /// For example, for `def f($x): $x + 1`, `f(2)` is replaced by `2 + 1`.
+    /// Only bodies that contain no calls and bind no variables are inlined;
+    /// in particular, recursive definitions are never inlined.
+    fn inline(&mut self) {
+        // inlined bodies contain no calls, so we do not need to visit them
+        for i in 0..self.defs.len() {
+            let call = match &self.defs[i] {
+                Filter::Call(call) if matches!(call.typ, CallTyp::Normal) => call,
+                _ => continue,
+            };
+            let consts = call.args.iter().map(|arg| match arg {
+                Bind::Var(id) => match &self.defs[id.0] {
+                    f @ (Filter::Bool(_)
+                    | Filter::Int(_)
+                    | Filter::Float(_)
+                    | Filter::Num(_)
+                    | Filter::Str(_)) => Some(f.clone()),
+                    _ => None,
+                },
+                Bind::Fun(_) => None,
+            });
+            let id = call.id;
+            if let Some(consts) = consts.collect::<Option<Vec<_>>>() {
+                if self.inlinable(id, consts.len(), &mut 0) {
+                    self.defs[i] = self.subst(id, &consts);
+                }
+            }
+        }
+    }
+
+    /// Return true if the filter has at most [`INLINE_SIZE`] nodes,
+    /// contains no calls, binds no variables, and refers only to the first `vars` variables.
+    fn inlinable(&self, id: AbsId, vars: usize, size: &mut usize) -> bool {
+        *size += 1;
+        if *size > INLINE_SIZE {
+            return false;
+        }
+        let mut ok = |id: &AbsId| self.inlinable(*id, vars, size);
+        match &self.defs[id.0] {
+            Filter::Id | Filter::ToString | Filter::ObjEmpty => true,
+            Filter::Bool(_) | Filter::Int(_) | Filter::Float(_) | Filter::Num(_) => true,
+            Filter::Str(_) => true,
+            Filter::Var(v) => *v < vars,
+            Filter::Array(f) | Filter::Neg(f) | Filter::At(f, _) => ok(f),
+            Filter::ObjSingle(l, r) | Filter::Try(l, r) | Filter::Pipe(l, false, r) => {
+                ok(l) && ok(r)
+            }
+            Filter::Comma(l, r) | Filter::Alt(l, r) => ok(l) && ok(r),
+            Filter::Logic(l, _, r) | Filter::Math(l, _, r) | Filter::Ord(l, _, r) => ok(l) && ok(r),
+            Filter::Ite(if_, then_, else_) => ok(if_) && ok(then_) && ok(else_),
+            Filter::Concat(parts) => parts.iter().all(ok),
+            Filter::Path(f, path) => {
+                ok(f)
+                    && path.0.iter().all(|(part, _)| match part {
+                        path::Part::Index(i) => ok(i),
+                        path::Part::Range(from, upto) => from.iter().chain(upto).all(&mut ok),
+                    })
+            }
+            _ => false,
+        }
+    }
+
+    /// Copy an inlinable filter, replacing variables by the given constants.
+    ///
+    /// The last constant corresponds to the variable with index 0.
+    fn subst(&mut self, id: AbsId, consts: &[Filter]) -> Filter {
+        let get = |ctx: &mut Self, id: AbsId| {
+            let f = ctx.subst(id, consts);
+            ctx.id_of_ast(f)
+        };
+        match self.defs[id.0].clone() {
+            Filter::Var(v) => consts[consts.len() - 1 - v].clone(),
+            Filter::Array(f) => Filter::Array(get(self, f)),
+            Filter::Neg(f) => Filter::Neg(get(self, f)),
+            Filter::At(f, loc) => Filter::At(get(self, f), loc),
+            Filter::ObjSingle(l, r) => Filter::ObjSingle(get(self, l), get(self, r)),
+            Filter::Try(l, r) => Filter::Try(get(self, l), get(self, r)),
+            Filter::Pipe(l, bind, r) => Filter::Pipe(get(self, l), bind, get(self, r)),
+            Filter::Comma(l, r) => Filter::Comma(get(self, l), get(self, r)),
+            Filter::Alt(l, r) => Filter::Alt(get(self, l), get(self, r)),
+            Filter::Logic(l, stop, r) => Filter::Logic(get(self, l), stop, get(self, r)),
+            Filter::Math(l, op, r) => Filter::Math(get(self, l), op, get(self, r)),
+            Filter::Ord(l, op, r) => Filter::Ord(get(self, l), op, get(self, r)),
+            Filter::Ite(if_, then_, else_) => {
+                Filter::Ite(get(self, if_), get(self, then_), get(self, else_))
+            }
+            Filter::Concat(parts) => Filter::Concat(parts.iter().map(|p| get(self, *p)).collect()),
+            Filter::Path(f, path) => {
+                let f = get(self, f);
+                let path = path.0.into_iter().map(|(part, opt)| {
+                    let part = match part {
+                        path::Part::Index(i) => path::Part::Index(get(self, i)),
+                        path::Part::Range(from, upto) => path::Part::Range(
+                            from.map(|i| get(self, i)),
+                            upto.map(|i| get(self, i)),
+                        ),
+                    };
+                    (part, opt)
+                });
+                Filter::Path(f, Path(path.collect()))
+            }
+            f => f,
+        }
+    }
+
+    /// Replace filters by their values if these are known at compile time,
+    /// and replace conditionals whose condition is known by the branch taken.
+    ///
+    /// For example, `if 1 < 2 then "a\(1 + 2)" else . end` is replaced by `"a3"`.
+    fn fold(&mut self) {
+        // folding a filter may enable folding the filters that refer to it,
+        // which may have any index, so we repeat until no filter changes anymore
+        loop {
+            let mut changed = false;
+            for i in 0..self.defs.len() {
+                if let Some(f) = self.fold_one(&self.defs[i]) {
+                    self.defs[i] = f;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+    }
+
+    fn fold_one(&self, f: &Filter) -> Option<Filter> {
+        match f {
+            Filter::Bool(_) | Filter::Int(_) | Filter::Float(_) | Filter::Num(_) => None,
+            Filter::Str(_) => None,
+            Filter::Ite(if_, then_, else_) => {
+                let branch = if self.value(*if_)?.as_bool() {
+                    then_
+                } else {
+                    else_
+                };
+                Some(self.defs[branch.0].clone())
+            }
+            // a constant yields exactly one value
+            Filter::Alt(l, r) => {
+                let branch = if self.value(*l)?.as_bool() { l } else { r };
+                Some(self.defs[branch.0].clone())
+            }
+            f => match self.eval(f)? {
+                Val::Bool(b) => Some(Filter::Bool(b)),
+                Val::Int(i) => Some(Filter::Int(i)),
+                Val::Float(f) => Some(Filter::Float(f)),
+                Val::Num(n) => Some(Filter::Num(n)),
+                Val::Str(s) => Some(Filter::Str((*s).clone())),
+                _ => None,
+            },
+        }
+    }
+
+    fn value(&self, id: AbsId) -> Option<Val> {
+        self.eval(&self.defs[id.0])
+    }
+
+    /// Return the single value that a filter yields for any input, if this is known.
+    ///
+    /// Filters that may yield an error are not evaluated,
+    /// so that the error is yielded at runtime.
+    fn eval(&self, f: &Filter) -> Option<Val> {
+        Some(match f {
+            Filter::Bool(b) => Val::Bool(*b),
+            Filter::Int(i) => Val::Int(*i),
+            Filter::Float(f) => Val::Float(*f),
+            Filter::Num(n) => Val::Num(n.clone()),
+            Filter::Str(s) => Val::str(s.clone()),
+            Filter::Neg(f) => (-self.value(*f)?).ok()?,
+            Filter::At(f, _) => self.value(*f)?,
+            Filter::Math(l, op, r) => op.run(self.value(*l)?, self.value(*r)?).ok()?,
+            Filter::Ord(l, op, r) => Val::Bool(op.run(&self.value(*l)?, &self.value(*r)?)),
+            Filter::Logic(l, stop, r) => {
+                let l = self.value(*l)?.as_bool();
+                Val::Bool(if l == *stop {
+                    l
+                } else {
+                    self.value(*r)?.as_bool()
+                })
+            }
+            // this occurs in string interpolation
+            Filter::Pipe(l, false, TOSTRING) => Val::str(self.value(*l)?.to_string_or_clone()),
+            Filter::Pipe(l, false, r) => {
+                self.value(*l)?;
+                self.value(*r)?
+            }
+            Filter::Concat(parts) => {
+                let mut parts = parts.iter().rev().map(|p| self.value(*p));
+                let last = parts.next()??;
+                parts.try_fold(last, |acc, x| (x? + acc).ok())?
+            }
+            _ => return None,
+        })
+    }
+
     fn get_callable(&self, hir::RelId(id): hir::RelId) -> &Callable {
         &self.callable[id]
     }
@@ -107,14 +454,23 @@ impl Ctx {
     }
 
     fn def(&mut self, def: mir::Def) -> AbsId {
-        let id = AbsId(self.defs.len());
-        self.defs.push(Filter::default());
+        let id = self.id_of_ast(Filter::default());
+        self.parents.push((id, self.current));
+        // the body of the definition is stored at its ID
+        self.owner[id.0] = Some(id);
+        let name = def.lhs.name.clone();
+        let (arity, loc) = (def.lhs.args.len(), def.loc);
+        self.names.push((id, filter::Def { name, arity, loc }));
         self.callable.push(Callable {
             sig: def.lhs.clone(),
             id,
-            tailrec: def.tailrec,
+            located: def.source.is_some(),
         });
+        let parent = self.current.replace(id);
+        let source = core::mem::replace(&mut self.source, def.source);
         *self.get_def(id) = self.main(def.rhs);
+        self.source = source;
+        self.current = parent;
         let last = self.callable.last_mut().unwrap();
         assert!(last.id == id);
         id
@@ -123,6 +479,7 @@ impl Ctx {
     fn id_of_ast(&mut self, f: filter::Ast) -> AbsId {
         let len = self.defs.len();
         self.defs.push(f);
+        self.owner.push(self.current);
         AbsId(len)
     }
 
@@ -142,9 +499,12 @@ impl Ctx {
             Part::Str(s) => Filter::Str(s),
             Part::Fun(f) => Filter::Pipe(self.get(f), false, fmt),
         });
-        let mut iter = iter.collect::<Vec<_>>().into_iter().rev();
-        let last = iter.next().unwrap_or_else(|| Filter::Str("".into()));
-        iter.fold(last, |acc, x| self.add(x, acc))
+        let mut parts = iter.collect::<Vec<_>>();
+        match parts.len() {
+            0 => Filter::Str("".into()),
+            1 => parts.pop().unwrap(),
+            _ => Filter::Concat(parts.into_iter().map(|p| self.id_of_ast(p)).collect()),
+        }
     }
 
     fn of_key_val(&mut self, kv: KeyVal<Spanned<mir::Filter>>) -> Filter {
@@ -164,33 +524,68 @@ impl Ctx {
         }
     }
 
+    /// Return true if running the filter may yield an error that
+    /// does not stem from one of its subfilters.
+    ///
+    /// Calls to definitions without known source may yield such errors, because
+    /// errors in these definitions are not located.
+    fn fails(&self, f: &mir::Filter) -> bool {
+        use mir::Filter as Expr;
+        match f {
+            Expr::Call(mir::Call::Native(..), _) => true,
+            Expr::Call(mir::Call::Def { id, .. }, _) => !self.get_callable(id.clone()).located,
+            Expr::Neg(_) | Expr::Object(_) | Expr::Path(..) => true,
+            Expr::Binary(_, BinaryOp::Math(_) | BinaryOp::Assign(_), _) => true,
+            _ => false,
+        }
+    }
+
     /// Convert a MIR filter to a LIR filter.
+    ///
+    /// If the source of the filter is known and the filter may fail,
+    /// then the filter is wrapped with its location.
     fn filter(&mut self, f: Spanned<mir::Filter>) -> Filter {
+        let loc = match &self.source {
+            Some(source) if self.fails(&f.0) => Some(Loc {
+                source: source.clone(),
+                span: f.1,
+            }),
+            _ => None,
+        };
+        let f = self.unlocated(f.0);
+        match loc {
+            Some(loc) => Filter::At(self.id_of_ast(f), loc),
+            None => f,
+        }
+    }
+
+    /// Convert a MIR filter to a LIR filter, without locating it.
+    fn unlocated(&mut self, f: mir::Filter) -> Filter {
         use mir::Filter as Expr;
 
-        match f.0 {
+        match f {
             Expr::Var(v) => Filter::Var(v),
             Expr::Call(call, args) => {
                 let args: Vec<_> = args.into_iter().map(|a| self.get(a)).collect();
                 match call {
                     mir::Call::Arg(a) if args.is_empty() => Filter::Var(a),
-                    mir::Call::Arg(_) => panic!("higher-order argument encountered"),
-                    mir::Call::Native(n) => Filter::Native(n, args.into()),
+                    // filter arguments can be passed on to other filters, but
+                    // they cannot take arguments themselves, because
+                    // HIR resolves only calls without arguments to filter arguments
+                    mir::Call::Arg(_) => unreachable!("filter argument called with arguments"),
+                    mir::Call::Native(i, n) => Filter::Native(i, n, args.into()),
                     mir::Call::Def { id, skip, tail } => {
                         let callable = self.get_callable(id);
                         let args = callable.sig.args.iter().zip(args);
-                        let typ = match (tail, callable.tailrec) {
-                            // TR call from inside itself
-                            (true, true) => CallTyp::Throw,
-                            (true, false) => panic!("TR call from inside a non-TR filter"),
-                            // call from outside or non-TR call from inside a TR filter
-                            (false, true) => CallTyp::Catch,
-                            // call from outside or non-TR call from inside a non-TR filter
-                            (false, false) => CallTyp::Normal,
-                        };
                         Filter::Call(filter::Call {
                             id: callable.id,
-                            typ,
+                            // this is decided later by `tailrec`, which
+                            // takes calls that "throw" to be in tail position
+                            typ: if tail {
+                                CallTyp::Throw
+                            } else {
+                                CallTyp::Normal
+                            },
                             skip,
                             args: args.map(|(ty, a)| ty.as_ref().map(|_| a)).collect(),
                         })
@@ -202,9 +597,13 @@ impl Ctx {
                 Filter::Fold(typ, self.get(*xs), self.get(*init), self.get(*f))
             }
 
+            Expr::Destructure(..) => unreachable!("destructuring is eliminated by HIR"),
+            Expr::Label(_, f) => Filter::Label(self.get(*f)),
+            Expr::Break(x) => Filter::Break(x),
             Expr::Id => Filter::Id,
             Expr::Num(hir::Num::Float(f)) => Filter::Float(f),
             Expr::Num(hir::Num::Int(i)) => Filter::Int(i),
+            Expr::Num(hir::Num::Dec(n)) => Filter::Num(n.into()),
             Expr::Str(s) => self.of_str(*s),
             Expr::Array(a) => Filter::Array(a.map_or(EMPTY, |a| self.get(*a))),
             Expr::Object(o) => {
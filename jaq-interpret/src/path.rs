@@ -1,7 +1,7 @@
 use crate::box_iter::{box_once, flat_map_with, map_with, BoxIter};
-use crate::error::{Error, Type};
+use crate::error::{Error, Loc, Type};
 use crate::results::then;
-use crate::val::{Val, ValR, ValRs};
+use crate::val::{rc_unwrap_or_clone, Val, ValR, ValRs};
 use alloc::{boxed::Box, rc::Rc, vec::Vec};
 pub use jaq_syn::path::Opt;
 
@@ -42,8 +42,9 @@ impl<'a, U: Clone + 'a> Path<U> {
 }
 
 impl Path<Val> {
-    pub fn run<'a>(self, v: Val) -> ValRs<'a> {
-        run(self.0.into_iter(), v)
+    /// Return the values at the path, locating errors at `loc` if it is given.
+    pub fn run<'a>(self, v: Val, loc: Option<&'a Loc>) -> ValRs<'a> {
+        run(self.0.into_iter(), v, loc)
     }
 
     pub fn update<'a, F: Fn(Val) -> ValRs<'a>>(mut self, v: Val, f: F) -> ValR {
@@ -56,14 +57,15 @@ impl Path<Val> {
     }
 }
 
-fn run<'a, I>(mut iter: I, val: Val) -> ValRs<'a>
+fn run<'a, I>(mut iter: I, val: Val, loc: Option<&'a Loc>) -> ValRs<'a>
 where
     I: Iterator<Item = (Part<Val>, Opt)> + Clone + 'a,
 {
     if let Some((part, opt)) = iter.next() {
         let essential = matches!(opt, Opt::Essential);
         let ys = part.run(val).filter(move |v| essential || v.is_ok());
-        flat_map_with(ys, iter, move |v, iter| then(v, |v| run(iter, v)))
+        let ys = ys.map(move |y| y.map_err(|e| e.at(loc)));
+        flat_map_with(ys, iter, move |v, iter| then(v, |v| run(iter, v, loc)))
     } else {
         box_once(Ok(val))
     }
@@ -96,7 +98,7 @@ impl Part<Val> {
                     Val::Str(s) => Ok(o.get(&*s).cloned().unwrap_or(Val::Null)),
                     i => Err(Error::Index(Val::Obj(o.clone()), i.clone())),
                 },
-                _ => Err(Error::Type(current, Type::Iter)),
+                _ => Err(Error::Index(current, idx)),
             }),
             Self::Range(None, None) => then(current.try_into_iter(), |iter| Box::new(iter.map(Ok))),
             Self::Range(from, upto) => box_once(match current {
@@ -122,7 +124,7 @@ impl Part<Val> {
                         Val::str(s.chars().skip(skip).take(take).collect())
                     })
                 }
-                _ => Err(Error::Type(current, Type::Range)),
+                _ => Err(Error::Index(current, range_obj(from, upto))),
             }),
         }
     }
@@ -143,7 +145,12 @@ impl Part<Val> {
                     };
                     match o.entry(Rc::clone(i)) {
                         Occupied(mut e) => {
-                            match f(e.get().clone()).next().transpose()? {
+                            // take the value out of the object instead of cloning it,
+                            // so that it stays unique and `f` can update it in place
+                            match f(core::mem::replace(e.get_mut(), Val::Null))
+                                .next()
+                                .transpose()?
+                            {
                                 Some(y) => e.insert(y),
                                 None => e.remove(),
                             };
@@ -164,14 +171,17 @@ impl Part<Val> {
                         Err(e) => return opt.fail(v, |_| e),
                     };
 
-                    if let Some(y) = f(a[i].clone()).next().transpose()? {
+                    if let Some(y) = f(core::mem::replace(&mut a[i], Val::Null))
+                        .next()
+                        .transpose()?
+                    {
                         a[i] = y;
                     } else {
                         a.remove(i);
                     }
                     Ok(v)
                 }
-                _ => opt.fail(v, |v| Error::Type(v, Type::Iter)),
+                _ => opt.fail(v, |v| Error::Index(v, idx.clone())),
             },
             Self::Range(None, None) => match v.try_map(&f)? {
                 y @ (Val::Arr(_) | Val::Obj(_)) => Ok(y),
@@ -190,9 +200,9 @@ impl Part<Val> {
                     let from = abs_bound(from, len, 0);
                     let upto = abs_bound(upto, len, len);
                     let (skip, take) = skip_take(from, upto);
-                    let arr = Val::arr(a.iter().skip(skip).take(take).cloned().collect());
+                    let arr = Val::arr(a.drain(skip..skip + take).collect());
                     let y = f(arr).map(|y| y?.into_arr()).next().transpose()?;
-                    a.splice(skip..skip + take, (*y.unwrap_or_default()).clone());
+                    a.splice(skip..skip, rc_unwrap_or_clone(y.unwrap_or_default()));
                     Ok(v)
                 }
                 _ => opt.fail(v, |v| Error::Type(v, Type::Arr)),
@@ -276,8 +286,18 @@ impl<F> From<Part<F>> for Path<F> {
     }
 }
 
+/// Bounds of a slice as object, similar to how jq represents them.
+fn range_obj(from: Option<Val>, upto: Option<Val>) -> Val {
+    let bound = |k: &str, v: Option<Val>| (Rc::new(k.into()), v.unwrap_or(Val::Null));
+    Val::obj(
+        [bound("start", from), bound("end", upto)]
+            .into_iter()
+            .collect(),
+    )
+}
+
 fn skip_take(from: usize, until: usize) -> (usize, usize) {
-    (from, if until > from { until - from } else { 0 })
+    (from, until.saturating_sub(from))
 }
 
 /// If a range bound is given, absolutise and clip it between 0 and `len`,
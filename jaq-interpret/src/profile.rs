@@ -0,0 +1,115 @@
+//! Measurement of the calls to definitions during evaluation.
+
+use crate::filter::{Id, Owned};
+use crate::val::ValRs;
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::cell::RefCell;
+use core::time::Duration;
+
+/// Record of how often definitions are called and how long their calls take.
+///
+/// This can be used to find the definitions on which evaluation spends most time.
+pub struct Profile<'a> {
+    clock: &'a dyn Fn() -> Duration,
+    counts: RefCell<Vec<Counts>>,
+}
+
+#[derive(Clone, Default)]
+struct Counts {
+    calls: usize,
+    outputs: usize,
+    time: Duration,
+    /// number of calls to the definition that are currently computing outputs
+    active: usize,
+}
+
+/// Statistics about the calls to a definition.
+#[derive(Clone, Debug)]
+pub struct Stats {
+    /// name of the definition, such as `map`
+    pub name: String,
+    /// number of arguments of the definition
+    pub arity: usize,
+    /// name of the source file and line of the definition, if known
+    ///
+    /// This is unknown for definitions inserted via [`crate::ParseCtx::insert_defs`],
+    /// such as those of the standard library.
+    pub loc: Option<(String, usize)>,
+    /// number of calls
+    pub calls: usize,
+    /// number of values yielded by the calls
+    pub outputs: usize,
+    /// time spent to yield the outputs of the calls
+    ///
+    /// Time spent in a call that occurs during another call to
+    /// the same definition is counted only once.
+    pub time: Duration,
+}
+
+impl<'a> Profile<'a> {
+    /// Create a new profile.
+    ///
+    /// The `clock` has to return the time that elapsed since some fixed instant.
+    pub fn new(clock: &'a dyn Fn() -> Duration) -> Self {
+        let counts = RefCell::new(Vec::new());
+        Self { clock, counts }
+    }
+
+    /// Run `f` on the counts of the definition with the given ID.
+    fn with<T>(&self, id: Id, f: impl FnOnce(&mut Counts) -> T) -> T {
+        let mut counts = self.counts.borrow_mut();
+        if counts.len() <= id.0 {
+            counts.resize(id.0 + 1, Counts::default());
+        }
+        f(&mut counts[id.0])
+    }
+
+    /// Run `f` and add the time that it takes to the definition with the given ID.
+    fn measure<T>(&self, id: Id, f: impl FnOnce() -> T) -> T {
+        let outermost = self.with(id, |c| {
+            c.active += 1;
+            c.active == 1
+        });
+        let start = outermost.then(self.clock);
+        let y = f();
+        let end = start.map(|start| (self.clock)().saturating_sub(start));
+        self.with(id, |c| {
+            c.active -= 1;
+            c.time += end.unwrap_or_default();
+        });
+        y
+    }
+
+    /// Count a call to the definition with the given ID, whose outputs are yielded by `f`.
+    pub(crate) fn call(&'a self, id: Id, f: impl FnOnce() -> ValRs<'a>) -> ValRs<'a> {
+        self.with(id, |c| c.calls += 1);
+        let mut ys = self.measure(id, f);
+        Box::new(core::iter::from_fn(move || {
+            let y = self.measure(id, || ys.next());
+            if let Some(Ok(_)) = y {
+                self.with(id, |c| c.outputs += 1);
+            }
+            y
+        }))
+    }
+
+    /// Return the statistics of all definitions of `filter` that were called,
+    /// starting with those that took the most time.
+    pub fn stats(&self, filter: &Owned) -> Vec<Stats> {
+        let counts = self.counts.borrow();
+        let stats = filter.2.iter().filter_map(|(id, def)| {
+            let c = counts.get(id.0).filter(|c| c.calls > 0)?;
+            Some(Stats {
+                name: def.name.clone(),
+                arity: def.arity,
+                loc: def.loc.clone(),
+                calls: c.calls,
+                outputs: c.outputs,
+                time: c.time,
+            })
+        });
+        let mut stats: Vec<_> = stats.collect();
+        stats.sort_by(|a, b| b.time.cmp(&a.time).then(b.calls.cmp(&a.calls)));
+        stats
+    }
+}
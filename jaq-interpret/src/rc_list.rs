@@ -62,6 +62,11 @@ impl<T> List<T> {
         cur
     }
 
+    /// Return a number that identifies the list uniquely as long as it is alive.
+    pub fn id(&self) -> usize {
+        alloc::rc::Rc::as_ptr(&self.0) as usize
+    }
+
     #[cfg(test)]
     fn iter(&self) -> impl Iterator<Item = &T> {
         use alloc::boxed::Box;
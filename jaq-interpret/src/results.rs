@@ -34,6 +34,19 @@ pub fn run_if_ok<'a, T, E>(x: T, e: &mut Option<E>, f: &impl Fn(T) -> Results<'a
     }
 }
 
+/// Consume all results, returning the last value and the number of values.
+///
+/// Return the first error if there is one.
+/// This allows deciding whether a filter yielded any output at all and
+/// whether its last output was true, such as for `--exit-status`,
+/// without collecting all outputs.
+pub fn last_count<T, E>(
+    iter: impl IntoIterator<Item = Result<T, E>>,
+) -> Result<(Option<T>, usize), E> {
+    iter.into_iter()
+        .try_fold((None, 0), |(_, n), x| Ok((Some(x?), n + 1)))
+}
+
 // TODO for v2.0: remove this
 // if `inner` is true, output values that yield non-empty output;
 // if `outer` is true, output values that yield     empty output
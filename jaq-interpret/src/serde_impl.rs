@@ -0,0 +1,633 @@
+//! Conversion between values and types implementing `Serialize` / `Deserialize`.
+//!
+//! Values are serialised like `serde_json` serialises JSON values,
+//! meaning that enum variants are externally tagged, e.g. `{"Variant": ...}`.
+use crate::val::Val;
+use crate::{Ctx, Error, FilterT};
+use alloc::string::{String, ToString};
+use alloc::{boxed::Box, rc::Rc, vec::Vec};
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+use serde::de::value::{Error as SerdeError, MapDeserializer, SeqDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+
+impl Val {
+    /// Convert a serialisable value to a value.
+    pub fn from_serialize<T: Serialize + ?Sized>(x: &T) -> Result<Self, SerdeError> {
+        x.serialize(Serializer)
+    }
+
+    /// Convert a value to a deserialisable type.
+    ///
+    /// Because `Val` implements `Deserializer`, this is equivalent to `T::deserialize(v)`.
+    pub fn deserialize_into<T: DeserializeOwned>(self) -> Result<T, SerdeError> {
+        T::deserialize(self)
+    }
+}
+
+impl crate::Filter {
+    /// Run the filter on a serialisable input and deserialise its outputs.
+    ///
+    /// Failure to serialise the input or to deserialise an output
+    /// is reported as error.
+    pub fn run_serde<'a, T, U>(
+        &'a self,
+        ctx: Ctx<'a>,
+        input: &T,
+    ) -> Box<dyn Iterator<Item = Result<U, Error>> + 'a>
+    where
+        T: Serialize + ?Sized,
+        U: DeserializeOwned + 'a,
+    {
+        match Val::from_serialize(input) {
+            Ok(v) => Box::new(
+                self.run((ctx, v))
+                    .map(|y| y?.deserialize_into().map_err(Error::str)),
+            ),
+            Err(e) => Box::new(core::iter::once(Err(Error::str(e)))),
+        }
+    }
+}
+
+impl Serialize for Val {
+    fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use ser::{SerializeMap, SerializeSeq};
+        match self {
+            Self::Null => s.serialize_unit(),
+            Self::Bool(b) => s.serialize_bool(*b),
+            Self::Int(i) => s.serialize_i64(*i as i64),
+            Self::Float(f) => s.serialize_f64(*f),
+            Self::BigInt(i) => {
+                if let Some(i) = i.to_i128() {
+                    s.serialize_i128(i)
+                } else if let Some(u) = i.to_u128() {
+                    s.serialize_u128(u)
+                } else {
+                    Err(ser::Error::custom(format_args!(
+                        "integer does not fit into 128 bits: {i}"
+                    )))
+                }
+            }
+            Self::Num(n) => Self::from_dec_str(n).serialize(s),
+            Self::Str(x) => s.serialize_str(x),
+            Self::Arr(a) => {
+                let mut seq = s.serialize_seq(Some(a.len()))?;
+                a.iter().try_for_each(|x| seq.serialize_element(x))?;
+                seq.end()
+            }
+            Self::Obj(o) => {
+                let mut map = s.serialize_map(Some(o.len()))?;
+                o.iter()
+                    .try_for_each(|(k, v)| map.serialize_entry(&**k, v))?;
+                map.end()
+            }
+        }
+    }
+}
+
+impl<'de> de::Deserialize<'de> for Val {
+    fn deserialize<D: de::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        d.deserialize_any(ValVisitor)
+    }
+}
+
+struct ValVisitor;
+
+impl<'de> Visitor<'de> for ValVisitor {
+    type Value = Val;
+
+    fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str("any valid JSON value")
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<Val, E> {
+        Ok(Val::Bool(b))
+    }
+
+    fn visit_i64<E>(self, i: i64) -> Result<Val, E> {
+        Ok(int(i))
+    }
+
+    fn visit_u64<E>(self, u: u64) -> Result<Val, E> {
+        Ok(int(u))
+    }
+
+    fn visit_i128<E>(self, i: i128) -> Result<Val, E> {
+        Ok(int(i))
+    }
+
+    fn visit_u128<E>(self, u: u128) -> Result<Val, E> {
+        Ok(int(u))
+    }
+
+    fn visit_f64<E>(self, f: f64) -> Result<Val, E> {
+        Ok(Val::Float(f))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Val, E> {
+        Ok(Val::str(s.to_string()))
+    }
+
+    fn visit_string<E>(self, s: String) -> Result<Val, E> {
+        Ok(Val::str(s))
+    }
+
+    fn visit_unit<E>(self) -> Result<Val, E> {
+        Ok(Val::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Val, E> {
+        Ok(Val::Null)
+    }
+
+    fn visit_some<D: de::Deserializer<'de>>(self, d: D) -> Result<Val, D::Error> {
+        de::Deserialize::deserialize(d)
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Val, A::Error> {
+        let mut a = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(x) = seq.next_element()? {
+            a.push(x);
+        }
+        Ok(Val::arr(a))
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Val, A::Error> {
+        let mut o = Vec::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some((k, v)) = map.next_entry::<String, Val>()? {
+            o.push((Rc::new(k), v));
+        }
+        Ok(Val::obj(o.into_iter().collect()))
+    }
+}
+
+fn int<I: Copy + TryInto<isize> + Into<BigInt>>(i: I) -> Val {
+    i.try_into().map_or_else(|_| Val::big(i.into()), Val::Int)
+}
+
+impl<'de> de::Deserializer<'de> for Val {
+    type Error = SerdeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self {
+            Self::Null => visitor.visit_unit(),
+            Self::Bool(b) => visitor.visit_bool(b),
+            Self::Int(i) => visitor.visit_i64(i as i64),
+            Self::Float(f) => visitor.visit_f64(f),
+            Self::BigInt(i) => {
+                if let Some(i) = i.to_i64() {
+                    visitor.visit_i64(i)
+                } else if let Some(u) = i.to_u64() {
+                    visitor.visit_u64(u)
+                } else if let Some(i) = i.to_i128() {
+                    visitor.visit_i128(i)
+                } else if let Some(u) = i.to_u128() {
+                    visitor.visit_u128(u)
+                } else {
+                    visitor.visit_f64(i.to_f64().unwrap_or(f64::NAN))
+                }
+            }
+            Self::Num(n) => Self::from_dec_str(&n).deserialize_any(visitor),
+            Self::Str(s) => visitor.visit_str(&s),
+            Self::Arr(a) => {
+                let mut seq = SeqDeserializer::new(a.iter().cloned());
+                let y = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(y)
+            }
+            Self::Obj(o) => {
+                let mut map =
+                    MapDeserializer::new(o.iter().map(|(k, v)| ((**k).clone(), v.clone())));
+                let y = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(y)
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self {
+            Self::Null => visitor.visit_none(),
+            v => visitor.visit_some(v),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        match self {
+            Self::Str(s) => visitor.visit_enum((*s).clone().into_deserializer()),
+            Self::Obj(o) if o.len() == 1 => {
+                let (k, v) = o.iter().next().unwrap();
+                visitor.visit_enum(Enum((**k).clone(), v.clone()))
+            }
+            v => Err(de::Error::invalid_type(
+                unexpected(&v),
+                &"string or object with one key",
+            )),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, SerdeError> for Val {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+fn unexpected(v: &Val) -> de::Unexpected<'_> {
+    match v {
+        Val::Null => de::Unexpected::Unit,
+        Val::Bool(b) => de::Unexpected::Bool(*b),
+        Val::Int(i) => de::Unexpected::Signed(*i as i64),
+        Val::Float(f) => de::Unexpected::Float(*f),
+        Val::BigInt(_) | Val::Num(_) => de::Unexpected::Other("number"),
+        Val::Str(s) => de::Unexpected::Str(s),
+        Val::Arr(_) => de::Unexpected::Seq,
+        Val::Obj(_) => de::Unexpected::Map,
+    }
+}
+
+/// Externally tagged enum variant, given by its name and its contents.
+struct Enum(String, Val);
+
+impl<'de> de::EnumAccess<'de> for Enum {
+    type Error = SerdeError;
+    type Variant = Val;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Val), SerdeError>
+    where
+        V: de::DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(self.0.into_deserializer())?;
+        Ok((variant, self.1))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Val {
+    type Error = SerdeError;
+
+    fn unit_variant(self) -> Result<(), SerdeError> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, SerdeError>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value, SerdeError>
+    where
+        V: Visitor<'de>,
+    {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError>
+    where
+        V: Visitor<'de>,
+    {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+/// Serializer that yields values.
+struct Serializer;
+
+/// Serializer for arrays, optionally wrapped in an object with the given key.
+struct SerArr(Option<&'static str>, Vec<Val>);
+
+/// Serializer for objects, optionally wrapped in an object with the given key.
+struct SerObj(
+    Option<&'static str>,
+    Vec<(Rc<String>, Val)>,
+    Option<Rc<String>>,
+);
+
+/// Wrap a value into an object with a single key if a key is given.
+fn tag(variant: Option<&'static str>, v: Val) -> Val {
+    match variant {
+        None => v,
+        Some(k) => Val::obj(core::iter::once((Rc::new(k.to_string()), v)).collect()),
+    }
+}
+
+impl ser::Serializer for Serializer {
+    type Ok = Val;
+    type Error = SerdeError;
+    type SerializeSeq = SerArr;
+    type SerializeTuple = SerArr;
+    type SerializeTupleStruct = SerArr;
+    type SerializeTupleVariant = SerArr;
+    type SerializeMap = SerObj;
+    type SerializeStruct = SerObj;
+    type SerializeStructVariant = SerObj;
+
+    fn serialize_bool(self, b: bool) -> ValS {
+        Ok(Val::Bool(b))
+    }
+
+    fn serialize_i8(self, i: i8) -> ValS {
+        Ok(int(i))
+    }
+
+    fn serialize_i16(self, i: i16) -> ValS {
+        Ok(int(i))
+    }
+
+    fn serialize_i32(self, i: i32) -> ValS {
+        Ok(int(i))
+    }
+
+    fn serialize_i64(self, i: i64) -> ValS {
+        Ok(int(i))
+    }
+
+    fn serialize_i128(self, i: i128) -> ValS {
+        Ok(int(i))
+    }
+
+    fn serialize_u8(self, u: u8) -> ValS {
+        Ok(int(u))
+    }
+
+    fn serialize_u16(self, u: u16) -> ValS {
+        Ok(int(u))
+    }
+
+    fn serialize_u32(self, u: u32) -> ValS {
+        Ok(int(u))
+    }
+
+    fn serialize_u64(self, u: u64) -> ValS {
+        Ok(int(u))
+    }
+
+    fn serialize_u128(self, u: u128) -> ValS {
+        Ok(int(u))
+    }
+
+    fn serialize_f32(self, f: f32) -> ValS {
+        Ok(Val::Float(f.into()))
+    }
+
+    fn serialize_f64(self, f: f64) -> ValS {
+        Ok(Val::Float(f))
+    }
+
+    fn serialize_char(self, c: char) -> ValS {
+        Ok(Val::str(c.to_string()))
+    }
+
+    fn serialize_str(self, s: &str) -> ValS {
+        Ok(Val::str(s.to_string()))
+    }
+
+    fn serialize_bytes(self, b: &[u8]) -> ValS {
+        Ok(Val::arr(b.iter().map(|b| Val::Int((*b).into())).collect()))
+    }
+
+    fn serialize_none(self) -> ValS {
+        Ok(Val::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, x: &T) -> ValS {
+        x.serialize(self)
+    }
+
+    fn serialize_unit(self) -> ValS {
+        Ok(Val::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> ValS {
+        Ok(Val::Null)
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _i: u32, variant: &'static str) -> ValS {
+        Ok(Val::str(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, x: &T) -> ValS
+    where
+        T: Serialize + ?Sized,
+    {
+        x.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _i: u32,
+        variant: &'static str,
+        x: &T,
+    ) -> ValS
+    where
+        T: Serialize + ?Sized,
+    {
+        Ok(tag(Some(variant), x.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerArr, SerdeError> {
+        Ok(SerArr(None, Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerArr, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SerArr, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _i: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerArr, SerdeError> {
+        Ok(SerArr(Some(variant), Vec::with_capacity(len)))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<SerObj, SerdeError> {
+        Ok(SerObj(None, Vec::with_capacity(len.unwrap_or(0)), None))
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerObj, SerdeError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _i: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerObj, SerdeError> {
+        Ok(SerObj(Some(variant), Vec::with_capacity(len), None))
+    }
+}
+
+type ValS = Result<Val, SerdeError>;
+
+impl SerArr {
+    fn push<T: Serialize + ?Sized>(&mut self, x: &T) -> Result<(), SerdeError> {
+        self.1.push(x.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn finish(self) -> ValS {
+        Ok(tag(self.0, Val::arr(self.1)))
+    }
+}
+
+impl ser::SerializeSeq for SerArr {
+    type Ok = Val;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, x: &T) -> Result<(), SerdeError> {
+        self.push(x)
+    }
+
+    fn end(self) -> ValS {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SerArr {
+    type Ok = Val;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, x: &T) -> Result<(), SerdeError> {
+        self.push(x)
+    }
+
+    fn end(self) -> ValS {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SerArr {
+    type Ok = Val;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, x: &T) -> Result<(), SerdeError> {
+        self.push(x)
+    }
+
+    fn end(self) -> ValS {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SerArr {
+    type Ok = Val;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, x: &T) -> Result<(), SerdeError> {
+        self.push(x)
+    }
+
+    fn end(self) -> ValS {
+        self.finish()
+    }
+}
+
+impl SerObj {
+    fn field<T: Serialize + ?Sized>(&mut self, k: &str, v: &T) -> Result<(), SerdeError> {
+        self.1
+            .push((Rc::new(k.to_string()), v.serialize(Serializer)?));
+        Ok(())
+    }
+
+    fn finish(self) -> ValS {
+        Ok(tag(self.0, Val::obj(self.1.into_iter().collect())))
+    }
+}
+
+impl ser::SerializeMap for SerObj {
+    type Ok = Val;
+    type Error = SerdeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, k: &T) -> Result<(), SerdeError> {
+        // like `serde_json`, we accept also numbers and booleans as keys
+        self.2 = Some(match k.serialize(Serializer)? {
+            Val::Str(s) => s,
+            v @ (Val::Bool(_) | Val::Int(_) | Val::BigInt(_) | Val::Float(_)) => {
+                Rc::new(v.to_string())
+            }
+            _ => return Err(ser::Error::custom("object key must be a string")),
+        });
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(), SerdeError> {
+        let k = self
+            .2
+            .take()
+            .expect("serialize_value called before serialize_key");
+        self.1.push((k, v.serialize(Serializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> ValS {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for SerObj {
+    type Ok = Val;
+    type Error = SerdeError;
+
+    fn serialize_field<T>(&mut self, k: &'static str, v: &T) -> Result<(), SerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.field(k, v)
+    }
+
+    fn end(self) -> ValS {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for SerObj {
+    type Ok = Val;
+    type Error = SerdeError;
+
+    fn serialize_field<T>(&mut self, k: &'static str, v: &T) -> Result<(), SerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.field(k, v)
+    }
+
+    fn end(self) -> ValS {
+        self.finish()
+    }
+}
@@ -0,0 +1,312 @@
+//! Styled serialisation of values.
+//!
+//! This allows embedders to highlight the tokens of a value,
+//! for example via ANSI escape sequences ([`ColorScheme`]) or
+//! via the styles of a terminal UI library (by implementing [`Style`]).
+
+use crate::Val;
+use alloc::string::String;
+use core::fmt::{self, Write};
+
+/// Kind of a token in the serialisation of a value.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Token {
+    /// `null`
+    Null,
+    /// `false`
+    False,
+    /// `true`
+    True,
+    /// Number
+    Num,
+    /// String that is not an object key
+    Str,
+    /// Brackets and commas of an array
+    Arr,
+    /// Braces, commas, and colons of an object
+    Obj,
+    /// Object key
+    Key,
+}
+
+/// Emission of style spans around tokens.
+///
+/// Every token is written between a call to `start` and a call to `end`.
+pub trait Style {
+    /// Start a span for a token.
+    fn start(&self, token: Token, w: &mut dyn Write) -> fmt::Result;
+    /// End a span that was started for a token.
+    fn end(&self, token: Token, w: &mut dyn Write) -> fmt::Result;
+}
+
+/// Plain output without any styling.
+impl Style for () {
+    fn start(&self, _: Token, _: &mut dyn Write) -> fmt::Result {
+        Ok(())
+    }
+    fn end(&self, _: Token, _: &mut dyn Write) -> fmt::Result {
+        Ok(())
+    }
+}
+
+/// Color scheme that styles tokens with ANSI escape sequences.
+///
+/// Every field contains the SGR parameters for a kind of token, such as `"1;30"`.
+/// Tokens with empty parameters are written without any escape sequence.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColorScheme {
+    /// Parameters for [`Token::Null`]
+    pub null: String,
+    /// Parameters for [`Token::False`]
+    pub r#false: String,
+    /// Parameters for [`Token::True`]
+    pub r#true: String,
+    /// Parameters for [`Token::Num`]
+    pub num: String,
+    /// Parameters for [`Token::Str`]
+    pub str: String,
+    /// Parameters for [`Token::Arr`]
+    pub arr: String,
+    /// Parameters for [`Token::Obj`]
+    pub obj: String,
+    /// Parameters for [`Token::Key`]
+    pub key: String,
+}
+
+/// The colors used by jq 1.7.1.
+impl Default for ColorScheme {
+    fn default() -> Self {
+        Self {
+            null: "1;30".into(),
+            r#false: "0;39".into(),
+            r#true: "0;39".into(),
+            num: "0;39".into(),
+            str: "0;32".into(),
+            arr: "1;39".into(),
+            obj: "1;39".into(),
+            key: "34;1".into(),
+        }
+    }
+}
+
+impl ColorScheme {
+    /// Parse a color scheme in the format of jq's `JQ_COLORS`, such as `0;90:0;37:0;37`.
+    ///
+    /// The colors are separated by `:` and given in the order
+    /// null, false, true, numbers, strings, arrays, objects, object keys.
+    /// Colors that are not given are taken from the default scheme.
+    /// Return `None` if there are too many colors or
+    /// a color contains anything else than digits and `;`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut scheme = Self::default();
+        let mut fields = [
+            &mut scheme.null,
+            &mut scheme.r#false,
+            &mut scheme.r#true,
+            &mut scheme.num,
+            &mut scheme.str,
+            &mut scheme.arr,
+            &mut scheme.obj,
+            &mut scheme.key,
+        ]
+        .into_iter();
+        for color in s.split(':') {
+            if !color.chars().all(|c| c.is_ascii_digit() || c == ';') {
+                return None;
+            }
+            *fields.next()? = color.into();
+        }
+        drop(fields);
+        Some(scheme)
+    }
+
+    fn get(&self, token: Token) -> &str {
+        match token {
+            Token::Null => &self.null,
+            Token::False => &self.r#false,
+            Token::True => &self.r#true,
+            Token::Num => &self.num,
+            Token::Str => &self.str,
+            Token::Arr => &self.arr,
+            Token::Obj => &self.obj,
+            Token::Key => &self.key,
+        }
+    }
+}
+
+impl Style for ColorScheme {
+    fn start(&self, token: Token, w: &mut dyn Write) -> fmt::Result {
+        match self.get(token) {
+            "" => Ok(()),
+            color => write!(w, "\x1b[{color}m"),
+        }
+    }
+    fn end(&self, token: Token, w: &mut dyn Write) -> fmt::Result {
+        match self.get(token) {
+            "" => Ok(()),
+            _ => w.write_str("\x1b[0m"),
+        }
+    }
+}
+
+/// Serialise a value as JSON, styling its tokens.
+///
+/// If `indent` is `None`, the output is compact, else
+/// every array element and object entry is put on its own line,
+/// prefixed by `indent` once per nesting level.
+pub fn write(w: &mut dyn Write, v: &Val, style: &dyn Style, indent: Option<&str>) -> fmt::Result {
+    let opts = Options {
+        indent,
+        ..Options::default()
+    };
+    opts.write(w, v, style)
+}
+
+/// Options for the serialisation of values.
+#[derive(Clone, Copy)]
+pub struct Options<'a> {
+    /// string to indent every nesting level with, or `None` to omit whitespace
+    pub indent: Option<&'a str>,
+    /// escape all non-ASCII characters in strings
+    pub ascii: bool,
+    /// function to write finite floats with, by default like [`Val`]'s `Display`
+    pub float: fn(&mut dyn Write, f64) -> fmt::Result,
+}
+
+impl Default for Options<'_> {
+    fn default() -> Self {
+        Self {
+            indent: None,
+            ascii: false,
+            float: |w, f| write!(w, "{f:?}"),
+        }
+    }
+}
+
+impl<'a> Options<'a> {
+    /// Serialise a value as JSON, styling its tokens.
+    pub fn write(&self, w: &mut dyn Write, v: &Val, style: &dyn Style) -> fmt::Result {
+        let opts = *self;
+        Writer { w, style, opts }.val(v, 0)
+    }
+}
+
+struct Writer<'a> {
+    w: &'a mut dyn Write,
+    style: &'a dyn Style,
+    opts: Options<'a>,
+}
+
+impl<'a> Writer<'a> {
+    fn token(
+        &mut self,
+        token: Token,
+        f: impl FnOnce(&mut dyn Write) -> fmt::Result,
+    ) -> fmt::Result {
+        self.style.start(token, self.w)?;
+        f(self.w)?;
+        self.style.end(token, self.w)
+    }
+
+    fn punct(&mut self, token: Token, s: &str) -> fmt::Result {
+        self.token(token, |w| w.write_str(s))
+    }
+
+    /// Start a new line if output is not compact.
+    fn newline(&mut self, level: usize) -> fmt::Result {
+        if let Some(indent) = self.opts.indent {
+            self.w.write_char('\n')?;
+            (0..level).try_for_each(|_| self.w.write_str(indent))?;
+        }
+        Ok(())
+    }
+
+    /// Write a sequence of items between delimiters, separated by commas.
+    fn seq<T>(
+        &mut self,
+        token: Token,
+        delims: (&str, &str),
+        items: impl ExactSizeIterator<Item = T>,
+        level: usize,
+        mut f: impl FnMut(&mut Self, T) -> fmt::Result,
+    ) -> fmt::Result {
+        self.punct(token, delims.0)?;
+        let empty = items.len() == 0;
+        for (i, item) in items.enumerate() {
+            if i > 0 {
+                self.punct(token, ",")?;
+            }
+            self.newline(level + 1)?;
+            f(self, item)?;
+        }
+        if !empty {
+            self.newline(level)?;
+        }
+        self.punct(token, delims.1)
+    }
+
+    fn val(&mut self, v: &Val, level: usize) -> fmt::Result {
+        match v {
+            Val::Null => self.punct(Token::Null, "null"),
+            Val::Bool(false) => self.punct(Token::False, "false"),
+            Val::Bool(true) => self.punct(Token::True, "true"),
+            Val::Float(f) if !f.is_finite() => self.punct(Token::Null, "null"),
+            Val::Float(f) => {
+                let float = self.opts.float;
+                self.token(Token::Num, |w| float(w, *f))
+            }
+            Val::Int(_) | Val::BigInt(_) | Val::Num(_) => {
+                self.token(Token::Num, |w| write!(w, "{v}"))
+            }
+            Val::Str(s) => {
+                let ascii = self.opts.ascii;
+                self.token(Token::Str, |w| write_str(w, s, ascii))
+            }
+            Val::Arr(a) => self.seq(Token::Arr, ("[", "]"), a.iter(), level, |this, x| {
+                this.val(x, level + 1)
+            }),
+            Val::Obj(o) => self.seq(Token::Obj, ("{", "}"), o.iter(), level, |this, (k, v)| {
+                let ascii = this.opts.ascii;
+                this.token(Token::Key, |w| write_str(w, k, ascii))?;
+                this.punct(Token::Obj, ":")?;
+                if this.opts.indent.is_some() {
+                    this.w.write_char(' ')?;
+                }
+                this.val(v, level + 1)
+            }),
+        }
+    }
+}
+
+/// Write a string as JSON string literal, escaping characters where necessary.
+///
+/// If `ascii` is true, then also escape all non-ASCII characters.
+fn write_str(w: &mut dyn Write, s: &str, ascii: bool) -> fmt::Result {
+    w.write_char('"')?;
+    // start of the part of `s` that has not been written yet
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        let escape = match c {
+            '"' => "\\\"",
+            '\\' => "\\\\",
+            '\n' => "\\n",
+            '\t' => "\\t",
+            '\r' => "\\r",
+            '\u{08}' => "\\b",
+            '\u{0c}' => "\\f",
+            _ if c < ' ' || (ascii && !c.is_ascii()) => "",
+            _ => continue,
+        };
+        w.write_str(&s[start..i])?;
+        if escape.is_empty() {
+            for u in c.encode_utf16(&mut [0; 2]) {
+                write!(w, "\\u{u:04x}")?;
+            }
+        } else {
+            w.write_str(escape)?;
+        }
+        start = i + c.len_utf8();
+    }
+    w.write_str(&s[start..])?;
+    w.write_char('"')
+}
@@ -8,6 +8,8 @@ use core::fmt;
 #[cfg(feature = "hifijson")]
 use hifijson::{LexAlloc, Token};
 use jaq_syn::MathOp;
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, ToPrimitive, Zero};
 
 /// JSON value with sharing.
 ///
@@ -18,6 +20,10 @@ use jaq_syn::MathOp;
 ///
 /// Operations on numbers follow a few principles:
 /// * The sum, difference, product, and remainder of two integers is integer.
+///   If the result does not fit into a machine-sized integer,
+///   it is a big integer instead.
+/// * The quotient of two integers, at least one of them big, is integer
+///   if the division is exact.
 /// * Any other operation between two numbers yields a float.
 #[derive(Clone, Debug)]
 pub enum Val {
@@ -29,6 +35,10 @@ pub enum Val {
     Int(isize),
     /// Floating-point number
     Float(f64),
+    /// Integer not fitting into `Int`
+    ///
+    /// Arithmetic returns `Int` instead of this whenever the result fits into `Int`.
+    BigInt(Rc<BigInt>),
     /// Floating-point number or integer not fitting into `Int`
     Num(Rc<String>),
     /// String
@@ -50,7 +60,7 @@ pub type ValRs<'a> = Box<dyn Iterator<Item = ValR> + 'a>;
 
 // This might be included in the Rust standard library:
 // <https://github.com/rust-lang/rust/issues/93610>
-fn rc_unwrap_or_clone<T: Clone>(a: Rc<T>) -> T {
+pub(crate) fn rc_unwrap_or_clone<T: Clone>(a: Rc<T>) -> T {
     Rc::try_unwrap(a).unwrap_or_else(|a| (*a).clone())
 }
 
@@ -70,6 +80,12 @@ impl Val {
         Self::Obj(m.into())
     }
 
+    /// Construct an integer value, using `Int` if the integer fits into it.
+    pub fn big(i: BigInt) -> Self {
+        i.to_isize()
+            .map_or_else(|| Self::BigInt(i.into()), Self::Int)
+    }
+
     /// True if the value is neither null nor false.
     pub fn as_bool(&self) -> bool {
         !matches!(self, Self::Null | Self::Bool(false))
@@ -89,6 +105,7 @@ impl Val {
         match self {
             Self::Int(n) => Ok(*n as f64),
             Self::Float(n) => Ok(*n),
+            Self::BigInt(i) => Ok(big_f64(i)),
             Self::Num(n) => n.parse().or(Err(Error::Type(self.clone(), Type::Float))),
             _ => Err(Error::Type(self.clone(), Type::Float)),
         }
@@ -135,19 +152,27 @@ impl Val {
         }
     }
 
-    /// Try to parse a string to a [`Self::Float`], else return [`Self::Null`].
+    /// Try to parse a string to an integer, else to a [`Self::Float`],
+    /// else return [`Self::Null`].
     pub fn from_dec_str(n: &str) -> Self {
-        n.parse().map_or(Self::Null, Self::Float)
+        match n.parse() {
+            Ok(i) => Self::big(i),
+            Err(_) => n.parse().map_or(Self::Null, Self::Float),
+        }
     }
 
     /// Apply a rounding function to floating-point numbers, then convert them to integers.
     ///
+    /// Floats that are too large for `Int` become `BigInt`,
+    /// whereas infinity and NaN stay floats.
     /// Return integers unchanged, and fail on any other input.
     pub fn round(&self, f: impl FnOnce(f64) -> f64) -> Result<Self, Error> {
         match self {
-            Self::Int(_) => Ok(self.clone()),
-            // TODO: this should fail if float does not fit into isize!
-            Self::Float(x) => Ok(Self::Int(f(*x) as isize)),
+            Self::Int(_) | Self::BigInt(_) => Ok(self.clone()),
+            Self::Float(x) => {
+                let y = f(*x);
+                Ok(BigInt::from_f64(y).map_or(Self::Float(y), Self::big))
+            }
             Self::Num(n) => Self::from_dec_str(n).round(f),
             _ => Err(Error::Type(self.clone(), Type::Num)),
         }
@@ -317,6 +342,7 @@ impl From<Val> for serde_json::Value {
             Val::Bool(b) => Bool(b),
             Val::Int(i) => Number(i.into()),
             Val::Float(f) => serde_json::Number::from_f64(f).map_or(Null, Number),
+            Val::BigInt(i) => Number(serde_json::Number::from_str(&i.to_string()).unwrap()),
             Val::Num(n) => Number(serde_json::Number::from_str(&n).unwrap()),
             Val::Str(s) => String((*s).clone()),
             Val::Arr(a) => Array(a.iter().map(|x| x.clone().into()).collect()),
@@ -336,8 +362,14 @@ impl core::ops::Add for Val {
         match (self, rhs) {
             // `null` is a neutral element for addition
             (Null, x) | (x, Null) => Ok(x),
-            (Int(x), Int(y)) => Ok(Int(x + y)),
+            (Int(x), Int(y)) => Ok(x
+                .checked_add(y)
+                .map_or_else(|| big_op(x, y, |x, y| x + y), Int)),
+            (x @ (Int(_) | BigInt(_)), y @ (Int(_) | BigInt(_))) => {
+                Ok(Self::big(big(&x) + big(&y)))
+            }
             (Int(i), Float(f)) | (Float(f), Int(i)) => Ok(Float(f + i as f64)),
+            (BigInt(i), Float(f)) | (Float(f), BigInt(i)) => Ok(Float(f + big_f64(&i))),
             (Float(x), Float(y)) => Ok(Float(x + y)),
             (Num(n), r) => Self::from_dec_str(&n) + r,
             (l, Num(n)) => l + Self::from_dec_str(&n),
@@ -364,9 +396,16 @@ impl core::ops::Sub for Val {
     fn sub(self, rhs: Self) -> Self::Output {
         use Val::*;
         match (self, rhs) {
-            (Int(x), Int(y)) => Ok(Int(x - y)),
+            (Int(x), Int(y)) => Ok(x
+                .checked_sub(y)
+                .map_or_else(|| big_op(x, y, |x, y| x - y), Int)),
+            (x @ (Int(_) | BigInt(_)), y @ (Int(_) | BigInt(_))) => {
+                Ok(Self::big(big(&x) - big(&y)))
+            }
             (Float(f), Int(i)) => Ok(Float(f - i as f64)),
             (Int(i), Float(f)) => Ok(Float(i as f64 - f)),
+            (Float(f), BigInt(i)) => Ok(Float(f - big_f64(&i))),
+            (BigInt(i), Float(f)) => Ok(Float(big_f64(&i) - f)),
             (Float(x), Float(y)) => Ok(Float(x - y)),
             (Num(n), r) => Self::from_dec_str(&n) - r,
             (l, Num(n)) => l - Self::from_dec_str(&n),
@@ -380,16 +419,27 @@ impl core::ops::Sub for Val {
     }
 }
 
+/// Deeply merge `r` into `l`.
+///
+/// This modifies `l` in place if it is not shared, and
+/// it takes the entries of `r` without cloning them if `r` is not shared.
 fn obj_merge(l: &mut Rc<Map<Rc<String>, Val>>, r: Rc<Map<Rc<String>, Val>>) {
+    if l.is_empty() {
+        *l = r;
+        return;
+    }
     let l = Rc::make_mut(l);
-    let r = rc_unwrap_or_clone(r).into_iter();
-    r.for_each(|(k, v)| match (l.get_mut(&k), v) {
+    let mut merge = |k, v| match (l.get_mut(&k), v) {
         (Some(Val::Obj(l)), Val::Obj(r)) => obj_merge(l, r),
         (Some(l), r) => *l = r,
         (None, r) => {
             l.insert(k, r);
         }
-    })
+    };
+    match Rc::try_unwrap(r) {
+        Ok(r) => r.into_iter().for_each(|(k, v)| merge(k, v)),
+        Err(r) => r.iter().for_each(|(k, v)| merge(k.clone(), v.clone())),
+    }
 }
 
 impl core::ops::Mul for Val {
@@ -397,8 +447,14 @@ impl core::ops::Mul for Val {
     fn mul(self, rhs: Self) -> Self::Output {
         use Val::*;
         match (self, rhs) {
-            (Int(x), Int(y)) => Ok(Int(x * y)),
+            (Int(x), Int(y)) => Ok(x
+                .checked_mul(y)
+                .map_or_else(|| big_op(x, y, |x, y| x * y), Int)),
+            (x @ (Int(_) | BigInt(_)), y @ (Int(_) | BigInt(_))) => {
+                Ok(Self::big(big(&x) * big(&y)))
+            }
             (Float(f), Int(i)) | (Int(i), Float(f)) => Ok(Float(f * i as f64)),
+            (Float(f), BigInt(i)) | (BigInt(i), Float(f)) => Ok(Float(f * big_f64(&i))),
             (Float(x), Float(y)) => Ok(Float(x * y)),
             (Str(s), Int(i)) | (Int(i), Str(s)) if i > 0 => Ok(Self::str(s.repeat(i as usize))),
             // string multiplication with negatives or 0 results in null
@@ -435,7 +491,17 @@ impl core::ops::Div for Val {
         use Val::*;
         match (self, rhs) {
             (Int(x), Int(y)) => Ok(Float(x as f64 / y as f64)),
+            (x @ (Int(_) | BigInt(_)), y @ (Int(_) | BigInt(_))) => {
+                let (x, y) = (big(&x), big(&y));
+                if !y.is_zero() && (&x % &y).is_zero() {
+                    Ok(Self::big(x / y))
+                } else {
+                    Ok(Float(big_f64(&x) / big_f64(&y)))
+                }
+            }
             (Float(f), Int(i)) => Ok(Float(f / i as f64)),
+            (Float(f), BigInt(i)) => Ok(Float(f / big_f64(&i))),
+            (BigInt(i), Float(f)) => Ok(Float(big_f64(&i) / f)),
             (Int(i), Float(f)) => Ok(Float(i as f64 / f)),
             (Float(x), Float(y)) => Ok(Float(x / y)),
             (Num(n), r) => Self::from_dec_str(&n) / r,
@@ -451,7 +517,14 @@ impl core::ops::Rem for Val {
     fn rem(self, rhs: Self) -> Self::Output {
         use Val::*;
         match (self, rhs) {
-            (Int(x), Int(y)) if y != 0 => Ok(Int(x % y)),
+            (Int(x), Int(y)) if y != 0 => Ok(x
+                .checked_rem(y)
+                .map_or_else(|| big_op(x, y, |x, y| x % y), Int)),
+            (x @ (Int(_) | BigInt(_)), y @ (Int(_) | BigInt(_))) if y != Int(0) => {
+                Ok(Self::big(big(&x) % big(&y)))
+            }
+            (Num(n), r) => Self::from_dec_str(&n) % r,
+            (l, Num(n)) => l % Self::from_dec_str(&n),
             (l, r) => Err(Error::MathOp(l, MathOp::Rem, r)),
         }
     }
@@ -462,7 +535,8 @@ impl core::ops::Neg for Val {
     fn neg(self) -> Self::Output {
         use Val::*;
         match self {
-            Int(x) => Ok(Int(-x)),
+            Int(x) => Ok(x.checked_neg().map_or_else(|| big_op(x, 0, |x, _| -x), Int)),
+            BigInt(x) => Ok(Self::big(-&*x)),
             Float(x) => Ok(Float(-x)),
             Num(n) => -Self::from_dec_str(&n),
             x => Err(Error::Type(x, Type::Num)),
@@ -476,10 +550,11 @@ impl PartialEq for Val {
             (Self::Null, Self::Null) => true,
             (Self::Bool(x), Self::Bool(y)) => x == y,
             (Self::Int(x), Self::Int(y)) => x == y,
-            (Self::Int(i), Self::Float(f)) | (Self::Float(f), Self::Int(i)) => {
-                float_eq(&(*i as f64), f)
-            }
-            (Self::Float(x), Self::Float(y)) => float_eq(x, y),
+            (Self::Float(x), Self::Float(y)) => float_cmp(x, y) == Ordering::Equal,
+            (Self::Int(_) | Self::BigInt(_) | Self::Float(_), Self::BigInt(_))
+            | (Self::BigInt(_), Self::Int(_) | Self::Float(_))
+            | (Self::Int(_), Self::Float(_))
+            | (Self::Float(_), Self::Int(_)) => self.cmp(other) == Ordering::Equal,
             (Self::Num(x), Self::Num(y)) if Rc::ptr_eq(x, y) => true,
             (Self::Num(n), y) => &Self::from_dec_str(n) == y,
             (x, Self::Num(n)) => x == &Self::from_dec_str(n),
@@ -506,9 +581,14 @@ impl Ord for Val {
             (Self::Null, Self::Null) => Equal,
             (Self::Bool(x), Self::Bool(y)) => x.cmp(y),
             (Self::Int(x), Self::Int(y)) => x.cmp(y),
-            (Self::Int(i), Self::Float(f)) => float_cmp(&(*i as f64), f),
-            (Self::Float(f), Self::Int(i)) => float_cmp(f, &(*i as f64)),
             (Self::Float(x), Self::Float(y)) => float_cmp(x, y),
+            (x @ (Self::Int(_) | Self::BigInt(_)), y @ (Self::Int(_) | Self::BigInt(_))) => {
+                big(x).cmp(&big(y))
+            }
+            (i @ (Self::Int(_) | Self::BigInt(_)), Self::Float(f)) => int_float_cmp(i, *f),
+            (Self::Float(f), i @ (Self::Int(_) | Self::BigInt(_))) => {
+                int_float_cmp(i, *f).reverse()
+            }
             (Self::Num(x), Self::Num(y)) if Rc::ptr_eq(x, y) => Equal,
             (Self::Num(n), y) => Self::from_dec_str(n).cmp(y),
             (x, Self::Num(n)) => x.cmp(&Self::from_dec_str(n)),
@@ -539,8 +619,8 @@ impl Ord for Val {
             (Self::Bool(_), _) => Less,
             (_, Self::Bool(_)) => Greater,
             // numbers are smaller than anything else, except for nulls and bools
-            (Self::Int(_) | Self::Float(_), _) => Less,
-            (_, Self::Int(_) | Self::Float(_)) => Greater,
+            (Self::Int(_) | Self::Float(_) | Self::BigInt(_), _) => Less,
+            (_, Self::Int(_) | Self::Float(_) | Self::BigInt(_)) => Greater,
             // etc.
             (Self::Str(_), _) => Less,
             (_, Self::Str(_)) => Greater,
@@ -550,8 +630,51 @@ impl Ord for Val {
     }
 }
 
-fn float_eq(left: &f64, right: &f64) -> bool {
-    float_cmp(left, right) == Ordering::Equal
+/// Convert an integer value to a big integer.
+///
+/// This must only be called with `Int` or `BigInt`,
+/// which all callers ensure by matching on these variants first.
+fn big(v: &Val) -> BigInt {
+    match v {
+        Val::Int(i) => BigInt::from(*i),
+        Val::BigInt(i) => (**i).clone(),
+        _ => unreachable!("not an integer: {v}"),
+    }
+}
+
+/// Perform an operation on two machine-sized integers that has overflowed.
+fn big_op(x: isize, y: isize, f: impl FnOnce(BigInt, BigInt) -> BigInt) -> Val {
+    Val::big(f(x.into(), y.into()))
+}
+
+fn big_f64(i: &BigInt) -> f64 {
+    i.to_f64().unwrap_or(f64::NAN)
+}
+
+/// Compare an integer value with a float by their exact values.
+///
+/// Converting the integer to a float would lose precision for large integers,
+/// making, e.g., both 2^53 and 2^53 + 1 equal to the same float,
+/// but not to each other.
+fn int_float_cmp(i: &Val, f: f64) -> Ordering {
+    const EXACT: usize = 1 << f64::MANTISSA_DIGITS;
+    match i {
+        // integers up to this size are exactly representable as floats
+        Val::Int(i) if i.unsigned_abs() <= EXACT => float_cmp(&(*i as f64), &f),
+        // infinity and NaN are greater or smaller than any integer
+        _ if !f.is_finite() => float_cmp(&0., &f),
+        _ => {
+            let floor = f.floor();
+            // this conversion is exact, because `floor` is a finite integer
+            let floor_big = BigInt::from_f64(floor).unwrap_or_default();
+            let frac = if f > floor {
+                Ordering::Less
+            } else {
+                Ordering::Equal
+            };
+            big(i).cmp(&floor_big).then(frac)
+        }
+    }
 }
 
 fn float_cmp(left: &f64, right: &f64) -> Ordering {
@@ -570,6 +693,7 @@ impl fmt::Display for Val {
             Self::Int(i) => i.fmt(f),
             Self::Float(x) if x.is_finite() => write!(f, "{x:?}"),
             Self::Float(_) => "null".fmt(f),
+            Self::BigInt(i) => i.fmt(f),
             Self::Num(n) => n.fmt(f),
             Self::Str(s) => write!(f, "\"{s}\""),
             Self::Arr(a) => {
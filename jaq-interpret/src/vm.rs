@@ -0,0 +1,880 @@
+//! Bytecode of filters and a machine to run it.
+//!
+//! Every filter is compiled to a short sequence of operations,
+//! whose operands refer to the filters that they run.
+//! Running a filter moves through these operations without recursion:
+//! the frames to return to after a filter yields an output are kept in a list, and
+//! the states to backtrack to after a filter yields no more outputs are kept in a vector.
+//! That way, deeply recursive filters and deeply nested values
+//! need only heap memory, but no native stack.
+//!
+//! Only native filters, updates, path indices, the values consumed by `reduce`/`foreach`,
+//! and calls that are profiled run in machines of their own,
+//! which the machine consumes like iterators.
+
+use crate::box_iter::{box_once, flat_map_with};
+use crate::filter::{self, Args, Ast, Break, CallTyp, FilterT, Id, Ref};
+use crate::into_iter::{collect_if_once, Either};
+use crate::path::Path;
+use crate::results::then;
+use crate::val::{Val, ValR, ValRs};
+use crate::{rc_lazy_list, Bind, Ctx, Error, Loc, Vars};
+use alloc::{boxed::Box, rc::Rc, string::String, vec::Vec};
+use jaq_syn::filter::FoldType;
+use jaq_syn::{MathOp, OrdOp};
+
+/// Filter definitions together with their bytecode.
+#[derive(Clone, Debug)]
+pub(crate) struct Code {
+    ast: Box<[Ast]>,
+    ops: Box<[Op]>,
+    /// position of the first operation of every filter in `ast`
+    entry: Box<[usize]>,
+}
+
+impl core::ops::Deref for Code {
+    type Target = [Ast];
+
+    fn deref(&self) -> &[Ast] {
+        &self.ast
+    }
+}
+
+/// Operation of the machine.
+///
+/// Operations that follow a `Call` take the output of the called filter.
+/// Every other operation runs a filter, given by the `Id` of its definition, on the input.
+#[derive(Clone, Debug)]
+enum Op {
+    /// yield the input
+    Input,
+    /// yield the input converted to a string
+    ToString,
+    /// yield a value
+    Val(Val),
+    /// run a filter, and continue with its outputs at the next operation
+    Call(Id),
+    /// run a filter, and yield its outputs
+    Jump(Id),
+    /// run a filter, and afterwards, continue at the next operation
+    Fork(Id),
+
+    /// run a filter, and yield the array of its outputs once it has no more outputs
+    Collect(Id),
+    /// add an output to the array of `Collect`
+    Push,
+    /// save an output, then call a filter
+    Save(Id),
+    /// yield an object from a saved key and a value
+    Obj(Option<Loc>),
+    /// yield values, and run a filter on errors
+    Catch(Id),
+    Neg(Option<Loc>),
+    /// run a filter on outputs
+    Then(Id),
+    /// bind outputs to a variable, then run a filter
+    Bind(Id),
+    /// run a filter, and yield only its outputs that are true or that cannot be caught;
+    /// if there were none, continue after the next operation
+    Alt(Id),
+    /// yield an output of the first filter of `Alt` if it passes
+    AltOut,
+    Ite(Id, Id),
+    /// evaluate the indices of a path, then call the filter of the path
+    ///
+    /// The `Id` is the one of the path itself.
+    Path(Id),
+    /// yield the values at a path
+    PathRun(Option<Loc>),
+    Update(Id),
+    /// update a path with an arithmetic operation on an output
+    UpdateMath(Id, MathOp),
+    /// update a path with an output
+    Assign(Id),
+    /// if an output is `false` (or `true` if given), call a filter
+    Logic(bool, Id),
+    /// yield whether an output is true
+    ToBool,
+    Math(MathOp, Option<Loc>),
+    Ord(OrdOp),
+    /// add an output to the parts of a string, then call a filter
+    Cons(Id),
+    /// yield the concatenation of the parts of a string and an output
+    Concat,
+
+    /// prepare the values that a fold iterates over, then call its initial filter
+    ///
+    /// The `Id` is the one of the fold itself.
+    Fold(Id),
+    /// take an accumulator, yield it if given, and continue at the given position
+    FoldOut(bool, usize),
+    /// call the update of a fold with the next value and the accumulator;
+    /// if there is no next value, yield the accumulator if given
+    FoldNext(Id, bool),
+
+    Var(usize),
+    /// run a filter with a new label
+    Label(Id),
+    /// yield an output that does not break out of the label of `Label`
+    LabelOut,
+    Break(usize),
+
+    /// prepare the context for the arguments of a call
+    ///
+    /// The `Id` is the one of the call itself.
+    CallStart(Id),
+    /// bind an output to the next variable argument
+    BindArg,
+    /// bind the next filter argument
+    BindFun(Id),
+    /// run a filter in the context of the call
+    Enter(Id),
+
+    /// run a native filter, locating its errors if a location is given
+    Native(Id, Option<Loc>),
+    /// locate errors
+    ///
+    /// When a `Locate` directly follows the `Call` at the start of a filter,
+    /// calling that filter locates the errors in the frame of the caller instead.
+    Locate(Loc),
+}
+
+impl Code {
+    pub(crate) fn new(ast: Box<[Ast]>) -> Self {
+        let mut ops = Vec::new();
+        let entry = (0..ast.len()).map(|id| {
+            let pc = ops.len();
+            compile(&ast, Id(id), None, &mut ops);
+            pc
+        });
+        let entry = entry.collect();
+        let ops = ops.into();
+        Self { ast, ops, entry }
+    }
+}
+
+/// Compile the filter `id` to operations, locating its errors at `loc` if it is given.
+fn compile(ast: &[Ast], id: Id, loc: Option<&Loc>, ops: &mut Vec<Op>) {
+    let loc = || loc.cloned();
+    match &ast[id.0] {
+        Ast::Id => ops.push(Op::Input),
+        Ast::ToString => ops.push(Op::ToString),
+        Ast::Bool(b) => ops.push(Op::Val(Val::Bool(*b))),
+        Ast::Int(n) => ops.push(Op::Val(Val::Int(*n))),
+        Ast::Float(x) => ops.push(Op::Val(Val::Float(*x))),
+        Ast::Num(n) => ops.push(Op::Val(Val::Num(n.clone()))),
+        Ast::Str(s) => ops.push(Op::Val(Val::str(s.clone()))),
+        Ast::ObjEmpty => ops.push(Op::Val(Val::Obj(Default::default()))),
+        Ast::Array(f) => ops.extend([Op::Collect(*f), Op::Push]),
+        Ast::ObjSingle(k, v) => ops.extend([Op::Call(*k), Op::Save(*v), Op::Obj(loc())]),
+        Ast::Try(f, c) => ops.extend([Op::Call(*f), Op::Catch(*c)]),
+        Ast::Neg(f) => ops.extend([Op::Call(*f), Op::Neg(loc())]),
+        Ast::Pipe(l, false, r) => ops.extend([Op::Call(*l), Op::Then(*r)]),
+        Ast::Pipe(l, true, r) => ops.extend([Op::Call(*l), Op::Bind(*r)]),
+        Ast::Comma(l, r) => ops.extend([Op::Fork(*l), Op::Jump(*r)]),
+        Ast::Alt(l, r) => ops.extend([Op::Alt(*l), Op::AltOut, Op::Jump(*r)]),
+        Ast::Ite(if_, then_, else_) => ops.extend([Op::Call(*if_), Op::Ite(*then_, *else_)]),
+        Ast::Path(..) => ops.extend([Op::Path(id), Op::PathRun(loc())]),
+        Ast::Update(..) => ops.push(Op::Update(id)),
+        Ast::UpdateMath(path, op, f) => ops.extend([Op::Call(*f), Op::UpdateMath(*path, *op)]),
+        Ast::Assign(path, f) => ops.extend([Op::Call(*f), Op::Assign(*path)]),
+        Ast::Logic(l, stop, r) => ops.extend([Op::Call(*l), Op::Logic(*stop, *r), Op::ToBool]),
+        Ast::Math(l, op, r) => ops.extend([Op::Call(*l), Op::Save(*r), Op::Math(*op, loc())]),
+        Ast::Ord(l, op, r) => ops.extend([Op::Call(*l), Op::Save(*r), Op::Ord(*op)]),
+        Ast::Concat(parts) => match parts.split_first() {
+            None => ops.push(Op::Val(Val::str(String::new()))),
+            Some((head, tail)) => {
+                ops.push(Op::Call(*head));
+                ops.extend(tail.iter().map(|part| Op::Cons(*part)));
+                ops.push(Op::Concat)
+            }
+        },
+        Ast::Fold(typ, _, _, f) => {
+            // the position of `FoldNext`, to which every accumulator leads
+            let next = ops.len() + 2;
+            let (init, acc) = match typ {
+                FoldType::Reduce => (false, false),
+                FoldType::For => (true, true),
+                FoldType::Foreach => (false, true),
+            };
+            let reduce = matches!(typ, FoldType::Reduce);
+            ops.extend([Op::Fold(id), Op::FoldOut(init, next)]);
+            ops.extend([Op::FoldNext(*f, reduce), Op::FoldOut(acc, next)])
+        }
+        Ast::Var(v) => ops.push(Op::Var(*v)),
+        Ast::Label(f) => ops.extend([Op::Label(*f), Op::LabelOut]),
+        Ast::Break(v) => ops.push(Op::Break(*v)),
+        Ast::Call(call) => {
+            ops.push(Op::CallStart(id));
+            for arg in call.args.iter() {
+                match arg {
+                    Bind::Var(a) => ops.extend([Op::Call(*a), Op::BindArg]),
+                    Bind::Fun(a) => ops.push(Op::BindFun(*a)),
+                }
+            }
+            ops.push(Op::Enter(call.id))
+        }
+        Ast::Native(..) => ops.push(Op::Native(id, loc())),
+        Ast::At(f, loc) => match &ast[f.0] {
+            // these filters locate their errors themselves, which is
+            // cheaper than locating all errors that pass through them
+            Ast::ObjSingle(..) | Ast::Neg(_) | Ast::Path(..) | Ast::Math(..) | Ast::Native(..) => {
+                compile(ast, *f, Some(loc), ops)
+            }
+            // the errors of calls that throw are located by the call that catches them,
+            // so that tail-recursive calls need no frame
+            Ast::Call(call) if matches!(call.typ, CallTyp::Throw) => compile(ast, *f, None, ops),
+            _ => ops.extend([Op::Call(*f), Op::Locate(loc.clone())]),
+        },
+    }
+}
+
+type Cv<'a> = (Ctx<'a>, Val);
+
+/// Local data of a filter that is being run.
+#[derive(Clone, Default)]
+enum Data<'a> {
+    #[default]
+    None,
+    /// output of a filter, such as the left-hand side of an arithmetic operation
+    Val(ValR),
+    /// outputs of the previous parts of a string
+    Vals(Vec<ValR>),
+    /// position of a fork, such as the one of `Collect` or `Alt`
+    Fork(usize),
+    /// position of the fork of a label and the identifier of the label
+    Label(usize, usize),
+    /// variables of a call, with the arguments bound so far
+    Vars(Vars),
+    /// indices of a path
+    Path(Path<Either<core::iter::Once<ValR>, rc_lazy_list::List<'a, ValR>>>),
+    /// remaining values of a fold
+    Xs(rc_lazy_list::List<'a, ValR>),
+}
+
+impl<'a> Data<'a> {
+    fn fork(&self) -> usize {
+        match self {
+            Self::Fork(i) => *i,
+            _ => unreachable!(),
+        }
+    }
+
+    fn val(self) -> ValR {
+        match self {
+            Self::Val(x) => x,
+            _ => unreachable!(),
+        }
+    }
+
+    fn parts(self) -> Vec<ValR> {
+        match self {
+            Self::Vals(parts) => parts,
+            _ => Vec::new(),
+        }
+    }
+
+    fn vars(self) -> Vars {
+        match self {
+            Self::Vars(vars) => vars,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Filter to return to with an output.
+#[derive(Clone)]
+struct Frame<'a> {
+    /// position of the operation that takes the output
+    ret: usize,
+    /// location of the errors that the frame takes
+    loc: Option<&'a Loc>,
+    input: Val,
+    vars: Vars,
+    data: Data<'a>,
+}
+
+/// Frames to return to, starting with the most recent one.
+#[derive(Clone, Default)]
+struct Cont<'a>(Option<Rc<(Frame<'a>, Cont<'a>)>>);
+
+impl<'a> Cont<'a> {
+    fn push(self, frame: Frame<'a>) -> Self {
+        Self(Some(Rc::new((frame, self))))
+    }
+
+    fn top(&self) -> Option<&Frame<'a>> {
+        self.0.as_ref().map(|rc| &rc.0)
+    }
+}
+
+impl<'a> Drop for Cont<'a> {
+    fn drop(&mut self) {
+        // drop the frames one after the other, because
+        // dropping them recursively would overflow the stack for long lists
+        let mut next = self.0.take();
+        while let Some(rc) = next {
+            next = Rc::try_unwrap(rc)
+                .ok()
+                .and_then(|(_, mut tail)| tail.0.take());
+        }
+    }
+}
+
+/// Registers of the machine.
+#[derive(Clone)]
+struct State<'a> {
+    input: Val,
+    vars: Vars,
+    data: Data<'a>,
+    cont: Cont<'a>,
+}
+
+impl<'a> State<'a> {
+    /// Restore the registers from the most recent frame and
+    /// return the position and the location of the frame.
+    fn pop(&mut self) -> Option<(usize, Option<&'a Loc>)> {
+        let rc = self.cont.0.take()?;
+        let ret = (rc.0.ret, rc.0.loc);
+        match Rc::try_unwrap(rc) {
+            Ok((frame, cont)) => {
+                self.input = frame.input;
+                self.vars = frame.vars;
+                self.data = frame.data;
+                self.cont = cont;
+            }
+            Err(rc) => {
+                let (frame, cont) = &*rc;
+                self.input = frame.input.clone();
+                self.vars = frame.vars.clone();
+                self.data = frame.data.clone();
+                self.cont = cont.clone();
+            }
+        }
+        Some(ret)
+    }
+}
+
+/// State to backtrack to.
+enum Fork<'a> {
+    /// run the operation at the given position
+    Resume(usize, State<'a>),
+    /// yield the remaining outputs of an iterator, such as of a native filter
+    Iter(ValRs<'a>, Cont<'a>),
+    /// yield the array of the collected values
+    Collect(Vec<Val>, Cont<'a>),
+    /// run the operation at the given position unless
+    /// the first filter of an alternative yielded an output
+    Alt(bool, usize, State<'a>),
+    /// start of the outputs of a label
+    Label,
+}
+
+/// What the machine does next.
+enum Step {
+    /// run the operation at the given position
+    Go(usize),
+    /// run the operation at the given position, which takes an output
+    Back(usize, ValR),
+    /// yield an output to the most recent frame
+    Ret(ValR),
+    /// backtrack
+    Fail,
+}
+
+/// Machine that runs a filter and yields its outputs.
+pub(crate) struct Vm<'a> {
+    code: &'a Code,
+    /// context of the filter, whose variables are replaced by those in the registers
+    ctx: Ctx<'a>,
+    s: State<'a>,
+    forks: Vec<Fork<'a>>,
+    /// what to do when asked for the next output, if not backtracking
+    start: Option<Step>,
+}
+
+impl<'a> Vm<'a> {
+    /// Run a filter on a value.
+    pub(crate) fn new(f: Ref<'a>, (ctx, input): Cv<'a>) -> Self {
+        let vars = ctx.vars.clone();
+        let mut vm = Self::at(f.1, ctx, input, vars, Data::None);
+        vm.start = Some(vm.enter(f.0));
+        vm
+    }
+
+    fn at(code: &'a Code, ctx: Ctx<'a>, input: Val, vars: Vars, data: Data<'a>) -> Self {
+        let cont = Cont::default();
+        let s = State {
+            input,
+            vars,
+            data,
+            cont,
+        };
+        let (forks, start) = (Vec::new(), None);
+        Self {
+            code,
+            ctx,
+            s,
+            forks,
+            start,
+        }
+    }
+
+    /// Return the context with the current variables and input.
+    fn cv(&self) -> Cv<'a> {
+        (
+            self.ctx.with_vars(self.s.vars.clone()),
+            self.s.input.clone(),
+        )
+    }
+
+    /// Take the input and return the context with the current variables.
+    fn take_cv(&mut self) -> Cv<'a> {
+        let ctx = self.ctx.with_vars(self.s.vars.clone());
+        (ctx, core::mem::replace(&mut self.s.input, Val::Null))
+    }
+
+    fn take_data(&mut self) -> Data<'a> {
+        core::mem::take(&mut self.s.data)
+    }
+
+    /// Run the filter `f`.
+    fn enter(&mut self, f: Id) -> Step {
+        if let Some(progress) = self.ctx.progress {
+            progress.step()
+        }
+        self.s.data = Data::None;
+        Step::Go(self.code.entry[f.0])
+    }
+
+    /// Run the filter `f`, returning its outputs to the operation at `ret`.
+    fn call(&mut self, ret: usize, f: Id) -> Step {
+        let pc = self.code.entry[f.0];
+        // filters that yield exactly one value do not need a frame
+        let y = match &self.code.ops[pc] {
+            Op::Input => self.s.input.clone(),
+            Op::Val(v) => v.clone(),
+            Op::Var(v) => match self.s.vars.get(*v) {
+                Some(Bind::Var(v)) => v.clone(),
+                _ => return self.call_with(ret, f, self.s.vars.clone()),
+            },
+            _ => return self.call_with(ret, f, self.s.vars.clone()),
+        };
+        if let Some(progress) = self.ctx.progress {
+            progress.step()
+        }
+        Step::Back(ret, Ok(y))
+    }
+
+    /// Run the filter `f` with the variables `vars`, returning its outputs to the operation at `ret`.
+    fn call_with(&mut self, ret: usize, f: Id, vars: Vars) -> Step {
+        let code = self.code;
+        let pc = code.entry[f.0];
+        // locate errors in the frame instead of returning them through a `Locate`
+        let (f, loc) = match (&code.ops[pc], code.ops.get(pc + 1)) {
+            (Op::Call(g), Some(Op::Locate(loc))) => (*g, Some(loc)),
+            _ => (f, None),
+        };
+        let frame = Frame {
+            ret,
+            loc,
+            input: self.s.input.clone(),
+            vars: core::mem::replace(&mut self.s.vars, vars),
+            data: self.take_data(),
+        };
+        self.s.cont = core::mem::take(&mut self.s.cont).push(frame);
+        self.enter(f)
+    }
+
+    /// Yield the outputs of an iterator.
+    fn iter(&mut self, mut ys: ValRs<'a>) -> Step {
+        // iterators with at most one output, such as those of most natives,
+        // do not need a fork, which would keep the frames and values alive
+        if matches!(ys.size_hint().1, Some(0 | 1)) {
+            return ys.next().map_or(Step::Fail, Step::Ret);
+        }
+        let cont = core::mem::take(&mut self.s.cont);
+        self.forks.push(Fork::Iter(ys, cont));
+        Step::Fail
+    }
+
+    /// Perform an operation that does not take an output.
+    fn go(&mut self, pc: usize) -> Step {
+        let code = self.code;
+        let w = move |id: &Id| Ref(*id, code);
+        match &code.ops[pc] {
+            Op::Input => Step::Ret(Ok(core::mem::replace(&mut self.s.input, Val::Null))),
+            Op::ToString => Step::Ret(Ok(Val::str(self.s.input.to_string_or_clone()))),
+            Op::Val(v) => Step::Ret(Ok(v.clone())),
+            Op::Call(f) => self.call(pc + 1, *f),
+            Op::Jump(f) => self.enter(*f),
+            Op::Fork(f) => {
+                self.forks.push(Fork::Resume(pc + 1, self.s.clone()));
+                self.enter(*f)
+            }
+            Op::Collect(f) => {
+                let cont = self.s.cont.clone();
+                self.s.data = Data::Fork(self.forks.len());
+                self.forks.push(Fork::Collect(Vec::new(), cont));
+                self.call(pc + 1, *f)
+            }
+            Op::Alt(l) => {
+                self.s.data = Data::Fork(self.forks.len());
+                self.forks.push(Fork::Alt(false, pc + 2, self.s.clone()));
+                self.call(pc + 1, *l)
+            }
+            Op::Path(id) => match &code[id.0] {
+                Ast::Path(f, path) => {
+                    let cv = self.cv();
+                    let path = path.map_ref(|i| collect_if_once(w(i).run(cv.clone())));
+                    self.s.data = Data::Path(path);
+                    self.call(pc + 1, *f)
+                }
+                _ => unreachable!(),
+            },
+            Op::Update(id) => match &code[id.0] {
+                Ast::Update(path, f) => {
+                    let cv = self.take_cv();
+                    let ctx = cv.0.clone();
+                    let f = Box::new(move |v| w(f).run((ctx.clone(), v)));
+                    self.iter(w(path).update(cv, f))
+                }
+                _ => unreachable!(),
+            },
+            Op::Fold(id) => match &code[id.0] {
+                Ast::Fold(_, xs, init, _) => {
+                    let xs = w(xs).run(self.cv());
+                    self.s.data = Data::Xs(rc_lazy_list::List::from_iter(xs));
+                    self.call(pc + 1, *init)
+                }
+                _ => unreachable!(),
+            },
+            Op::FoldNext(f, reduce) => {
+                let mut xs = match self.take_data() {
+                    Data::Xs(xs) => xs,
+                    _ => unreachable!(),
+                };
+                match xs.next() {
+                    None if *reduce => {
+                        Step::Ret(Ok(core::mem::replace(&mut self.s.input, Val::Null)))
+                    }
+                    None => Step::Fail,
+                    Some(Err(e)) => Step::Ret(Err(e)),
+                    Some(Ok(x)) => {
+                        self.s.data = Data::Xs(xs);
+                        let vars = self.s.vars.clone().cons_var(x);
+                        self.call_with(pc + 1, *f, vars)
+                    }
+                }
+            }
+            Op::Var(v) => match self.s.vars.get(*v).unwrap() {
+                Bind::Var(v) => Step::Ret(Ok(v.clone())),
+                Bind::Fun((f, vars)) => {
+                    let f = *f;
+                    self.s.vars = vars.clone();
+                    self.enter(f)
+                }
+            },
+            Op::Label(f) => {
+                let vars = self.s.vars.clone().cons_var(Val::Null);
+                self.s.data = Data::Label(self.forks.len(), vars.id(0));
+                self.forks.push(Fork::Label);
+                self.call_with(pc + 1, *f, vars)
+            }
+            Op::Break(v) => Step::Ret(Err(Error::Break(Break(self.s.vars.id(*v))))),
+            Op::CallStart(id) => match &code[id.0] {
+                Ast::Call(call) => {
+                    let vars = self.s.vars.clone().skip(call.skip);
+                    match self.ctx.profile {
+                        // run the call in a machine of its own, so that
+                        // the profile can measure when it yields outputs
+                        Some(profile) => {
+                            let (ctx, input) = self.take_cv();
+                            let caller = self.s.vars.clone();
+                            let mut vm = Self::at(code, ctx, input, caller, Data::Vars(vars));
+                            vm.start = Some(Step::Go(pc + 1));
+                            self.iter(profile.call(call.id, || Box::new(vm)))
+                        }
+                        None => {
+                            self.s.data = Data::Vars(vars);
+                            Step::Go(pc + 1)
+                        }
+                    }
+                }
+                _ => unreachable!(),
+            },
+            Op::BindFun(arg) => {
+                let vars = self.take_data().vars();
+                let ctx = self.ctx.with_vars(vars);
+                let caller = self.ctx.with_vars(self.s.vars.clone());
+                self.s.data = Data::Vars(filter::bind_fun(w(arg), ctx, &caller).vars);
+                Step::Go(pc + 1)
+            }
+            Op::Enter(f) => {
+                self.s.vars = self.take_data().vars();
+                self.enter(*f)
+            }
+            Op::Native(id, loc) => match &code[id.0] {
+                Ast::Native(_, native, args) => {
+                    let ys = native.run(Args(args, code), self.take_cv());
+                    match loc {
+                        Some(loc) => {
+                            self.iter(Box::new(ys.map(|y| y.map_err(|e| e.at(Some(loc))))))
+                        }
+                        None => self.iter(ys),
+                    }
+                }
+                _ => unreachable!(),
+            },
+            _ => unreachable!("operation takes an output"),
+        }
+    }
+
+    /// Perform an operation that takes an output `y`.
+    fn back(&mut self, pc: usize, y: ValR) -> Step {
+        let code = self.code;
+        let w = move |id: &Id| Ref(*id, code);
+        match &code.ops[pc] {
+            Op::Push => match y {
+                Ok(y) => {
+                    if let Fork::Collect(ys, _) = &mut self.forks[self.s.data.fork()] {
+                        ys.push(y)
+                    }
+                    Step::Fail
+                }
+                Err(e) => {
+                    // stop collecting when an error occurs
+                    self.forks.truncate(self.s.data.fork());
+                    Step::Ret(Err(e))
+                }
+            },
+            Op::Save(f) => {
+                self.s.data = Data::Val(y);
+                self.call(pc + 1, *f)
+            }
+            Op::Obj(loc) => {
+                let obj = |k: ValR, v: ValR| {
+                    let k = k?.to_str().map_err(|e| e.at(loc.as_ref()))?;
+                    Ok(Val::obj([(k, v?)].into_iter().collect()))
+                };
+                Step::Ret(obj(self.take_data().val(), y))
+            }
+            Op::Catch(c) => match y {
+                // halting and breaking cannot be caught
+                Ok(_) | Err(Error::Halt(..) | Error::Break(_)) => Step::Ret(y),
+                Err(e) => {
+                    self.s.input = e.as_val();
+                    self.enter(*c)
+                }
+            },
+            Op::Neg(loc) => Step::Ret(y.and_then(|v| (-v).map_err(|e| e.at(loc.as_ref())))),
+            Op::Then(r) => match y {
+                Ok(y) => {
+                    self.s.input = y;
+                    self.enter(*r)
+                }
+                Err(e) => Step::Ret(Err(e)),
+            },
+            Op::Bind(r) => match y {
+                Ok(y) => {
+                    self.s.vars = self.s.vars.clone().cons_var(y);
+                    self.enter(*r)
+                }
+                Err(e) => Step::Ret(Err(e)),
+            },
+            Op::AltOut => {
+                // like in jq, errors are ignored, except for halting and breaking
+                let pass = match &y {
+                    Ok(v) => v.as_bool(),
+                    Err(e) => matches!(e, Error::Halt(..) | Error::Break(_)),
+                };
+                if !pass {
+                    return Step::Fail;
+                }
+                if let Fork::Alt(yielded, ..) = &mut self.forks[self.s.data.fork()] {
+                    *yielded = true
+                }
+                Step::Ret(y)
+            }
+            Op::Ite(then_, else_) => match y {
+                Ok(v) => self.enter(if v.as_bool() { *then_ } else { *else_ }),
+                Err(e) => Step::Ret(Err(e)),
+            },
+            Op::PathRun(loc) => match (y, self.take_data()) {
+                (Ok(y), Data::Path(path)) => {
+                    let loc = loc.as_ref();
+                    let paths = path.explode();
+                    let ys =
+                        flat_map_with(paths, y, move |path, y| then(path, |path| path.run(y, loc)));
+                    self.iter(ys)
+                }
+                (y, _) => Step::Ret(y),
+            },
+            Op::UpdateMath(path, op) => match y {
+                Ok(y) => {
+                    let f = Box::new(move |x| box_once(op.run(x, y.clone())));
+                    let ys = w(path).update(self.take_cv(), f);
+                    self.iter(ys)
+                }
+                Err(e) => Step::Ret(Err(e)),
+            },
+            Op::Assign(path) => match y {
+                Ok(y) => {
+                    let f = Box::new(move |_| box_once(Ok(y.clone())));
+                    let ys = w(path).update(self.take_cv(), f);
+                    self.iter(ys)
+                }
+                Err(e) => Step::Ret(Err(e)),
+            },
+            Op::Logic(stop, r) => match y {
+                Ok(l) if l.as_bool() == *stop => Step::Ret(Ok(Val::Bool(*stop))),
+                Ok(_) => self.call(pc + 1, *r),
+                Err(e) => Step::Ret(Err(e)),
+            },
+            Op::ToBool => Step::Ret(y.map(|r| Val::Bool(r.as_bool()))),
+            Op::Math(op, loc) => Step::Ret(match (self.take_data().val(), y) {
+                (Ok(x), Ok(y)) => op.run(x, y).map_err(|e| e.at(loc.as_ref())),
+                (Err(e), _) | (_, Err(e)) => Err(e),
+            }),
+            Op::Ord(op) => Step::Ret(match (self.take_data().val(), y) {
+                (Ok(x), Ok(y)) => Ok(Val::Bool(op.run(&x, &y))),
+                (Err(e), _) | (_, Err(e)) => Err(e),
+            }),
+            Op::Cons(f) => {
+                let mut parts = self.take_data().parts();
+                parts.push(y);
+                self.s.data = Data::Vals(parts);
+                self.call(pc + 1, *f)
+            }
+            Op::Concat => {
+                let mut parts = self.take_data().parts();
+                parts.push(y);
+                Step::Ret(concat(parts))
+            }
+            Op::FoldOut(yield_, next) => match y {
+                Ok(y) if *yield_ => {
+                    self.s.input = y.clone();
+                    self.forks.push(Fork::Resume(*next, self.s.clone()));
+                    Step::Ret(Ok(y))
+                }
+                Ok(y) => {
+                    self.s.input = y;
+                    Step::Go(*next)
+                }
+                Err(e) => Step::Ret(Err(e)),
+            },
+            Op::LabelOut => match (y, &self.s.data) {
+                (Err(Error::Break(Break(b))), Data::Label(fork, id)) if b == *id => {
+                    // discard all remaining outputs of the label
+                    self.forks.truncate(*fork);
+                    Step::Fail
+                }
+                (y, _) => Step::Ret(y),
+            },
+            Op::BindArg => match y {
+                Ok(y) => {
+                    self.s.data = Data::Vars(self.take_data().vars().cons_var(y));
+                    Step::Go(pc + 1)
+                }
+                Err(e) => Step::Ret(Err(e)),
+            },
+            Op::Locate(loc) => Step::Ret(y.map_err(|e| e.at(Some(loc)))),
+            _ => unreachable!("operation does not take an output"),
+        }
+    }
+
+    /// Return to the most recent fork.
+    fn backtrack(&mut self) -> Option<Step> {
+        loop {
+            let code = self.code;
+            // take outputs from iterators without popping them
+            if let Some((Fork::Iter(ys, cont), below)) = self.forks.split_last_mut() {
+                let collect =
+                    cont.top()
+                        .and_then(|frame| match (&code.ops[frame.ret], &frame.data) {
+                            (Op::Push, Data::Fork(i)) => below.get_mut(*i),
+                            _ => None,
+                        });
+                if let Some(Fork::Collect(vs, _)) = collect {
+                    // collect the outputs directly into the array
+                    // instead of returning them one by one to `Push`
+                    for y in ys.by_ref() {
+                        match y {
+                            Ok(y) => vs.push(y),
+                            Err(e) => {
+                                self.s.cont = cont.clone();
+                                return Some(Step::Ret(Err(e)));
+                            }
+                        }
+                    }
+                } else if let Some(y) = ys.next() {
+                    self.s.cont = cont.clone();
+                    return Some(Step::Ret(y));
+                }
+                self.forks.pop();
+                continue;
+            }
+            return Some(match self.forks.pop()? {
+                Fork::Resume(pc, s) | Fork::Alt(false, pc, s) => {
+                    self.s = s;
+                    Step::Go(pc)
+                }
+                Fork::Collect(ys, cont) => {
+                    self.s.cont = cont;
+                    Step::Ret(Ok(Val::arr(ys)))
+                }
+                Fork::Alt(true, ..) | Fork::Label => continue,
+                Fork::Iter(..) => unreachable!(),
+            });
+        }
+    }
+}
+
+impl<'a> Iterator for Vm<'a> {
+    type Item = ValR;
+
+    fn next(&mut self) -> Option<ValR> {
+        let mut step = self.start.take().unwrap_or(Step::Fail);
+        loop {
+            step = match step {
+                Step::Go(pc) => self.go(pc),
+                Step::Back(pc, y) => self.back(pc, y),
+                Step::Ret(y) => match self.s.pop() {
+                    Some((ret, loc)) => match loc {
+                        Some(loc) => Step::Back(ret, y.map_err(|e| e.at(Some(loc)))),
+                        None => Step::Back(ret, y),
+                    },
+                    None => {
+                        // do not keep values alive that are no longer needed
+                        self.s.input = Val::Null;
+                        self.s.data = Data::None;
+                        return Some(y);
+                    }
+                },
+                Step::Fail => self.backtrack()?,
+            }
+        }
+    }
+}
+
+/// Concatenate values, yielding the first error among them if there is any.
+///
+/// If all values are strings, this allocates the output string only once.
+fn concat(xs: Vec<ValR>) -> ValR {
+    let xs = xs.into_iter().collect::<Result<Vec<_>, _>>()?;
+    let strs: Option<Vec<&str>> = xs
+        .iter()
+        .map(|x| match x {
+            Val::Str(s) => Some(&***s),
+            _ => None,
+        })
+        .collect();
+    if let Some(strs) = strs {
+        let mut out = String::with_capacity(strs.iter().map(|s| s.len()).sum());
+        strs.into_iter().for_each(|s| out.push_str(s));
+        return Ok(Val::str(out));
+    }
+    let mut xs = xs.into_iter().rev();
+    let last = xs.next().unwrap_or_else(|| Val::str(String::new()));
+    xs.try_fold(last, |acc, x| x + acc)
+}
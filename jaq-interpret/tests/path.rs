@@ -36,6 +36,8 @@ fn index_access() {
         r#".["b", "a"]"#,
         [json!(2), json!(1)],
     );
+    let ab = json!({"a": 1, "b": 2});
+    gives(ab, r#".[("a", "b")]"#, [json!(1), json!(2)]);
 }
 
 #[test]
@@ -53,6 +55,11 @@ fn iter_access() {
 fn range_access() {
     give(json!("Möwe"), ".[1:-1]", json!("öw"));
     give(json!("नमस्ते"), ".[1:5]", json!("मस्त"));
+    give(json!("hello"), ".[1:3]", json!("el"));
+    give(json!("hello"), ".[-10:2]", json!("he"));
+    give(json!("hello"), ".[3:100]", json!("lo"));
+    give(json!("hello"), ".[4:2]", json!(""));
+    give(json!("a✓é😀b"), ".[1:4]", json!("✓é😀"));
 
     give(json!([0, 1, 2]), ".[-4:4]", json!([0, 1, 2]));
     give(json!([0, 1, 2]), ".[0:3]", json!([0, 1, 2]));
@@ -94,6 +101,15 @@ fn index_update() {
     give(json!([0, 1, 2]), r#".["a", 0]? |= .+1"#, json!([1, 1, 2]));
     give(json!([0, 1, 2]), r#".[3]? |= .+1"#, json!([0, 1, 2]));
     give(json!("asdf"), ".[0]? |= .+1", json!("asdf"));
+    let ab = json!({"a": 1, "b": 2});
+    give(ab, r#".[("a", "b")] |= .+1"#, json!({"a": 2, "b": 3}));
+
+    // optional paths that do not apply leave the input unchanged
+    give(json!(5), ".a? = 1", json!(5));
+    give(json!(5), "(.a?) |= 1", json!(5));
+    give(json!([1]), ".a? += 1", json!([1]));
+    give(json!({"a": 1}), ".a? |= .+1", json!({"a": 2}));
+    give(json!({"a": 1}), ".a? = 2", json!({"a": 2}));
 }
 
 #[test]
@@ -168,3 +184,50 @@ fn update_complex() {
     // does *not* work when `a` is a string, an array, or an object!
     fail(json!(0), "0 |= .+1", Error::PathExp);
 }
+
+#[test]
+fn update_in_place() {
+    use jaq_interpret::{Ctx, FilterT, ParseCtx, RcIter, Val};
+    use std::rc::Rc;
+    let run = |f: &str, x: Val| {
+        let (f, _) = jaq_parse::parse(f, jaq_parse::main());
+        let mut ctx = ParseCtx::new(Vec::new());
+        let f = ctx.compile(f.unwrap());
+        assert!(ctx.errs.is_empty());
+        let inputs = RcIter::new(core::iter::empty());
+        let mut out = f.run((Ctx::new([], &inputs), x));
+        out.next().unwrap().unwrap()
+    };
+    let leaf = |v: &Val| match v {
+        Val::Obj(o) => match &o[&"a".to_string()] {
+            Val::Arr(a) => match &a[0] {
+                Val::Obj(o) => Rc::as_ptr(o),
+                _ => panic!(),
+            },
+            _ => panic!(),
+        },
+        _ => panic!(),
+    };
+
+    // a uniquely owned value is updated in place, down to the updated leaf
+    let x = Val::from(json!({"a": [{"b": 1}, 2], "c": 3}));
+    let x_ptr = leaf(&x);
+    let y = run(".a[0].b = 4", x);
+    assert_eq!(leaf(&y), x_ptr);
+    assert_eq!(y, Val::from(json!({"a": [{"b": 4}, 2], "c": 3})));
+
+    // a shared value is not modified, ...
+    let x = Val::from(json!({"a": [{"b": 1}, [2]], "c": [3]}));
+    let y = run(".a[0].b |= .+1 | .a[1:] = [5]", x.clone());
+    assert_eq!(x, Val::from(json!({"a": [{"b": 1}, [2]], "c": [3]})));
+    assert_eq!(y, Val::from(json!({"a": [{"b": 2}, 5], "c": [3]})));
+    // ... but the parts of it that are not on the updated path are shared
+    let ptr = |v: &Val| match v {
+        Val::Obj(o) => match &o[&"c".to_string()] {
+            Val::Arr(a) => Rc::as_ptr(a),
+            _ => panic!(),
+        },
+        _ => panic!(),
+    };
+    assert_eq!(ptr(&x), ptr(&y));
+}
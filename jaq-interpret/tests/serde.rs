@@ -0,0 +1,90 @@
+//! Tests for conversion between values and serialisable types.
+
+use jaq_interpret::{Ctx, Filter, ParseCtx, RcIter, Val};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Role {
+    Admin,
+    Guest { since: u32 },
+    Bot(String),
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct User {
+    id: u64,
+    name: String,
+    email: Option<String>,
+    roles: Vec<Role>,
+}
+
+fn alice() -> User {
+    User {
+        id: u64::MAX,
+        name: "alice".into(),
+        email: None,
+        roles: vec![
+            Role::Admin,
+            Role::Guest { since: 2020 },
+            Role::Bot("x".into()),
+        ],
+    }
+}
+
+#[test]
+fn round_trip() {
+    let v = Val::from_serialize(&alice()).unwrap();
+    let j = json!({
+        "id": u64::MAX,
+        "name": "alice",
+        "email": null,
+        "roles": ["Admin", {"Guest": {"since": 2020}}, {"Bot": "x"}]
+    });
+    // values are serialised like by `serde_json`
+    assert_eq!(v, Val::from(j.clone()));
+    assert_eq!(serde_json::to_value(&v).unwrap(), j);
+
+    assert_eq!(v.clone().deserialize_into::<User>().unwrap(), alice());
+    let v: Val = serde_json::from_value(j).unwrap();
+    assert_eq!(v.deserialize_into::<User>().unwrap(), alice());
+
+    let err = Val::from(json!({"id": "1"})).deserialize_into::<User>();
+    assert!(err.is_err());
+
+    // integers are serialised with at most 128 bits
+    let big = Val::from_dec_str(&"9".repeat(40));
+    assert!(serde_json::to_value(big).is_err());
+}
+
+fn compile(src: &str) -> Filter {
+    let (f, errs) = jaq_parse::parse(src, jaq_parse::main());
+    assert!(errs.is_empty());
+    let mut ctx = ParseCtx::new(Vec::new());
+    let f = ctx.compile(f.unwrap());
+    assert!(ctx.errs.is_empty());
+    f
+}
+
+#[test]
+fn run_serde() {
+    let inputs = RcIter::new(core::iter::empty());
+    let f = compile(r#".name, (.roles[1].Guest.since | . + 1)"#);
+    let out = f.run_serde::<_, Val>(Ctx::new([], &inputs), &alice());
+    let out: Result<Vec<_>, _> = out.collect();
+    assert_eq!(out.unwrap(), [Val::from(json!("alice")), Val::Int(2021)]);
+
+    let f = compile(r#".email = "a@b.c" | .roles = []"#);
+    let out = f.run_serde(Ctx::new([], &inputs), &alice()).next();
+    let user = User {
+        email: Some("a@b.c".into()),
+        roles: Vec::new(),
+        ..alice()
+    };
+    assert_eq!(out, Some(Ok(user)));
+
+    // outputs that do not match the target type yield errors
+    let f = compile(".name");
+    let out = f.run_serde::<_, u32>(Ctx::new([], &inputs), &alice());
+    assert!(out.collect::<Vec<_>>()[0].is_err());
+}
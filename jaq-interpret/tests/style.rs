@@ -0,0 +1,83 @@
+use core::fmt::{self, Write};
+use jaq_interpret::style::{self, ColorScheme, Style, Token};
+use jaq_interpret::Val;
+use serde_json::json;
+
+/// Wrap every token with markers such as `<Num>` and `</Num>`.
+struct Markers;
+
+impl Style for Markers {
+    fn start(&self, token: Token, w: &mut dyn Write) -> fmt::Result {
+        write!(w, "<{token:?}>")
+    }
+    fn end(&self, token: Token, w: &mut dyn Write) -> fmt::Result {
+        write!(w, "</{token:?}>")
+    }
+}
+
+fn write(v: serde_json::Value, style: &dyn Style, indent: Option<&str>) -> String {
+    let mut s = String::new();
+    style::write(&mut s, &Val::from(v), style, indent).unwrap();
+    s
+}
+
+#[test]
+fn markers() {
+    let v = json!([null, true, 1.5, {"a": "b\n"}]);
+    let out = concat!(
+        "<Arr>[</Arr><Null>null</Null><Arr>,</Arr><True>true</True><Arr>,</Arr>",
+        "<Num>1.5</Num><Arr>,</Arr>",
+        r#"<Obj>{</Obj><Key>"a"</Key><Obj>:</Obj><Str>"b\n"</Str><Obj>}</Obj>"#,
+        "<Arr>]</Arr>"
+    );
+    assert_eq!(write(v, &Markers, None), out);
+}
+
+#[test]
+fn plain() {
+    let v = json!({"a": [1, false], "b": {}, "c": []});
+    let out = "{\n  \"a\": [\n    1,\n    false\n  ],\n  \"b\": {},\n  \"c\": []\n}";
+    assert_eq!(write(v.clone(), &(), Some("  ")), out);
+    assert_eq!(write(v, &(), None), r#"{"a":[1,false],"b":{},"c":[]}"#);
+    assert_eq!(write(json!("\"\\\u{1}"), &(), None), r#""\"\\\u0001""#);
+}
+
+#[test]
+fn ansi() {
+    let scheme = ColorScheme {
+        num: "31".into(),
+        ..ColorScheme::default()
+    };
+    assert_eq!(write(json!(1), &scheme, None), "\x1b[31m1\x1b[0m");
+    let out = "\x1b[0;32m\"a\"\x1b[0m";
+    assert_eq!(write(json!("a"), &ColorScheme::default(), None), out);
+}
+
+#[test]
+fn parse() {
+    let scheme = ColorScheme::parse("0;90::1").unwrap();
+    assert_eq!(scheme.null, "0;90");
+    assert_eq!(scheme.r#false, "");
+    assert_eq!(scheme.r#true, "1");
+    assert_eq!(scheme.num, ColorScheme::default().num);
+    // tokens without color are written without escape sequences
+    assert_eq!(
+        write(json!([false]), &scheme, None).matches('\x1b').count(),
+        4
+    );
+
+    assert_eq!(ColorScheme::parse("red"), None);
+    assert_eq!(ColorScheme::parse("1:1:1:1:1:1:1:1:1"), None);
+}
+
+#[test]
+fn ascii() {
+    let opts = style::Options {
+        ascii: true,
+        ..style::Options::default()
+    };
+    let mut s = String::new();
+    let v = Val::from(json!({"ä": "é\u{1}😀"}));
+    opts.write(&mut s, &v, &()).unwrap();
+    assert_eq!(s, r#"{"\u00e4":"\u00e9\u0001\ud83d\ude00"}"#);
+}
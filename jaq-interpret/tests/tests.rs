@@ -2,7 +2,8 @@
 
 pub mod common;
 
-use common::{give, gives};
+use common::{fail, give, gives};
+use jaq_interpret::{Error, Val};
 use serde_json::json;
 
 #[test]
@@ -36,6 +37,21 @@ fn add() {
     give(json!({"a": 1}), ". + {}", json!({"a": 1}));
 }
 
+// unlike `*`, `+` does not merge nested objects
+yields!(
+    add_obj_shallow,
+    "{a: {b: 1}, c: 2} + {a: {d: 3}, e: 4}",
+    json!({"a": {"d": 3}, "c": 2, "e": 4})
+);
+
+// the integer or the fractional part of a number may be omitted
+yields!(
+    num_dot,
+    "[.5, 5., 5.0, .5e1, 1,.5]",
+    json!([0.5, 5.0, 5.0, 5.0, 1, 0.5])
+);
+yields!(num_dot_path, "{e1: 2} | .e1", 2);
+
 #[test]
 fn sub() {
     give(json!(1), ". - -2", json!(3));
@@ -63,6 +79,37 @@ fn mul() {
     );
 }
 
+#[test]
+fn mul_obj_in_place() {
+    use jaq_interpret::Val;
+    use std::rc::Rc;
+    let ptr = |v: &Val| match v {
+        Val::Obj(o) => Rc::as_ptr(o),
+        _ => panic!(),
+    };
+
+    // a uniquely owned left operand is updated in place
+    let l = Val::from(json!({"a": {"b": 1}, "c": 2}));
+    let r = Val::from(json!({"a": {"d": 3}}));
+    let (l_ptr, r_clone) = (ptr(&l), r.clone());
+    let y = (l * r).unwrap();
+    assert_eq!(ptr(&y), l_ptr);
+    assert_eq!(y, Val::from(json!({"a": {"b": 1, "d": 3}, "c": 2})));
+    // the shared right operand is not modified
+    assert_eq!(r_clone, Val::from(json!({"a": {"d": 3}})));
+
+    // merging into an empty object yields the right operand without cloning it
+    let y = (Val::from(json!({})) * r_clone.clone()).unwrap();
+    assert_eq!(ptr(&y), ptr(&r_clone));
+
+    let objs: Vec<_> = (0..1000)
+        .map(|i| json!({"k": {format!("{i}"): i}}))
+        .collect();
+    let merged: serde_json::Map<_, _> = (0..1000).map(|i| (format!("{i}"), json!(i))).collect();
+    let f = "reduce .[] as $o ({}; . * $o)";
+    give(json!(objs), f, json!({"k": merged}));
+}
+
 yields!(div_str, r#""abcabcdab" / "ab""#, ["", "c", "cd", ""]);
 yields!(div_str_empty, r#""" / """#, json!([]));
 yields!(div_str_empty_str, r#""" / "ab""#, json!([]));
@@ -84,6 +131,26 @@ fn alt() {
     give(json!([1, 2]), r#"[.[] // -"a"]"#, json!([1, 2]));
 }
 
+// `l // r` falls back to `r` if `l` yields only errors, `false`, or `null`, whereas
+// `try l catch r` replaces every error yielded by `l`
+#[test]
+fn alt_vs_try() {
+    let cases = [
+        ("(1 | .a)", json!([0]), json!([0])),
+        ("(0 == 1)", json!([0]), json!([false])),
+        ("{}.a", json!([0]), json!([null])),
+        ("{}[]", json!([0]), json!([])),
+        ("1", json!([1]), json!([1])),
+        ("({}.a, 2)", json!([2]), json!([null, 2])),
+        ("(1, (1 | .a))", json!([1]), json!([1, 0])),
+        ("((1 | .a), 1)", json!([1]), json!([0, 1])),
+    ];
+    for (l, alt, try_) in cases {
+        give(json!(null), &format!("[{l} // 0]"), alt);
+        give(json!(null), &format!("[try {l} catch 0]"), try_);
+    }
+}
+
 #[test]
 fn try_() {
     give(json!(0), ".?", json!(0));
@@ -91,6 +158,98 @@ fn try_() {
     give(json!(0), r#"[(1, -"a", 2)?]"#, json!([1, 2]));
 }
 
+#[test]
+fn label_break() {
+    give(json!(null), "[label $x | 1, break $x, 2]", json!([1]));
+    let f = "[label $out | foreach .[] as $x (0; . + $x) | if . > 3 then ., break $out else . end]";
+    give(json!([1, 2, 3, 4]), f, json!([1, 3, 6]));
+    // break only stops the innermost label of its name
+    let f = "[.[] | label $x | .[] | if . == 2 then break $x else . end]";
+    give(json!([[1, 2, 3], [4]]), f, json!([1, 4]));
+    give(
+        json!(null),
+        "[label $x | (label $x | 1, break $x), 2]",
+        json!([1, 2]),
+    );
+    give(
+        json!(null),
+        "[label $x | label $y | 1, break $x, 2]",
+        json!([1]),
+    );
+    // break is neither caught by `try` nor by `//`
+    give(json!(null), "[label $x | try break $x catch 1]", json!([]));
+    give(json!(null), "[label $x | (1, break $x)?, 2]", json!([1]));
+    give(json!(null), "[label $x | break $x // 1]", json!([]));
+    // a break passed to a recursive filter refers to the label of the caller
+    let f = "def f(g): label $x | g, f(break $x); [label $y | 1, f(break $y)]";
+    give(json!(null), f, json!([1]));
+    let f = "def f: label $x | 1, f; [label $y | f | ., break $y]";
+    give(json!(null), f, json!([1]));
+    give(json!([1, 2]), "label $x | .[0] |= 3", json!([3, 2]));
+    // labels are no variables, and `label` and `break` are no keywords
+    give(
+        json!({"label": 1, "break": 2}),
+        "{label, break}",
+        json!({"label": 1, "break": 2}),
+    );
+}
+
+#[test]
+fn destructure() {
+    let f = ". as [$x, [$y], {a: $z, $w}] | [$x, $y, $z, $w]";
+    give(json!([1, [2], {"a": 3, "w": 4}]), f, json!([1, 2, 3, 4]));
+    // missing values are null
+    give(json!([0]), ". as [$x, $y] | [$x, $y]", json!([0, null]));
+    // `$x: p` binds `$x` and destructures its value
+    let f = ". as {$a: [$b]} | [$a, $b]";
+    give(json!({"a": [1]}), f, json!([[1], 1]));
+    // keys are computed from the destructured value
+    let f = r#"{"a": 1, "b": "a"} as {(.b): $x, "b": $y} | [$x, $y, .]"#;
+    give(json!(0), f, json!([1, "a", 0]));
+    // every output of a key yields a binding
+    give(
+        json!({"a": 1, "b": 2}),
+        "[. as {(\"a\", \"b\"): $x} | $x]",
+        json!([1, 2]),
+    );
+    fail(
+        json!(0),
+        ". as [$x] | $x",
+        Error::Index(Val::Int(0), Val::Int(0)),
+    );
+}
+
+#[test]
+fn destructure_alt() {
+    let f = "[.[] as [$x] ?// {a: $x} ?// $x | $x]";
+    give(json!([[1], {"a": 2}, 3]), f, json!([1, 2, 3]));
+    // variables not bound by the current pattern are null
+    let f = "[.[] as [$x] ?// {$y} | [$x, $y]]";
+    give(json!([[1], {"y": 2}]), f, json!([[1, null], [null, 2]]));
+    // an error in the body makes the next pattern be tried
+    give(json!([1]), ". as [$x] ?// $x | $x[0]", json!(1));
+    // errors with the last pattern are yielded
+    fail(
+        json!(0),
+        ". as [$x] ?// [$y] | $x",
+        Error::Index(Val::Int(0), Val::Int(0)),
+    );
+    // alternative patterns are no paths
+    fail(
+        json!([[1]]),
+        "(.[] as [$a] ?// $a | .[0]) |= 5",
+        Error::PathExp,
+    );
+}
+
+#[test]
+fn destructure_fold() {
+    let f = "reduce ([1, 2], [3, 4]) as [$a, $b] (0; . + $a * $b)";
+    give(json!(null), f, json!(14));
+    let f = r#"[foreach ({"a": 1}, [2]) as {$a} ?// [$a] (0; . + $a)]"#;
+    give(json!(null), f, json!([1, 3]));
+}
+
 #[test]
 fn precedence() {
     // concatenation binds stronger than application
@@ -117,6 +276,31 @@ fn precedence() {
     give(json!(null), "2 * 3 + 1", json!(7));
 }
 
+yields!(
+    str_escapes,
+    r#""\n\t\r\b\f\/\\\"\u00e9""#,
+    "\n\t\r\u{8}\u{c}/\\\"é"
+);
+// a surrogate pair encodes a single character
+yields!(
+    str_surrogate_pair,
+    r#""\ud83d\ude00\u0041 \(1)\ud83d\ude00""#,
+    "😀A 1😀"
+);
+
+#[test]
+fn str_lone_surrogate() {
+    for f in [
+        r#""\ud83d""#,
+        r#""\ud83dA""#,
+        r#""\ud83d\u0041""#,
+        r#""\ude00""#,
+    ] {
+        let (_, errs) = jaq_parse::parse(f, jaq_parse::main());
+        assert!(!errs.is_empty(), "{f}");
+    }
+}
+
 yields!(interpolation, r#"1 | "yields \(.+1)!""#, "yields 2!");
 // this diverges from jq, which yields ["2 2", "3 2", "2 4", "3 4"],
 // probably due to different order of evaluation addition
@@ -131,6 +315,35 @@ yields!(
     r#"def @say: "say " + .; @say "I \("disco"), you \("party")""#,
     "I say disco, you say party"
 );
+#[test]
+fn interpolation_concat() {
+    let parts: Vec<_> = (0..50).map(|i| format!("{i}")).collect();
+    let f = parts
+        .iter()
+        .map(|p| format!("-\\({p})"))
+        .collect::<String>();
+    give(
+        json!(null),
+        &format!("\"{f}\""),
+        json!(format!("-{}", parts.join("-"))),
+    );
+
+    // interpolation behaves like a chain of additions,
+    // also with respect to multiple outputs and errors
+    let f = r#"[try "\(1, 2):\(3, (1 | .a))!" catch "e"]"#;
+    let g = r#"[try ("\(1, 2)" + (":" + ("\(3, (1 | .a))" + "!"))) catch "e"]"#;
+    let out = json!(["1:3!", "e", "2:3!", "e"]);
+    give(json!(null), f, out.clone());
+    give(json!(null), g, out);
+
+    // formatters may yield values that are not strings
+    give(
+        json!(null),
+        r#"def @null: {}.a; @null "a\(1)b""#,
+        json!("ab"),
+    );
+}
+
 yields!(
     interpolation_nested,
     r#""Here \("be \("nestings")")""#,
@@ -153,6 +366,12 @@ yields!(
     "{a: 1, b: 2} | {a, c: 3}",
     json!({"a": 1, "c": 3})
 );
+yields!(
+    obj_proj_multi,
+    r#"{name: "a", age: 5, extra: 9} | {name, age}"#,
+    json!({"name": "a", "age": 5})
+);
+yields!(obj_proj_missing, "{a: 1} | {b}", json!({"b": null}));
 yields!(
     obj_multi_keys,
     r#"[{("a", "b"): 1}]"#,
@@ -183,13 +402,35 @@ fn if_then_else() {
 yields!(
     try_catch_does_not_short_circuit,
     "[try (\"1\", \"2\", {}[0], \"4\") catch .]",
-    ["1", "2", "cannot index {} with 0", "4"]
+    ["1", "2", "Cannot index object with number", "4"]
 );
 yields!(
     try_catch_nested,
     "try try {}[0] catch {}[1] catch .",
-    "cannot index {} with 1"
+    "Cannot index object with number"
+);
+// error messages for indexing are the same as in jq
+yields!(
+    index_err_num,
+    "try (1 | .a) catch .",
+    r#"Cannot index number with "a""#
+);
+yields!(
+    index_err_obj,
+    "try ({} | .[0]) catch .",
+    "Cannot index object with number"
+);
+yields!(
+    index_err_arr,
+    "try ([1] | .a) catch .",
+    r#"Cannot index array with "a""#
 );
+yields!(
+    index_err_slice,
+    "try (1 | .[1:2]) catch .",
+    "Cannot index number with object"
+);
+
 yields!(
     try_catch_multi_valued,
     "[(try (1,2,3[0]) catch (3,4)) | . - 1]",
@@ -240,6 +481,87 @@ fn ord() {
     give(json!({"a": 2}), r#". < {"a": 1, "b": 0}"#, json!(true));
 }
 
+#[test]
+fn ord_int() {
+    use jaq_interpret::Val;
+    use std::rc::Rc;
+    let num = |n: &str| Val::Num(Rc::new(n.to_string()));
+
+    // these integers are equal when converted to floats
+    let (x, y) = (num("123456789012345678"), num("123456789012345679"));
+    assert!(x < y);
+    assert_ne!(x, y);
+    assert!(Val::Int(123456789012345679) > x);
+    assert_eq!(Val::Int(123456789012345678), x);
+    give(
+        json!(null),
+        "123456789012345678 < 123456789012345679",
+        json!(true),
+    );
+
+    let (x, y) = (
+        num("12345678901234567890123"),
+        num("12345678901234567890124"),
+    );
+    assert!(x < y);
+    assert!(num("-12345678901234567890123") < num("-1"));
+
+    // numbers that are not integers are still compared as floats
+    assert_eq!(num("1.0"), Val::Int(1));
+    assert!(num("1e3") > num("999"));
+
+    // integers are compared exactly also with floats, so ordering is transitive
+    let (i, j, f) = (
+        Val::Int(1 << 53),
+        Val::Int((1 << 53) + 1),
+        Val::Float(2f64.powi(53)),
+    );
+    assert_eq!(i, f);
+    assert!(f < j);
+    assert_ne!(j, f);
+    let f = Val::Float(2f64.powi(80));
+    assert_eq!(num("1208925819614629174706176"), f);
+    assert!(num("1208925819614629174706177") > f);
+    assert!(Val::Int(isize::MAX) < Val::Float(f64::INFINITY));
+    assert!(Val::Int(isize::MIN) < Val::Float(-0.5));
+    give(
+        json!(null),
+        "[9007199254740993 > 9007199254740992.0, 9007199254740992 == 9007199254740992.0]",
+        json!([true, true]),
+    );
+}
+
+#[test]
+fn bigint() {
+    let max = isize::MAX;
+    // integers that overflow machine-sized integers become big integers
+    give(json!(max), ". + 1", json!(max as u64 + 1));
+    give(json!(max), "(. + 1) - 1", json!(max));
+    give(json!(max), "-. - 1 | -.", json!(max as u64 + 1));
+    let sq = "85070591730234615847396907784232501249";
+    give(json!(max), &format!(". * . == {sq}"), json!(true));
+    give(json!(max), &format!(". * . - {sq}"), json!(0));
+
+    let big = "100000000000000000000000";
+    give(
+        json!(null),
+        &format!("{big} / 10 == {}", &big[..big.len() - 1]),
+        json!(true),
+    );
+    give(json!(null), &format!("{big} / 3 | . > 3.3e22"), json!(true));
+    give(json!(null), &format!("{big} % 7"), json!(5));
+    give(json!(null), &format!("{big} - ({big} - 1)"), json!(1));
+    give(
+        json!(null),
+        &format!("{big} > 1e22 and {big} < 1e30"),
+        json!(true),
+    );
+    // the float closest to 10^23 is not exactly 10^23
+    give(json!(null), &format!("{big} == {big}.0"), json!(false));
+    give(json!(null), "99999999999999991611392 == 1e23", json!(true));
+    give(json!(null), &format!("{big} + 0.5 | . > 9e22"), json!(true));
+}
+
 #[test]
 fn eq() {
     give(json!(1), ". == 1", json!(true));
@@ -286,6 +608,15 @@ fn vars() {
     give(json!(null), f, out());
 }
 
+// the body is run once for every output of the bound filter
+yields!(var_iter, "[(1, 2, 3) as $x | $x * 10]", [10, 20, 30]);
+yields!(var_iter_id, "[1 | . as $x | ($x, $x)]", [1, 1]);
+yields!(
+    var_iter_nested,
+    "[(1, 2) as $x | (3, 4) as $y | [$x, $y]]",
+    [[1, 3], [1, 4], [2, 3], [2, 4]]
+);
+
 yields!(shadow_funs, "def a: 1; def b: a; def a: 2; a + b", 3);
 yields!(shadow_vars, "1 as $x | 2 as $x | $x", 2);
 // arguments from the right are stronger than from the left
@@ -297,14 +628,110 @@ yields!(args_mixed, "def f(a; $b): a + $b; 1 as $a | f($a; 2)", 3);
 
 yields!(nested_comb_args, "def f(a): def g(b): a + b; g(1); f(2)", 3);
 
+yields!(
+    twice_arg,
+    "def twice(f): f | f; def inc: . + 1; 1 | twice(inc)",
+    3
+);
+// filter arguments can be forwarded to other filters, also via nested definitions
+yields!(forward_arg, "def b(g): g; def a(f): b(f); 1 | a(. + 1)", 2);
+yields!(
+    forward_arg_nested,
+    "def b(g): g | g; def a(f): def c: b(f | f); c; 1 | a(. * 2)",
+    16
+);
+
 const ACKERMANN: &str = "def ack($m; $n):
   if $m == 0 then $n + 1
   elif $n == 0 then ack($m-1; 1)
   else ack($m-1; ack($m; $n-1))
   end;";
 
+const REPEAT_APPLY: &str =
+    "def repeat_apply(f; n): if n <= 0 then . else f | repeat_apply(f; n-1) end;";
+
+// the argument `n-1` is evaluated on every call instead of nesting closures
+yields!(
+    repeat_apply,
+    &(REPEAT_APPLY.to_owned() + "0 | repeat_apply(.+1; 100000)"),
+    100000
+);
+// arguments that depend on their input are still evaluated only when used
+yields!(
+    repeat_apply_input,
+    &(REPEAT_APPLY.to_owned() + "[3 | repeat_apply(. - 1; . - 1)]"),
+    [2]
+);
+// errors of arguments are yielded only when the arguments are used
+yields!(const_arg_error, r#"def f(x; y): y; f("a" - 1; 2)"#, 2);
+// passing on a filter argument in a recursive call does not nest closures
+yields!(
+    recurse_with_arg,
+    "def f(g; $n): if $n <= 0 then . else g | f(g; $n-1) end; 0 | f(.+1; 100000)",
+    100000
+);
+
 yields!(ackermann, &(ACKERMANN.to_owned() + "ack(3; 4)"), 125);
 
+/// Run a filter with `null` as input, returning its outputs and the number of steps taken.
+fn run_steps(f: &str) -> (Vec<jaq_interpret::ValR>, usize) {
+    use jaq_interpret::{Ctx, FilterT, ParseCtx, Progress, RcIter};
+    let (f, _) = jaq_parse::parse(f, jaq_parse::main());
+    let mut ctx = ParseCtx::new(Vec::new());
+    let f = ctx.compile(f.unwrap());
+    assert!(ctx.errs.is_empty());
+    let inputs = RcIter::new(core::iter::empty());
+    let mut report = |_| ();
+    let progress = Progress::new(1, &mut report);
+    let ctx = Ctx::new([], &inputs).with_progress(&progress);
+    let out: Vec<_> = f.run((ctx, Val::Null)).collect();
+    (out, progress.steps())
+}
+
+#[test]
+fn inline() {
+    let run = run_steps;
+
+    // a call with constant arguments takes as many steps as its inlined body
+    let f = "def f($x; $y): [$x * 10 + $y, $y]; f(1; 2)";
+    assert_eq!(run(f), run("[1 * 10 + 2, 2]"));
+    assert_eq!(run(f).0, [Ok(Val::from(json!([12, 2])))]);
+
+    // calls with non-constant arguments are not inlined
+    let f = "def f($x; $y): [$x * 10 + $y, $y]; f(1; 1 + 1)";
+    assert_eq!(run(f).0, run("[1 * 10 + 2, 2]").0);
+    assert!(run(f).1 > run("[1 * 10 + 2, 2]").1);
+
+    // recursive definitions are not inlined
+    let f = "def f($n): if $n > 0 then f($n - 1) else $n end; f(3)";
+    assert_eq!(run(f).0, [Ok(Val::Int(0))]);
+}
+
+#[test]
+fn fold() {
+    let run = run_steps;
+
+    // constant expressions take as many steps as their values
+    assert_eq!(
+        run("if 1 < 2 then \"a\\(1 + 2)\" else 0 end"),
+        run("\"a3\"")
+    );
+    assert_eq!(
+        run("[-1, 2 * 3, 1 and (1 > 2 // 2)]"),
+        run("[-1, 6, 1 == 1]")
+    );
+    assert_eq!(
+        run("if 1 > 2 then . else [2] end").0,
+        [Ok(json!([2]).into())]
+    );
+    assert_eq!(run("1 > 2 // 1").0, [Ok(Val::Int(1))]);
+
+    // errors are not folded, but still yielded
+    let f = "1 + {}";
+    assert_eq!(run(f).0.len(), 1);
+    assert!(run(f).0[0].is_err());
+}
+
 #[test]
 fn reduce() {
     let ff = |s| format!(". as $x | reduce 2 as $y (4; {}) | . + $x", s);
@@ -316,11 +743,37 @@ fn reduce() {
     give(json!(1), &f, json!(11));
 }
 
+// filter arguments used in folds
+yields!(
+    reduce_arg_update,
+    "def sum_by(f): reduce .[] as $x (0; . + ($x | f)); [{n: 1}, {n: 2}] | sum_by(.n)",
+    3
+);
+yields!(
+    reduce_arg_source,
+    "def sum(xs): reduce xs as $x (0; . + $x); sum(1, 2, 3)",
+    6
+);
+yields!(
+    foreach_arg,
+    "def running(f): [foreach .[] as $x (0; . + ($x | f))]; [{n: 1}, {n: 2}] | running(.n)",
+    [1, 3]
+);
+
 yields!(
     foreach_cumulative_sum,
     "[1, 2, 3] | [foreach .[] as $x (0; .+$x)]",
     [1, 3, 6]
 );
+// `foreach` yields every intermediate state, whereas `reduce` yields only the last one
+yields!(
+    foreach_states,
+    "[foreach (1, 2, 3) as $x (0; .+$x)]",
+    [1, 3, 6]
+);
+yields!(reduce_state, "[reduce (1, 2, 3) as $x (0; .+$x)]", [6]);
+yields!(foreach_empty, "[foreach {}[] as $x (0; .+$x)]", json!([]));
+yields!(reduce_empty, "[reduce {}[] as $x (0; .+$x)]", [0]);
 yields!(
     for_cumulative_sum,
     "[1, 2, 3] | [for .[] as $x (0; .+$x)]",
@@ -340,3 +793,360 @@ yields!(
     "[for (3,4) as $x (1; .+$x, .*$x)]",
     [1, 4, 8, 16, 3, 7, 12]
 );
+
+#[test]
+fn last_count() {
+    use jaq_interpret::{results::last_count, Ctx, FilterT, ParseCtx, RcIter, Val};
+    let run = |f: &str| {
+        let (f, _) = jaq_parse::parse(f, jaq_parse::main());
+        let mut ctx = ParseCtx::new(Vec::new());
+        let f = ctx.compile(f.unwrap());
+        assert!(ctx.errs.is_empty());
+        let inputs = RcIter::new(core::iter::empty());
+        let out = last_count(f.run((Ctx::new([], &inputs), Val::Null)));
+        out.map(|(last, n)| (last.map(|v| v.as_bool()), n))
+    };
+    assert_eq!(run("{}[]"), Ok((None, 0)));
+    assert_eq!(run("1, (0 == 1)"), Ok((Some(false), 2)));
+    assert_eq!(run("(0 == 1), 0"), Ok((Some(true), 2)));
+    assert!(run("1, ([] | .a), 2").is_err());
+}
+
+#[test]
+fn eval_first() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use jaq_interpret::{Native, ParseCtx, Val};
+    // number of times that `count` was run
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let count = Native::new(|_, cv| {
+        Box::new(core::iter::once_with(move || {
+            COUNT.fetch_add(1, Ordering::SeqCst);
+            Ok(cv.1)
+        }))
+    });
+
+    let run = |f: &str| {
+        let (f, _) = jaq_parse::parse(f, jaq_parse::main());
+        let mut ctx = ParseCtx::new(Vec::new());
+        ctx.insert_native("count".to_string(), 0, count.clone());
+        let f = ctx.compile(f.unwrap());
+        assert!(ctx.errs.is_empty());
+        f.eval_first(Val::Int(0))
+    };
+    assert_eq!(
+        run("(1 | count), (2 | count), (3 | count)"),
+        Ok(Some(Val::Int(1)))
+    );
+    assert_eq!(COUNT.load(Ordering::SeqCst), 1);
+    assert_eq!(run("{}[]"), Ok(None));
+    assert!(run("[] | .a").is_err());
+}
+
+#[test]
+fn loc() {
+    use jaq_interpret::{Ctx, FilterT, ParseCtx, RcIter, Val};
+    let run = |src: &str, source: bool| {
+        let (f, _) = jaq_parse::parse(src, jaq_parse::main());
+        let mut ctx = ParseCtx::new(Vec::new());
+        if source {
+            ctx.set_source("<repl>".to_string(), src.to_string());
+        }
+        let f = ctx.compile(f.unwrap());
+        let inputs = RcIter::new(core::iter::empty());
+        let out: Vec<_> = f.run((Ctx::new([], &inputs), Val::Null)).collect();
+        (out, ctx.errs.len())
+    };
+    let loc = |line| Ok(Val::from(json!({"file": "<repl>", "line": line})));
+    assert_eq!(run("$__loc__", true), (vec![loc(1)], 0));
+    assert_eq!(
+        run("\"ä\",\n\n $__loc__", true),
+        (vec![Ok(Val::str("ä".into())), loc(3)], 0)
+    );
+    // leading whitespace and comments are not part of the location
+    assert_eq!(run("# header\n\n  $__loc__", true), (vec![loc(3)], 0));
+    // bound variables take precedence
+    assert_eq!(
+        run("1 as $__loc__ | $__loc__", true),
+        (vec![Ok(Val::Int(1))], 0)
+    );
+    // without a source, `$__loc__` is undefined
+    assert_eq!(run("$__loc__", false).1, 1);
+}
+
+#[test]
+fn error_loc() {
+    use jaq_interpret::{error::Error, Ctx, FilterT, ParseCtx, RcIter, Val};
+    let run = |src: &str| {
+        let (f, _) = jaq_parse::parse(src, jaq_parse::main());
+        let mut ctx = ParseCtx::new(Vec::new());
+        ctx.set_source("<repl>".to_string(), src.to_string());
+        let f = ctx.compile(f.unwrap());
+        assert!(ctx.errs.is_empty());
+        let inputs = RcIter::new(core::iter::empty());
+        let mut out = f.run((Ctx::new([], &inputs), Val::Null));
+        out.next().unwrap()
+    };
+    let err = run("1 |\n[0 | .a]").unwrap_err();
+    let loc = err.loc().unwrap();
+    assert_eq!((loc.span.clone(), loc.line()), (9..11, 2));
+    assert_eq!(&loc.source.0, "<repl>");
+    assert_eq!(
+        err.unlocated(),
+        &Error::Index(Val::Int(0), Val::str("a".into()))
+    );
+
+    // errors stemming from subfilters are located there
+    let err = run("def f: -{}; 1 + f").unwrap_err();
+    assert_eq!(err.loc().unwrap().span, 7..10);
+    // caught errors are unaffected by their location
+    assert_eq!(
+        run(r#"try (1 - "a") catch ."#),
+        run(r#""cannot calculate 1 - \"a\"""#)
+    );
+}
+
+#[test]
+fn modules() {
+    use jaq_interpret::{Ctx, FilterT, ParseCtx, RcIter, Val};
+    let mods = [
+        ("a", "def f: 1; def g: f + 1;"),
+        (
+            "b",
+            "import \"a\" as a;\ndef h: a::g * 10; def loc: $__loc__;",
+        ),
+        ("c", "import \"c\" as c; def x: 1;"),
+        ("d", "def f: 1;\ndef g: h;"),
+    ];
+    let run = |src: &str| {
+        let mut ctx = ParseCtx::new(Vec::new());
+        for (path, src) in mods {
+            let (m, errs) = jaq_parse::parse(src, jaq_parse::module(jaq_parse::defs()));
+            assert!(errs.is_empty());
+            let source = Some((format!("{path}.jq"), src.to_string()));
+            ctx.insert_module(path.to_string(), m.unwrap(), source);
+        }
+        let (f, errs) = jaq_parse::parse(src, jaq_parse::module(jaq_parse::main()));
+        assert!(errs.is_empty());
+        let f = ctx.compile_module(f.unwrap());
+        let inputs = RcIter::new(core::iter::empty());
+        let out: Result<Vec<_>, _> = f.run((Ctx::new([], &inputs), Val::Null)).collect();
+        let errs: Vec<_> = ctx
+            .errs
+            .iter()
+            .map(|(e, span)| (e.to_string(), span.clone()))
+            .collect();
+        (out.unwrap(), errs)
+    };
+    let ints = |xs: &[isize]| xs.iter().map(|x| Val::Int(*x)).collect::<Vec<_>>();
+
+    assert_eq!(
+        run(r#"import "a" as a; a::f, a::g"#),
+        (ints(&[1, 2]), vec![])
+    );
+    assert_eq!(run(r#"import "b" as b; b::h"#), (ints(&[20]), vec![]));
+    assert_eq!(run(r#"include "a"; g"#), (ints(&[2]), vec![]));
+    // definitions in the main module shadow included ones, but not imported ones
+    let src = r#"import "a" as a; include "a"; def f: 5; f, a::f, g"#;
+    assert_eq!(run(src), (ints(&[5, 1, 2]), vec![]));
+
+    let loc = Val::from(json!({"file": "b.jq", "line": 2}));
+    assert_eq!(run(r#"import "b" as b; b::loc"#), (vec![loc], vec![]));
+
+    let undefined = |f: &str, span| vec![(format!("undefined function {f}"), span)];
+    // definitions have to be accessed via the name of their module
+    assert_eq!(run(r#"import "a" as a; f"#).1, undefined("f/0", 17..18));
+    // modules imported by other modules are not accessible
+    assert_eq!(
+        run(r#"import "b" as b; a::f"#).1,
+        undefined("a::f/0", 17..21)
+    );
+
+    let err = |e: &str| vec![(e.to_string(), 8..9)];
+    assert_eq!(run(r#"import "x" as x; 1"#).1, err("module not found: x"));
+    assert_eq!(
+        run(r#"import "c" as c; 1"#).1,
+        err("module c imports itself")
+    );
+    let e = "undefined function h/0 in d.jq:2";
+    assert_eq!(run(r#"import "d" as d; 1"#).1, err(e));
+}
+
+#[test]
+fn progress() {
+    use jaq_interpret::{Ctx, FilterT, ParseCtx, Progress, RcIter, Val};
+    let (f, _) = jaq_parse::parse("[.[] | . + 1]", jaq_parse::main());
+    let mut ctx = ParseCtx::new(Vec::new());
+    let f = ctx.compile(f.unwrap());
+    assert!(ctx.errs.is_empty());
+
+    let input = Val::from(json!(vec![0; 10_000]));
+    let inputs = RcIter::new(core::iter::empty());
+    let mut reported = Vec::new();
+    let mut report = |steps| reported.push(steps);
+    let steps = {
+        let progress = Progress::new(1000, &mut report);
+        let ctx = Ctx::new([], &inputs).with_progress(&progress);
+        assert_eq!(f.run((ctx, input)).count(), 1);
+        progress.steps()
+    };
+
+    // every element needs at least one and at most ten steps
+    assert!((10_000..=100_000).contains(&steps));
+    assert_eq!(reported.len(), steps / 1000);
+    assert!(reported
+        .iter()
+        .enumerate()
+        .all(|(i, s)| *s == (i + 1) * 1000));
+}
+
+#[test]
+fn native_closure() {
+    use jaq_interpret::{Ctx, FilterT, Native, ParseCtx, RcIter, Val};
+    use std::{cell::Cell, collections::HashMap, rc::Rc};
+
+    let users: HashMap<_, _> = [(1, "alice"), (2, "bob")].into_iter().collect();
+    let calls = Rc::new(Cell::new(0));
+    let mut ctx = ParseCtx::new(Vec::new());
+
+    // native filter that captures host state
+    let c = calls.clone();
+    let lookup = Native::from_fn(move |_, cv| {
+        c.set(c.get() + 1);
+        let name = cv.1.as_int().map(|id| users.get(&id).copied());
+        let name = name.map(|n| n.map_or(Val::Null, |n| Val::str(n.into())));
+        Box::new(core::iter::once(name))
+    });
+    ctx.insert_native("lookup_user".to_string(), 0, lookup);
+
+    // native filter that takes a filter argument and supports updates
+    let twice = Native::from_fn_with_update(
+        |args, cv| {
+            let f = args.get(0).run(cv.clone());
+            Box::new(f.chain(args.get(0).run(cv)))
+        },
+        |_, cv, f| f(cv.1),
+    );
+    ctx.insert_native("twice".to_string(), 1, twice);
+
+    let run = |ctx: &mut ParseCtx, src: &str| {
+        let (f, errs) = jaq_parse::parse(src, jaq_parse::main());
+        assert!(errs.is_empty());
+        let f = ctx.compile(f.unwrap());
+        assert!(ctx.errs.is_empty());
+        let inputs = RcIter::new(core::iter::empty());
+        let out: Result<Vec<_>, _> = f.run((Ctx::new([], &inputs), Val::Int(1))).collect();
+        out.unwrap()
+    };
+    let name = |n: &str| Val::str(n.into());
+
+    assert_eq!(run(&mut ctx, "lookup_user"), [name("alice")]);
+    assert_eq!(
+        run(&mut ctx, "2, 3 | lookup_user"),
+        [name("bob"), Val::Null]
+    );
+    assert_eq!(calls.get(), 3);
+    assert_eq!(run(&mut ctx, "twice(., . + 1)"), [1, 2, 1, 2].map(Val::Int));
+    assert_eq!(run(&mut ctx, "twice(.) |= . + 1"), [Val::Int(2)]);
+}
+
+#[test]
+fn undefined() {
+    use jaq_interpret::ParseCtx;
+    let errs = |f: &str| {
+        let (f, _) = jaq_parse::parse(f, jaq_parse::main());
+        let mut ctx = ParseCtx::new(Vec::new());
+        ctx.compile(f.unwrap());
+        let errs = ctx.errs.into_iter();
+        errs.map(|(e, span)| (e.to_string(), span))
+            .collect::<Vec<_>>()
+    };
+    let foo = String::from("undefined function foo/1");
+    assert_eq!(errs("1 + foo(1)"), [(foo, 4..10)]);
+    assert_eq!(errs("def foo($x): $x; foo(1)"), []);
+    let label = String::from("undefined label $x");
+    assert_eq!(errs("label $y | break $x"), [(label, 11..19)]);
+    assert_eq!(errs("label $x | $x").len(), 1);
+
+    // all undefined references are reported
+    let e = errs("def foo: 1; foo(1) | $x | bar");
+    let e: Vec<_> = e.into_iter().map(|(e, _)| e).collect();
+    let msgs = [
+        "function foo/1, did you mean foo/0?",
+        "variable $x",
+        "function bar/0",
+    ];
+    assert_eq!(e, msgs.map(|m| format!("undefined {m}")));
+
+    // calls with the wrong arity suggest the arities that are defined
+    let e = "undefined function f/2, did you mean f/0 or f/1?";
+    let src = "def f: 1; def f(g): g; f(1; 2)";
+    assert_eq!(errs(src), [(e.to_string(), 23..30)]);
+}
+
+// `..` does not overflow the stack on deeply nested values
+#[test]
+fn recurse_deep() {
+    let f = "reduce .[] as $x (0; {a: .}) | [..] | [.[50000], .[49999], .[50001]]";
+    give(json!(vec![0; 50000]), f, json!([0, {"a": 0}, null]));
+}
+
+// recursion that is not in tail position does not overflow the stack either,
+// even when it runs over deeply nested values
+#[test]
+fn recurse_deep_non_tail() {
+    let f = "def depth: if . == 0 then 0 else (.a | depth) + 1 end;
+        reduce .[] as $x (0; {a: .}) | depth";
+    give(json!(vec![0; 5000]), f, json!(5000));
+}
+
+// tail calls do not overflow the stack, also when
+// they occur in `if` and `catch` or in nested definitions
+yields!(
+    tailrec_ite,
+    "def f: if . > 0 then . - 1 | f else . end; 100000 | f",
+    0
+);
+yields!(
+    tailrec_catch,
+    "def f: if . > 0 then . as $x | try ([] | .[{}]) catch ($x - 1 | f) else . end; 100000 | f",
+    0
+);
+yields!(
+    tailrec_mutual,
+    "def f($n): def g: if $n > 0 then f($n - 1) else $n end; g; f(100000)",
+    0
+);
+
+// a definition called not in tail position does not throw tail calls
+yields!(
+    tailrec_nested,
+    "def f: def g: if . > 0 then [. - 1 | g] else -1 | f end; if . < 0 then 2 else g end; 1 | f",
+    [2]
+);
+
+#[test]
+fn profile() {
+    use jaq_interpret::{Ctx, FilterT, ParseCtx, Profile, RcIter};
+    let f = "def f: if . > 0 then [. - 1 | f] else . end;\n2 | f";
+    let (f, _) = jaq_parse::parse(f, jaq_parse::main());
+    let mut ctx = ParseCtx::new(Vec::new());
+    ctx.set_source(
+        "test.jq".into(),
+        "def f: if . > 0 then [. - 1 | f] else . end;\n".into(),
+    );
+    let f = ctx.compile(f.unwrap());
+    assert!(ctx.errs.is_empty());
+
+    let inputs = RcIter::new(core::iter::empty());
+    let clock = core::time::Duration::default;
+    let profile = Profile::new(&clock);
+    let ctx = Ctx::new([], &inputs).with_profile(&profile);
+    let out: Vec<_> = f.run((ctx, Val::Null)).collect();
+    assert_eq!(out, [Ok(Val::from(json!([[0]])))]);
+
+    let stats = profile.stats(&f);
+    assert_eq!(stats.len(), 1);
+    assert_eq!((stats[0].name.as_str(), stats[0].arity), ("f", 0));
+    assert_eq!(stats[0].loc, Some(("test.jq".into(), 1)));
+    assert_eq!((stats[0].calls, stats[0].outputs), (3, 3));
+}
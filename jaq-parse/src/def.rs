@@ -1,7 +1,7 @@
 use super::{filter::filter, Delim, Token};
 use alloc::vec::Vec;
 use chumsky::prelude::*;
-use jaq_syn::{Arg, Call, Def, Main};
+use jaq_syn::{Arg, Call, Def, Import, Main, Module};
 
 /// A (potentially empty) parenthesised and `;`-separated sequence of arguments.
 fn args<T, P>(arg: P) -> impl Parser<Token, Vec<T>, Error = P::Error> + Clone
@@ -36,14 +36,31 @@ where
         Token::Var(name) => Arg::new_var(name),
     };
 
+    let name = select! { Token::Ident(name) => name }.labelled("filter name");
+    let lhs = name
+        .map_with_span(|name, span| (name, span))
+        .then(args(arg.map_with_span(|arg, span| (arg, span))).labelled("filter args"))
+        .map(|((name, name_span), args)| {
+            let (args, arg_spans) = args.into_iter().unzip();
+            let lhs_spans = Call {
+                name: name_span,
+                args: arg_spans,
+            };
+            (Call { name, args }, lhs_spans)
+        });
+
     let defs = def.repeated().collect();
 
     just(Token::Def)
-        .ignore_then(call(arg))
+        .ignore_then(lhs)
         .then_ignore(just(Token::Colon))
         .then(defs.then(filter()).map(|(defs, body)| Main { defs, body }))
         .then_ignore(just(Token::Semicolon))
-        .map(|(lhs, rhs)| Def { lhs, rhs })
+        .map(|((lhs, lhs_spans), rhs)| Def {
+            lhs,
+            rhs,
+            lhs_spans,
+        })
         .labelled("definition")
 }
 
@@ -58,3 +75,42 @@ pub fn main() -> impl Parser<Token, Main, Error = Simple<Token>> + Clone {
         .then(filter())
         .map(|(defs, body)| Main { defs, body })
 }
+
+/// Parser for a module directive, such as `import "foo" as foo;` or `include "foo";`.
+fn import() -> impl Parser<Token, Import, Error = Simple<Token>> + Clone {
+    let path = select! { Token::Str(path) => path }
+        .map_with_span(|path, span| (path, span))
+        .delimited_by(just(Token::Quote), just(Token::Quote))
+        .labelled("module path");
+    let alias = select! { Token::Ident(alias) if !alias.contains("::") => alias };
+
+    let import = just(Token::Import)
+        .ignore_then(path.clone())
+        .then_ignore(just(Token::As))
+        .then(alias.labelled("module name"))
+        .map(|(path, alias)| Import {
+            path,
+            alias: Some(alias),
+        });
+    let include = just(Token::Include)
+        .ignore_then(path)
+        .map(|path| Import { path, alias: None });
+
+    import
+        .or(include)
+        .then_ignore(just(Token::Semicolon))
+        .labelled("module directive")
+}
+
+/// Parser for a (potentially empty) sequence of module directives, followed by a body.
+///
+/// The body is usually given by [`main`] for main programs and by [`defs`] for library modules.
+pub fn module<B, P>(body: P) -> impl Parser<Token, Module<B>, Error = Simple<Token>> + Clone
+where
+    P: Parser<Token, B, Error = Simple<Token>> + Clone,
+{
+    import()
+        .repeated()
+        .then(body)
+        .map(|(imports, body)| Module { imports, body })
+}
@@ -1,7 +1,7 @@
 use super::{prec_climb, Delim, Token};
 use alloc::{boxed::Box, string::String, string::ToString, vec::Vec};
 use chumsky::prelude::*;
-use jaq_syn::filter::{AssignOp, BinaryOp, Filter, Fold, FoldType, KeyVal};
+use jaq_syn::filter::{AssignOp, BinaryOp, Filter, Fold, FoldType, KeyPattern, KeyVal, Pattern};
 use jaq_syn::{MathOp, OrdOp, Spanned};
 
 fn variable() -> impl Parser<Token, String, Error = Simple<Token>> + Clone {
@@ -11,6 +11,48 @@ fn variable() -> impl Parser<Token, String, Error = Simple<Token>> + Clone {
     .labelled("variable")
 }
 
+fn pattern<'a, P>(
+    filter: P,
+) -> impl Parser<Token, Pattern<Spanned<Filter>>, Error = P::Error> + Clone + 'a
+where
+    P: Parser<Token, Spanned<Filter>, Error = Simple<Token>> + Clone + 'a,
+{
+    recursive(|pattern| {
+        let is_pat = just(Token::Colon).ignore_then(pattern.clone());
+        let var = variable().map_with_span(|x, span| (x, span));
+        let key_var = var
+            .clone()
+            .then(is_pat.clone().or_not())
+            .map(|(x, p)| KeyPattern::Var(x, p));
+        let key_str =
+            super::path::key(filter.clone()).map_with_span(|key, span| (Filter::from(key), span));
+        let key_filter = key_str
+            .or(Delim::Paren.around(filter))
+            .then(is_pat)
+            .map(|(key, p)| KeyPattern::Filter(key, p));
+        let key_pats = key_var.or(key_filter).separated_by(just(Token::Comma));
+        let pats = pattern.separated_by(just(Token::Comma));
+
+        choice((
+            var.map(Pattern::Var),
+            Delim::Brack.around(pats).map(Pattern::Arr),
+            Delim::Brace.around(key_pats).map(Pattern::Obj),
+        ))
+    })
+    .labelled("pattern")
+}
+
+/// Alternative patterns, such as `[$x] ?// $x`.
+fn patterns<'a, P>(
+    filter: P,
+) -> impl Parser<Token, Vec<Pattern<Spanned<Filter>>>, Error = P::Error> + Clone + 'a
+where
+    P: Parser<Token, Spanned<Filter>, Error = Simple<Token>> + Clone + 'a,
+{
+    let alt_pattern = just(Token::Question).then(just(Token::Op("//".to_string())));
+    pattern(filter).separated_by(alt_pattern).at_least(1)
+}
+
 fn if_then_else<P>(filter: P) -> impl Parser<Token, Spanned<Filter>, Error = P::Error> + Clone
 where
     P: Parser<Token, Spanned<Filter>, Error = Simple<Token>> + Clone,
@@ -26,9 +68,9 @@ where
         .map_with_span(|(if_thens, else_), span| (Filter::Ite(if_thens, else_), span))
 }
 
-fn fold<P>(filter: P) -> impl Parser<Token, Spanned<Filter>, Error = P::Error> + Clone
+fn fold<'a, P>(filter: P) -> impl Parser<Token, Spanned<Filter>, Error = P::Error> + Clone + 'a
 where
-    P: Parser<Token, Spanned<Filter>, Error = Simple<Token>> + Clone,
+    P: Parser<Token, Spanned<Filter>, Error = Simple<Token>> + Clone + 'a,
 {
     let arg = || filter.clone().map(Box::new);
     let args = arg().then_ignore(just(Token::Semicolon)).then(arg());
@@ -40,12 +82,43 @@ where
     inner
         .then(arg())
         .then_ignore(just(Token::As))
-        .then(variable())
+        .then(patterns(filter.clone()))
         .then(Delim::Paren.around(args))
-        .map(|(((inner, xs), x), (init, f))| (inner, Fold { xs, x, init, f }))
+        .map(|(((inner, xs), pats), (init, f))| {
+            let (x, pats) = match &pats[..] {
+                // binding a single variable is not destructuring
+                [Pattern::Var(x)] => (x.clone(), Vec::new()),
+                _ => (
+                    (String::new(), 0..0),
+                    pats.into_iter().map(|p| p.map(&mut Box::new)).collect(),
+                ),
+            };
+            (
+                inner,
+                Fold {
+                    xs,
+                    x,
+                    pats,
+                    init,
+                    f,
+                },
+            )
+        })
         .map_with_span(|(inner, fold), span| (Filter::Fold(inner, fold), span))
 }
 
+fn label<P>(filter: P) -> impl Parser<Token, Spanned<Filter>, Error = P::Error> + Clone
+where
+    P: Parser<Token, Spanned<Filter>, Error = Simple<Token>> + Clone,
+{
+    // `label` and `break` are no keywords, so that they can still be used as object keys
+    just(Token::Ident("label".to_string()))
+        .ignore_then(variable())
+        .then_ignore(just(Token::Op("|".to_string())))
+        .then(filter.map(Box::new))
+        .map_with_span(|(x, f), span| (Filter::Label(x, f), span))
+}
+
 fn try_catch<P>(filter: P) -> impl Parser<Token, Spanned<Filter>, Error = P::Error> + Clone
 where
     P: Parser<Token, Spanned<Filter>, Error = Simple<Token>> + Clone,
@@ -74,6 +147,8 @@ where
 
     let recurse = just(Token::DotDot);
 
+    let break_ = just(Token::Ident("break".to_string())).ignore_then(variable());
+
     let array = Delim::Brack.around(filter.clone().or_not());
 
     let is_val = just(Token::Colon).ignore_then(no_comma);
@@ -94,6 +169,7 @@ where
         num.map_with_span(|num, span| (Filter::Num(num), span)),
         array.map_with_span(|arr, span| (Filter::Array(arr.map(Box::new)), span)),
         object.map_with_span(|obj, span| (Filter::Object(obj), span)),
+        break_.map_with_span(|x, span| (Filter::Break(x), span)),
         call.map_with_span(|call, span| (Filter::from(call), span)),
         variable().map_with_span(|v, span| (Filter::Var(v), span)),
         recurse.map_with_span(|_, span| (Filter::Recurse, span)),
@@ -136,17 +212,57 @@ impl prec_climb::Op for BinaryOp {
     }
 }
 
-impl prec_climb::Output<BinaryOp> for Spanned<Filter> {
-    fn from_op(lhs: Self, op: BinaryOp, rhs: Self) -> Self {
-        Filter::binary(lhs, op, rhs)
+/// Binary operator or destructuring, such as `as [$x, $y] |`.
+#[derive(Clone)]
+enum Infix {
+    Binary(BinaryOp),
+    Destructure(Vec<Pattern<Spanned<Filter>>>),
+}
+
+impl prec_climb::Op for Infix {
+    fn prec(&self) -> usize {
+        match self {
+            Self::Binary(op) => op.prec(),
+            Self::Destructure(_) => BinaryOp::Pipe(None).prec(),
+        }
+    }
+
+    fn right_assoc(&self) -> bool {
+        match self {
+            Self::Binary(op) => op.right_assoc(),
+            Self::Destructure(_) => BinaryOp::Pipe(None).right_assoc(),
+        }
+    }
+}
+
+impl prec_climb::Output<Infix> for Spanned<Filter> {
+    fn from_op(lhs: Self, op: Infix, rhs: Self) -> Self {
+        match op {
+            Infix::Binary(op) => Filter::binary(lhs, op, rhs),
+            Infix::Destructure(ps) => {
+                let span = lhs.1.start..rhs.1.end;
+                let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+                (Filter::Destructure(lhs, ps, rhs), span)
+            }
+        }
     }
 }
 
-fn binary_op() -> impl Parser<Token, BinaryOp, Error = Simple<Token>> + Clone {
-    let as_var = just(Token::As).ignore_then(variable()).or_not();
-    let pipe = as_var
+fn binary_op<'a, P>(filter: P) -> impl Parser<Token, Infix, Error = P::Error> + Clone + 'a
+where
+    P: Parser<Token, Spanned<Filter>, Error = Simple<Token>> + Clone + 'a,
+{
+    let as_patterns = just(Token::As).ignore_then(patterns(filter)).or_not();
+    let pipe = as_patterns
         .then_ignore(just(Token::Op("|".to_string())))
-        .map(BinaryOp::Pipe);
+        .map(|ps| match ps {
+            None => Infix::Binary(BinaryOp::Pipe(None)),
+            Some(ps) => match &ps[..] {
+                // binding a single variable is just a pipe
+                [Pattern::Var(x)] => Infix::Binary(BinaryOp::Pipe(Some(x.clone()))),
+                _ => Infix::Destructure(ps),
+            },
+        });
 
     let assign = |op: AssignOp| just(Token::Op(op.to_string())).to(BinaryOp::Assign(op));
     let update_with = |op: MathOp| assign(AssignOp::UpdateWith(op));
@@ -154,8 +270,7 @@ fn binary_op() -> impl Parser<Token, BinaryOp, Error = Simple<Token>> + Clone {
     let ord = |op: OrdOp| just(Token::Op(op.to_string())).to(BinaryOp::Ord(op));
     let math = |op: MathOp| just(Token::Op(op.to_string())).to(BinaryOp::Math(op));
 
-    choice((
-        pipe,
+    let op = choice((
         // normally, here would be `,`,
         // however, in some contexts, we want to exclude `,`
         // (for example, `f` and `g` in `{a: f, b: g}` must not contain `,`)
@@ -181,13 +296,14 @@ fn binary_op() -> impl Parser<Token, BinaryOp, Error = Simple<Token>> + Clone {
         math(MathOp::Mul),
         math(MathOp::Div),
         math(MathOp::Rem),
-    ))
+    ));
+    pipe.or(op.map(Infix::Binary))
 }
 
 fn climb<F, O>(f: F, op: O) -> impl Parser<Token, Spanned<Filter>, Error = O::Error> + Clone
 where
     F: Parser<Token, Spanned<Filter>, Error = Simple<Token>> + Clone,
-    O: Parser<Token, BinaryOp, Error = Simple<Token>> + Clone,
+    O: Parser<Token, Infix, Error = Simple<Token>> + Clone,
 {
     use prec_climb::Output;
     f.clone()
@@ -215,6 +331,7 @@ pub fn filter() -> impl Parser<Token, Spanned<Filter>, Error = Simple<Token>> +
 
     // named operators, such as `reduce` or `if-then-else`
     let named = choice((
+        label(with_comma.clone()),
         path.map_with_span(|(f, path), span| Filter::path(f, path, span)),
         fold(with_comma.clone()),
         if_then_else(with_comma.clone()),
@@ -234,8 +351,8 @@ pub fn filter() -> impl Parser<Token, Spanned<Filter>, Error = Simple<Token>> +
     let neg = neg(try_).boxed();
     let tc = recursive(|f| try_catch(f).or(neg));
 
-    let op = binary_op().boxed();
-    let comma = just(Token::Comma).to(BinaryOp::Comma);
+    let op = binary_op(with_comma.clone()).boxed();
+    let comma = just(Token::Comma).to(Infix::Binary(BinaryOp::Comma));
 
     sans_comma.define(climb(tc.clone(), op.clone()));
     with_comma.define(climb(tc, op.or(comma)));
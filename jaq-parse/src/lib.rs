@@ -2,6 +2,8 @@
 #![no_std]
 #![forbid(unsafe_code)]
 #![warn(missing_docs)]
+// `select!` from chumsky yields closures returning large errors
+#![allow(clippy::result_large_err)]
 
 extern crate alloc;
 
@@ -14,7 +16,7 @@ mod token;
 
 use jaq_syn as syn;
 
-pub use def::{defs, main};
+pub use def::{defs, main, module};
 use token::{Delim, Token};
 
 use alloc::{string::String, string::ToString, vec::Vec};
@@ -26,7 +28,7 @@ pub type Error = Simple<String>;
 
 fn lex() -> impl Parser<char, Vec<Spanned<Token>>, Error = Simple<char>> {
     recursive(token::tree)
-        .map_with_span(|tree, span| tree.tokens(span))
+        .map(|(tree, span)| tree.tokens(span))
         .repeated()
         .flatten()
         .collect()
@@ -58,3 +60,93 @@ where
 
     (parsed, errs)
 }
+
+/// Return true if a filter is invalid, but might become valid by appending input to it.
+///
+/// This is the case for unclosed delimiters, unterminated strings, and
+/// trailing operators, such as in `[1,` or `.a +`.
+/// A REPL can use this to decide whether to read more lines before running a filter.
+/// Note that the empty filter is considered incomplete.
+pub fn is_incomplete(src: &str) -> bool {
+    fn at_end<T: core::hash::Hash + Eq>(errs: &[Simple<T>]) -> bool {
+        !errs.is_empty() && errs.iter().all(|e| e.found().is_none())
+    }
+
+    let (tokens, lex_errs) = lex().then_ignore(end()).parse_recovery(src);
+    // after a lexing error, parse errors are not reliable
+    match tokens {
+        Some(tokens) if lex_errs.is_empty() => {
+            let len = src.chars().count();
+            let stream = chumsky::Stream::from_iter(len..len + 1, tokens.into_iter());
+            let (_, errs) = module(main()).then_ignore(end()).parse_recovery(stream);
+            at_end(&errs)
+        }
+        _ => at_end(&lex_errs),
+    }
+}
+
+/// Parse a filter and print it in a canonical format.
+///
+/// Formatting preserves the meaning of the filter as well as its comments.
+/// However, comments are moved to their own lines before
+/// the definition or filter body that contains them.
+pub fn format_filter(src: &str) -> Result<String, Vec<Error>> {
+    match parse(src, module(main())) {
+        (Some(main), errs) if errs.is_empty() => Ok(main.format(&comments(src), &def_spans(src))),
+        (_, errs) => Err(errs),
+    }
+}
+
+/// Return the positions of all `def` keywords in a filter.
+fn def_spans(src: &str) -> Vec<syn::Span> {
+    let tokens = lex().parse(src).unwrap_or_default();
+    let defs = tokens.into_iter().filter(|(token, _)| *token == Token::Def);
+    defs.map(|(_, span)| span).collect()
+}
+
+/// Return the comments of a filter that can be lexed without errors.
+fn comments(src: &str) -> Vec<syn::Comment> {
+    let mut comments = Vec::new();
+    // for every string that we are in, `None` if we are in its text, or else
+    // the number of parentheses open in the interpolation that we are in
+    let mut strings: Vec<Option<usize>> = Vec::new();
+    // is there code before the current position on the same line?
+    let mut code = false;
+    let mut chars = src.chars().enumerate().peekable();
+    while let Some((i, c)) = chars.next() {
+        match (strings.last().copied(), c) {
+            (None | Some(Some(_)), '#') => {
+                let rest = core::iter::from_fn(|| chars.next_if(|(_, c)| *c != '\n'));
+                let text: String = core::iter::once(c).chain(rest.map(|(_, c)| c)).collect();
+                let span = i..i + text.chars().count();
+                let trailing = code;
+                comments.push(syn::Comment {
+                    text,
+                    span,
+                    trailing,
+                });
+                continue;
+            }
+            (None | Some(Some(_)), '"') => strings.push(None),
+            (Some(None), '"') => {
+                strings.pop();
+            }
+            (Some(None), '\\') => {
+                if chars.next_if(|(_, c)| *c == '(').is_some() {
+                    *strings.last_mut().unwrap() = Some(0)
+                } else {
+                    chars.next();
+                }
+            }
+            (Some(Some(parens)), '(') => *strings.last_mut().unwrap() = Some(parens + 1),
+            (Some(Some(parens)), ')') => *strings.last_mut().unwrap() = parens.checked_sub(1),
+            _ => (),
+        }
+        if c == '\n' {
+            code = false
+        } else if !c.is_whitespace() {
+            code = true
+        }
+    }
+    comments
+}
@@ -98,6 +98,8 @@ pub enum Token {
     Foreach,
     Try,
     Catch,
+    Import,
+    Include,
 }
 
 impl fmt::Display for Token {
@@ -128,36 +130,59 @@ impl fmt::Display for Token {
             Self::Foreach => "foreach".fmt(f),
             Self::Try => "try".fmt(f),
             Self::Catch => "catch".fmt(f),
+            Self::Import => "import".fmt(f),
+            Self::Include => "include".fmt(f),
         }
     }
 }
 
 // A parser for numbers
+//
+// Like in jq, the integer part or the fractional part may be omitted,
+// such as in `5.` or `.5`, but not both.
 fn num() -> impl Parser<char, String, Error = Simple<char>> {
     let comma = just('.').chain(text::digits(10).or_not());
+    let int = text::int(10).chain::<char, _, _>(comma.or_not());
+    let frac = just('.').chain::<char, _, _>(text::digits(10));
 
     let exp = one_of("eE")
         .chain(one_of("+-").or_not())
         .chain::<char, _, _>(text::digits(10));
 
-    text::int(10)
-        .chain::<char, _, _>(comma.or_not())
-        .chain::<char, _, _>(exp.or_not())
-        .collect()
+    int.or(frac).chain::<char, _, _>(exp.or_not()).collect()
 }
 
 // A parser for strings; adapted from Chumsky's JSON example parser.
 fn char_() -> impl Parser<char, char, Error = Simple<char>> {
-    let unicode = filter(|c: &char| c.is_ascii_hexdigit())
-        .repeated()
-        .exactly(4)
-        .collect::<String>()
-        .validate(|digits, span, emit| {
-            char::from_u32(u32::from_str_radix(&digits, 16).unwrap()).unwrap_or_else(|| {
-                emit(Simple::custom(span, "invalid unicode character"));
-                '\u{FFFD}' // unicode replacement character
-            })
-        });
+    let hex4 = || {
+        filter(|c: &char| c.is_ascii_hexdigit())
+            .repeated()
+            .exactly(4)
+            .collect::<String>()
+            .map(|digits| u32::from_str_radix(&digits, 16).unwrap())
+    };
+
+    // a high surrogate followed by a low surrogate, such as `\ud83d\ude00`,
+    // encodes a single character outside the Basic Multilingual Plane
+    let surrogate = |range: core::ops::Range<u32>| {
+        hex4().try_map(move |u, span| {
+            let err = || Simple::custom(span, "expected surrogate");
+            range.contains(&u).then_some(u).ok_or_else(err)
+        })
+    };
+    let high = surrogate(0xD800..0xDC00);
+    let low = surrogate(0xDC00..0xE000);
+    let pair = high
+        .then_ignore(just("\\u"))
+        .then(low)
+        .map(|(h, l)| 0x10000 + ((h - 0xD800) << 10) + (l - 0xDC00));
+
+    let unicode = pair.or(hex4()).validate(|u, span, emit| {
+        char::from_u32(u).unwrap_or_else(|| {
+            emit(Simple::custom(span, "invalid unicode character"));
+            '\u{FFFD}' // unicode replacement character
+        })
+    });
 
     let escape = just('\\').ignore_then(choice((
         just('\\'),
@@ -175,9 +200,9 @@ fn char_() -> impl Parser<char, char, Error = Simple<char>> {
 }
 
 pub fn tree(
-    tree: impl Parser<char, Tree, Error = Simple<char>> + Clone,
-) -> impl Parser<char, Tree, Error = Simple<char>> {
-    let trees = || tree.clone().map_with_span(|t, span| (t, span)).repeated();
+    tree: impl Parser<char, Spanned<Tree>, Error = Simple<char>> + Clone,
+) -> impl Parser<char, Spanned<Tree>, Error = Simple<char>> {
+    let trees = || tree.clone().repeated();
     let paren = trees().delimited_by(just('('), just(')'));
     let brack = trees().delimited_by(just('['), just(']'));
     let brace = trees().delimited_by(just('{'), just('}'));
@@ -193,7 +218,9 @@ pub fn tree(
         .delimited_by(just('"'), just('"'))
         .labelled("string");
 
-    let comment = just("#").then(take_until(just('\n'))).padded();
+    let comment = just("#")
+        .then(take_until(just('\n').ignored().or(end())))
+        .padded();
 
     let strategy = |open, close, others| {
         nested_delimiters(open, close, others, |_span| Tree::Token(Token::Dot))
@@ -209,6 +236,8 @@ pub fn tree(
     .recover_with(strategy('(', ')', [('[', ']'), ('{', '}')]))
     .recover_with(strategy('[', ']', [('{', '}'), ('(', ')')]))
     .recover_with(strategy('{', '}', [('(', ')'), ('[', ']')]))
+    // the span of a tree excludes the whitespace and comments around it
+    .map_with_span(|t, span| (t, span))
     .padded_by(comment.repeated())
     .padded()
 }
@@ -219,8 +248,13 @@ pub fn token() -> impl Parser<char, Token, Error = Simple<char>> {
 
     let var = just('$').ignore_then(text::ident());
 
-    // A parser for identifiers and keywords
-    let ident = just('@').or_not().chain::<char, _, _>(text::ident());
+    // A parser for identifiers and keywords,
+    // where identifiers may be prefixed by module names, such as `foo::bar`
+    let ns = text::ident().chain::<char, _, _>(just(':').repeated().exactly(2));
+    let ident = just('@')
+        .or_not()
+        .chain::<char, _, _>(ns.repeated().flatten());
+    let ident = ident.chain::<char, _, _>(text::ident());
     let ident = ident.collect().map(|ident: String| match ident.as_str() {
         "def" => Token::Def,
         "if" => Token::If,
@@ -236,12 +270,15 @@ pub fn token() -> impl Parser<char, Token, Error = Simple<char>> {
         "foreach" => Token::Foreach,
         "try" => Token::Try,
         "catch" => Token::Catch,
+        "import" => Token::Import,
+        "include" => Token::Include,
         _ => Token::Ident(ident),
     });
 
     choice((
         ident,
         just("..").to(Token::DotDot),
+        num().map(Token::Num),
         just('.').to(Token::Dot),
         just(':').to(Token::Colon),
         just(';').to(Token::Semicolon),
@@ -249,6 +286,5 @@ pub fn token() -> impl Parser<char, Token, Error = Simple<char>> {
         just('?').to(Token::Question),
         op.map(Token::Op),
         var.map(Token::Var),
-        num().map(Token::Num),
     ))
 }
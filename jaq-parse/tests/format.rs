@@ -0,0 +1,120 @@
+use jaq_parse::format_filter;
+
+fn format(src: &str) -> String {
+    format_filter(src).unwrap()
+}
+
+/// Remove all spans from the debug output of a parsed filter.
+fn without_spans(src: &str) -> String {
+    let (main, errs) = jaq_parse::parse(src, jaq_parse::main());
+    assert!(errs.is_empty());
+    let debug = format!("{:?}", main.unwrap());
+    // spans are the only numbers in the debug output not enclosed in quotes
+    let mut out = String::new();
+    let mut quoted = false;
+    let mut chars = debug.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quoted => {
+                out.push(c);
+                out.extend(chars.next());
+                continue;
+            }
+            '"' => quoted = !quoted,
+            c if !quoted && (c.is_ascii_digit() || c == '.') => continue,
+            _ => (),
+        }
+        out.push(c)
+    }
+    out
+}
+
+/// Check that formatting preserves the meaning and is idempotent.
+fn check(src: &str) -> String {
+    let formatted = format(src);
+    assert_eq!(without_spans(src), without_spans(&formatted), "{formatted}");
+    assert_eq!(format(&formatted), formatted);
+    formatted
+}
+
+#[test]
+fn spacing() {
+    assert_eq!(check(".a|.b,.c"), ".a | .b, .c");
+    assert_eq!(
+        check("{a:1,\"b c\":2,(.d):3,e}"),
+        r#"{a: 1, "b c": 2, (.d): 3, e}"#
+    );
+    assert_eq!(check("f( 1 ;2 )"), "f(1; 2)");
+    assert_eq!(check(r#"@base64 "x\(.a)\n""#), r#"@base64 "x\(.a)\n""#);
+    assert_eq!(check(r#"."if"[0]?.a[1:]"#), r#"."if"[0]?.a[1:]"#);
+}
+
+#[test]
+fn parens() {
+    assert_eq!(check("(1, 2) | (3 | 4)"), "1, 2 | 3 | 4");
+    assert_eq!(check("1, (2 | 3)"), "1, (2 | 3)");
+    assert_eq!(check("(1 + 2) * 3 - (4 - 5)"), "(1 + 2) * 3 - (4 - 5)");
+    assert_eq!(check("{a: (1, 2), b: (3 | 4)}"), "{a: (1, 2), b: (3 | 4)}");
+    assert_eq!(check("-(1 + 2) | (.a)? | .a?"), "-(1 + 2) | (.a)? | .a?");
+    assert_eq!(check("try (try 1) catch 2"), "try (try 1) catch 2");
+    assert_eq!(
+        check("(.. | .a), (..).a, (1).a"),
+        "(.. | .a), (..).a, (1).a"
+    );
+    assert_eq!(check(". as $x | $x"), ". as $x | $x");
+    assert_eq!(
+        check("(label $x | 1, break $x) | 2, (label $y | 3)"),
+        "(label $x | 1, break $x) | 2, (label $y | 3)"
+    );
+    assert_eq!(
+        check("(1 as [$x, {$y: [$z], a: $w}] ?// $x | $x), 2"),
+        "(1 as [$x, {$y: [$z], a: $w}] ?// $x | $x), 2"
+    );
+    let obj = r#". as {"a b": $x, (.c): $y, "d": $z} | $x"#;
+    assert_eq!(check(obj), r#". as {"a b": $x, (.c): $y, d: $z} | $x"#);
+    assert_eq!(
+        check("reduce .[] as [$x, {(.a): $y}] ?// $x (0; $x)"),
+        "reduce .[] as [$x, {(.a): $y}] ?// $x (0; $x)"
+    );
+}
+
+#[test]
+fn layout() {
+    let src = "def f(g; $x): def h: g; reduce h as $y ($x; . + $y); [f(.[]; 0)]";
+    let out = "def f(g; $x):\n  def h: g;\n  reduce h as $y ($x; . + $y);\n[f(.[]; 0)]";
+    assert_eq!(check(src), out);
+
+    let long = ".[] | select(.name | test(\"^a\")) as $x | {name, value: .value + 1} | tojson | ltrimstr(\"x\")";
+    let out = ".[]\n| select(.name | test(\"^a\")) as $x\n| {name, value: .value + 1}\n| tojson\n| ltrimstr(\"x\")";
+    assert_eq!(check(long), out);
+}
+
+#[test]
+fn comments() {
+    // comments in a filter body are moved before it
+    assert_eq!(check("1 # comment\n+ 2"), "# comment\n1 + 2");
+    assert_eq!(check("1 # comment"), "1 # comment");
+    let s = r##""# not \("#") # x" # c"##;
+    assert_eq!(check(s), s);
+
+    let src = "# a\ndef f: 1; # b\n# c\ndef g:\n def h: [1, # d\n 2]; h;\ng\n# e";
+    let out = "# a\ndef f: 1; # b\n# c\ndef g:\n  # d\n  def h: [1, 2];\n  h;\ng\n# e";
+    assert_eq!(check(src), out);
+
+    let src = "# a\nimport \"m\" as m; # b\n.";
+    assert_eq!(format(src), "# a\nimport \"m\" as m;\n# b\n.");
+}
+
+#[test]
+fn modules() {
+    let src = r#"import "lib/a"as a;include "b";def f: a::g; f"#;
+    let out = "import \"lib/a\" as a;\ninclude \"b\";\ndef f: a::g;\nf";
+    assert_eq!(format(src), out);
+    assert_eq!(format(out), out);
+}
+
+#[test]
+fn format_std() {
+    let src = String::from(include_str!("../../jaq-std/src/std.jq")) + "\n.";
+    check(&src);
+}
@@ -0,0 +1,27 @@
+use jaq_parse::is_incomplete;
+
+#[test]
+fn incomplete() {
+    let inc = [
+        "[1,",
+        ".a + ",
+        "\"abc",
+        "\"\\(1",
+        "(1",
+        "{a: 1",
+        "if . then 1",
+        "def f: 1;",
+    ];
+    inc.iter().for_each(|f| assert!(is_incomplete(f), "{f}"));
+}
+
+#[test]
+fn complete_or_erroneous() {
+    // valid filters
+    let valid = ["[1, 2]", ".a + 1", "def f: 1; f"];
+    // errors that cannot be fixed by appending input
+    let err = ["[1,]", "[1, )", "1 +)", "1 2"];
+    for f in valid.iter().chain(&err) {
+        assert!(!is_incomplete(f), "{f}")
+    }
+}
@@ -0,0 +1,77 @@
+use jaq_syn::lint::{lint, Lint};
+use jaq_syn::Arg;
+
+/// Assert that the lints of a filter are the given ones, where
+/// every lint is given with the source text that its span points to.
+fn lints(src: &str, expected: &[(Lint, &str)]) {
+    let (main, errs) = jaq_parse::parse(src, jaq_parse::main());
+    assert!(errs.is_empty());
+    let lints = lint(&main.unwrap()).into_iter();
+    // replace spans by the source text they point to
+    let chars: Vec<char> = src.chars().collect();
+    let text = |span: jaq_syn::Span| chars[span].iter().collect::<String>();
+    // spans may include trailing whitespace
+    let lints = lints.map(|(l, span)| (l, text(span).trim().to_string()));
+    let expected = expected.iter().map(|(l, s)| (l.clone(), s.to_string()));
+    assert_eq!(lints.collect::<Vec<_>>(), expected.collect::<Vec<_>>());
+}
+
+fn var(x: &str) -> Lint {
+    Lint::Unused(Arg::Var(x.to_string()))
+}
+
+fn fun(f: &str, arity: usize) -> Lint {
+    Lint::Unused(Arg::Fun((f.to_string(), arity)))
+}
+
+#[test]
+fn unused() {
+    lints("1 as $x | 2", &[(var("x"), "$x")]);
+    lints("1 as $x | $x", &[]);
+    lints("1 as $_x | 2", &[]);
+    lints("reduce .[] as $x (0; .)", &[(var("x"), "$x")]);
+    lints(". as [$x, $y] | $y", &[(var("x"), "$x")]);
+    lints("reduce .[] as [$x, $y] (0; $y)", &[(var("x"), "$x")]);
+    lints(". as {$x, a: [$y]} | $x", &[(var("y"), "$y")]);
+    // variables of alternative patterns are located at their first occurrence
+    lints(". as [$x] ?// $x | 1", &[(var("x"), "$x")]);
+    lints(". as [$x] ?// $y | $x", &[(var("y"), "$y")]);
+
+    lints("def f: 1; 2", &[(fun("f", 0), "f")]);
+    lints("def f($x): 1; f(2)", &[(var("x"), "$x")]);
+    lints("def f: 1; f", &[]);
+    // recursive calls do not count
+    lints("def f: f; 2", &[(fun("f", 0), "f")]);
+    // calls from later definitions count
+    lints("def f: 1; def g: f; g", &[]);
+    lints("def f(g; $x): $x; f(1; 2)", &[(fun("g", 0), "g")]);
+    // a filter and a variable with the same name are different
+    lints("def f($x): x; f(1)", &[(var("x"), "$x")]);
+}
+
+#[test]
+fn condition() {
+    let c = Lint::Const;
+    lints("if true then 1 else 2 end", &[(c(true), "true")]);
+    lints("if . then 1 elif null then 2 end", &[(c(false), "null")]);
+    lints(r#"if "a" then 1 else 2 end"#, &[(c(true), r#""a""#)]);
+    lints(r#"if "\(.)" then 1 else 2 end"#, &[]);
+    // shadowed definitions are not constant
+    lints("def true: .; if true then 1 else 2 end", &[]);
+}
+
+#[test]
+fn index() {
+    lints("1[0]", &[(Lint::Index("number"), "1[0]")]);
+    lints("true.a?", &[(Lint::Index("boolean"), "true.a?")]);
+    lints(r#""abc"[]"#, &[(Lint::Index("string"), r#""abc"[]"#)]);
+    lints(r#""abc"[1:]"#, &[]);
+    lints("null.a", &[]);
+}
+
+#[test]
+fn deprecated() {
+    let d = Lint::Deprecated("leaf_paths".to_string(), "paths(scalars)");
+    lints("[leaf_paths]", &[(d, "leaf_paths")]);
+    lints("def leaf_paths: 1; leaf_paths", &[]);
+}
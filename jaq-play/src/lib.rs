@@ -1,5 +1,5 @@
 use core::fmt::{self, Debug, Display, Formatter};
-use jaq_interpret::{Ctx, Filter, FilterT, ParseCtx, RcIter, Val};
+use jaq_interpret::{style, Ctx, Filter, FilterT, ParseCtx, RcIter, Val};
 use wasm_bindgen::prelude::*;
 
 struct Pp<'a> {
@@ -50,6 +50,7 @@ impl<'a> Display for Pp<'a> {
             Val::Int(i) => span(f, "number", i),
             Val::Float(x) if x.is_finite() => span_dbg(f, "number", x),
             Val::Float(_) => span(f, "null", "null"),
+            Val::BigInt(i) => span(f, "number", i),
             Val::Num(n) => span(f, "number", n),
             Val::Str(s) => span_dbg(f, "string", escape(s)),
             Val::Arr(a) if a.is_empty() => write!(f, "[]"),
@@ -164,7 +165,9 @@ pub fn run(filter: &str, input: &str, settings: &JsValue, scope: &Scope) {
                 .unwrap();
         }
         Err(Error::Jaq(e)) => {
-            scope.post_message(&format!("⚠️ Error: {e}").into()).unwrap();
+            scope
+                .post_message(&format!("⚠️ Error: {e}").into())
+                .unwrap();
         }
     }
 
@@ -175,13 +178,7 @@ pub fn run(filter: &str, input: &str, settings: &JsValue, scope: &Scope) {
 fn process(filter: &str, input: &str, _settings: &Settings, f: impl Fn(Val)) -> Result<(), Error> {
     let filter = parse(filter, Vec::new()).map_err(Error::Chumsky)?;
 
-    let mut lexer = hifijson::SliceLexer::new(input.as_bytes());
-    let inputs = core::iter::from_fn(move || {
-        use hifijson::token::Lex;
-        Some(Val::parse(lexer.ws_token()?, &mut lexer).map_err(|e| e.to_string()))
-    });
-
-    let inputs = RcIter::new(inputs);
+    let inputs = RcIter::new(json_values(input));
 
     for x in &inputs {
         let x = x.map_err(Error::Hifijson)?;
@@ -192,6 +189,89 @@ fn process(filter: &str, input: &str, _settings: &Settings, f: impl Fn(Val)) ->
     Ok(())
 }
 
+/// Parse a sequence of JSON values.
+fn json_values(input: &str) -> impl Iterator<Item = Result<Val, String>> + '_ {
+    let mut lexer = hifijson::SliceLexer::new(input.as_bytes());
+    core::iter::from_fn(move || {
+        use hifijson::token::Lex;
+        Some(Val::parse(lexer.ws_token()?, &mut lexer).map_err(|e| e.to_string()))
+    })
+}
+
+/// Filter that was compiled by [`compile`] and can be run on many inputs.
+#[wasm_bindgen]
+pub struct Compiled(Filter);
+
+/// Compile a filter.
+///
+/// On failure, this throws an array of error objects with the fields
+/// `message`, `start`, and `end`, where
+/// `start` and `end` are the character offsets delimiting the error in the filter.
+#[wasm_bindgen]
+pub fn compile(filter: &str) -> Result<Compiled, js_sys::Array> {
+    parse(filter, Vec::new()).map(Compiled).map_err(|errs| {
+        let span = |e: &ChumskyError| Some(e.span());
+        errs.iter().map(|e| error(&message(e), span(e))).collect()
+    })
+}
+
+#[wasm_bindgen]
+impl Compiled {
+    /// Run the filter on all JSON values in `input`.
+    ///
+    /// This returns an array with one object per output, which is either
+    /// `{ value }`, where `value` is the output value serialised to JSON, or
+    /// `{ message, start, end }` if an error occurred, where
+    /// `start` and `end` delimit the part of the filter that caused the error.
+    /// Errors that stem from parsing the input stop the run,
+    /// are the last element of the array, and have neither `start` nor `end`.
+    pub fn run(&self, input: &str) -> js_sys::Array {
+        let results = js_sys::Array::new();
+        let inputs = RcIter::new(json_values(input));
+        for x in &inputs {
+            let x = match x {
+                Ok(x) => x,
+                Err(e) => {
+                    results.push(&error(&format!("cannot parse input: {e}"), None));
+                    break;
+                }
+            };
+            for y in self.0.run((Ctx::new([], &inputs), x)) {
+                results.push(&match y {
+                    Ok(y) => {
+                        let mut s = String::new();
+                        style::write(&mut s, &y, &(), None).unwrap();
+                        object(&[("value", s.into())])
+                    }
+                    Err(e) => error(&e.to_string(), e.loc().map(|loc| loc.span.clone())),
+                });
+            }
+        }
+        results
+    }
+}
+
+/// Create a JavaScript object with the given fields.
+fn object(fields: &[(&str, JsValue)]) -> JsValue {
+    let obj = js_sys::Object::new();
+    for (k, v) in fields {
+        js_sys::Reflect::set(&obj, &(*k).into(), v).unwrap();
+    }
+    obj.into()
+}
+
+/// Create an error object, with a span if the error can be located in the filter.
+fn error(message: &str, span: Option<core::ops::Range<usize>>) -> JsValue {
+    match span {
+        Some(span) => object(&[
+            ("message", message.into()),
+            ("start", span.start.into()),
+            ("end", span.end.into()),
+        ]),
+        None => object(&[("message", message.into())]),
+    }
+}
+
 type ChumskyError = chumsky::error::Simple<String>;
 
 fn parse(filter_str: &str, vars: Vec<String>) -> Result<Filter, Vec<ChumskyError>> {
@@ -199,6 +279,8 @@ fn parse(filter_str: &str, vars: Vec<String>) -> Result<Filter, Vec<ChumskyError
     defs.insert_natives(jaq_core::core());
     defs.insert_defs(jaq_std::std());
     assert!(defs.errs.is_empty());
+    // this locates the runtime errors that occur in the filter
+    defs.set_source("<filter>".into(), filter_str.into());
     let (filter, errs) = jaq_parse::parse(filter_str, jaq_parse::main());
     if !errs.is_empty() {
         return Err(errs);
@@ -215,39 +297,14 @@ fn parse(filter_str: &str, vars: Vec<String>) -> Result<Filter, Vec<ChumskyError
     }
 }
 
-fn report<'a>(e: chumsky::error::Simple<String>) -> ariadne::Report<'a> {
+fn report<'a>(e: ChumskyError) -> ariadne::Report<'a> {
     use ariadne::{Color, Fmt, Label, Report, ReportKind};
     use chumsky::error::SimpleReason;
 
     let (red, yellow) = (Color::Unset, Color::Unset);
     let config = ariadne::Config::default().with_color(false);
 
-    let msg = if let SimpleReason::Custom(msg) = e.reason() {
-        msg.clone()
-    } else {
-        let found = if e.found().is_some() {
-            "Unexpected token"
-        } else {
-            "Unexpected end of input"
-        };
-        let when = if let Some(label) = e.label() {
-            format!(" while parsing {label}")
-        } else {
-            String::new()
-        };
-        let expected = if e.expected().len() == 0 {
-            "something else".to_string()
-        } else {
-            e.expected()
-                .map(|expected| match expected {
-                    Some(expected) => expected.to_string(),
-                    None => "end of input".to_string(),
-                })
-                .collect::<Vec<_>>()
-                .join(", ")
-        };
-        format!("{found}{when}, expected {expected}",)
-    };
+    let msg = message(&e);
 
     let label = if let SimpleReason::Custom(msg) = e.reason() {
         msg.clone()
@@ -276,3 +333,34 @@ fn report<'a>(e: chumsky::error::Simple<String>) -> ariadne::Report<'a> {
 
     report.with_config(config).finish()
 }
+
+/// Message describing a parse error, without the location of the error.
+fn message(e: &ChumskyError) -> String {
+    use chumsky::error::SimpleReason;
+    if let SimpleReason::Custom(msg) = e.reason() {
+        msg.clone()
+    } else {
+        let found = if e.found().is_some() {
+            "Unexpected token"
+        } else {
+            "Unexpected end of input"
+        };
+        let when = if let Some(label) = e.label() {
+            format!(" while parsing {label}")
+        } else {
+            String::new()
+        };
+        let expected = if e.expected().len() == 0 {
+            "something else".to_string()
+        } else {
+            e.expected()
+                .map(|expected| match expected {
+                    Some(expected) => expected.to_string(),
+                    None => "end of input".to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        format!("{found}{when}, expected {expected}",)
+    }
+}
@@ -0,0 +1,115 @@
+//! Test that serialised filters behave like freshly compiled ones.
+
+use jaq_interpret::{Ctx, Filter, FilterT, ParseCtx, RcIter, Val, ValR};
+use serde_json::json;
+
+const SRC: &str = r#"def f($x): $x * 10 + 1;
+[.[] | select(. > 1) | f(.)], (to_entries | map("\(.key)=\(.value)") | join(",")),
+(reduce .[] as $x (0; . + $x)), ([paths] | length), (try error("x") catch .)"#;
+
+fn parse_ctx() -> ParseCtx {
+    let mut ctx = ParseCtx::new(Vec::new());
+    ctx.insert_natives(jaq_core::core());
+    ctx.insert_defs(jaq_std::std());
+    ctx
+}
+
+fn compile(ctx: &mut ParseCtx, src: &str) -> Filter {
+    let (f, errs) = jaq_parse::parse(src, jaq_parse::main());
+    assert!(errs.is_empty());
+    let f = ctx.compile(f.unwrap());
+    assert!(ctx.errs.is_empty());
+    f
+}
+
+fn run(f: &Filter) -> Vec<ValR> {
+    let inputs = RcIter::new(core::iter::empty());
+    let input = Val::from(json!([3, 1, 2]));
+    f.run((Ctx::new([], &inputs), input)).collect()
+}
+
+#[test]
+fn round_trip() {
+    let mut ctx = parse_ctx();
+    let f = compile(&mut ctx, SRC);
+    let bytes = ctx.serialize(&f, SRC.as_bytes());
+
+    // deserialise in a fresh context, as a CLI would do on its next start
+    let g = parse_ctx().deserialize(&bytes, SRC.as_bytes()).unwrap();
+    assert_eq!(run(&f), run(&g));
+    assert_eq!(run(&g)[0], Ok(Val::from(json!([31, 21]))));
+}
+
+#[test]
+fn round_trip_loc() {
+    let src = "def f: .a; [1, 2] | .[0], map(f)";
+    let mut ctx = parse_ctx();
+    ctx.set_source("test.jq".into(), src.into());
+    let f = compile(&mut ctx, src);
+    let bytes = ctx.serialize(&f, src.as_bytes());
+    let g = parse_ctx().deserialize(&bytes, src.as_bytes()).unwrap();
+
+    let err = run(&g).pop().unwrap().unwrap_err();
+    assert_eq!(run(&f), run(&g));
+    assert_eq!(err.loc().unwrap().source.0, "test.jq");
+    assert_eq!(err.loc().unwrap().span, 7..9);
+}
+
+#[test]
+fn invalidate() {
+    let mut ctx = parse_ctx();
+    let f = compile(&mut ctx, SRC);
+    let bytes = ctx.serialize(&f, SRC.as_bytes());
+
+    // different key
+    assert!(ctx.deserialize(&bytes, b".").is_none());
+    // truncated data
+    let len = bytes.len();
+    assert!(ctx.deserialize(&bytes[..len - 1], SRC.as_bytes()).is_none());
+    // missing native filters
+    assert!(ParseCtx::new(Vec::new())
+        .deserialize(&bytes, SRC.as_bytes())
+        .is_none());
+}
+
+fn natives_ctx(vars: &[&str]) -> ParseCtx {
+    let mut ctx = ParseCtx::new(vars.iter().map(|v| v.to_string()).collect());
+    ctx.insert_natives(jaq_core::core());
+    ctx
+}
+
+#[test]
+fn global_vars() {
+    let src = "[$x, $y]";
+    let f = compile(&mut natives_ctx(&["x", "y"]), src);
+    let bytes = natives_ctx(&["x", "y"]).serialize(&f, src.as_bytes());
+    let g = natives_ctx(&["x", "y"]).deserialize(&bytes, src.as_bytes());
+
+    let inputs = RcIter::new(core::iter::empty());
+    let vars = [Val::Int(1), Val::Int(2)];
+    let out: Vec<_> = g
+        .unwrap()
+        .run((Ctx::new(vars, &inputs), Val::Null))
+        .collect();
+    assert_eq!(out, [Ok(Val::from(json!([1, 2])))]);
+
+    // running the filter with fewer variables would access unbound variables
+    assert!(natives_ctx(&["x"])
+        .deserialize(&bytes, src.as_bytes())
+        .is_none());
+}
+
+#[test]
+fn corrupt() {
+    let src =
+        "def f($x): $x * 10 + 1; [.[] | f(.)], reduce .[] as $x (0; . + $x), label $l | break $l";
+    let ctx = natives_ctx(&["v"]);
+    let f = compile(&mut natives_ctx(&["v"]), src);
+    let bytes = ctx.serialize(&f, src.as_bytes());
+    // deserialising must not panic, whatever byte is changed
+    for i in 0..bytes.len() {
+        let mut bytes = bytes.clone();
+        bytes[i] = bytes[i].wrapping_add(1);
+        ctx.deserialize(&bytes, src.as_bytes());
+    }
+}
@@ -2,7 +2,8 @@
 
 pub mod common;
 
-use common::{give, gives};
+use common::{fail, give, gives};
+use jaq_interpret::{Error, Val};
 use serde_json::json;
 
 #[test]
@@ -25,6 +26,26 @@ fn any() {
     give(json!({"a": false, "b": true}), "any", json!(true));
 }
 
+#[test]
+fn business_days() {
+    // Monday, 2021-01-04
+    let mon = 1609718400;
+    let week = 7 * 86400;
+    give(
+        json!(mon),
+        &format!("business_days(.; . + {week})"),
+        json!(5),
+    );
+    // Wednesday, 2021-01-06 (at noon)
+    let holiday = mon + 2 * 86400 + 43200;
+    let f = format!("business_days(.; . + {week}; [{holiday}])");
+    give(json!(mon), &f, json!(4));
+    // Saturday to Monday
+    let sat = mon - 2 * 86400;
+    give(json!(sat), "business_days(.; . + 2 * 86400)", json!(0));
+    give(json!(mon), "business_days(.; . - 86400)", json!(0));
+}
+
 #[test]
 fn date() {
     // aliases for fromdateiso8601 and todateiso8601
@@ -40,8 +61,20 @@ fn date() {
         "todate",
         json!("1970-01-02T00:00:00.123456789Z"),
     );
+    give(
+        json!(86400),
+        "gmtime | todate",
+        json!("1970-01-02T00:00:00Z"),
+    );
+    give(json!(null), "[try todate catch 0]", json!([0]));
 }
 
+yields!(
+    dateadd_datesub,
+    r#"86400 | [dateadd("seconds"; 60), datesub("seconds"; 60), date]"#,
+    json!([86460, 86340, "1970-01-02T00:00:00Z"])
+);
+
 #[test]
 fn date_roundtrip() {
     let epoch = 946684800;
@@ -66,6 +99,12 @@ yields!(
     [-2.5, 0.0, -0.5, 2.0, -0.5, -0.0]
 );
 
+yields!(
+    delpaths,
+    r#"{"a": [1, 2, 3], "b": 4} | delpaths([["a", 0], ["b"], ["a", 2]])"#,
+    json!({"a": [2]})
+);
+
 #[test]
 fn entries() {
     let obj = json!({"a": 1, "b": 2});
@@ -104,6 +143,39 @@ yields!(flatten_obj, "{a: 1} | flatten", json!([{"a": 1}]));
 // jq gives an error here
 yields!(flatten_num, "0 | flatten", [0]);
 
+#[test]
+fn getpath() {
+    let v = json!({"a": {"b": [1, 2]}});
+    give(v.clone(), r#"getpath(["a", "b", 1])"#, json!(2));
+    give(v.clone(), r#"getpath(["a", "c", "d"])"#, json!(null));
+    give(v.clone(), "getpath([])", v);
+
+    // paths through scalars yield null
+    give(json!(5), r#"getpath(["a", "b"])"#, json!(null));
+    give(json!({"a": 5}), r#"getpath(["a", "b"])"#, json!(null));
+    // keys of the wrong type yield an error
+    give(json!([1]), r#"[try getpath(["a"])]"#, json!([]));
+    give(json!({"a": 5}), "[.a?.b?.c?]", json!([]));
+}
+
+#[test]
+fn halt_error() {
+    let err = Error::Halt(Some(Val::str("bye".to_string())), 5);
+    fail(json!("bye"), "halt_error", err);
+}
+
+#[test]
+fn humanize_bytes() {
+    give(json!(1536), "humanize_bytes", json!("1.5 KiB"));
+    give(json!(1536), "humanize_bytes | parse_bytes", json!(1536));
+    give(json!(1024), "humanize_bytes", json!("1 KiB"));
+    give(json!(100), "humanize_bytes", json!("100 B"));
+    give(json!(-2048), "humanize_bytes", json!("-2 KiB"));
+    let v = json!(3_221_225_473_u64);
+    give(v, "humanize_bytes(3)", json!("3 GiB"));
+    give(json!(1234567), "humanize_bytes(2)", json!("1.18 MiB"));
+}
+
 #[test]
 fn inside() {
     give(
@@ -124,6 +196,29 @@ fn inside() {
     give(json!({"foo": 12, "bar": [{"barp": 15}]}), f, json!(false));
 }
 
+yields!(inside_obj, r#"{"a": 1} | inside({"a": 1, "b": 2})"#, true);
+yields!(
+    inside_obj_not,
+    r#"{"a": 1, "c": 3} | inside({"a": 1, "b": 2})"#,
+    false
+);
+
+yields!(
+    invert,
+    r#"{"a": "x", "b": "y"} | invert"#,
+    json!({"x": "a", "y": "b"})
+);
+yields!(
+    invert_collide,
+    r#"{"a": 1, "b": "1", "c": 2} | invert"#,
+    json!({"1": "b", "2": "c"})
+);
+yields!(
+    invert_err,
+    r#"try ({"a": []} | invert) catch ."#,
+    "cannot use array as object key"
+);
+
 yields!(isfinite_true, "all((0, 1, nan); isfinite)", true);
 yields!(
     isfinite_false,
@@ -147,7 +242,39 @@ yields!(
 // 2 + 1 + 3 + 1 + 4 + 1 + 5
 yields!(join_nums, r#"[2, 3, 4, 5] | join(1)"#, 17);
 
+#[test]
+fn justify() {
+    give(json!("ab"), "ljust(5)", json!("ab   "));
+    give(json!("ab"), "rjust(5)", json!("   ab"));
+    give(json!("ab"), "center(5)", json!("  ab "));
+    give(json!("a"), "center(4)", json!(" a  "));
+    give(
+        json!("äb"),
+        r#"[ljust(4; "-"), rjust(4; "ö"), center(4; "*")]"#,
+        json!(["äb--", "ööäb", "*äb*"]),
+    );
+    // strings at or over width pass through
+    give(
+        json!("abcde"),
+        "[ljust(5), rjust(3), center(-1)]",
+        json!(["abcde", "abcde", "abcde"]),
+    );
+    give(
+        json!("a"),
+        r#"[try ljust(3; "ab") catch 0, try rjust(3; "") catch 0]"#,
+        json!([0, 0]),
+    );
+}
+
 yields!(map, "[1, 2] | map(.+1)", [2, 3]);
+// the argument of `map` captures `$n` from the caller
+yields!(map_capture, "5 as $n | [1, 2] | map(. + $n)", [6, 7]);
+// variables bound inside the callee do not shadow captured variables
+yields!(
+    map_capture_shadow,
+    "def g(f): 0 as $n | map(f + $n); 5 as $n | [1, 2] | g(. + $n)",
+    [6, 7]
+);
 
 yields!(
     keys,
@@ -194,6 +321,27 @@ fn min_max() {
     );
 }
 
+#[test]
+fn nest() {
+    let v = json!([
+        {"country": "AT", "city": "Vienna", "name": "a"},
+        {"country": "FR", "city": "Paris", "name": "b"},
+        {"country": "AT", "city": "Graz", "name": "c"},
+        {"country": "AT", "city": "Vienna", "name": "d"}
+    ]);
+    let out = json!({
+        "AT": {"Graz": [{"name": "c"}], "Vienna": [{"name": "a"}, {"name": "d"}]},
+        "FR": {"Paris": [{"name": "b"}]}
+    });
+    give(v.clone(), r#"nest(["country", "city"])"#, out);
+    give(v, r#"nest([]) | length"#, json!(4));
+    give(
+        json!([{"n": 1}, {}]),
+        r#"nest(["n"])"#,
+        json!({"1": [{}], "null": [{}]}),
+    );
+}
+
 #[test]
 fn nth() {
     let fib = "[0,1] | recurse([.[1], add]) | .[0]";
@@ -203,6 +351,49 @@ fn nth() {
     give(json!(10), &format!("nth(.; {})", fib), json!(55));
 }
 
+#[test]
+fn parse_bytes() {
+    give(json!("1.5 KiB"), "parse_bytes", json!(1536));
+    give(json!("2MB"), "parse_bytes", json!(2000000));
+    give(json!(" 10 "), "parse_bytes", json!(10));
+    give(json!("1.5 B"), "parse_bytes", json!(1.5));
+    give(json!("1 XB"), "[try parse_bytes catch 0]", json!([0]));
+}
+
+#[test]
+fn patch() {
+    let v = json!({"a": 1, "b": [1, 2]});
+    let ops = r#"[
+      {"op": "add", "path": "/c", "value": 3},
+      {"op": "remove", "path": "/a"},
+      {"op": "add", "path": "/b/-", "value": 4},
+      {"op": "add", "path": "/b/0", "value": 0}
+    ]"#;
+    give(
+        v,
+        &format!("patch({ops})"),
+        json!({"b": [0, 1, 2, 4], "c": 3}),
+    );
+
+    let v = json!({"a": {"b": 1}});
+    let ops = r#"[
+      {"op": "replace", "path": "/a/b", "value": 2},
+      {"op": "copy", "from": "/a", "path": "/c~1d"},
+      {"op": "move", "from": "/a/b", "path": "/e~0f"},
+      {"op": "test", "path": "/c~1d/b", "value": 2}
+    ]"#;
+    let out = json!({"a": {}, "c/d": {"b": 2}, "e~f": 2});
+    give(v, &format!("patch({ops})"), out);
+
+    let v = json!({"a": 1});
+    let test = r#"patch([{"op": "test", "path": "/a", "value": 2}])"#;
+    give(v.clone(), &format!("[try {test} catch 0]"), json!([0]));
+    let remove = r#"patch([{"op": "remove", "path": "/b"}])"#;
+    give(v.clone(), &format!("[try {remove} catch 0]"), json!([0]));
+    let add = r#"patch([{"op": "add", "path": "", "value": 2}])"#;
+    give(v, add, json!(2));
+}
+
 yields!(paths_num, "1 | [paths]", json!([]));
 yields!(paths_null, "null | [paths]", json!([]));
 yields!(paths_arr, "[1, 2] | [paths]", [[0], [1]]);
@@ -212,6 +403,22 @@ yields!(
     json!([["a"], ["a", 0], ["a", 1], ["a", 1, 0], ["b"], ["b", "c"]])
 );
 
+#[test]
+fn ptr() {
+    give(json!({"a": {"b": [10, 20]}}), r#"ptr("/a/b/1")"#, json!(20));
+    let v = json!({"a/b": {"c~d": 1, "": 2}});
+    give(v.clone(), r#"ptr("/a~1b/c~0d")"#, json!(1));
+    give(v.clone(), r#"ptr("/a~1b/")"#, json!(2));
+    give(v.clone(), r#"ptr("")"#, v.clone());
+    give(v.clone(), r#"[try ptr("/x") catch 0]"#, json!([0]));
+    give(v, r#"[try ptr("a~1b") catch 0]"#, json!([0]));
+    give(
+        json!([1, 2]),
+        r#"[try ptr("/2"), try ptr("/-") catch 0]"#,
+        json!([0]),
+    );
+}
+
 yields!(range_many, "[range(-1, 1; 0, 2)]", json!([-1, -1, 0, 1, 1]));
 
 #[test]
@@ -295,6 +502,41 @@ yields!(
     json!([[1], 2, 3])
 );
 
+#[test]
+fn relative_time() {
+    let t = 1700000000;
+    give(
+        json!(t),
+        ". as $t | relative_time($t; $t + 7200)",
+        json!("2 hours ago"),
+    );
+    give(
+        json!(t),
+        ". as $t | relative_time($t; $t - 7200)",
+        json!("in 2 hours"),
+    );
+    give(
+        json!(t),
+        ". as $t | relative_time($t; $t + 3 * 86400 + 5)",
+        json!("3 days ago"),
+    );
+    give(
+        json!(t),
+        ". as $t | relative_time($t; $t - 61)",
+        json!("in 1 minute"),
+    );
+    give(
+        json!(t),
+        ". as $t | relative_time($t; $t + 0.5)",
+        json!("0 seconds ago"),
+    );
+    give(
+        json!(t),
+        ". as $t | relative_time($t; $t + 400 * 86400)",
+        json!("1 year ago"),
+    );
+}
+
 #[test]
 fn repeat() {
     let y = json!([0, 1, 0, 1]);
@@ -321,6 +563,30 @@ yields!(
 );
 
 // here we diverge from jq, which returns ["a", "b", "a", "A", "b", "B"]
+yields!(
+    capture_unmatched,
+    r#""b" | capture("(?<x>a)?(?<y>b)")"#,
+    json!({"x": null, "y": "b"})
+);
+
+yields!(
+    match_captures,
+    r#""ab" | match("(a)(?<b>b)") | .captures | map(.name)"#,
+    json!([null, "b"])
+);
+
+yields!(
+    regex_array,
+    r#""aA" | [test(["A"]), test(["a", "x"]), match(["A", "gi"]).offset, capture(["(?<x>.)", null])]"#,
+    json!([true, true, 0, 1, {"x": "a"}])
+);
+
+yields!(
+    scan_global,
+    r#""a1b22" | [scan("[0-9]+")] + [scan("([a-z])([0-9])")]"#,
+    json!(["1", "22", ["a", "1"], ["b", "2"]])
+);
+
 yields!(
     scan,
     r#""abAB" | [scan("a", "b"; "g", "gi")]"#,
@@ -346,8 +612,38 @@ fn select() {
     give(v.clone(), "[.[] | iterables]", iterables);
     give(v.clone(), "[.[] | scalars]", scalars);
     give(v.clone(), "[.[] | values]", values);
+
+    // calls with arguments can be passed as filter arguments
+    let f = "def apply(f; x): x | f; [apply(select(. > 0); -1, 1, 0, 2)]";
+    give(json!(null), f, json!([1, 2]));
+    let f = "def apply(f; x): x | f; [apply(limit(2; .[]); [1, 2, 3])]";
+    give(json!(null), f, json!([1, 2]));
 }
 
+yields!(
+    select_keys,
+    r#"{"a": 1, "b": 2, "c": 3} | select_keys(["c", "a", "d"])"#,
+    json!({"c": 3, "a": 1})
+);
+yields!(select_keys_none, r#"{"a": 1} | select_keys([])"#, json!({}));
+
+yields!(
+    setpath,
+    r#"{"a": [1, 2]} | [setpath(["a", 1]; 3), setpath([]; 0)]"#,
+    json!([{"a": [1, 3]}, 0])
+);
+
+yields!(
+    setpath_missing,
+    r#"{} | [setpath(["a", "b"]; 1), setpath(["c", 2]; 1)]"#,
+    json!([{"a": {"b": 1}}, {"c": [null, null, 1]}])
+);
+yields!(
+    setpath_null,
+    r#"null | [setpath(["a"]; 1), setpath([1, "b"]; 2)]"#,
+    json!([{"a": 1}, [null, {"b": 2}]])
+);
+
 yields!(
     significand_inf,
     "infinite | significand | . == infinite",
@@ -365,6 +661,78 @@ yields!(
     [-1.929, -1.1, -1.0, -1.0, 0.0, 1.31072, 1.0, 1.0, 1.1, 1.929]
 );
 
+yields!(
+    sql_index,
+    r#"INDEX({"id": 1, "v": "a"}, {"id": 2, "v": "b"}, {"id": 1, "v": "c"}; .id)"#,
+    json!({"1": {"id": 1, "v": "c"}, "2": {"id": 2, "v": "b"}})
+);
+yields!(
+    sql_index_arr,
+    r#"["a", "bc", "de"] | INDEX(length)"#,
+    json!({"1": "a", "2": "de"})
+);
+yields!(
+    sql_group_by,
+    "GROUP_BY(range(5); . % 2)",
+    json!([[0, 2, 4], [1, 3]])
+);
+yields!(sql_unique_by, "UNIQUE_BY(range(5); . % 2)", [0, 1]);
+yields!(sql_in, "[2, 5] | map(IN(range(4)))", [true, false]);
+yields!(
+    sql_in_src,
+    "[IN(1, 2; 3, 4), IN(1, 2; 2, 3)]",
+    [false, true]
+);
+yields!(
+    sql_any_all,
+    "[ANY(range(3); . == 2), ALL(range(3); . < 2)]",
+    [true, false]
+);
+
+yields!(split_str, r#""a, b, c" | split(", ")"#, ["a", "b", "c"]);
+yields!(split_str_none, r#""abc" | split("x")"#, ["abc"]);
+yields!(splits, r#"["a1b22c" | splits("[0-9]+")]"#, ["a", "b", "c"]);
+
+#[test]
+fn stream() {
+    let v = json!({"a": [1, {"b": 2}], "c": {}});
+    let events = json!([
+        [["a", 0], 1],
+        [["a", 1, "b"], 2],
+        [["a", 1, "b"]],
+        [["a", 1]],
+        [["c"], {}],
+        [["c"]]
+    ]);
+    give(v.clone(), "[tostream]", events.clone());
+    give(v.clone(), "[fromstream(tostream)]", json!([v]));
+    give(events, "[fromstream(.[])]", json!([v]));
+    give(
+        json!([3, []]),
+        "[.[] | tostream]",
+        json!([[[], 3], [[], []]]),
+    );
+
+    let f = "[1 | truncate_stream([[0], 1], [[1, 0], 2], [[1, 0]], [[1]])]";
+    give(json!(null), f, json!([[[0], 2], [[0]]]));
+    let f = "[fromstream(1 | truncate_stream([[0], 1], [[1, 0], 2], [[1, 0]], [[1]]))]";
+    give(json!(null), f, json!([[2]]));
+}
+
+#[test]
+fn tostring_tojson() {
+    give(json!("a"), "tostring", json!("a"));
+    give(json!("a"), "tostring | tostring", json!("a"));
+    give(json!([1]), "tostring", json!("[1]"));
+    give(json!("a"), "tojson", json!("\"a\""));
+    give(json!([1]), "tojson", json!("[1]"));
+
+    // interpolation uses `tostring` by default and `tojson` for `@json`
+    let f = r#"[. as $x | "\($x)", @text "\($x)", @json "\($x)"]"#;
+    give(json!("a"), f, json!(["a", "a", "\"a\""]));
+    give(json!([1]), f, json!(["[1]", "[1]", "[1]"]));
+}
+
 #[test]
 fn transpose() {
     let y = json!([[1, 2], [3, null]]);
@@ -385,6 +753,13 @@ fn typ() {
     give(json!(null), "type", json!("null"));
 }
 
+// objects are equal regardless of the order of their keys
+yields!(
+    unique_containers,
+    r#"[{"a": 1}, [1, [2]], {"b": 2}, {"a": 1}, [1, [2]], {"c": 1, "d": 2}, {"d": 2, "c": 1}] | unique"#,
+    json!([[1, [2]], {"a": 1}, {"b": 2}, {"c": 1, "d": 2}])
+);
+
 #[test]
 fn walk() {
     give(
@@ -398,8 +773,20 @@ fn walk() {
         r#"walk(if . < {} then . + 1 else . + {"l": length} end)"#,
         json!({"a": {"b": 2, "c": 3, "l": 2}, "l": 1}),
     );
+
+    let inc = r#"walk(if type == "number" then . + 1 else . end)"#;
+    give(json!({"a": {"b": 1}}), inc, json!({"a": {"b": 2}}));
+    give(json!(1), inc, json!(2));
 }
 
+// children are transformed before their parents
+yields!(
+    walk_post_order,
+    "[1, [2]] | walk(if type == \"array\" then add else . * 10 end)",
+    30
+);
+yields!(walk_scalar_once, "[0 | walk(. + 1)]", [1]);
+
 #[test]
 fn while_until() {
     give(
@@ -1,5 +1,5 @@
 use crate::filter::Filter;
-use crate::Spanned;
+use crate::{Span, Spanned};
 use alloc::{string::String, vec::Vec};
 use core::ops::Deref;
 #[cfg(feature = "serde")]
@@ -33,6 +33,8 @@ pub struct Def<Rhs = Main> {
     pub lhs: Call,
     /// right-hand side, i.e. what the LHS should be defined as, e.g. `[.[] | f]`
     pub rhs: Rhs,
+    /// positions of the name and the arguments of the LHS
+    pub lhs_spans: Call<Span, Span>,
 }
 
 /// Argument of a definition, such as `$v` or `f` in `def foo($v; f): ...`.
@@ -130,3 +132,28 @@ pub struct Main<F = Filter> {
     /// Body of the filter, e.g. `[.[] | f`.
     pub body: Spanned<F>,
 }
+
+/// Module directive, such as `import "foo" as foo;` or `include "foo";`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug)]
+pub struct Import {
+    /// Path of the module, e.g. `"lib/foo"`
+    pub path: Spanned<String>,
+    /// Name under which the definitions of the module are accessible, e.g. `foo`
+    ///
+    /// If this is `None`, the definitions are included without prefix.
+    pub alias: Option<String>,
+}
+
+/// (Potentially empty) sequence of module directives, followed by a body.
+///
+/// The body of a main program is a [`Main`],
+/// whereas the body of a library module is a sequence of definitions.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug)]
+pub struct Module<B = Main> {
+    /// Module directives at the top of the module
+    pub imports: Vec<Import>,
+    /// Body of the module
+    pub body: B,
+}
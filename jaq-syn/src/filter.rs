@@ -31,11 +31,15 @@ impl fmt::Display for AssignOp {
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone, Debug)]
 pub enum BinaryOp {
-    /// Application, i.e. `l | r` if no string is given, else `l as $x | r`
-    Pipe(Option<String>),
+    /// Application, i.e. `l | r` if no variable is given, else `l as $x | r`
+    Pipe(Option<Spanned<String>>),
     /// Concatenation, i.e. `l, r`
     Comma,
     /// Alternation, i.e. `l // r`
+    ///
+    /// This yields the outputs of `l` that are neither `false` nor `null`,
+    /// or the outputs of `r` if there are no such outputs.
+    /// Unlike `try l catch r`, errors yielded by `l` are ignored.
     Alt,
     /// Logical disjunction, i.e. `l or r`
     Or,
@@ -80,7 +84,13 @@ pub struct Fold<F> {
     /// Generator
     pub xs: F,
     /// Name of assigned variable
-    pub x: String,
+    pub x: Spanned<String>,
+    /// Alternative destructuring patterns, such as in `reduce .[] as [$x, $y] (...)`
+    ///
+    /// If this is not empty, then every generated value is destructured
+    /// like by [`Filter::Destructure`] instead of being assigned to `x`,
+    /// which is then empty.
+    pub pats: Vec<Pattern<F>>,
     /// Initial values
     pub init: F,
     /// Updater
@@ -99,6 +109,72 @@ pub enum FoldType {
     Foreach,
 }
 
+/// Pattern that destructures a value, such as `$x`, `[$x, $y]`, or `{a: $x, $y}`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug)]
+pub enum Pattern<F> {
+    /// Variable, such as `$x` (without leading '$'), binding the whole value
+    Var(Spanned<String>),
+    /// Array pattern, such as `[$x, $y]`, matching the array elements in order
+    Arr(Vec<Self>),
+    /// Object pattern, such as `{a: $x, $y}`, matching the values of given keys
+    Obj(Vec<KeyPattern<F>>),
+}
+
+/// An element of an object pattern.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug)]
+pub enum KeyPattern<F> {
+    /// Variable with optional pattern, e.g. `$x` or `$x: [$y]`
+    ///
+    /// This binds the value of the key `"x"` to `$x`,
+    /// and additionally matches it against the pattern if one is given.
+    Var(Spanned<String>, Option<Pattern<F>>),
+    /// Key with pattern for its value, e.g. `a: $x`, `"a": $x`, or `(f): $x`
+    Filter(F, Pattern<F>),
+}
+
+impl<F> Pattern<F> {
+    /// Apply a function to the filters in the pattern, such as the `f` in `{(f): $x}`.
+    pub fn map<G>(self, f: &mut impl FnMut(F) -> G) -> Pattern<G> {
+        match self {
+            Self::Var(x) => Pattern::Var(x),
+            Self::Arr(ps) => Pattern::Arr(ps.into_iter().map(|p| p.map(f)).collect()),
+            Self::Obj(kps) => Pattern::Obj(kps.into_iter().map(|kp| kp.map(f)).collect()),
+        }
+    }
+
+    /// Return the variables bound by the pattern, in order of their appearance.
+    pub fn vars(&self) -> Vec<&Spanned<String>> {
+        let mut vars = Vec::new();
+        self.collect_vars(&mut vars);
+        vars
+    }
+
+    fn collect_vars<'a>(&'a self, vars: &mut Vec<&'a Spanned<String>>) {
+        match self {
+            Self::Var(x) => vars.push(x),
+            Self::Arr(ps) => ps.iter().for_each(|p| p.collect_vars(vars)),
+            Self::Obj(kps) => kps.iter().for_each(|kp| match kp {
+                KeyPattern::Var(x, p) => {
+                    vars.push(x);
+                    p.iter().for_each(|p| p.collect_vars(vars))
+                }
+                KeyPattern::Filter(_, p) => p.collect_vars(vars),
+            }),
+        }
+    }
+}
+
+impl<F> KeyPattern<F> {
+    fn map<G>(self, f: &mut impl FnMut(F) -> G) -> KeyPattern<G> {
+        match self {
+            Self::Var(x, p) => KeyPattern::Var(x, p.map(|p| p.map(f))),
+            Self::Filter(k, p) => KeyPattern::Filter(f(k), p.map(f)),
+        }
+    }
+}
+
 /// Function from value to stream of values, such as `.[] | add / length`.
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone, Debug)]
@@ -131,6 +207,25 @@ pub enum Filter<C = String, V = String, Num = String> {
     /// The first field indicates whether to yield intermediate results
     /// (`false` for `reduce` and `true` for `foreach`).
     Fold(FoldType, Fold<Box<Spanned<Self>>>),
+    /// Destructuring, e.g. `l as [$x, $y] | r` or `l as [$x] ?// $x | r`
+    ///
+    /// The patterns are tried in order: if binding a pattern or
+    /// running `r` with it fails, the next pattern is tried,
+    /// and errors are only yielded for the last pattern.
+    /// A variable bound only by other patterns than the current one is `null`.
+    /// Binding a single variable, such as in `l as $x | r`,
+    /// is represented by [`BinaryOp::Pipe`] instead.
+    Destructure(
+        Box<Spanned<Self>>,
+        Vec<Pattern<Spanned<Self>>>,
+        Box<Spanned<Self>>,
+    ),
+    /// Label, i.e. `label $x | f`
+    ///
+    /// This yields the outputs of `f` until `break $x` is executed in `f`.
+    Label(String, Box<Spanned<Self>>),
+    /// Break out of the innermost label of the given name, i.e. `break $x`
+    Break(V),
     /// `try` and optional `catch`
     TryCatch(Box<Spanned<Self>>, Option<Box<Spanned<Self>>>),
     /// Error suppression, e.g. `keys?`
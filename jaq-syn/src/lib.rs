@@ -7,14 +7,17 @@ extern crate alloc;
 
 mod def;
 pub mod filter;
+pub mod lint;
 mod ops;
 pub mod path;
+mod pretty;
 pub mod string;
 pub mod test;
 
-pub use def::{Arg, Call, Def, Main};
+pub use def::{Arg, Call, Def, Import, Main, Module};
 pub use ops::{MathOp, OrdOp};
 use path::Path;
+pub use pretty::Comment;
 pub use string::Str;
 
 /// Position information.
@@ -0,0 +1,318 @@
+//! Static analysis of filters to find likely mistakes.
+//!
+//! The analysis considers only the given filter and its definitions.
+//! Calls that do not refer to these definitions are assumed to
+//! refer to the standard library or to imported modules.
+
+use crate::filter::{BinaryOp, Filter, Fold, KeyPattern, KeyVal, Pattern};
+use crate::path::Part;
+use crate::string::{self, Str};
+use crate::{Arg, Main, Span, Spanned};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::fmt;
+
+/// Likely mistake in a filter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Lint {
+    /// variable or filter that is bound but never used, the latter given by its name and arity
+    Unused(Arg<String, (String, usize)>),
+    /// condition of an `if` that is always true or always false,
+    /// so that one of the branches is never taken
+    Const(bool),
+    /// path applied to a literal of the given type that can never be accessed by the path,
+    /// such as `1[0]` or `"abc".a`
+    Index(&'static str),
+    /// call to a deprecated filter, with its replacement
+    Deprecated(String, &'static str),
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unused(Arg::Var(x)) => write!(f, "unused variable ${x}"),
+            Self::Unused(Arg::Fun((name, arity))) => write!(f, "unused function {name}/{arity}"),
+            Self::Const(b) => write!(f, "condition is always {b}, so a branch is never taken"),
+            Self::Index(typ) => write!(f, "a {typ} can never be accessed by this path"),
+            Self::Deprecated(name, new) => write!(f, "{name} is deprecated, use {new} instead"),
+        }
+    }
+}
+
+/// Deprecated filters without arguments, together with their replacements.
+const DEPRECATED: [(&str, &str); 2] = [
+    ("leaf_paths", "paths(scalars)"),
+    ("recurse_down", "recurse"),
+];
+
+/// Return likely mistakes in a filter, ordered by their position.
+///
+/// Variables and definitions whose names start with `_` are never reported as unused.
+/// A definition that is only called by itself is reported as unused.
+pub fn lint(main: &Main) -> Vec<Spanned<Lint>> {
+    let mut ctx = Ctx::default();
+    ctx.main(main);
+    let mut lints = ctx.lints;
+    lints.sort_by_key(|(_, span)| (span.start, span.end));
+    lints
+}
+
+/// Variable or filter that is in scope.
+struct Binding<'a> {
+    name: &'a str,
+    /// `None` for variables, else the arity of the filter
+    arity: Option<usize>,
+    /// position reported if the binding is unused
+    span: Span,
+    used: bool,
+    /// is the binding a definition whose body is currently analysed?
+    defining: bool,
+}
+
+#[derive(Default)]
+struct Ctx<'a> {
+    bound: Vec<Binding<'a>>,
+    lints: Vec<Spanned<Lint>>,
+}
+
+impl<'a> Ctx<'a> {
+    fn bind(&mut self, name: &'a str, arity: Option<usize>, span: Span) {
+        self.bound.push(Binding {
+            name,
+            arity,
+            span,
+            used: false,
+            defining: false,
+        })
+    }
+
+    /// Remove the most recent binding, reporting it if it was not used.
+    fn unbind(&mut self) {
+        let b = self.bound.pop().unwrap();
+        if !b.used && !b.name.starts_with('_') {
+            let name = b.name.to_string();
+            let unused = match b.arity {
+                None => Arg::Var(name),
+                Some(arity) => Arg::Fun((name, arity)),
+            };
+            self.lints.push((Lint::Unused(unused), b.span));
+        }
+    }
+
+    /// Return the position of the innermost binding of a variable or filter, if it is bound.
+    fn resolve(&self, name: &str, arity: Option<usize>) -> Option<usize> {
+        (self.bound.iter()).rposition(|b| b.name == name && b.arity == arity)
+    }
+
+    /// Mark the innermost binding of a variable or filter as used, if it is bound.
+    fn use_(&mut self, name: &str, arity: Option<usize>) -> bool {
+        let i = self.resolve(name, arity);
+        if let Some(b) = i.map(|i| &mut self.bound[i]) {
+            // recursive calls do not count as uses
+            b.used |= !b.defining;
+        }
+        i.is_some()
+    }
+
+    fn main(&mut self, main: &'a Main) {
+        for def in &main.defs {
+            let spans = &def.lhs_spans;
+            self.bind(&def.lhs.name, Some(def.lhs.args.len()), spans.name.clone());
+            let i = self.bound.len() - 1;
+            self.bound[i].defining = true;
+            for (arg, span) in def.lhs.args.iter().zip(&spans.args) {
+                match arg {
+                    Arg::Var(x) => self.bind(x, None, span.clone()),
+                    Arg::Fun(f) => self.bind(f, Some(0), span.clone()),
+                }
+            }
+            self.main(&def.rhs);
+            def.lhs.args.iter().for_each(|_| self.unbind());
+            self.bound[i].defining = false;
+        }
+        self.filter(&main.body);
+        main.defs.iter().for_each(|_| self.unbind());
+    }
+
+    /// Return the type of a literal, if the filter is one.
+    fn literal(&self, f: &Filter) -> Option<&'static str> {
+        match f {
+            Filter::Num(_) => Some("number"),
+            Filter::Str(_) => Some("string"),
+            Filter::Call(name, args)
+                if args.is_empty() && self.resolve(name, Some(0)).is_none() =>
+            {
+                matches!(name.as_str(), "true" | "false").then_some("boolean")
+            }
+            _ => None,
+        }
+    }
+
+    /// Return the boolean value of a filter if it always yields a single value with that value.
+    fn truth(&self, f: &Filter) -> Option<bool> {
+        match f {
+            Filter::Num(_) | Filter::Array(_) => Some(true),
+            Filter::Str(s) => s
+                .parts
+                .iter()
+                .all(|p| matches!(p, string::Part::Str(_)))
+                .then_some(true),
+            Filter::Call(name, args)
+                if args.is_empty() && self.resolve(name, Some(0)).is_none() =>
+            {
+                match name.as_str() {
+                    "true" => Some(true),
+                    "false" | "null" => Some(false),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn str(&mut self, s: &'a Str<Spanned<Filter>>) {
+        s.fmt.iter().for_each(|fmt| self.filter(fmt));
+        for part in &s.parts {
+            if let string::Part::Fun(f) = part {
+                self.filter(f)
+            }
+        }
+    }
+
+    fn pattern<F: Borrow<Spanned<Filter>>>(&mut self, p: &'a Pattern<F>) {
+        match p {
+            Pattern::Var(_) => (),
+            Pattern::Arr(ps) => ps.iter().for_each(|p| self.pattern(p)),
+            Pattern::Obj(kps) => kps.iter().for_each(|kp| match kp {
+                KeyPattern::Var(_, p) => p.iter().for_each(|p| self.pattern(p)),
+                KeyPattern::Filter(k, p) => {
+                    self.filter(k.borrow());
+                    self.pattern(p)
+                }
+            }),
+        }
+    }
+
+    /// Analyse alternative patterns and bind the variables bound by any of them.
+    ///
+    /// A variable is located at its first occurrence.
+    /// Return the number of bound variables.
+    fn patterns<F: Borrow<Spanned<Filter>>>(&mut self, ps: &'a [Pattern<F>]) -> usize {
+        let mut vars: Vec<&Spanned<String>> = Vec::new();
+        for p in ps {
+            self.pattern(p);
+            for x in p.vars() {
+                if !vars.iter().any(|v| v.0 == x.0) {
+                    vars.push(x)
+                }
+            }
+        }
+        vars.iter()
+            .for_each(|(x, span)| self.bind(x, None, span.clone()));
+        vars.len()
+    }
+
+    fn filter(&mut self, (f, span): &'a Spanned<Filter>) {
+        match f {
+            Filter::Call(name, args) => {
+                args.iter().for_each(|arg| self.filter(arg));
+                if !self.use_(name, Some(args.len())) && args.is_empty() {
+                    if let Some((_, new)) = DEPRECATED.iter().find(|(old, _)| old == name) {
+                        self.lints
+                            .push((Lint::Deprecated(name.clone(), new), span.clone()));
+                    }
+                }
+            }
+            Filter::Var(x) => {
+                self.use_(x, None);
+            }
+            Filter::Num(_) | Filter::Id | Filter::Recurse | Filter::Break(_) => (),
+            Filter::Str(s) => self.str(s),
+            Filter::Array(f) => f.iter().for_each(|f| self.filter(f)),
+            Filter::Object(kvs) => kvs.iter().for_each(|kv| match kv {
+                KeyVal::Filter(k, v) => {
+                    self.filter(k);
+                    self.filter(v)
+                }
+                KeyVal::Str(k, v) => {
+                    self.str(k);
+                    v.iter().for_each(|v| self.filter(v))
+                }
+            }),
+            Filter::Path(f, path) => {
+                self.filter(f);
+                if let Some(typ) = self.literal(&f.0) {
+                    // strings can be sliced, but not indexed or iterated
+                    let fails = |part: &Part<_>| match part {
+                        Part::Range(Some(_), _) | Part::Range(_, Some(_)) => typ != "string",
+                        Part::Range(None, None) | Part::Index(_) => true,
+                    };
+                    if matches!(path.first(), Some((part, _)) if fails(part)) {
+                        self.lints.push((Lint::Index(typ), span.clone()));
+                    }
+                }
+                for (part, _) in path {
+                    match part {
+                        Part::Index(i) => self.filter(i),
+                        Part::Range(from, upto) => {
+                            from.iter().chain(upto).for_each(|i| self.filter(i))
+                        }
+                    }
+                }
+            }
+            Filter::Ite(branches, else_) => {
+                for (cond, then) in branches {
+                    self.filter(cond);
+                    if let Some(b) = self.truth(&cond.0) {
+                        self.lints.push((Lint::Const(b), cond.1.clone()));
+                    }
+                    self.filter(then);
+                }
+                else_.iter().for_each(|f| self.filter(f));
+            }
+            Filter::Fold(
+                _,
+                Fold {
+                    xs,
+                    x,
+                    pats,
+                    init,
+                    f,
+                },
+            ) => {
+                self.filter(xs);
+                self.filter(init);
+                let vars = if pats.is_empty() {
+                    self.bind(&x.0, None, x.1.clone());
+                    1
+                } else {
+                    self.patterns(pats)
+                };
+                self.filter(f);
+                (0..vars).for_each(|_| self.unbind());
+            }
+            Filter::Destructure(l, patterns, r) => {
+                self.filter(l);
+                let vars = self.patterns(patterns);
+                self.filter(r);
+                (0..vars).for_each(|_| self.unbind());
+            }
+            Filter::Binary(l, BinaryOp::Pipe(Some(x)), r) => {
+                self.filter(l);
+                self.bind(&x.0, None, x.1.clone());
+                self.filter(r);
+                self.unbind();
+            }
+            Filter::Binary(l, _, r) => {
+                self.filter(l);
+                self.filter(r)
+            }
+            Filter::Label(_, f) | Filter::Try(f) | Filter::Neg(f) => self.filter(f),
+            Filter::TryCatch(f, c) => {
+                self.filter(f);
+                c.iter().for_each(|c| self.filter(c))
+            }
+        }
+    }
+}
@@ -0,0 +1,483 @@
+//! Canonical formatting of filters.
+//!
+//! Filters are printed with a single space around binary operators and
+//! with parentheses only where they are required to preserve the meaning.
+//! Every definition starts on a new line, and pipelines that
+//! do not fit into a line are broken before every `|`.
+//! Comments are printed on their own lines before
+//! the definition or filter body that contains them,
+//! except for comments after definitions on the same line.
+
+use crate::filter::{BinaryOp, Filter, FoldType, KeyPattern, KeyVal, Pattern};
+use crate::path::{Opt, Part, Path};
+use crate::string::{self, Str};
+use crate::{Arg, Def, Import, Main, MathOp, Module, OrdOp, Span, Spanned};
+use alloc::string::{String, ToString};
+use alloc::{format, vec::Vec};
+use core::{borrow::Borrow, fmt, iter::Peekable, slice};
+
+/// Maximal width of a line, after which pipelines are broken.
+const WIDTH: usize = 80;
+
+/// Indentation per nesting level.
+const INDENT: &str = "  ";
+
+/// Precedence of `try ... catch ...` and negation.
+const TRY_NEG: usize = 100;
+/// Precedence of postfix `?`, `reduce`, `foreach`, `if`, and paths.
+const NAMED: usize = 101;
+/// Precedence of filters that are never ambiguous.
+const ATOM: usize = 102;
+
+impl fmt::Display for Main {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = String::new();
+        main(self, 0, &mut Comments::new(&[], &[]), &mut out);
+        out.fmt(f)
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.format(&[], &[]).fmt(f)
+    }
+}
+
+impl fmt::Display for Def {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = String::new();
+        def(self, 0, &mut Comments::new(&[], &[]), &mut out);
+        out.fmt(f)
+    }
+}
+
+/// Comment in the source of a filter.
+#[derive(Clone, Debug)]
+pub struct Comment {
+    /// text of the comment, including the leading `#`
+    pub text: String,
+    /// position of the comment in the source
+    pub span: Span,
+    /// is the comment preceded by code on the same line?
+    pub trailing: bool,
+}
+
+impl Module {
+    /// Print a module together with the comments from its source.
+    ///
+    /// To place the comments, this takes the positions of
+    /// all `def` keywords in the source, besides the comments.
+    /// Both must be ordered by their positions.
+    pub fn format(&self, comments: &[Comment], defs: &[Span]) -> String {
+        let mut out = String::new();
+        let cs = &mut Comments::new(comments, defs);
+        for Import { path, alias } in &self.imports {
+            cs.before(path.1.start, 0, &mut out);
+            let path = str_(&Str::from(path.0.clone()));
+            match alias {
+                Some(alias) => out.push_str(&format!("import {path} as {alias};\n")),
+                None => out.push_str(&format!("include {path};\n")),
+            }
+        }
+        main(&self.body, 0, cs, &mut out);
+        for c in cs.comments.by_ref() {
+            out.push(if c.trailing { ' ' } else { '\n' });
+            out.push_str(&c.text);
+        }
+        out
+    }
+}
+
+/// Comments that remain to be printed.
+struct Comments<'a> {
+    comments: Peekable<slice::Iter<'a, Comment>>,
+    /// positions of the `def` keywords of the definitions that remain to be printed
+    defs: Peekable<slice::Iter<'a, Span>>,
+}
+
+impl<'a> Comments<'a> {
+    fn new(comments: &'a [Comment], defs: &'a [Span]) -> Self {
+        let comments = comments.iter().peekable();
+        let defs = defs.iter().peekable();
+        Self { comments, defs }
+    }
+
+    /// Print all remaining comments that start before the given position, each on its own line.
+    fn before(&mut self, pos: usize, level: usize, out: &mut String) {
+        while let Some(c) = self.comments.next_if(|c| c.span.start < pos) {
+            out.push_str(&c.text);
+            out.push('\n');
+            indent(level, out);
+        }
+    }
+
+    /// Print the next comment on the current line if it is
+    /// a trailing comment that starts before the given position.
+    fn trailing(&mut self, pos: usize, out: &mut String) {
+        if let Some(c) = self.comments.next_if(|c| c.trailing && c.span.start < pos) {
+            out.push(' ');
+            out.push_str(&c.text);
+        }
+    }
+}
+
+fn indent(level: usize, out: &mut String) {
+    (0..level).for_each(|_| out.push_str(INDENT))
+}
+
+fn main(m: &Main, level: usize, cs: &mut Comments, out: &mut String) {
+    for (i, d) in m.defs.iter().enumerate() {
+        def(d, level, cs, out);
+        let next = match cs.defs.peek() {
+            Some(span) if i + 1 < m.defs.len() => span.start,
+            _ => m.body.1.start,
+        };
+        cs.trailing(next, out);
+        out.push('\n');
+        indent(level, out);
+    }
+    cs.before(m.body.1.end, level, out);
+    body(&m.body.0, level, out)
+}
+
+fn def(d: &Def, level: usize, cs: &mut Comments, out: &mut String) {
+    if let Some(span) = cs.defs.next() {
+        cs.before(span.start, level, out);
+    }
+    // a body without definitions may be printed on the same line as `def`,
+    // so its comments are printed before `def`
+    if d.rhs.defs.is_empty() {
+        cs.before(d.rhs.body.1.end, level, out);
+    }
+    out.push_str("def ");
+    out.push_str(&d.lhs.name);
+    let args = d.lhs.args.iter().map(|a| match a {
+        Arg::Var(v) => format!("${v}"),
+        Arg::Fun(f) => f.clone(),
+    });
+    out.push_str(&args_str(args.collect()));
+    out.push(':');
+
+    let one_line = filter(&d.rhs.body.0);
+    let width = out.lines().last().map_or(0, str::len);
+    // account for the space before and the semicolon after the body
+    if d.rhs.defs.is_empty() && width + one_line.len() + 2 <= WIDTH {
+        out.push(' ');
+        out.push_str(&one_line);
+    } else {
+        out.push('\n');
+        indent(level + 1, out);
+        main(&d.rhs, level + 1, cs, out);
+    }
+    out.push(';');
+}
+
+/// Print `(a; b)`, or nothing if there are no arguments.
+fn args_str(args: Vec<String>) -> String {
+    if args.is_empty() {
+        String::new()
+    } else {
+        format!("({})", args.join("; "))
+    }
+}
+
+/// Print the body of a definition or of a Main filter.
+///
+/// This breaks pipelines that do not fit into a line.
+fn body(f: &Filter, level: usize, out: &mut String) {
+    let one_line = filter(f);
+    if level * INDENT.len() + one_line.len() <= WIDTH {
+        return out.push_str(&one_line);
+    }
+    let mut f = f;
+    while let Filter::Binary(l, BinaryOp::Pipe(x), r) = f {
+        out.push_str(&operand(&l.0, |p| p == 0));
+        if let Some(x) = x {
+            out.push_str(" as $");
+            out.push_str(&x.0);
+        }
+        out.push('\n');
+        indent(level, out);
+        out.push_str("| ");
+        f = &r.0;
+    }
+    out.push_str(&filter(f))
+}
+
+/// Return the precedence of a filter, following the parser.
+fn prec(f: &Filter) -> usize {
+    match f {
+        Filter::Destructure(..) | Filter::Label(..) => 0,
+        Filter::Binary(_, op, _) => match op {
+            BinaryOp::Pipe(_) => 0,
+            BinaryOp::Comma => 1,
+            BinaryOp::Assign(_) => 2,
+            BinaryOp::Alt => 3,
+            BinaryOp::Or => 4,
+            BinaryOp::And => 5,
+            BinaryOp::Ord(OrdOp::Eq | OrdOp::Ne) => 6,
+            BinaryOp::Ord(_) => 7,
+            BinaryOp::Math(MathOp::Add | MathOp::Sub) => 8,
+            BinaryOp::Math(MathOp::Mul | MathOp::Div) => 9,
+            BinaryOp::Math(MathOp::Rem) => 10,
+        },
+        Filter::TryCatch(..) | Filter::Neg(_) => TRY_NEG,
+        Filter::Try(_) | Filter::Fold(..) | Filter::Ite(..) | Filter::Path(..) => NAMED,
+        _ => ATOM,
+    }
+}
+
+/// Print a filter, surrounding it with parentheses if `parens` holds for its precedence.
+fn operand(f: &Filter, parens: impl FnOnce(usize) -> bool) -> String {
+    let s = filter(f);
+    if parens(prec(f)) {
+        format!("({s})")
+    } else {
+        s
+    }
+}
+
+/// Print a filter on a single line.
+fn filter(f: &Filter) -> String {
+    match f {
+        Filter::Call(name, args) => {
+            let args = args.iter().map(|(a, _)| filter(a)).collect();
+            format!("{name}{}", args_str(args))
+        }
+        Filter::Var(v) => format!("${v}"),
+        Filter::Num(n) => n.clone(),
+        Filter::Str(s) => str_(s),
+        Filter::Array(None) => "[]".to_string(),
+        Filter::Array(Some(a)) => format!("[{}]", filter(&a.0)),
+        Filter::Object(kvs) if kvs.is_empty() => "{}".to_string(),
+        Filter::Object(kvs) => {
+            let kvs: Vec<_> = kvs.iter().map(key_val).collect();
+            format!("{{{}}}", kvs.join(", "))
+        }
+        Filter::Id => ".".to_string(),
+        Filter::Recurse => "..".to_string(),
+        Filter::Path(f, path) => {
+            let (base, path) = match &f.0 {
+                Filter::Id => (".".to_string(), id_path(path)),
+                Filter::Num(_) | Filter::Recurse => (format!("({})", filter(&f.0)), path_str(path)),
+                f => (operand(f, |p| p < ATOM), path_str(path)),
+            };
+            base + &path
+        }
+        Filter::Ite(if_thens, else_) => {
+            let mut s = String::new();
+            for (i, (if_, then)) in if_thens.iter().enumerate() {
+                s.push_str(if i == 0 { "if " } else { " elif " });
+                s.push_str(&filter(&if_.0));
+                s.push_str(" then ");
+                s.push_str(&filter(&then.0));
+            }
+            if let Some(else_) = else_ {
+                s.push_str(" else ");
+                s.push_str(&filter(&else_.0));
+            }
+            s + " end"
+        }
+        Filter::Fold(typ, fold) => {
+            let typ = match typ {
+                FoldType::Reduce => "reduce",
+                FoldType::For => "for",
+                FoldType::Foreach => "foreach",
+            };
+            let xs = operand(&fold.xs.0, |p| p <= TRY_NEG);
+            let (init, f) = (filter(&fold.init.0), filter(&fold.f.0));
+            let x = if fold.pats.is_empty() {
+                format!("${}", fold.x.0)
+            } else {
+                let ps: Vec<_> = fold.pats.iter().map(pattern).collect();
+                ps.join(" ?// ")
+            };
+            format!("{typ} {xs} as {x} ({init}; {f})")
+        }
+        Filter::Destructure(l, ps, r) => {
+            let ps: Vec<_> = ps.iter().map(pattern).collect();
+            let l = operand(&l.0, |p| p == 0);
+            format!("{l} as {} | {}", ps.join(" ?// "), filter(&r.0))
+        }
+        Filter::Label(x, f) => format!("label ${x} | {}", filter(&f.0)),
+        Filter::Break(x) => format!("break ${x}"),
+        Filter::TryCatch(try_, catch_) => {
+            // parenthesise nested `try`, so that `catch` is attributed correctly
+            let body =
+                |f: &Filter| operand(f, |p| p < TRY_NEG || matches!(f, Filter::TryCatch(..)));
+            let mut s = format!("try {}", body(&try_.0));
+            if let Some(catch_) = catch_ {
+                s.push_str(" catch ");
+                s.push_str(&body(&catch_.0));
+            }
+            s
+        }
+        Filter::Try(f) => {
+            // a `?` directly after a path would make the path optional instead
+            let parens = |p| p < NAMED || matches!(f.0, Filter::Try(_) | Filter::Path(..));
+            operand(&f.0, parens) + "?"
+        }
+        Filter::Neg(f) => {
+            let parens = |p| p < NAMED || matches!(f.0, Filter::Neg(_));
+            format!("-{}", operand(&f.0, parens))
+        }
+        Filter::Binary(l, op, r) => {
+            let p = prec(f);
+            let right = matches!(op, BinaryOp::Pipe(_) | BinaryOp::Assign(_));
+            let l = operand(&l.0, |lp| if right { lp <= p } else { lp < p });
+            let r = operand(&r.0, |rp| if right { rp < p } else { rp <= p });
+            let op = match op {
+                BinaryOp::Pipe(None) => " | ".to_string(),
+                BinaryOp::Pipe(Some((x, _))) => format!(" as ${x} | "),
+                BinaryOp::Comma => ", ".to_string(),
+                BinaryOp::Alt => " // ".to_string(),
+                BinaryOp::Or => " or ".to_string(),
+                BinaryOp::And => " and ".to_string(),
+                BinaryOp::Math(op) => format!(" {op} "),
+                BinaryOp::Assign(op) => format!(" {op} "),
+                BinaryOp::Ord(op) => format!(" {op} "),
+            };
+            l + &op + &r
+        }
+    }
+}
+
+fn pattern<F: Borrow<Spanned<Filter>>>(p: &Pattern<F>) -> String {
+    match p {
+        Pattern::Var((x, _)) => format!("${x}"),
+        Pattern::Arr(ps) => {
+            let ps: Vec<_> = ps.iter().map(pattern).collect();
+            format!("[{}]", ps.join(", "))
+        }
+        Pattern::Obj(kps) => {
+            let kps: Vec<_> = kps.iter().map(key_pattern).collect();
+            format!("{{{}}}", kps.join(", "))
+        }
+    }
+}
+
+fn key_pattern<F: Borrow<Spanned<Filter>>>(kp: &KeyPattern<F>) -> String {
+    match kp {
+        KeyPattern::Var((x, _), None) => format!("${x}"),
+        KeyPattern::Var((x, _), Some(p)) => format!("${x}: {}", pattern(p)),
+        KeyPattern::Filter(k, p) => match &k.borrow().0 {
+            Filter::Str(k) => {
+                let k = ident(k).map_or_else(|| str_(k), |k| k.to_string());
+                format!("{k}: {}", pattern(p))
+            }
+            k => format!("({}): {}", filter(k), pattern(p)),
+        },
+    }
+}
+
+fn key_val(kv: &KeyVal<Spanned<Filter>>) -> String {
+    // object values must not contain a comma on the top level
+    let val = |v: &Spanned<Filter>| operand(&v.0, |p| p <= 1);
+    match kv {
+        KeyVal::Filter(k, v) => format!("({}): {}", filter(&k.0), val(v)),
+        KeyVal::Str(k, v) => {
+            let k = ident(k).map_or_else(|| str_(k), |k| k.to_string());
+            match v {
+                Some(v) => format!("{k}: {}", val(v)),
+                None => k,
+            }
+        }
+    }
+}
+
+/// If a string is a constant that can be written as an identifier, return it.
+fn ident<T>(s: &Str<T>) -> Option<&str> {
+    const KEYWORDS: [&str; 16] = [
+        "def", "if", "then", "elif", "else", "end", "or", "and", "as", "reduce", "for", "foreach",
+        "try", "catch", "import", "include",
+    ];
+    match (&s.fmt, &s.parts[..]) {
+        (None, [string::Part::Str(s)]) => {
+            let mut chars = s.chars();
+            let head = chars
+                .next()
+                .filter(|c| c.is_ascii_alphabetic() || *c == '_');
+            let ident = head.is_some() && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+            (ident && !KEYWORDS.contains(&s.as_str())).then_some(s)
+        }
+        _ => None,
+    }
+}
+
+fn str_(s: &Str<Spanned<Filter>>) -> String {
+    let mut out = String::new();
+    if let Some(fmt) = &s.fmt {
+        out.push_str(&filter(&fmt.0));
+        out.push(' ');
+    }
+    out.push('"');
+    for part in &s.parts {
+        match part {
+            string::Part::Str(s) => s.chars().for_each(|c| escape(c, &mut out)),
+            string::Part::Fun(f) => out.push_str(&format!("\\({})", filter(&f.0))),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn escape(c: char, out: &mut String) {
+    match c {
+        '"' => out.push_str("\\\""),
+        '\\' => out.push_str("\\\\"),
+        '\n' => out.push_str("\\n"),
+        '\t' => out.push_str("\\t"),
+        '\r' => out.push_str("\\r"),
+        '\u{08}' => out.push_str("\\b"),
+        '\u{0c}' => out.push_str("\\f"),
+        c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+        c => out.push(c),
+    }
+}
+
+/// Print a path after the identity filter, such as `.a[0]` or `.[]?`.
+fn id_path(path: &Path<Filter>) -> String {
+    let mut parts = path.iter();
+    let first = parts.next().map_or_else(String::new, |(part, opt)| {
+        let s = match part {
+            Part::Index((Filter::Str(s), _)) if s.fmt.is_none() => key(s),
+            part => range(part),
+        };
+        s + opt_str(opt)
+    });
+    parts.fold(first, |acc, p| acc + &path_part(p))
+}
+
+/// Print a path after a filter other than the identity.
+fn path_str(path: &Path<Filter>) -> String {
+    path.iter().map(path_part).collect()
+}
+
+fn path_part((part, opt): &(Part<Spanned<Filter>>, Opt)) -> String {
+    let s = match part {
+        Part::Index((Filter::Str(s), _)) if s.fmt.is_none() => format!(".{}", key(s)),
+        part => range(part),
+    };
+    s + opt_str(opt)
+}
+
+/// Print a key without a leading dot, such as `a` or `"a b"`.
+fn key(s: &Str<Spanned<Filter>>) -> String {
+    ident(s).map_or_else(|| str_(s), |s| s.to_string())
+}
+
+fn range(part: &Part<Spanned<Filter>>) -> String {
+    let f = |f: &Option<Spanned<Filter>>| f.as_ref().map_or_else(String::new, |f| filter(&f.0));
+    match part {
+        Part::Index(i) => format!("[{}]", filter(&i.0)),
+        Part::Range(None, None) => "[]".to_string(),
+        Part::Range(from, upto) => format!("[{}:{}]", f(from), f(upto)),
+    }
+}
+
+fn opt_str(opt: &Opt) -> &'static str {
+    match opt {
+        Opt::Optional => "?",
+        Opt::Essential => "",
+    }
+}
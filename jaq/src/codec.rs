@@ -0,0 +1,243 @@
+//! Reading and writing values in formats other than JSON.
+
+use jaq_interpret::Val;
+use std::io::{self, Write};
+
+/// Format in which values can be read and written.
+///
+/// To support a new format, add a variant here,
+/// implement its decoding and encoding below, and
+/// add corresponding command-line flags.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Yaml,
+    Cbor,
+    MsgPack,
+    Toml,
+}
+
+impl Codec {
+    /// Decode the values in the given data on demand, stopping after the first error.
+    pub fn decode<'a>(
+        self,
+        data: impl AsRef<[u8]> + 'a,
+    ) -> Box<dyn Iterator<Item = io::Result<Val>> + 'a> {
+        match self {
+            Self::Yaml => match std::str::from_utf8(data.as_ref()) {
+                Ok(_) => Box::new(yaml_decode(data)),
+                Err(e) => Box::new(core::iter::once(Err(invalid_data(e)))),
+            },
+            Self::Cbor => Box::new(binary(data, jaq_core::cbor::decode_from)),
+            Self::MsgPack => Box::new(binary(data, jaq_core::msgpack::decode_from)),
+            Self::Toml => Box::new(core::iter::once(toml_decode(data.as_ref()))),
+        }
+    }
+
+    /// Write a value, where `first` indicates whether no value has been written yet.
+    pub fn encode(self, v: Val, first: bool, w: &mut impl Write) -> io::Result<()> {
+        let mut out = Vec::new();
+        match self {
+            Self::Yaml => {
+                if !first {
+                    writeln!(w, "---")?;
+                }
+                let yaml = serde_yaml::to_string(&val_to_yaml(v)?).map_err(invalid_data)?;
+                return write!(w, "{yaml}");
+            }
+            Self::Toml => {
+                if !first {
+                    writeln!(w)?;
+                }
+                let toml = toml::to_string(&val_to_toml(v)?).map_err(invalid_data)?;
+                return write!(w, "{toml}");
+            }
+            Self::Cbor => jaq_core::cbor::encode_into(&v, &mut out),
+            Self::MsgPack => jaq_core::msgpack::encode_into(&v, &mut out).map_err(invalid_data)?,
+        }
+        w.write_all(&out)
+    }
+}
+
+fn invalid_data(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// Decode a sequence of concatenated binary values.
+fn binary<'a>(
+    data: impl AsRef<[u8]> + 'a,
+    decode: impl Fn(&mut &[u8]) -> jaq_interpret::ValR + 'a,
+) -> impl Iterator<Item = io::Result<Val>> + 'a {
+    // position of the next value, or `None` after an error
+    let mut pos = Some(0);
+    core::iter::from_fn(move || {
+        let mut rest = data.as_ref().get(pos?..).filter(|rest| !rest.is_empty())?;
+        let len = rest.len();
+        let v = decode(&mut rest).map_err(invalid_data);
+        pos = pos.filter(|_| v.is_ok()).map(|pos| pos + len - rest.len());
+        Some(v)
+    })
+}
+
+fn yaml_decode<'a>(data: impl AsRef<[u8]> + 'a) -> impl Iterator<Item = io::Result<Val>> + 'a {
+    use serde::Deserialize;
+    let mut docs = serde_yaml::Deserializer::from_reader(io::Cursor::new(data));
+    let mut err = false;
+    core::iter::from_fn(move || {
+        // the deserializer may keep yielding the same error
+        if err {
+            return None;
+        }
+        let v = serde_yaml::Value::deserialize(docs.next()?).map_err(invalid_data);
+        err = v.is_err();
+        Some(v.map(yaml_to_val))
+    })
+}
+
+/// Convert a YAML value to a value.
+///
+/// Because object keys must be strings,
+/// non-string keys of YAML mappings are converted to their YAML representation.
+/// YAML tags are discarded.
+fn yaml_to_val(v: serde_yaml::Value) -> Val {
+    use serde_yaml::Value;
+    let key = |k| match k {
+        Value::String(s) => s,
+        k => serde_yaml::to_string(&k).map_or_else(|_| String::new(), |s| s.trim_end().into()),
+    };
+    match v {
+        Value::Null => Val::Null,
+        Value::Bool(b) => Val::Bool(b),
+        Value::Number(n) => match n.as_i64().and_then(|i| isize::try_from(i).ok()) {
+            Some(i) => Val::Int(i),
+            None if n.is_f64() => Val::Float(n.as_f64().unwrap_or_default()),
+            None => Val::Num(n.to_string().into()),
+        },
+        Value::String(s) => Val::str(s),
+        Value::Sequence(a) => Val::arr(a.into_iter().map(yaml_to_val).collect()),
+        Value::Mapping(m) => {
+            let kvs = m.into_iter().map(|(k, v)| (key(k).into(), yaml_to_val(v)));
+            Val::obj(kvs.collect())
+        }
+        Value::Tagged(t) => yaml_to_val(t.value),
+    }
+}
+
+/// Convert a value to a YAML value.
+///
+/// This fails if the value contains integers that do not fit into 64 bits,
+/// because YAML numbers are limited to these.
+fn val_to_yaml(v: Val) -> io::Result<serde_yaml::Value> {
+    use serde_yaml::Value;
+    Ok(match v {
+        Val::Null => Value::Null,
+        Val::Bool(b) => Value::Bool(b),
+        Val::Int(i) => Value::Number((i as i64).into()),
+        Val::Float(f) => Value::Number(f.into()),
+        Val::Num(n) => val_to_yaml(Val::from_dec_str(&n))?,
+        Val::BigInt(i) => {
+            let s = i.to_string();
+            match (s.parse::<i64>(), s.parse::<u64>()) {
+                (Ok(i), _) => Value::Number(i.into()),
+                (_, Ok(u)) => Value::Number(u.into()),
+                _ => {
+                    let e =
+                        format!("cannot write {i} as YAML, because it does not fit into 64 bits");
+                    return Err(invalid_data(e));
+                }
+            }
+        }
+        Val::Str(s) => Value::String((*s).clone()),
+        Val::Arr(a) => Value::Sequence(
+            a.iter()
+                .cloned()
+                .map(val_to_yaml)
+                .collect::<Result<_, _>>()?,
+        ),
+        Val::Obj(o) => {
+            let kvs = o.iter().map(|(k, v)| {
+                let v = val_to_yaml(v.clone())?;
+                Ok::<_, io::Error>((Value::String((**k).clone()), v))
+            });
+            Value::Mapping(kvs.collect::<Result<_, _>>()?)
+        }
+    })
+}
+
+fn toml_decode(data: &[u8]) -> io::Result<Val> {
+    let s = std::str::from_utf8(data).map_err(invalid_data)?;
+    Ok(toml_to_val(toml::from_str(s).map_err(invalid_data)?))
+}
+
+/// Convert a TOML value to a value, where dates and times are converted to strings.
+fn toml_to_val(v: toml::Value) -> Val {
+    use toml::Value;
+    match v {
+        Value::Boolean(b) => Val::Bool(b),
+        Value::Integer(i) => Val::big(i.into()),
+        Value::Float(f) => Val::Float(f),
+        Value::String(s) => Val::str(s),
+        Value::Datetime(d) => Val::str(d.to_string()),
+        Value::Array(a) => Val::arr(a.into_iter().map(toml_to_val).collect()),
+        Value::Table(t) => Val::obj(
+            t.into_iter()
+                .map(|(k, v)| (k.into(), toml_to_val(v)))
+                .collect(),
+        ),
+    }
+}
+
+/// Convert a value to a TOML document.
+///
+/// This fails if the value is not an object, because
+/// TOML documents are tables, or if it contains null,
+/// because TOML has no null.
+fn val_to_toml(v: Val) -> io::Result<toml::Value> {
+    match v {
+        Val::Obj(_) => toml_value(v),
+        v => Err(invalid_data(format!(
+            "cannot write {v} as TOML document, because it is not an object"
+        ))),
+    }
+}
+
+/// Convert a value to a TOML value.
+///
+/// This fails if the value contains null, an integer that does not fit into 64 bits, or
+/// an array that contains both objects and other values.
+fn toml_value(v: Val) -> io::Result<toml::Value> {
+    use toml::Value;
+    Ok(match v {
+        Val::Null => return Err(invalid_data("cannot write null as TOML")),
+        Val::Bool(b) => Value::Boolean(b),
+        Val::Int(i) => Value::Integer(i as i64),
+        Val::Float(f) => Value::Float(f),
+        Val::Num(n) => toml_value(Val::from_dec_str(&n))?,
+        Val::BigInt(i) => match i.to_string().parse::<i64>() {
+            Ok(i) => Value::Integer(i),
+            Err(_) => {
+                let e = format!("cannot write {i} as TOML, because it does not fit into 64 bits");
+                return Err(invalid_data(e));
+            }
+        },
+        Val::Str(s) => Value::String((*s).clone()),
+        Val::Arr(a) => {
+            let a: Vec<_> = a
+                .iter()
+                .cloned()
+                .map(toml_value)
+                .collect::<Result<_, _>>()?;
+            let tables = a.iter().filter(|v| v.is_table()).count();
+            if tables > 0 && tables < a.len() {
+                let e = "cannot write array of objects and other values as TOML";
+                return Err(invalid_data(e));
+            }
+            Value::Array(a)
+        }
+        Val::Obj(o) => {
+            let kvs = o
+                .iter()
+                .map(|(k, v)| Ok(((**k).clone(), toml_value(v.clone())?)));
+            Value::Table(kvs.collect::<io::Result<_>>()?)
+        }
+    })
+}
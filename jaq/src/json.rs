@@ -0,0 +1,90 @@
+//! Writing values as JSON, optionally indented and colored.
+//!
+//! Values are written token by token via [`jaq_interpret::style`], so that
+//! no formatted copy of a value is built in memory before writing it.
+
+use core::fmt;
+use jaq_interpret::style::{ColorScheme, Options};
+use jaq_interpret::Val;
+use std::io::{self, Write};
+
+/// How to write JSON values.
+#[derive(Clone, Default)]
+pub struct Format {
+    /// string to indent every level with, or `None` to omit whitespace
+    pub indent: Option<String>,
+    /// escape all non-ASCII characters in strings
+    pub ascii: bool,
+    /// colors to use, or `None` to write without colors
+    pub colors: Option<ColorScheme>,
+    /// write floats with 17 significant digits instead of the shortest representation
+    pub fixed_floats: bool,
+}
+
+impl Format {
+    /// Write a value.
+    pub fn write(&self, w: &mut impl Write, v: &Val) -> io::Result<()> {
+        let float: fn(&mut dyn fmt::Write, f64) -> fmt::Result = if self.fixed_floats {
+            |w, f| w.write_str(&fmt_17g(f))
+        } else {
+            // this formats floats like `serde_json`, so that
+            // they are written the same way as they were before
+            |w, f| match serde_json::Number::from_f64(f) {
+                Some(n) => write!(w, "{n}"),
+                None => w.write_str("null"),
+            }
+        };
+        let opts = Options {
+            indent: self.indent.as_deref(),
+            ascii: self.ascii,
+            float,
+        };
+        let mut w = IoWriter { w, err: None };
+        let result = match &self.colors {
+            Some(colors) => opts.write(&mut w, v, colors),
+            None => opts.write(&mut w, v, &()),
+        };
+        match (result, w.err) {
+            (_, Some(e)) => Err(e),
+            (Err(fmt::Error), None) => Err(io::Error::new(io::ErrorKind::Other, "formatter error")),
+            (Ok(()), None) => Ok(()),
+        }
+    }
+}
+
+/// Format a float like C's `printf("%.17g", x)`.
+fn fmt_17g(x: f64) -> String {
+    // remove trailing zeros after the decimal point, as `%g` does
+    fn trim(s: &str) -> &str {
+        if s.contains('.') {
+            s.trim_end_matches('0').trim_end_matches('.')
+        } else {
+            s
+        }
+    }
+    let e = format!("{x:.16e}");
+    let (mant, exp) = e.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    if (-4..17).contains(&exp) {
+        let prec = (16 - exp) as usize;
+        trim(&format!("{x:.prec$}")).to_string()
+    } else {
+        let sign = if exp < 0 { '-' } else { '+' };
+        format!("{}e{sign}{:02}", trim(mant), exp.abs())
+    }
+}
+
+/// Adapter to write formatted text to a byte stream, remembering the last I/O error.
+struct IoWriter<'a, W> {
+    w: &'a mut W,
+    err: Option<io::Error>,
+}
+
+impl<W: Write> fmt::Write for IoWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.w.write_all(s.as_bytes()).map_err(|e| {
+            self.err = Some(e);
+            fmt::Error
+        })
+    }
+}
@@ -1,5 +1,13 @@
-use clap::{Parser, ValueEnum};
-use jaq_interpret::{Ctx, Filter, FilterT, ParseCtx, RcIter, Val};
+mod codec;
+mod json;
+mod parallel;
+mod repl;
+
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser, ValueEnum};
+use codec::Codec;
+use jaq_interpret::results::last_count;
+use jaq_interpret::{Ctx, Diagnostic, Filter, FilterT, ParseCtx, Profile, RcIter, Val};
+use std::cell::Cell;
 use std::io::{self, BufRead, Write};
 use std::path::PathBuf;
 use std::process::{ExitCode, Termination};
@@ -37,9 +45,19 @@ struct Cli {
     slurp: bool,
 
     /// Overwrite input file with its output
+    ///
+    /// The output is first written to a temporary file,
+    /// which then replaces the input file.
+    /// If there are several outputs, they are all written to the file.
+    /// If there is no output, then the input file is left unchanged,
+    /// and an error is reported.
     #[arg(short, long)]
     in_place: bool,
 
+    /// Keep a copy of every file overwritten by `--in-place` with suffix s
+    #[arg(long, value_name = "s", requires = "in_place")]
+    backup: Option<String>,
+
     /// Write strings without escaping them with quotes
     #[arg(short, long)]
     raw_output: bool,
@@ -51,38 +69,136 @@ struct Cli {
     #[arg(short = 'R', long)]
     raw_input: bool,
 
+    /// Read input values as sequence of `[path, leaf]` events
+    ///
+    /// This yields the same values as `tostream`, but
+    /// without holding whole input values in memory.
+    #[arg(long)]
+    stream: bool,
+
+    /// Read and print JSON values as text sequences (RFC 7464)
+    ///
+    /// Every input value is preceded by an ASCII record separator (RS).
+    /// A malformed input record yields a warning and is skipped,
+    /// and reading continues after the next RS.
+    /// Every output value is preceded by RS, too.
+    #[arg(long, conflicts_with = "output_separator")]
+    seq: bool,
+
+    /// Read input values as YAML
+    ///
+    /// Every document of a multi-document stream yields a separate value.
+    #[arg(long)]
+    yaml_input: bool,
+
+    /// Read input values as CBOR data items
+    ///
+    /// Byte strings are read as arrays of integers.
+    #[arg(long)]
+    cbor_input: bool,
+
+    /// Read input values as MessagePack values
+    ///
+    /// Binary data is read as arrays of integers.
+    #[arg(long)]
+    msgpack_input: bool,
+
+    /// Read input values as TOML documents
+    ///
+    /// Dates and times are read as strings.
+    #[arg(long)]
+    toml_input: bool,
+
+    /// Print output values as YAML documents
+    #[arg(long)]
+    yaml_output: bool,
+
+    /// Print output values as CBOR data items, without separators
+    #[arg(long)]
+    cbor_output: bool,
+
+    /// Print output values as MessagePack values, without separators
+    #[arg(long)]
+    msgpack_output: bool,
+
+    /// Print output values as TOML documents
+    ///
+    /// This fails for output values that are not objects or that contain null.
+    #[arg(long)]
+    toml_output: bool,
+
     /// Print JSON compactly, omitting whitespace
     #[arg(short, long)]
     compact_output: bool,
 
-    /// Use n spaces for indentation
-    #[arg(long, value_name = "n", default_value_t = 2)]
-    indent: usize,
+    /// Use n spaces for indentation, where n is at most 7
+    #[arg(long, value_name = "n", default_value_t = 2, value_parser = clap::value_parser!(u8).range(..=7))]
+    indent: u8,
 
     /// Use tabs for indentation rather than spaces
     #[arg(long)]
     tab: bool,
 
+    /// Escape all non-ASCII characters in JSON strings
+    ///
+    /// Like in jq, this prints strings as JSON strings
+    /// even when the option `--raw-output` is used.
+    #[arg(short, long)]
+    ascii_output: bool,
+
     /// Do not print a newline after each value
     ///
     /// Unlike jq, this does not enable `--raw-output`.
     #[arg(short, long)]
     join_output: bool,
 
+    /// Print STR between output values instead of a newline after each value
+    #[arg(long, value_name = "STR")]
+    output_separator: Option<String>,
+
     /// Color output
+    ///
+    /// The colors can be set via the environment variable `JAQ_COLORS`,
+    /// which has the same format as `JQ_COLORS` in jq, such as
+    /// `0;90:0;39:0;39:0;39:0;32:1;39:1;39:34;1` for the colors of
+    /// null, false, true, numbers, strings, arrays, objects, and object keys.
     #[arg(long, value_name = "WHEN", default_value = "auto")]
     color: Color,
 
+    /// Format floating-point numbers
+    ///
+    /// By default, floats are printed with the shortest representation
+    /// that yields the same number when read back.
+    /// With `fixed`, floats are printed with 17 significant digits,
+    /// like jq 1.6 does, e.g. `0.1` is printed as `0.10000000000000001`.
+    #[arg(long, value_name = "MODE", default_value = "shortest")]
+    float_format: FloatFormat,
+
     /// Read filter from a file
     ///
     /// In this case, all arguments are interpreted as input files.
     #[arg(short, long, value_name = "FILE")]
     from_file: Option<PathBuf>,
 
+    /// Search for modules in directory DIR
+    ///
+    /// This option can be given multiple times.
+    /// Directories are searched in the given order,
+    /// followed by those given in the environment variable `JAQ_LIBRARY_PATH`.
+    /// If no directory is given this way, jaq searches in
+    /// `~/.jq`, `$ORIGIN/../lib/jq`, and `$ORIGIN/../lib`,
+    /// where `$ORIGIN` is the directory containing the jaq executable.
+    #[arg(short = 'L', long, value_name = "DIR")]
+    library_path: Vec<PathBuf>,
+
     /// Set variable `$<a>` to string `<v>`
     #[arg(long, value_names = &["a", "v"])]
     arg: Vec<String>,
 
+    /// Set variable `$<a>` to JSON value `<v>`
+    #[arg(long, value_names = &["a", "v"])]
+    argjson: Vec<String>,
+
     /// Set variable `$<a>` to string containing the contents of file `f`
     #[arg(long, value_names = &["a", "f"])]
     rawfile: Vec<String>,
@@ -91,12 +207,107 @@ struct Cli {
     #[arg(long, value_names = &["a", "f"])]
     slurpfile: Vec<String>,
 
+    /// Add remaining arguments as strings to `$ARGS.positional`
+    ///
+    /// Arguments after this option are not interpreted as input files.
+    /// If no filter was given before, the first argument is the filter.
+    #[arg(long = "args", value_name = "ARG", num_args = 0.., action = ArgAction::Append)]
+    str_args: Vec<String>,
+
+    /// Add remaining arguments as JSON values to `$ARGS.positional`
+    ///
+    /// This can be combined with `--args`;
+    /// every argument is interpreted according to the last preceding option.
+    #[arg(long, value_name = "ARG", num_args = 0.., action = ArgAction::Append)]
+    jsonargs: Vec<String>,
+
+    /// Arguments for `$ARGS.positional`, with `true` if they are JSON
+    #[arg(skip)]
+    positional: Vec<(bool, String)>,
+
     /// Run tests from a file
     #[arg(long, value_name = "FILE")]
     run_tests: Option<PathBuf>,
 
+    /// Use a stack of n MiB for evaluation
+    ///
+    /// Filters run without growing the stack, but reading, writing, and
+    /// dropping deeply nested values may need more stack
+    /// than is provided by default by the operating system.
+    #[arg(long, value_name = "n")]
+    stack_size: Option<usize>,
+
+    /// Store compiled filters in a directory and load them from there
+    ///
+    /// When running a filter again with the same variables and modules,
+    /// its compiled version is loaded from the directory,
+    /// which saves the time to parse and compile the standard library.
+    #[arg(long, value_name = "DIR")]
+    cache: Option<PathBuf>,
+
+    /// Process inputs on n threads in parallel
+    ///
+    /// Every input value must be on a single line.
+    /// The input lines are distributed in chunks among the threads, and
+    /// the outputs are printed in the order of their inputs.
+    /// If n is not given, then as many threads as CPUs are used.
+    /// Note that n has to be given as `--parallel=n`, because
+    /// in `--parallel n`, n is taken to be the filter.
+    /// Because every chunk is processed separately,
+    /// `input` and `inputs` yield only values from the same chunk, and
+    /// `input_line_number` counts lines from the start of the chunk.
+    #[arg(
+        long,
+        value_name = "n",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "0",
+        conflicts_with_all = [
+            "null_input", "slurp", "in_place", "yaml_input", "cbor_input", "msgpack_input",
+            "toml_input", "yaml_output", "toml_output", "output_separator", "seq",
+        ],
+    )]
+    parallel: Option<usize>,
+
+    /// Read filters line by line from standard input and run them interactively
+    ///
+    /// All arguments are taken to be input files, which are read only once.
+    /// See the command `:help` for more information.
+    #[arg(long, conflicts_with_all = ["from_file", "in_place", "parallel"])]
+    repl: bool,
+
+    /// Print how often definitions were called and how long their calls took
+    ///
+    /// When the filter has finished, a report is printed to standard error,
+    /// showing for every definition that was called
+    /// the number of calls, the number of values that the calls yielded,
+    /// the time spent in the calls, and where the definition is located.
+    /// The definitions that took the most time come first.
+    #[arg(long, conflicts_with_all = ["parallel", "repl"])]
+    profile: bool,
+
+    /// Print the filter in a canonical format and exit
+    ///
+    /// This preserves the meaning of the filter as well as its comments,
+    /// but moves comments within a definition or filter body before it.
+    #[arg(long)]
+    format: bool,
+
+    /// Compile the filter and exit without reading any input
+    ///
+    /// This fails if the filter contains any errors,
+    /// such as calls to undefined filters.
+    /// Furthermore, this warns about likely mistakes,
+    /// such as unused definitions or conditions that are always true.
+    #[arg(long)]
+    check: bool,
+
     /// Filter to execute, followed by list of input files
     args: Vec<String>,
+
+    /// How to print JSON values, determined from the other options
+    #[arg(skip)]
+    json: json::Format,
 }
 
 #[derive(Clone, ValueEnum)]
@@ -106,86 +317,240 @@ enum Color {
     Never,
 }
 
+#[derive(Clone, ValueEnum)]
+enum FloatFormat {
+    Shortest,
+    Fixed,
+}
+
 impl Cli {
-    fn color_mode(&self) -> colored_json::ColorMode {
-        use colored_json::{ColorMode, Output};
+    /// Collect arguments for `$ARGS.positional` in the order they were given.
+    fn split_positional(&mut self, matches: &ArgMatches) {
+        let indices = |id| matches.indices_of(id).into_iter().flatten();
+        let take = |v: &mut Vec<String>| core::mem::take(v).into_iter();
+        let plain = indices("args").zip(take(&mut self.args).map(|a| (None, a)));
+        let strs = indices("str_args").zip(take(&mut self.str_args).map(|a| (Some(false), a)));
+        let jsons = indices("jsonargs").zip(take(&mut self.jsonargs).map(|a| (Some(true), a)));
+        let mut args: Vec<_> = plain.chain(strs).chain(jsons).collect();
+        args.sort_by_key(|(i, _)| *i);
+
+        let mut args = args.into_iter().map(|(_, a)| a);
+        if self.from_file.is_none() {
+            // the first argument is always the filter
+            self.args.extend(args.next().map(|(_, a)| a));
+        }
+        // whether previous arguments were JSON, or `None` if they were input files
+        let mut json = None;
+        for (mode, arg) in args {
+            json = mode.or(json);
+            match json {
+                None => self.args.push(arg),
+                Some(json) => self.positional.push((json, arg)),
+            }
+        }
+    }
+
+    /// Directories to search for modules.
+    fn library_path(&self) -> Vec<PathBuf> {
+        let mut dirs = self.library_path.clone();
+        if let Some(paths) = std::env::var_os("JAQ_LIBRARY_PATH") {
+            dirs.extend(std::env::split_paths(&paths));
+        }
+        if dirs.is_empty() {
+            let home = std::env::var_os("HOME").map(PathBuf::from);
+            dirs.extend(home.map(|home| home.join(".jq")));
+            let exe = std::env::current_exe().ok();
+            if let Some(origin) = exe.as_ref().and_then(|exe| exe.parent()) {
+                dirs.push(origin.join("../lib/jq"));
+                dirs.push(origin.join("../lib"));
+            }
+        }
+        dirs
+    }
+
+    /// Format of input values if they are not JSON.
+    fn input_codec(&self) -> Option<Codec> {
+        let flags = [
+            (self.yaml_input, Codec::Yaml),
+            (self.cbor_input, Codec::Cbor),
+            (self.msgpack_input, Codec::MsgPack),
+            (self.toml_input, Codec::Toml),
+        ];
+        flags
+            .into_iter()
+            .find_map(|(flag, codec)| flag.then_some(codec))
+    }
+
+    /// Format of output values if they are not JSON.
+    fn output_codec(&self) -> Option<Codec> {
+        let flags = [
+            (self.yaml_output, Codec::Yaml),
+            (self.cbor_output, Codec::Cbor),
+            (self.msgpack_output, Codec::MsgPack),
+            (self.toml_output, Codec::Toml),
+        ];
+        flags
+            .into_iter()
+            .find_map(|(flag, codec)| flag.then_some(codec))
+    }
+
+    fn colored(&self) -> bool {
         match self.color {
-            Color::Always => ColorMode::On,
-            Color::Auto if self.in_place => ColorMode::Off,
-            Color::Auto => ColorMode::Auto(Output::StdOut),
-            Color::Never => ColorMode::Off,
+            Color::Always => true,
+            Color::Auto if self.in_place => false,
+            Color::Auto => atty::is(atty::Stream::Stdout),
+            Color::Never => false,
+        }
+    }
+
+    /// Return how to print JSON values, warning if `JAQ_COLORS` is invalid.
+    fn json_format(&self) -> json::Format {
+        let indent = (!self.compact_output).then(|| match self.tab {
+            true => String::from("\t"),
+            false => " ".repeat(self.indent.into()),
+        });
+        let colors = std::env::var("JAQ_COLORS").ok().and_then(|s| {
+            let colors = jaq_interpret::style::ColorScheme::parse(&s);
+            if colors.is_none() {
+                eprintln!("Warning: invalid JAQ_COLORS, using default colors");
+            }
+            colors
+        });
+        let colors = self.colored().then(|| colors.unwrap_or_default());
+        json::Format {
+            indent,
+            ascii: self.ascii_output,
+            colors,
+            fixed_floats: matches!(self.float_format, FloatFormat::Fixed),
         }
     }
 }
 
 fn main() -> ExitCode {
-    match real_main() {
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    cli.split_positional(&matches);
+    cli.json = cli.json_format();
+    let stack_size = cli.stack_size;
+    let main = move || match real_main(&cli) {
         Ok(exit) => exit,
         Err(e) => e.report(),
+    };
+    match stack_size {
+        None => main(),
+        Some(mib) => {
+            let thread = std::thread::Builder::new().stack_size(mib.saturating_mul(1 << 20));
+            match thread.spawn(main) {
+                Ok(handle) => handle
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e)),
+                Err(e) => Error::Io(None, e).report(),
+            }
+        }
     }
 }
 
-fn real_main() -> Result<ExitCode, Error> {
-    let cli = Cli::parse();
-
+fn real_main(cli: &Cli) -> Result<ExitCode, Error> {
     use env_logger::Env;
     env_logger::Builder::from_env(Env::default().filter_or("LOG", "debug"))
         // omit name of module that emitted log message
         .format_target(false)
         .init();
 
+    let lib = cli.library_path();
     if let Some(test_file) = &cli.run_tests {
-        return Ok(run_tests(std::fs::File::open(test_file)?));
+        return Ok(run_tests(std::fs::File::open(test_file)?, &lib));
+    }
+
+    if cli.format {
+        let filter = match &cli.from_file {
+            Some(file) => std::fs::read_to_string(file)?,
+            None => cli.args.first().cloned().unwrap_or_else(|| ".".to_string()),
+        };
+        let formatted = jaq_parse::format_filter(&filter).map_err(|errs| {
+            let err = |error| ParseError {
+                error,
+                filter: filter.clone(),
+            };
+            errs.into_iter().map(err).collect::<Vec<_>>()
+        })?;
+        println!("{formatted}");
+        return Ok(ExitCode::SUCCESS);
     }
 
-    let (vars, ctx) = binds(&cli)?.into_iter().unzip();
+    if cli.repl {
+        return repl::run_repl(cli, &lib);
+    }
 
     let mut args = cli.args.iter();
-    let filter = match &cli.from_file {
-        Some(file) => parse(&std::fs::read_to_string(file)?, vars)?,
-        None => {
-            if let Some(filter) = args.next() {
-                parse(filter, vars)?
-            } else {
-                Filter::default()
-            }
-        }
+    // name and contents of the filter source
+    let src = match &cli.from_file {
+        Some(file) => Some((file.display().to_string(), std::fs::read_to_string(file)?)),
+        None => args.next().map(|f| ("<top-level>".to_string(), f.clone())),
+    };
+    let compile = || {
+        let (vars, ctx) = binds(cli)?.into_iter().unzip();
+        let filter = match &src {
+            Some((file, src)) => parse(file, src, vars, &lib, cli.cache.as_deref())?,
+            None => Filter::default(),
+        };
+        Ok((filter, ctx))
     };
+    // warn also about filters that do not compile,
+    // because calls to deprecated filters may be undefined in jaq
+    if let (true, Some((_, src))) = (cli.check, &src) {
+        print_lints(src)?;
+    }
+    let (filter, ctx) = compile()?;
     //println!("Filter: {:?}", filter);
+    if cli.check {
+        return Ok(ExitCode::SUCCESS);
+    }
     let files: Vec<_> = args.collect();
 
-    let last = if files.is_empty() {
-        let inputs = read_buffered(&cli, io::stdin().lock());
-        with_stdout(|out| run(&cli, &filter, ctx, inputs, |v| print(&cli, v, out)))?
+    let start = std::time::Instant::now();
+    let clock = move || start.elapsed();
+    let profile = Profile::new(&clock);
+    let profile = cli.profile.then_some(&profile);
+
+    let last = if let Some(workers) = cli.parallel {
+        parallel::run_parallel(cli, workers, &compile, &files)
+    } else if cli.in_place {
+        run_in_place(cli, &filter, ctx, &files, profile)
     } else {
-        let mut last = None;
-        for file in files {
-            let path = std::path::Path::new(file);
-            let file = load_file(path).map_err(|e| Error::Io(Some(file.to_string()), e))?;
-            let inputs = read_slice(&cli, &file);
-            if cli.in_place {
-                // create a temporary file where output is written to
-                let location = path.parent().unwrap();
-                let mut tmp = tempfile::Builder::new()
-                    .prefix("jaq")
-                    .tempfile_in(location)?;
-
-                last = run(&cli, &filter, ctx.clone(), inputs, |output| {
-                    print(&cli, output, tmp.as_file_mut())
-                })?;
-
-                // replace the input file with the temporary file
-                let perms = std::fs::metadata(path)?.permissions();
-                tmp.persist(path).map_err(Error::Persist)?;
-                std::fs::set_permissions(path, perms)?;
-            } else {
-                last = with_stdout(|out| {
-                    run(&cli, &filter, ctx.clone(), inputs, |v| print(&cli, v, out))
-                })?;
-            }
-        }
-        last
+        // all files are opened before running the filter, but
+        // their contents are read only when the filter requests more inputs
+        let opened = files.iter().map(|name| {
+            let file = open_file(std::path::Path::new(name));
+            Ok((
+                name.as_str(),
+                file.map_err(|e| Error::Io(Some(name.to_string()), e))?,
+            ))
+        });
+        let opened = opened.collect::<Result<Vec<_>, Error>>()?;
+        let current = Position::default();
+        let inputs = if files.is_empty() {
+            read_buffered(cli, io::stdin().lock(), &current.line)
+        } else {
+            Box::new(opened.iter().flat_map(|(name, file)| {
+                current.file.set(Some(*name));
+                match file {
+                    Ok(mmap) => read_slice(cli, mmap, &current.line),
+                    Err(file) => read_buffered(cli, io::BufReader::new(file), &current.line),
+                }
+            }))
+        };
+        let mut first = true;
+        with_stdout(|out| {
+            run(cli, &filter, ctx, &current, profile, inputs, |v| {
+                print(cli, v, &mut first, out)
+            })
+        })
     };
+    if let Some(profile) = profile {
+        print_profile(&profile.stats(&filter))?;
+    }
+    let last = last?;
 
     if cli.exit_status {
         last.map_or_else(
@@ -197,6 +562,62 @@ fn real_main() -> Result<ExitCode, Error> {
     }
 }
 
+/// Run the filter separately on every file, replacing the file by the output.
+fn run_in_place(
+    cli: &Cli,
+    filter: &Filter,
+    ctx: Vec<Val>,
+    files: &[&String],
+    profile: Option<&Profile>,
+) -> Result<Option<bool>, Error> {
+    if files.is_empty() {
+        let e = io::Error::new(io::ErrorKind::InvalidInput, "no input files to overwrite");
+        return Err(Error::Io(None, e));
+    }
+    let mut last = None;
+    // exit code of the last file that could not be replaced
+    let mut failed = None;
+    for name in files {
+        let io_err = |e| Error::Io(Some(name.to_string()), e);
+        // resolve symbolic links, so that we replace the file they point to
+        let path = &std::fs::canonicalize(name).map_err(io_err)?;
+        let file = load_file(path).map_err(io_err)?;
+        let pos = Position::default();
+        pos.file.set(Some(name.as_str()));
+        let inputs = read_slice(cli, &file, &pos.line);
+        // create a temporary file where output is written to
+        let location = path.parent().unwrap();
+        let mut tmp = tempfile::Builder::new()
+            .prefix("jaq")
+            .tempfile_in(location)
+            .map_err(io_err)?;
+
+        let mut first = true;
+        last = run(cli, filter, ctx.clone(), &pos, profile, inputs, |output| {
+            print(cli, output, &mut first, tmp.as_file_mut())
+        })?;
+
+        if first {
+            // report the error, but continue with the other files
+            let e = io::Error::new(io::ErrorKind::Other, "no output, leaving file unchanged");
+            failed = Some(io_err(e).write_report(&mut io::stderr().lock())?);
+            continue;
+        }
+        if let Some(suffix) = &cli.backup {
+            let backup = format!("{name}{suffix}");
+            std::fs::copy(path, &backup).map_err(|e| Error::Io(Some(backup), e))?;
+        }
+        // replace the input file with the temporary file
+        let perms = std::fs::metadata(path).map_err(io_err)?.permissions();
+        tmp.persist(path).map_err(Error::Persist)?;
+        std::fs::set_permissions(path, perms).map_err(io_err)?;
+    }
+    match failed {
+        Some(code) => Err(Error::Rendered(Vec::new(), code)),
+        None => Ok(last),
+    }
+}
+
 fn bind<F>(var_val: &mut Vec<(String, Val)>, args: &[String], f: F) -> Result<(), Error>
 where
     F: Fn(&str) -> Result<Val, Error>,
@@ -215,6 +636,7 @@ fn binds(cli: &Cli) -> Result<Vec<(String, Val)>, Error> {
     bind(&mut var_val, &cli.arg, |v| {
         Ok(Val::Str(v.to_string().into()))
     })?;
+    bind(&mut var_val, &cli.argjson, json_arg)?;
     bind(&mut var_val, &cli.rawfile, |f| {
         let s = std::fs::read_to_string(f).map_err(|e| Error::Io(Some(f.to_string()), e));
         Ok(Val::Str(s?.into()))
@@ -225,49 +647,176 @@ fn binds(cli: &Cli) -> Result<Vec<(String, Val)>, Error> {
         Ok(Val::arr(json_slice(&file).collect::<Result<Vec<_>, _>>()?))
     })?;
 
-    var_val.push(("ARGS".to_string(), args_named(&var_val)));
-    let env = std::env::vars().map(|(k, v)| (k.into(), Val::str(v)));
-    var_val.push(("ENV".to_string(), Val::obj(env.collect())));
+    let positional = cli.positional.iter().map(|(json, v)| {
+        if *json {
+            json_arg(v)
+        } else {
+            Ok(Val::Str(v.to_string().into()))
+        }
+    });
+    let positional = positional.collect::<Result<_, _>>()?;
+
+    var_val.push(("ARGS".to_string(), args(positional, &var_val)));
+    var_val.push(("ENV".to_string(), jaq_core::env_obj(std::env::vars_os())));
 
     Ok(var_val)
 }
 
-fn args_named(var_val: &[(String, Val)]) -> Val {
+/// Parse an argument that must consist of exactly one JSON value.
+fn json_arg(v: &str) -> Result<Val, Error> {
+    let mut vals = json_slice(v.as_bytes());
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "expected one JSON value");
+    match (vals.next(), vals.next()) {
+        (Some(val), None) => val,
+        (Some(Err(e)), _) => Err(e),
+        _ => Err(invalid()),
+    }
+    .map_err(|e| Error::Io(Some(v.to_string()), e))
+}
+
+fn args(positional: Vec<Val>, var_val: &[(String, Val)]) -> Val {
     let named = var_val
         .iter()
         .map(|(var, val)| (var.clone().into(), val.clone()));
-    let args = std::iter::once(("named".to_string().into(), Val::obj(named.collect())));
-    Val::obj(args.collect())
+    let args = [
+        ("positional".to_string().into(), Val::arr(positional)),
+        ("named".to_string().into(), Val::obj(named.collect())),
+    ];
+    Val::obj(args.into_iter().collect())
 }
 
-fn parse(filter_str: &str, vars: Vec<String>) -> Result<Filter, Vec<ParseError>> {
+fn parse_errors(errs: Vec<jaq_parse::Error>, src: &str) -> Error {
+    let err = |error| ParseError {
+        error,
+        filter: src.to_owned(),
+    };
+    Error::Chumsky(errs.into_iter().map(err).collect())
+}
+
+fn parse(
+    file: &str,
+    filter_str: &str,
+    vars: Vec<String>,
+    lib: &[PathBuf],
+    cache: Option<&std::path::Path>,
+) -> Result<Filter, Error> {
+    let mut key = Vec::new();
+    for part in [file, env!("CARGO_PKG_VERSION"), filter_str] {
+        cache_key(&mut key, part);
+    }
+    vars.iter().for_each(|v| cache_key(&mut key, v));
+
     let mut defs = ParseCtx::new(vars);
+    defs.set_source(file.to_string(), filter_str.to_string());
     defs.insert_natives(jaq_core::core());
+    let (filter, errs) = jaq_parse::parse(filter_str, jaq_parse::module(jaq_parse::main()));
+    if !errs.is_empty() {
+        return Err(parse_errors(errs, filter_str));
+    }
+    let filter = filter.unwrap();
+    let mut modules = Default::default();
+    load_modules(&mut defs, &filter.imports, lib, &mut modules)?;
+    for (path, src) in &modules {
+        cache_key(&mut key, path);
+        cache_key(&mut key, src);
+    }
+
+    // file in the cache directory for the filter, and the cached filter if it is valid
+    let cached = cache.map(|dir| {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        key.hash(&mut hasher);
+        let path = dir.join(format!("{:016x}.bin", hasher.finish()));
+        let bytes = std::fs::read(&path).ok();
+        let filter = bytes.and_then(|bytes| defs.deserialize(&bytes, &key));
+        (path, filter)
+    });
+    if let Some((_, Some(filter))) = cached {
+        return Ok(filter);
+    }
+
     defs.insert_defs(jaq_std::std());
     assert!(defs.errs.is_empty());
-    let (filter, errs) = jaq_parse::parse(filter_str, jaq_parse::main());
-    if !errs.is_empty() {
-        return Err(errs
-            .into_iter()
-            .map(|error| ParseError {
-                error,
-                filter: filter_str.to_owned(),
-            })
-            .collect());
+    let filter = defs.compile_module(filter);
+    if !defs.errs.is_empty() {
+        return Err(Error::Chumsky(
+            defs.errs
+                .into_iter()
+                .map(|error| ParseError {
+                    error: chumsky::error::Simple::custom(error.1, error.0.to_string()),
+                    filter: filter_str.to_owned(),
+                })
+                .collect(),
+        ));
     }
-    let filter = defs.compile(filter.unwrap());
-    if defs.errs.is_empty() {
-        Ok(filter)
-    } else {
-        Err(defs
-            .errs
-            .into_iter()
-            .map(|error| ParseError {
-                error: chumsky::error::Simple::custom(error.1, error.0.to_string()),
-                filter: filter_str.to_owned(),
-            })
-            .collect())
+    if let Some((path, None)) = cached {
+        let io_err = |e| Error::Io(Some(path.display().to_string()), e);
+        std::fs::create_dir_all(path.parent().unwrap()).map_err(io_err)?;
+        std::fs::write(&path, defs.serialize(&filter, &key)).map_err(io_err)?;
+    }
+    Ok(filter)
+}
+
+/// Append a length-prefixed string to a key that identifies a filter in the cache.
+fn cache_key(key: &mut Vec<u8>, s: &str) {
+    key.extend((s.len() as u64).to_le_bytes());
+    key.extend(s.as_bytes())
+}
+
+/// Find the file of a module, such as `foo/bar.jq` or `foo/bar/bar.jq` for `foo/bar`.
+fn find_module(lib: &[PathBuf], path: &str) -> Option<PathBuf> {
+    let path = std::path::Path::new(path);
+    let last = path.file_name()?;
+    let jq = |file: PathBuf| {
+        let mut file = file.into_os_string();
+        file.push(".jq");
+        PathBuf::from(file)
+    };
+    let candidates = lib.iter().flat_map(|dir| {
+        let dir = dir.join(path);
+        [jq(dir.clone()), jq(dir.join(last))]
+    });
+    candidates.into_iter().find(|file| file.is_file())
+}
+
+/// Parse all modules with given imports, including those that they import, and add them to `defs`.
+///
+/// Modules that cannot be found are skipped, yielding an error on compilation.
+fn load_modules(
+    defs: &mut ParseCtx,
+    imports: &[jaq_syn::Import],
+    lib: &[PathBuf],
+    loaded: &mut std::collections::BTreeMap<String, String>,
+) -> Result<(), Error> {
+    for import in imports {
+        let path = &import.path.0;
+        if loaded.contains_key(path) {
+            continue;
+        }
+        // modules that cannot be found are recorded with empty source
+        loaded.insert(path.clone(), String::new());
+        let file = match find_module(lib, path) {
+            Some(file) => file,
+            None => continue,
+        };
+        let name = file.display().to_string();
+        let src = std::fs::read_to_string(&file).map_err(|e| Error::Io(Some(name.clone()), e))?;
+        let (module, errs) = jaq_parse::parse(&src, jaq_parse::module(jaq_parse::defs()));
+        if !errs.is_empty() {
+            return Err(parse_errors(errs, &src));
+        }
+        let module = module.unwrap();
+        loaded.insert(path.clone(), src.clone());
+        load_modules(defs, &module.imports, lib, loaded)?;
+        defs.insert_module(path.clone(), module, Some((name, src)));
     }
+    Ok(())
+}
+
+/// Open a file, memory mapping it if possible, and otherwise returning it unread.
+fn open_file(path: &std::path::Path) -> io::Result<Result<memmap2::Mmap, std::fs::File>> {
+    let file = std::fs::File::open(path)?;
+    Ok(unsafe { memmap2::Mmap::map(&file) }.map_err(|_| file))
 }
 
 /// Try to load file by memory mapping and fall back to regular loading if it fails.
@@ -291,8 +840,43 @@ fn json_slice(slice: &[u8]) -> impl Iterator<Item = io::Result<Val>> + '_ {
     })
 }
 
-fn json_read<'a>(read: impl BufRead + 'a) -> impl Iterator<Item = io::Result<Val>> + 'a {
-    let mut lexer = hifijson::IterLexer::new(read.bytes());
+/// Parse JSON values like [`json_slice`], setting `line` to
+/// the number of the line on which the last value parsed ends.
+fn json_slice_lines<'a>(
+    slice: &'a [u8],
+    line: &'a Cell<usize>,
+) -> impl Iterator<Item = io::Result<Val>> + 'a {
+    let mut lexer = hifijson::SliceLexer::new(slice);
+    // number of newlines before `pos`
+    let (mut pos, mut newlines) = (0, 0);
+    core::iter::from_fn(move || {
+        use hifijson::token::Lex;
+        let v = Val::parse(lexer.ws_token()?, &mut lexer).map_err(invalid_data);
+        let end = slice.len() - lexer.as_slice().len();
+        newlines += slice[pos..end].iter().filter(|c| **c == b'\n').count();
+        pos = end;
+        line.set(newlines + 1);
+        Some(v)
+    })
+}
+
+/// Parse JSON values from a reader, setting `line` to
+/// the number of the line on which the last value parsed ends.
+fn json_read<'a>(
+    read: impl BufRead + 'a,
+    line: &'a Cell<usize>,
+) -> impl Iterator<Item = io::Result<Val>> + 'a {
+    let mut newlines = 0;
+    let bytes = read.bytes().inspect(move |b| match b {
+        // the lexer reads one byte past the end of numbers, and
+        // if this byte is a newline, then the number ends on the previous line
+        Ok(b'\n') => {
+            newlines += 1;
+            line.set(newlines)
+        }
+        _ => line.set(newlines + 1),
+    });
+    let mut lexer = hifijson::IterLexer::new(bytes);
     core::iter::from_fn(move || {
         use hifijson::token::Lex;
         let v = Val::parse(lexer.ws_token()?, &mut lexer);
@@ -300,24 +884,205 @@ fn json_read<'a>(read: impl BufRead + 'a) -> impl Iterator<Item = io::Result<Val
     })
 }
 
-fn read_buffered<'a, R>(cli: &Cli, read: R) -> Box<dyn Iterator<Item = io::Result<Val>> + 'a>
+/// Record separator (RS) of JSON text sequences.
+const RS: u8 = 0x1e;
+
+/// Parse the values in the records of a JSON text sequence.
+///
+/// A record that is not valid JSON yields a warning, and its values are skipped.
+fn seq<'a, R: AsRef<[u8]>>(
+    records: impl Iterator<Item = io::Result<R>> + 'a,
+) -> impl Iterator<Item = io::Result<Val>> + 'a {
+    records.flat_map(|record| {
+        let vals = record.and_then(|r| json_slice(r.as_ref()).collect::<io::Result<Vec<_>>>());
+        match vals {
+            Ok(vals) => vals.into_iter().map(Ok).collect(),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                eprintln!("Warning: ignoring malformed record: {e}");
+                Vec::new()
+            }
+            Err(e) => Vec::from([Err(e)]),
+        }
+    })
+}
+
+/// Incremental JSON parser that yields the events of `tostream`.
+struct Stream<L> {
+    lexer: L,
+    /// path to the current value
+    path: Vec<Val>,
+    /// for every enclosing value, whether it is an object (or else an array)
+    objs: Vec<bool>,
+    /// token that was read, but not yet processed
+    token: Option<hifijson::Token>,
+    /// whether we expect a value to come next
+    value: bool,
+}
+
+impl<L: hifijson::LexAlloc> Stream<L> {
+    fn new(lexer: L) -> Self {
+        let (path, objs, token) = (Vec::new(), Vec::new(), None);
+        Self {
+            lexer,
+            path,
+            objs,
+            token,
+            value: true,
+        }
+    }
+
+    fn key(&mut self, token: hifijson::Token) -> Result<Val, hifijson::Error> {
+        use hifijson::Error;
+        let key = self
+            .lexer
+            .str_colon(token, |lexer| lexer.str_string().map_err(Error::Str))?;
+        Ok(Val::str(key.to_string()))
+    }
+
+    fn leaf(&mut self, v: Val) -> Val {
+        self.value = false;
+        Val::arr(Vec::from([Val::arr(self.path.clone()), v]))
+    }
+
+    fn event(&mut self) -> Result<Option<Val>, hifijson::Error> {
+        use hifijson::token::{Expect, Token};
+        loop {
+            if self.value {
+                let token = match self.token.take().or_else(|| self.lexer.ws_token()) {
+                    Some(token) => token,
+                    None if self.objs.is_empty() => return Ok(None),
+                    None => Err(Expect::Value)?,
+                };
+                match token {
+                    Token::LSquare => match self.lexer.ws_token().ok_or(Expect::ValueOrEnd)? {
+                        Token::RSquare => return Ok(Some(self.leaf(Val::arr(Vec::new())))),
+                        token => {
+                            self.objs.push(false);
+                            self.path.push(Val::Int(0));
+                            self.token = Some(token);
+                        }
+                    },
+                    Token::LCurly => match self.lexer.ws_token().ok_or(Expect::ValueOrEnd)? {
+                        Token::RCurly => return Ok(Some(self.leaf(Val::obj(Default::default())))),
+                        token => {
+                            let key = self.key(token)?;
+                            self.objs.push(true);
+                            self.path.push(key);
+                        }
+                    },
+                    token => {
+                        let v = Val::parse(token, &mut self.lexer)?;
+                        return Ok(Some(self.leaf(v)));
+                    }
+                }
+            } else if let Some(obj) = self.objs.last().copied() {
+                match self.lexer.ws_token().ok_or(Expect::CommaOrEnd)? {
+                    Token::Comma => {
+                        let next = match self.path.pop() {
+                            Some(Val::Int(i)) if !obj => Val::Int(i + 1),
+                            _ => {
+                                let token = self.lexer.ws_token().ok_or(Expect::String)?;
+                                self.key(token)?
+                            }
+                        };
+                        self.path.push(next);
+                        self.value = true;
+                    }
+                    Token::RSquare if !obj => return Ok(Some(self.close())),
+                    Token::RCurly if obj => return Ok(Some(self.close())),
+                    _ => Err(Expect::CommaOrEnd)?,
+                }
+            } else {
+                // we are at the top level, so we expect the next input value
+                self.value = true;
+            }
+        }
+    }
+
+    /// Yield a closing event for the last value in the current array or object.
+    fn close(&mut self) -> Val {
+        let ev = Val::arr(Vec::from([Val::arr(self.path.clone())]));
+        self.objs.pop();
+        self.path.pop();
+        ev
+    }
+}
+
+fn stream_slice(slice: &[u8]) -> impl Iterator<Item = io::Result<Val>> + '_ {
+    let mut stream = Some(Stream::new(hifijson::SliceLexer::new(slice)));
+    core::iter::from_fn(move || {
+        let ev = stream.as_mut()?.event().map_err(invalid_data);
+        if ev.is_err() {
+            // do not continue after an error
+            stream = None;
+        }
+        ev.transpose()
+    })
+}
+
+fn stream_read<'a>(read: impl BufRead + 'a) -> impl Iterator<Item = io::Result<Val>> + 'a {
+    let mut stream = Some(Stream::new(hifijson::IterLexer::new(read.bytes())));
+    core::iter::from_fn(move || {
+        let st = stream.as_mut()?;
+        let ev = st
+            .event()
+            .map_err(|e| core::mem::take(&mut st.lexer.error).unwrap_or_else(|| invalid_data(e)));
+        if ev.is_err() {
+            stream = None;
+        }
+        ev.transpose()
+    })
+}
+
+/// Read input values from a reader.
+///
+/// When reading JSON values, `line` is set to the line on which the last value read ends.
+fn read_buffered<'a, R>(
+    cli: &Cli,
+    mut read: R,
+    line: &'a Cell<usize>,
+) -> Box<dyn Iterator<Item = io::Result<Val>> + 'a>
 where
     R: BufRead + 'a,
 {
     if cli.raw_input {
         Box::new(raw_input(cli.slurp, read).map(|r| r.map(Val::str)))
+    } else if cli.stream {
+        Box::new(collect_if(cli.slurp, stream_read(read), Val::arr))
+    } else if let Some(codec) = cli.input_codec() {
+        let mut buf = Vec::new();
+        let vals = match read.read_to_end(&mut buf) {
+            Ok(_) => codec.decode(buf),
+            Err(e) => Box::new(core::iter::once(Err(e))),
+        };
+        Box::new(collect_if(cli.slurp, vals, Val::arr))
+    } else if cli.seq {
+        Box::new(collect_if(cli.slurp, seq(read.split(RS)), Val::arr))
     } else {
-        let vals = json_read(read);
+        let vals = json_read(read, line);
         Box::new(collect_if(cli.slurp, vals, Val::arr))
     }
 }
 
-fn read_slice<'a>(cli: &Cli, slice: &'a [u8]) -> Box<dyn Iterator<Item = io::Result<Val>> + 'a> {
+/// Read input values from a slice, like [`read_buffered`].
+fn read_slice<'a>(
+    cli: &Cli,
+    slice: &'a [u8],
+    line: &'a Cell<usize>,
+) -> Box<dyn Iterator<Item = io::Result<Val>> + 'a> {
     if cli.raw_input {
         let read = io::BufReader::new(slice);
         Box::new(raw_input(cli.slurp, read).map(|r| r.map(Val::str)))
+    } else if cli.stream {
+        Box::new(collect_if(cli.slurp, stream_slice(slice), Val::arr))
+    } else if let Some(codec) = cli.input_codec() {
+        let vals = codec.decode(slice);
+        Box::new(collect_if(cli.slurp, vals, Val::arr))
+    } else if cli.seq {
+        let records = slice.split(|b| *b == RS).map(Ok);
+        Box::new(collect_if(cli.slurp, seq(records), Val::arr))
     } else {
-        let vals = json_slice(slice);
+        let vals = json_slice_lines(slice, line);
         Box::new(collect_if(cli.slurp, vals, Val::arr))
     }
 }
@@ -363,43 +1128,70 @@ enum Error {
     Persist(tempfile::PersistError),
     FalseOrNull,
     NoOutput,
+    /// report and exit code of an error that was rendered already, e.g. by another thread
+    Rendered(Vec<u8>, u8),
 }
 
-impl Termination for Error {
-    fn report(self) -> ExitCode {
-        let exit = match self {
+impl Error {
+    /// Write a report of the error and return the exit code.
+    fn write_report(self, w: &mut impl Write) -> io::Result<u8> {
+        Ok(match self {
             Self::FalseOrNull => 1,
             Self::Io(prefix, e) => {
-                eprint!("Error: ");
+                write!(w, "Error: ")?;
                 if let Some(p) = prefix {
-                    eprint!("{p}: ");
+                    write!(w, "{p}: ")?;
                 }
-                eprintln!("{e}");
+                writeln!(w, "{e}")?;
                 2
             }
             Self::Persist(e) => {
-                eprintln!("Error: {e}");
+                writeln!(w, "Error: {e}")?;
                 2
             }
             Self::Chumsky(e) => {
                 for err in e {
-                    report(err.error)
-                        .eprint(ariadne::Source::from(err.filter))
-                        .unwrap();
+                    report(err.error).write(ariadne::Source::from(err.filter), &mut *w)?;
                 }
                 3
             }
             Self::NoOutput => 4,
             Self::Parse(e) => {
-                eprintln!("Error: failed to parse: {e}");
+                writeln!(w, "Error: failed to parse: {e}")?;
                 5
             }
+            Self::Jaq(jaq_interpret::Error::Halt(v, code)) => {
+                match v {
+                    // strings are printed without quotes and without trailing newline
+                    Some(Val::Str(s)) => write!(w, "{s}")?,
+                    Some(v) => writeln!(w, "{v}")?,
+                    None => (),
+                }
+                // like jq, use only the lowest 8 bits of the exit code
+                code as u8
+            }
             Self::Jaq(e) => {
-                eprintln!("Error: {e}");
+                match e.loc() {
+                    Some(loc) => {
+                        let (file, src) = &*loc.source;
+                        let source = (file.as_str(), ariadne::Source::from(src));
+                        report_at(e.to_string(), loc).write(source, &mut *w)?
+                    }
+                    None => writeln!(w, "Error: {e}")?,
+                }
                 5
             }
-        };
-        ExitCode::from(exit)
+            Self::Rendered(report, code) => {
+                w.write_all(&report)?;
+                code
+            }
+        })
+    }
+}
+
+impl Termination for Error {
+    fn report(self) -> ExitCode {
+        ExitCode::from(self.write_report(&mut io::stderr().lock()).unwrap())
     }
 }
 
@@ -415,14 +1207,29 @@ impl From<Vec<ParseError>> for Error {
     }
 }
 
+/// Position of the last input value that was read.
+#[derive(Default)]
+struct Position<'a> {
+    /// file from which the value was read, or `None` for standard input
+    file: Cell<Option<&'a str>>,
+    /// number of the line on which the value ends, or 0 if unknown
+    line: Cell<usize>,
+}
+
 /// Run a filter with given input values and run `f` for every value output.
 ///
+/// The filter obtains the file name from `pos` whenever it runs `input_filename`, and
+/// it is run with the line number in `pos` at the time that its input is read.
+/// Diagnostics emitted by the filter, such as by `debug`, are printed to standard error.
+///
 /// This function cannot return an `Iterator` because it creates an `RcIter`.
 /// This is most unfortunate. We should think about how to simplify this ...
 fn run(
     cli: &Cli,
     filter: &Filter,
     vars: Vec<Val>,
+    pos: &Position,
+    profile: Option<&Profile>,
     iter: impl Iterator<Item = io::Result<Val>>,
     mut f: impl FnMut(Val) -> io::Result<()>,
 ) -> Result<Option<bool>, Error> {
@@ -435,45 +1242,92 @@ fn run(
     let iter = RcIter::new(iter);
     let null = RcIter::new(null);
 
-    let ctx = Ctx::new(vars, &iter);
+    let diagnose = |d| {
+        // like `eprintln!`, but without panicking if standard error is closed
+        let _ = print_diagnostic(d);
+    };
+    let file = || pos.file.get();
+    let ctx = Ctx::new(vars, &iter).with_diagnostics(&diagnose);
+    let ctx = ctx.with_input_filename(&file);
+    let ctx = match profile {
+        Some(profile) => ctx.with_profile(profile),
+        None => ctx,
+    };
 
     for item in if cli.null_input { &null } else { &iter } {
         let input = item.map_err(Error::Parse)?;
         //println!("Got {:?}", input);
-        for output in filter.run((ctx.clone(), input)) {
-            let output = output.map_err(Error::Jaq)?;
-            last = Some(output.as_bool());
-            f(output)?;
+        let ctx = ctx.clone().with_input_line_number(pos.line.get());
+        let outputs = filter.run((ctx, input)).map(|y| {
+            let y = y.map_err(Error::Jaq)?;
+            f(y.clone())?;
+            Ok::<_, Error>(y)
+        });
+        if let (Some(y), _) = last_count(outputs)? {
+            last = Some(y.as_bool());
         }
     }
     Ok(last)
 }
 
-fn print(cli: &Cli, val: Val, writer: &mut impl Write) -> io::Result<()> {
-    use colored_json::{ColoredFormatter, CompactFormatter, PrettyFormatter};
-    match val {
-        Val::Str(s) if cli.raw_output => write!(writer, "{s}")?,
-        _ => {
-            let val = serde_json::Value::from(val);
-            let mode = cli.color_mode();
-            let indent = if cli.tab {
-                String::from("\t")
-            } else {
-                " ".repeat(cli.indent)
-            };
+/// Print a diagnostic emitted by a filter to standard error.
+fn print_diagnostic(d: Diagnostic) -> io::Result<()> {
+    let mut stderr = io::stderr().lock();
+    let format = json::Format::default();
+    match d {
+        Diagnostic::Debug(v) => {
+            stderr.write_all(b"[\"DEBUG:\",")?;
+            format.write(&mut stderr, &v)?;
+            stderr.write_all(b"]\n")
+        }
+        Diagnostic::Stderr(Val::Str(s)) => stderr.write_all(s.as_bytes()),
+        Diagnostic::Stderr(v) => format.write(&mut stderr, &v),
+    }
+}
 
-            // this looks ugly, but it is hard to abstract over the `Formatter` because
-            // we cannot create a `Box<dyn Formatter>` because
-            // Rust says that the `Formatter` trait is not "object safe"
-            if cli.compact_output {
-                ColoredFormatter::new(CompactFormatter).write_colored_json(&val, writer, mode)
-            } else {
-                ColoredFormatter::new(PrettyFormatter::with_indent(indent.as_bytes()))
-                    .write_colored_json(&val, writer, mode)
-            }?;
+/// Print the statistics of called definitions to standard error.
+fn print_profile(stats: &[jaq_interpret::Stats]) -> io::Result<()> {
+    let mut stderr = io::stderr().lock();
+    writeln!(
+        stderr,
+        "{:>10} {:>10} {:>12}  definition",
+        "calls", "outputs", "time (ms)"
+    )?;
+    for s in stats {
+        let ms = s.time.as_secs_f64() * 1000.0;
+        let (calls, outputs) = (s.calls, s.outputs);
+        write!(
+            stderr,
+            "{calls:>10} {outputs:>10} {ms:>12.3}  {}/{}",
+            s.name, s.arity
+        )?;
+        match &s.loc {
+            Some((file, line)) => writeln!(stderr, " ({file}:{line})")?,
+            None => writeln!(stderr)?,
         }
+    }
+    Ok(())
+}
+
+/// Print a value, where `first` indicates whether no value has been printed yet.
+fn print(cli: &Cli, val: Val, first: &mut bool, writer: &mut impl Write) -> io::Result<()> {
+    if let Some(codec) = cli.output_codec() {
+        return codec.encode(val, core::mem::replace(first, false), writer);
+    }
+    if let Some(sep) = &cli.output_separator {
+        if !*first {
+            write!(writer, "{sep}")?;
+        }
+    }
+    *first = false;
+    if cli.seq {
+        writer.write_all(&[RS])?;
+    }
+    match val {
+        Val::Str(s) if cli.raw_output && !cli.ascii_output => write!(writer, "{s}")?,
+        _ => cli.json.write(writer, &val)?,
     };
-    if !cli.join_output {
+    if !cli.join_output && cli.output_separator.is_none() {
         writeln!(writer)?;
     }
     Ok(())
@@ -481,9 +1335,10 @@ fn print(cli: &Cli, val: Val, writer: &mut impl Write) -> io::Result<()> {
 
 fn with_stdout<T>(f: impl FnOnce(&mut io::StdoutLock) -> Result<T, Error>) -> Result<T, Error> {
     let mut stdout = io::stdout().lock();
-    let y = f(&mut stdout)?;
+    // flush also on error, such as when `halt` is called after some output
+    let y = f(&mut stdout);
     stdout.flush()?;
-    Ok(y)
+    y
 }
 
 fn report<'a>(e: chumsky::error::Simple<String>) -> ariadne::Report<'a> {
@@ -554,11 +1409,57 @@ fn report<'a>(e: chumsky::error::Simple<String>) -> ariadne::Report<'a> {
     report.with_config(config).finish()
 }
 
-fn run_test(test: jaq_syn::test::Test<String>) -> Result<(Val, Val), Error> {
+/// Report an error that occurred when running the filter at the given location.
+fn report_at<'a>(
+    msg: String,
+    loc: &'a jaq_interpret::Loc,
+) -> ariadne::Report<'a, (&'a str, jaq_syn::Span)> {
+    use ariadne::{Color, Label, Report, ReportKind};
+
+    let isatty = atty::is(atty::Stream::Stderr);
+    let red = if isatty { Color::Red } else { Color::Unset };
+    let config = ariadne::Config::default().with_color(isatty);
+
+    let file = loc.source.0.as_str();
+    Report::build(ReportKind::Error, file, loc.span.start)
+        .with_message(&msg)
+        .with_label(
+            Label::new((file, loc.span.clone()))
+                .with_message(msg)
+                .with_color(red),
+        )
+        .with_config(config)
+        .finish()
+}
+
+/// Print warnings about likely mistakes in a filter that compiles.
+fn print_lints(src: &str) -> io::Result<()> {
+    use ariadne::{Color, Label, Report, ReportKind};
+
+    let isatty = atty::is(atty::Stream::Stderr);
+    let yellow = if isatty { Color::Yellow } else { Color::Unset };
+    let config = ariadne::Config::default().with_color(isatty);
+
+    let (module, _) = jaq_parse::parse(src, jaq_parse::module(jaq_parse::main()));
+    let lints = module.map_or_else(Vec::new, |m| jaq_syn::lint::lint(&m.body));
+    let mut stderr = io::stderr().lock();
+    for (lint, span) in lints {
+        let msg = lint.to_string();
+        Report::build(ReportKind::Warning, (), span.start)
+            .with_message(&msg)
+            .with_label(Label::new(span).with_message(msg).with_color(yellow))
+            .with_config(config)
+            .finish()
+            .write(ariadne::Source::from(src), &mut stderr)?;
+    }
+    Ok(())
+}
+
+fn run_test(test: jaq_syn::test::Test<String>, lib: &[PathBuf]) -> Result<(Val, Val), Error> {
     let inputs = RcIter::new(Box::new(core::iter::empty()));
     let ctx = Ctx::new(Vec::new(), &inputs);
 
-    let filter = parse(&test.filter, Vec::new())?;
+    let filter = parse("<top-level>", &test.filter, Vec::new(), lib, None)?;
 
     use hifijson::token::Lex;
     let json = |s: String| {
@@ -572,14 +1473,14 @@ fn run_test(test: jaq_syn::test::Test<String>) -> Result<(Val, Val), Error> {
     Ok((Val::arr(expect?), Val::arr(obtain.map_err(Error::Jaq)?)))
 }
 
-fn run_tests(file: std::fs::File) -> ExitCode {
+fn run_tests(file: std::fs::File, lib: &[PathBuf]) -> ExitCode {
     let lines = io::BufReader::new(file).lines().map(|l| l.unwrap());
     let tests = jaq_syn::test::Parser::new(lines);
 
     let (mut passed, mut total) = (0, 0);
     for test in tests {
         println!("Testing {}", test.filter);
-        match run_test(test) {
+        match run_test(test, lib) {
             Err(e) => eprintln!("{e:?}"),
             Ok((expect, obtain)) if expect != obtain => {
                 eprintln!("expected {expect}, obtained {obtain}",);
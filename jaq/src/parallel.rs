@@ -0,0 +1,185 @@
+//! Processing of inputs with one value per line on multiple threads.
+//!
+//! A reader thread splits the input into chunks of whole lines and
+//! distributes them in turn among the workers.
+//! Every worker compiles its own filter, because filters and values
+//! cannot be shared between threads, and prints the outputs of a chunk to a buffer.
+//! The main thread collects the buffers in turn from the workers,
+//! which yields the outputs in the order of their inputs.
+
+use super::{print, read_slice, run, with_stdout, Cli, Error, Position};
+use jaq_interpret::{Filter, Val};
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread;
+
+/// Number of bytes after which the lines read so far are sent to a worker.
+const CHUNK_SIZE: usize = 1 << 16;
+
+/// Number of chunks that may wait for a worker or for the main thread.
+const QUEUE_SIZE: usize = 2;
+
+/// Lines read from the given file (or standard input if `None`).
+type Chunk = (Option<String>, io::Result<Vec<u8>>);
+
+/// Outputs of a chunk.
+struct Output {
+    /// printed output values
+    out: Vec<u8>,
+    /// last output value converted to a boolean
+    last: Option<bool>,
+    /// error report and exit code, if an error occurred
+    err: Option<(Vec<u8>, u8)>,
+}
+
+/// Run the filter yielded by `compile` on all inputs with the given number of workers.
+///
+/// If the number of workers is 0, then use as many workers as there are CPUs.
+pub fn run_parallel<C>(
+    cli: &Cli,
+    workers: usize,
+    compile: &C,
+    files: &[&String],
+) -> Result<Option<bool>, Error>
+where
+    C: Fn() -> Result<(Filter, Vec<Val>), Error> + Sync,
+{
+    let workers = match workers {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    thread::scope(|s| {
+        let (mut inputs, mut outputs) = (Vec::new(), Vec::new());
+        for _ in 0..workers {
+            let (chunk_tx, chunk_rx) = sync_channel(QUEUE_SIZE);
+            let (output_tx, output_rx) = sync_channel(QUEUE_SIZE);
+            let mut thread = thread::Builder::new();
+            if let Some(mib) = cli.stack_size {
+                thread = thread.stack_size(mib.saturating_mul(1 << 20));
+            }
+            thread.spawn_scoped(s, move || work(cli, compile, chunk_rx, output_tx))?;
+            inputs.push(chunk_tx);
+            outputs.push(output_rx);
+        }
+        s.spawn(move || read(files, inputs));
+        // when this returns, the receivers are dropped,
+        // which makes all other threads stop
+        collect(outputs)
+    })
+}
+
+/// Send the lines of all input files (or of standard input if there are none) to the workers.
+fn read(files: &[&String], workers: Vec<SyncSender<Chunk>>) {
+    let mut workers = workers.iter().cycle();
+    let mut send = |chunk| workers.next().unwrap().send(chunk).is_ok();
+    if files.is_empty() {
+        read_chunks(None, io::stdin().lock(), &mut send);
+    }
+    for file in files {
+        let name = Some(file.to_string());
+        let more = match std::fs::File::open(file) {
+            Ok(f) => read_chunks(name, io::BufReader::new(f), &mut send),
+            Err(e) => {
+                send((name, Err(e)));
+                false
+            }
+        };
+        if !more {
+            return;
+        }
+    }
+}
+
+/// Send chunks of lines to `send`, returning whether more chunks are accepted.
+fn read_chunks(
+    file: Option<String>,
+    mut read: impl BufRead,
+    send: &mut impl FnMut(Chunk) -> bool,
+) -> bool {
+    loop {
+        let mut buf = Vec::new();
+        while buf.len() < CHUNK_SIZE {
+            match read.read_until(b'\n', &mut buf) {
+                Ok(0) => return buf.is_empty() || send((file.clone(), Ok(buf))),
+                Ok(_) => (),
+                Err(e) => {
+                    send((file, Err(e)));
+                    return false;
+                }
+            }
+        }
+        if !send((file.clone(), Ok(buf))) {
+            return false;
+        }
+    }
+}
+
+/// Run the filter on all chunks received, and send back their outputs.
+fn work<C>(cli: &Cli, compile: &C, chunks: Receiver<Chunk>, outputs: SyncSender<Output>)
+where
+    C: Fn() -> Result<(Filter, Vec<Val>), Error>,
+{
+    let (filter, vars) = match compile() {
+        Ok(compiled) => compiled,
+        Err(e) => {
+            let err = Some(render(e));
+            let _ = outputs.send(Output {
+                out: Vec::new(),
+                last: None,
+                err,
+            });
+            return;
+        }
+    };
+    for (file, chunk) in chunks {
+        let mut out = Vec::new();
+        let mut first = true;
+        let last = chunk
+            .map_err(|e| Error::Io(file.clone(), e))
+            .and_then(|chunk| {
+                let pos = Position::default();
+                pos.file.set(file.as_deref());
+                let inputs = read_slice(cli, &chunk, &pos.line);
+                run(cli, &filter, vars.clone(), &pos, None, inputs, |v| {
+                    print(cli, v, &mut first, &mut out)
+                })
+            });
+        let output = match last {
+            Ok(last) => Output {
+                out,
+                last,
+                err: None,
+            },
+            Err(e) => Output {
+                out,
+                last: None,
+                err: Some(render(e)),
+            },
+        };
+        if outputs.send(output).is_err() {
+            return;
+        }
+    }
+}
+
+/// Print the outputs of all workers in turn, until a worker stops or fails.
+fn collect(outputs: Vec<Receiver<Output>>) -> Result<Option<bool>, Error> {
+    let mut last = None;
+    with_stdout(|stdout| {
+        for output in outputs.iter().cycle().map_while(|rx| rx.recv().ok()) {
+            stdout.write_all(&output.out)?;
+            last = output.last.or(last);
+            if let Some((report, code)) = output.err {
+                return Err(Error::Rendered(report, code));
+            }
+        }
+        Ok(last)
+    })
+}
+
+/// Render an error, which can then be sent to another thread.
+fn render(e: Error) -> (Vec<u8>, u8) {
+    let mut report = Vec::new();
+    let code = e.write_report(&mut report).unwrap();
+    (report, code)
+}
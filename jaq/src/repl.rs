@@ -0,0 +1,142 @@
+//! Interactive mode that reads filters line by line and runs them on the same inputs.
+//!
+//! The inputs are read only once, when the REPL starts.
+//! Every line is compiled and run separately, so errors in one line
+//! are reported without leaving the REPL.
+
+use super::{binds, load_file, parse, print, read_slice, run, with_stdout, Cli, Error, Position};
+use jaq_interpret::Val;
+use std::cell::Cell;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const HELP: &str = "\
+Every line is run as filter on the inputs, and
+`$last` is bound to the last output of the previous filter.
+Furthermore, the following commands are available:
+
+:let $x = f   bind the last output of `f` to `$x`
+:history      print all lines entered so far
+:help         print this help
+:quit         leave (also on end of input)
+";
+
+/// Variables that can be used by the filters entered into the REPL.
+struct Vars(Vec<(String, Val)>);
+
+impl Vars {
+    /// Set a variable, replacing the previous value of the variable if there is one.
+    fn set(&mut self, name: &str, v: Val) {
+        match self.0.iter_mut().find(|(x, _)| x == name) {
+            Some((_, x)) => *x = v,
+            None => self.0.push((name.to_string(), v)),
+        }
+    }
+}
+
+/// Read filters from standard input and run them on the values in the input files.
+///
+/// If there are no input files, then the filters are run with `null` as input.
+pub fn run_repl(cli: &Cli, lib: &[PathBuf]) -> Result<ExitCode, Error> {
+    let mut inputs = Vec::new();
+    for name in &cli.args {
+        let io_err = |e| Error::Io(Some(name.to_string()), e);
+        let file = load_file(std::path::Path::new(name)).map_err(io_err)?;
+        for v in read_slice(cli, &file, &Cell::new(0)) {
+            inputs.push(v.map_err(io_err)?);
+        }
+    }
+    if cli.args.is_empty() {
+        inputs.push(Val::Null);
+    }
+
+    let mut vars = Vars(binds(cli)?);
+    vars.set("last", Val::Null);
+    let mut history = Vec::new();
+    let interactive = atty::is(atty::Stream::Stdin);
+    let mut stdin = io::stdin().lock();
+    loop {
+        if interactive {
+            eprint!("> ");
+            io::stderr().flush()?;
+        }
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            return Ok(ExitCode::SUCCESS);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        history.push(line.to_string());
+
+        let mut stderr = io::stderr();
+        let (cmd, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let result = match cmd {
+            ":quit" => return Ok(ExitCode::SUCCESS),
+            ":help" => write!(stderr, "{HELP}").map_err(Error::from),
+            ":history" => history
+                .iter()
+                .enumerate()
+                .try_for_each(|(i, line)| writeln!(stderr, "{i:>4}  {line}").map_err(Error::from)),
+            ":let" => match let_var(rest) {
+                Some((x, f)) => eval(cli, lib, &vars, f, &inputs).map(|last| {
+                    vars.set(x, last.unwrap_or(Val::Null));
+                }),
+                None => writeln!(stderr, "Error: expected `:let $x = f`").map_err(Error::from),
+            },
+            cmd if cmd.starts_with(':') => {
+                writeln!(stderr, "Error: unknown command {cmd}").map_err(Error::from)
+            }
+            _ => eval(cli, lib, &vars, line, &inputs).map(|last| {
+                vars.set("last", last.unwrap_or(Val::Null));
+            }),
+        };
+        match result {
+            Ok(()) => (),
+            Err(e @ Error::Jaq(jaq_interpret::Error::Halt(..))) => return Err(e),
+            Err(e) => {
+                e.write_report(&mut stderr.lock())?;
+            }
+        }
+    }
+}
+
+/// Split `$x = f` into `x` and `f`.
+fn let_var(s: &str) -> Option<(&str, &str)> {
+    let (x, f) = s.trim().strip_prefix('$')?.split_once('=')?;
+    let x = x.trim();
+    let ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let digit = x.starts_with(|c: char| c.is_ascii_digit());
+    (!x.is_empty() && !digit && x.chars().all(ident)).then_some((x, f))
+}
+
+/// Compile a filter and print its outputs for all inputs, returning its last output.
+fn eval(
+    cli: &Cli,
+    lib: &[PathBuf],
+    vars: &Vars,
+    f: &str,
+    inputs: &[Val],
+) -> Result<Option<Val>, Error> {
+    let (names, vals) = vars.0.iter().cloned().unzip();
+    let filter = parse("<repl>", f, names, lib, None)?;
+    let inputs = inputs.iter().cloned().map(Ok);
+    let (mut first, mut last) = (true, None);
+    with_stdout(|out| {
+        run(
+            cli,
+            &filter,
+            vals,
+            &Position::default(),
+            None,
+            inputs,
+            |v| {
+                last = Some(v.clone());
+                print(cli, v, &mut first, out)
+            },
+        )
+    })?;
+    Ok(last)
+}
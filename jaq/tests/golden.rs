@@ -1,15 +1,36 @@
 use std::{env, io, process, str};
 
-fn golden_test(args: &[&str], input: &str, out_ex: &str) -> io::Result<()> {
-    let mut child = process::Command::new(env!("CARGO_BIN_EXE_jaq"))
-        .args(args)
+/// Return a command that runs jaq with the given arguments.
+fn command(args: &[&str]) -> process::Command {
+    let mut cmd = process::Command::new(env!("CARGO_BIN_EXE_jaq"));
+    cmd.args(args);
+    cmd
+}
+
+/// Run a command with the given standard input and return its output.
+fn output(cmd: &mut process::Command, stdin: &[u8]) -> io::Result<process::Output> {
+    use io::Write;
+    let mut child = cmd
         .stdin(process::Stdio::piped())
         .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
         .spawn()?;
-
-    use io::Write;
-    child.stdin.take().unwrap().write_all(input.as_bytes())?;
+    let mut child_stdin = child.stdin.take().unwrap();
+    let stdin = stdin.to_vec();
+    // write the input concurrently, so that jaq never blocks on a full output pipe
+    let writer = std::thread::spawn(move || child_stdin.write_all(&stdin));
     let output = child.wait_with_output()?;
+    writer.join().unwrap()?;
+    Ok(output)
+}
+
+/// Run jaq with the given arguments and standard input and return its output.
+fn jaq(args: &[&str], stdin: &[u8]) -> io::Result<process::Output> {
+    output(&mut command(args), stdin)
+}
+
+fn golden_test(args: &[&str], input: &str, out_ex: &str) -> io::Result<()> {
+    let output = jaq(args, input.as_bytes())?;
     assert!(output.status.success());
 
     let out_act = str::from_utf8(&output.stdout).expect("invalid UTF-8 in output");
@@ -36,6 +57,59 @@ test!(no_args, &[], "[0, 1]", "[\n  0,\n  1\n]");
 test!(one, &["1"], "0", "1");
 test!(sparse, &["."], "[2,3]", "[\n  2,\n  3\n]");
 
+test!(
+    indent,
+    &["--indent", "1", "."],
+    r#"{"a": [1, {}], "b": []}"#,
+    "{\n \"a\": [\n  1,\n  {}\n ],\n \"b\": []\n}"
+);
+test!(tab, &["--tab", "."], "[[0]]", "[\n\t[\n\t\t0\n\t]\n]");
+
+#[test]
+fn indent_too_wide() -> io::Result<()> {
+    let output = jaq(&["-n", "--indent", "8", "1"], b"")?;
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    Ok(())
+}
+test!(
+    ascii_output,
+    &["-ac", "."],
+    r#"["é\u0001😀", {"ä": "\"\\"}]"#,
+    r#"["\u00e9\u0001\ud83d\ude00",{"\u00e4":"\"\\"}]"#
+);
+test!(ascii_raw_output, &["-ar", "."], r#""é""#, r#""\u00e9""#);
+
+#[test]
+fn colors() -> io::Result<()> {
+    let colored = |colors: Option<&str>| {
+        let mut cmd = command(&["-nc", "--color", "always", r#"[null, {"a": "b"}]"#]);
+        match colors {
+            Some(colors) => cmd.env("JAQ_COLORS", colors),
+            None => cmd.env_remove("JAQ_COLORS"),
+        };
+        output(&mut cmd, b"")
+    };
+    let output = colored(None)?;
+    let default = concat!(
+        "\x1b[1;39m[\x1b[0m\x1b[1;30mnull\x1b[0m\x1b[1;39m,\x1b[0m",
+        "\x1b[1;39m{\x1b[0m\x1b[34;1m\"a\"\x1b[0m\x1b[1;39m:\x1b[0m\x1b[0;32m\"b\"\x1b[0m",
+        "\x1b[1;39m}\x1b[0m\x1b[1;39m]\x1b[0m"
+    );
+    assert_eq!(str::from_utf8(&output.stdout).unwrap().trim_end(), default);
+
+    let output = colored(Some("0;90::::0;33"))?;
+    let custom = default.replace("1;30", "0;90").replace("0;32", "0;33");
+    assert_eq!(str::from_utf8(&output.stdout).unwrap().trim_end(), custom);
+
+    // invalid colors yield a warning and the default colors
+    let output = colored(Some("red"))?;
+    assert!(output.status.success());
+    assert!(!output.stderr.is_empty());
+    assert_eq!(str::from_utf8(&output.stdout).unwrap().trim_end(), default);
+    Ok(())
+}
+
 test!(
     arg,
     &["--arg", "x", "y", "--arg", "a", "b", "$x + $a"],
@@ -43,6 +117,83 @@ test!(
     "\"yb\""
 );
 
+test!(
+    argjson,
+    &[
+        "-c",
+        "--argjson",
+        "x",
+        r#"{"a": [1]}"#,
+        "--arg",
+        "y",
+        "2",
+        "$x, $ARGS.named"
+    ],
+    "0",
+    r#"{"a":[1]}
+{"y":"2","x":{"a":[1]}}"#
+);
+
+#[test]
+fn argjson_invalid() -> io::Result<()> {
+    let output = jaq(&["-n", "--argjson", "x", "1 2", "$x"], b"")?;
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    Ok(())
+}
+
+test!(
+    args_positional,
+    &[
+        "-c",
+        "$ARGS",
+        "--args",
+        "a",
+        "1",
+        "--jsonargs",
+        "1",
+        r#"{"b": [2]}"#,
+        "--args",
+        "null"
+    ],
+    "0",
+    r#"{"positional":["a","1",1,{"b":[2]},"null"],"named":{}}"#
+);
+
+test!(
+    args_filter,
+    &["-n", "-c", "--jsonargs", "$ARGS.positional", "null", "[]"],
+    "",
+    "[null,[]]"
+);
+
+// numbers parsed by `fromjson` are the same as numbers parsed from input
+test!(
+    fromjson_num,
+    &[
+        "-c",
+        r#"., (tojson | fromjson), ("[9007199254740993, 1e1000, 1.0]" | fromjson)"#
+    ],
+    "[9007199254740993, 1e1000, 1.0]",
+    r#"[9007199254740993,1e1000,1.0]
+[9007199254740993,1e1000,1.0]
+[9007199254740993,1e1000,1.0]"#
+);
+
+// number literals are printed verbatim unless arithmetic is performed on them
+test!(
+    num_literal,
+    &[
+        "-c",
+        r#"., [1E+1000, 0.1000000000000000000000001, 1.50], (.[1] | . + 0), (1.50 | . * 2)"#
+    ],
+    "[1E+1000, 0.1000000000000000000000001, 1.50]",
+    r#"[1E+1000,0.1000000000000000000000001,1.50]
+[1E+1000,0.1000000000000000000000001,1.50]
+0.1
+3.0"#
+);
+
 test!(
     compact,
     &["-c", "."],
@@ -82,3 +233,560 @@ test!(
 "Two"
 "Three""#
 );
+
+test!(
+    output_separator,
+    &["-n", "--output-separator", ",", "1, 2, 3"],
+    "",
+    "1,2,3"
+);
+
+test!(
+    output_separator_raw,
+    &["-r", "--output-separator", " | ", ".[]"],
+    r#"["a", "b"] ["c"]"#,
+    "a | b | c"
+);
+
+test!(
+    float_format_shortest,
+    &["-c", "[1 / 10, 1 / 4, 1e100 * 1, 1e-5 * 1]"],
+    "null",
+    "[0.1,0.25,1e100,0.00001]"
+);
+
+test!(
+    float_format_fixed,
+    &[
+        "-c",
+        "--float-format",
+        "fixed",
+        "[1 / 10, 1 / 4, 1e100 * 1, 1e-5 * 1]"
+    ],
+    "null",
+    "[0.10000000000000001,0.25,1e+100,1.0000000000000001e-05]"
+);
+
+// floats are formatted while writing, also inside of nested values
+test!(
+    float_format_fixed_nested,
+    &["--float-format", "fixed", "--indent", "1", "{a: [1 / 10]}"],
+    "null",
+    r#"{
+ "a": [
+  0.10000000000000001
+ ]
+}"#
+);
+
+test!(
+    format,
+    &["--format", "def f:.+1;[.[]|f]|{a:(1,2)}"],
+    "",
+    "def f: . + 1;\n[.[] | f] | {a: (1, 2)}"
+);
+
+#[test]
+fn check() -> io::Result<()> {
+    let check = |f: &str| jaq(&["--check", f], b"");
+
+    let output = check("def f: . + 1; [.[] | f]")?;
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let output = check("def f: . + 1; [.[] | g]")?;
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+
+    // likely mistakes yield warnings, but do not make the check fail
+    let output = check("def f: . + 1; .[] as $x | 1")?;
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("unused function f/0"));
+    assert!(stderr.contains("unused variable $x"));
+    Ok(())
+}
+
+test!(
+    stream,
+    &["-c", "--stream", "."],
+    r#"{"a": [1, {"b": []}]} 2"#,
+    r#"[["a",0],1]
+[["a",1,"b"],[]]
+[["a",1,"b"]]
+[["a",1]]
+[["a"]]
+[[],2]"#
+);
+
+// malformed records are skipped, and multi-line records are supported
+test!(
+    seq,
+    &["-c", "--seq", "."],
+    "\u{1e}[1,\n2]\n\u{1e}{\"a\":\n\u{1e}3 4",
+    "\u{1e}[1,2]\n\u{1e}3\n\u{1e}4"
+);
+
+// text sequences have to end every value with a newline
+#[test]
+fn seq_output_separator() -> io::Result<()> {
+    let output = jaq(&["-n", "--seq", "--output-separator", ";", "1, 2"], b"")?;
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    Ok(())
+}
+
+test!(
+    stream_fromstream,
+    &[
+        "-c",
+        "-n",
+        "--stream",
+        "fromstream(1 | truncate_stream(inputs))"
+    ],
+    r#"{"a": 1, "b": [2, 3]}"#,
+    "[2,3]"
+);
+
+test!(
+    yaml_input,
+    &["-c", "--yaml-input", "."],
+    "a: [1, 2.5, x]\n1: !tag true\n---\n- null\n",
+    r#"{"a":[1,2.5,"x"],"1":true}
+[null]"#
+);
+
+test!(
+    yaml_output,
+    &["--yaml-output", ".[]"],
+    r#"[{"a": [1, "x"], "b": {}}, null]"#,
+    "a:\n- 1\n- x\nb: {}\n---\nnull"
+);
+
+#[test]
+fn yaml_output_big() -> io::Result<()> {
+    let output = jaq(&["-n", "--yaml-output", "[18446744073709551615]"], b"")?;
+    assert_eq!(output.stdout, b"- 18446744073709551615\n");
+    let output = jaq(&["-n", "--yaml-output", "[18446744073709551616]"], b"")?;
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    Ok(())
+}
+
+test!(
+    toml_input,
+    &["-c", "--toml-input", "."],
+    "a = 1979-05-27T07:32:00Z\n[t]\nx = [1, 2.5]\n",
+    r#"{"a":"1979-05-27T07:32:00Z","t":{"x":[1,2.5]}}"#
+);
+
+test!(
+    toml_output,
+    &["--toml-output", "."],
+    r#"{"t": {"x": 1}, "a": [{"b": "c"}]}"#,
+    "[[a]]\nb = \"c\"\n\n[t]\nx = 1"
+);
+
+#[test]
+fn toml_output_invalid() -> io::Result<()> {
+    let toml = |f: &str| jaq(&["-n", "--toml-output", f], b"");
+    let big = r#"{"a": 9223372036854775808}"#;
+    for f in [r#"{"a": null}"#, "[1]", r#"{"a": [1, {}]}"#, big] {
+        let output = toml(f)?;
+        assert!(!output.status.success());
+        assert!(output.stdout.is_empty());
+    }
+    Ok(())
+}
+
+#[test]
+fn binary() -> io::Result<()> {
+    let run = jaq;
+
+    // {"a": [1, "x"]}
+    let cbor = [0xa1, 0x61, 0x61, 0x82, 0x01, 0x61, 0x78];
+    let msgpack = [0x81, 0xa1, 0x61, 0x92, 0x01, 0xa1, 0x78];
+    let json = br#"{"a": [1, "x"]}"#;
+    let two = "{\"a\":[1,\"x\"]}\n".repeat(2);
+
+    assert_eq!(run(&["--cbor-output", "."], json)?.stdout, cbor);
+    assert_eq!(run(&["--msgpack-output", "."], json)?.stdout, msgpack);
+    let output = run(&["-c", "--cbor-input", "."], &[cbor, cbor].concat())?;
+    assert_eq!(output.stdout, two.as_bytes());
+    let output = run(
+        &["-c", "--msgpack-input", "."],
+        &[msgpack, msgpack].concat(),
+    )?;
+    assert_eq!(output.stdout, two.as_bytes());
+    let output = run(&["--cbor-input", "--msgpack-output", "."], &cbor)?;
+    assert_eq!(output.stdout, msgpack);
+
+    let output = run(&["--cbor-input", "."], &cbor[..3])?;
+    assert!(!output.status.success());
+    Ok(())
+}
+
+#[test]
+fn modules() -> io::Result<()> {
+    let lib = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/lib");
+    let run = |f: &str| {
+        let mut cmd = command(&["-n", "-c", "-L", lib, f]);
+        output(cmd.env_remove("JAQ_LIBRARY_PATH"), b"")
+    };
+
+    let output = run(r#"import "m" as m; import "sub" as s; 1 | m::inc, s::twice, m::loc.line"#)?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"2\n3\n3\n");
+
+    // `$__loc__` in a module yields the file of the module
+    let output = run(r#"import "m" as m; m::loc.file"#)?;
+    assert_eq!(output.stdout, format!("\"{lib}/m.jq\"\n").as_bytes());
+
+    let output = run(r#"include "m"; 1 | inc"#)?;
+    assert_eq!(output.stdout, b"2\n");
+
+    let output = run(r#"import "n" as n; 1"#)?;
+    assert_eq!(output.status.code(), Some(3));
+    Ok(())
+}
+
+test!(
+    loc,
+    &["-c", "$__loc__, input_filename"],
+    "0",
+    r#"{"file":"<top-level>","line":1}
+null"#
+);
+
+#[test]
+fn input_filename() -> io::Result<()> {
+    let file = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/lib/m.jq");
+    let output = jaq(&["-R", "-s", "input_filename", file], b"")?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, format!("\"{file}\"\n").as_bytes());
+
+    // the file name is that of the value read last, also when read by `input`
+    let dir = env::temp_dir();
+    let a = dir.join(format!("jaq-filename-a-{}.json", process::id()));
+    let b = dir.join(format!("jaq-filename-b-{}.json", process::id()));
+    std::fs::write(&a, "1")?;
+    std::fs::write(&b, "2")?;
+    let (a_str, b_str) = (a.to_str().unwrap(), b.to_str().unwrap());
+    let f = "[., input_filename, (input | input_filename)]";
+    let ab = jaq(&["-c", f, a_str, b_str], b"");
+    let n = jaq(&["-n", "input | input_filename", a_str, b_str], b"");
+    std::fs::remove_file(&a)?;
+    std::fs::remove_file(&b)?;
+    let (a, b) = (a_str, b_str);
+    assert_eq!(ab?.stdout, format!("[1,{a:?},{b:?}]\n").as_bytes());
+    assert_eq!(n?.stdout, format!("{a:?}\n").as_bytes());
+    Ok(())
+}
+
+// values are numbered by the line on which they end
+test!(
+    input_line_number,
+    &["-c", "[., input_line_number]"],
+    "1\n\"a\"\n\n{\"b\":\n2} 3",
+    r#"[1,1]
+["a",2]
+[{"b":2},5]
+[3,5]"#
+);
+
+#[test]
+fn input_line_number_file() -> io::Result<()> {
+    let file = env::temp_dir().join(format!("jaq-line-{}.json", process::id()));
+    std::fs::write(&file, "1\n\n[2,\n3]\n")?;
+    let output = jaq(&["-c", "input_line_number", file.to_str().unwrap()], b"")?;
+    std::fs::remove_file(file)?;
+    assert_eq!(output.stdout, b"1\n4\n");
+    Ok(())
+}
+
+#[test]
+fn debug_stderr() -> io::Result<()> {
+    let f = r#"[1, "a"] | debug, debug("msg", .[0]), ("x\n" | stderr), (2 | stderr) | length"#;
+    let output = jaq(&["-n", "-c", f], b"")?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"2\n2\n2\n2\n");
+    let stderr = r#"["DEBUG:",[1,"a"]]
+["DEBUG:","msg"]
+["DEBUG:",1]
+x
+2"#;
+    assert_eq!(output.stderr, stderr.as_bytes());
+    Ok(())
+}
+
+// the keys of an updated path are evaluated only once
+#[test]
+fn update_path_effects() -> io::Result<()> {
+    let output = jaq(&["-n", "-c", r#"{} | .[("a", "b") | stderr] |= 1"#], b"")?;
+    assert_eq!(output.stdout, b"{\"a\":1,\"b\":1}\n");
+    assert_eq!(output.stderr, b"ab");
+
+    let output = jaq(&["-c", "{} | .[input, input] |= 1"], br#""a" "b" "c""#)?;
+    assert_eq!(output.stdout, b"{\"b\":1,\"c\":1}\n");
+    Ok(())
+}
+
+#[test]
+fn in_place() -> io::Result<()> {
+    let file = env::temp_dir().join(format!("jaq-in-place-{}.json", process::id()));
+    let backup = file.with_extension("json.bak");
+    let jaq = |args: &[&str]| {
+        let args = [args, &[file.to_str().unwrap()]].concat();
+        Ok::<_, io::Error>(jaq(&args, b"")?.status)
+    };
+    std::fs::write(&file, "{\"a\": 1}\n")?;
+
+    assert!(jaq(&["-c", "-i", "--backup", ".bak", ".a += 1"])?.success());
+    assert_eq!(std::fs::read_to_string(&file)?, "{\"a\":2}\n");
+    assert_eq!(std::fs::read_to_string(&backup)?, "{\"a\": 1}\n");
+
+    // a filter without outputs leaves the file unchanged
+    assert!(!jaq(&["-i", "empty"])?.success());
+    assert_eq!(std::fs::read_to_string(&file)?, "{\"a\":2}\n");
+
+    // all outputs are written to the file
+    assert!(jaq(&["-c", "-i", ".a, .a"])?.success());
+    assert_eq!(std::fs::read_to_string(&file)?, "2\n2\n");
+
+    std::fs::remove_file(file)?;
+    std::fs::remove_file(backup)
+}
+
+// files without output do not prevent other files from being replaced
+#[test]
+fn in_place_no_output() -> io::Result<()> {
+    let a = env::temp_dir().join(format!("jaq-in-place-a-{}.json", process::id()));
+    let b = env::temp_dir().join(format!("jaq-in-place-b-{}.json", process::id()));
+    std::fs::write(&a, "1\n")?;
+    std::fs::write(&b, "2\n")?;
+    let (a_str, b_str) = (a.to_str().unwrap(), b.to_str().unwrap());
+    let output = jaq(&["-i", "select(. > 1) | . + 1", a_str, b_str], b"")?;
+    let (a_out, b_out) = (std::fs::read_to_string(&a)?, std::fs::read_to_string(&b)?);
+    std::fs::remove_file(&a)?;
+    std::fs::remove_file(&b)?;
+
+    assert!(!output.status.success());
+    let stderr = format!("Error: {a_str}: no output, leaving file unchanged\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), stderr);
+    assert_eq!((a_out.as_str(), b_out.as_str()), ("1\n", "3\n"));
+    Ok(())
+}
+
+// the file that a symbolic link points to is replaced, not the link
+#[cfg(unix)]
+#[test]
+fn in_place_symlink() -> io::Result<()> {
+    let file = env::temp_dir().join(format!("jaq-in-place-target-{}.json", process::id()));
+    let link = env::temp_dir().join(format!("jaq-in-place-link-{}.json", process::id()));
+    std::fs::write(&file, "1\n")?;
+    std::os::unix::fs::symlink(&file, &link)?;
+    let status = jaq(&["-i", ". + 1", link.to_str().unwrap()], b"")?.status;
+    let is_link = std::fs::symlink_metadata(&link)?.file_type().is_symlink();
+    let contents = std::fs::read_to_string(&file)?;
+    std::fs::remove_file(&link)?;
+    std::fs::remove_file(&file)?;
+
+    assert!(status.success());
+    assert!(is_link);
+    assert_eq!(contents, "2\n");
+    Ok(())
+}
+
+// errors in one line do not stop the REPL
+test!(
+    repl,
+    &["--repl", "-c"],
+    ".\n1 + 1\n:let $x = [$last]\n$x\n.a[\n$last",
+    "null\n2\n[2]\n[2]\n[2]"
+);
+
+// with `-n`, the filter runs once and reads the inputs of all files
+#[test]
+fn inputs_files() -> io::Result<()> {
+    let file = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/lib/m.jq");
+    let f = "[first(inputs), inputs] | length";
+    let output = jaq(&["-n", "-R", "-c", f, file, file], b"")?;
+    assert!(output.status.success());
+    let lines = std::fs::read_to_string(file)?.lines().count();
+    assert_eq!(output.stdout, format!("{}\n", 2 * lines).as_bytes());
+    Ok(())
+}
+
+// evaluation does not need a large stack, but
+// writing and dropping deeply nested values does
+#[test]
+fn stack_size() -> io::Result<()> {
+    let f = "def f: if . > 0 then [(. - 1 | f)] else . end; 100000 | f";
+    let output = jaq(&["-n", "-c", "--stack-size", "1024", f], b"")?;
+    assert!(output.status.success());
+    let expected = format!("{}0{}\n", "[".repeat(100000), "]".repeat(100000));
+    assert_eq!(output.stdout, expected.as_bytes());
+    Ok(())
+}
+
+// arguments such as `n-1` are evaluated on every call instead of nesting closures
+test!(
+    repeat_apply,
+    &[
+        "-n",
+        "def repeat_apply(f; n): if n <= 0 then . else f | repeat_apply(f; n-1) end;
+        0 | repeat_apply(.+1; 100000)"
+    ],
+    "",
+    "100000"
+);
+
+// the input is split into several chunks to be processed by different threads
+#[test]
+fn parallel() -> io::Result<()> {
+    let input: String = (0..20000).map(|i| format!("{{\"a\": {i}}}\n")).collect();
+    let output: Vec<_> = (0..20000).map(|i| (i * 2).to_string()).collect();
+    golden_test(&["--parallel=3", ".a * 2"], &input, &output.join("\n"))
+}
+
+test!(halt, &["-n", "1, halt, 2"], "", "1");
+
+#[test]
+fn halt_error() -> io::Result<()> {
+    let halt_error = |f: &str| jaq(&["-n", f], b"");
+
+    let output = halt_error(r#""bye\n" | halt_error(1)"#)?;
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stderr, b"bye\n");
+
+    let output = halt_error(r#"{"a": 1} | halt_error"#)?;
+    assert_eq!(output.status.code(), Some(5));
+    assert_eq!(
+        str::from_utf8(&output.stderr).unwrap().trim_end(),
+        r#"{"a":1}"#
+    );
+
+    // halting cannot be caught
+    let output = halt_error(r#"try ("bye" | halt_error(2)) catch 0"#)?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+
+    // uncaught errors yield exit code 5, like in jq
+    let output = halt_error(r#"(try error({"code": 3}) catch .code), error({"code": 4})"#)?;
+    assert_eq!(output.status.code(), Some(5));
+    assert_eq!(str::from_utf8(&output.stdout).unwrap().trim_end(), "3");
+    assert!(output.stderr.starts_with(b"Error: {\"code\":4}\n"));
+    Ok(())
+}
+
+#[test]
+fn runtime_error_loc() -> io::Result<()> {
+    let output = jaq(&["-n", "def f: .a;\n[1, 2] | map(f)"], b"")?;
+    assert_eq!(output.status.code(), Some(5));
+    let report = r#"Error: Cannot index number with "a"
+   ╭─[<top-level>:1:8]
+   │
+ 1 │ def f: .a;
+   │        ─┬  
+   │         ╰── Cannot index number with "a"
+───╯
+"#;
+    assert_eq!(String::from_utf8_lossy(&output.stderr), report);
+    Ok(())
+}
+
+#[test]
+fn profile() -> io::Result<()> {
+    let f = "def f: if . > 0 then [. - 1 | f] else . end; 2 | f";
+    let output = jaq(&["-n", "-c", "--profile", f], b"")?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"[[0]]\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let f = stderr.lines().find(|l| l.ends_with(" f/0 (<top-level>:1)"));
+    let counts: Vec<_> = f.unwrap().split_whitespace().take(2).collect();
+    assert_eq!(counts, ["3", "3"]);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn env_non_utf8() -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    let mut cmd = command(&["-n", "-c", "[$ENV.JAQ_X, env.JAQ_X, env.JAQ_UNSET]"]);
+    cmd.env("JAQ_X", std::ffi::OsStr::from_bytes(b"a\xff"));
+    let output = output(cmd.env_remove("JAQ_UNSET"), b"")?;
+    assert!(output.status.success());
+    assert_eq!(
+        output.stdout,
+        "[\"a\u{fffd}\",\"a\u{fffd}\",null]\n".as_bytes()
+    );
+    Ok(())
+}
+
+#[test]
+fn time_zone() -> io::Result<()> {
+    let local = |tz: &str| {
+        let f = "1704067200, 1720000000 | strflocaltime(\"%H %Z\")";
+        let mut cmd = command(&["-n", "-c", f]);
+        output(cmd.env("TZ", tz), b"")
+    };
+    let cest = b"\"01 CET\"\n\"11 CEST\"\n";
+
+    let output = local("CET-1CEST,M3.5.0,M10.5.0/3")?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, cest);
+
+    let output = local("AEST-10AEDT,M10.1.0,M4.1.0/3")?;
+    assert_eq!(output.stdout, b"\"11 AEDT\"\n\"19 AEST\"\n");
+
+    let output = local("EST5EDT")?;
+    assert_eq!(output.stdout, b"\"19 EST\"\n\"05 EDT\"\n");
+
+    assert!(!local("Nowhere/Unknown")?.status.success());
+
+    // TZif file without transitions, where only the POSIX TZ string yields DST
+    let mut tzif = Vec::new();
+    // version 1 and version 2 data, each with one time type and 4 characters of abbreviations
+    for _ in 0..2 {
+        tzif.extend(b"TZif2");
+        tzif.extend([0; 15]);
+        for count in [0, 0, 0, 0, 1, 4] {
+            tzif.extend(u32::to_be_bytes(count));
+        }
+        tzif.extend(i32::to_be_bytes(3600));
+        tzif.extend(b"\0\0CET\0");
+    }
+    tzif.extend(b"\nCET-1CEST,M3.5.0,M10.5.0/3\n");
+    let file = env::temp_dir().join(format!("jaq-tzif-{}", process::id()));
+    std::fs::write(&file, tzif)?;
+    let output = local(file.to_str().unwrap())?;
+    std::fs::remove_file(file)?;
+    assert_eq!(output.stdout, cest);
+    Ok(())
+}
+
+#[test]
+fn cache() -> io::Result<()> {
+    let dir = env::temp_dir().join(format!("jaq-cache-{}", process::id()));
+    let run = |f: &str| {
+        let dir = dir.to_str().unwrap();
+        jaq(&["-n", "-c", "--arg", "x", "1", "--cache", dir, f], b"")
+    };
+    let f = "[$x, $__loc__.line, (3 | tostream)]";
+    // the first run writes the cache, the second one reads it
+    let outputs = [run(f)?, run(f)?];
+    let files: Vec<_> = std::fs::read_dir(&dir)?.collect::<Result<_, _>>()?;
+    // a corrupt cache is ignored and overwritten
+    std::fs::write(files[0].path(), "invalid")?;
+    let corrupt = run(f)?;
+    let rewritten = std::fs::read(files[0].path())?;
+    std::fs::remove_dir_all(&dir)?;
+
+    assert_eq!(files.len(), 1);
+    for output in outputs.iter().chain([&corrupt]) {
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"[\"1\",1,[[],3]]\n");
+    }
+    assert_ne!(rewritten, b"invalid");
+    Ok(())
+}